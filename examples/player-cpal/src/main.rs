@@ -68,7 +68,7 @@ fn run(args: Vec<String>) -> Result<(), Box<Error>> {
                             let mut fi = info.lock().unwrap();
                             oxdz.frame_info(&mut fi);
                         }
-                        oxdz.fill_buffer(&mut buffer, 0);
+                        oxdz.fill_buffer(&mut buffer, 1);
                     },
     
                     _ => { }
@@ -82,6 +82,10 @@ fn run(args: Vec<String>) -> Result<(), Box<Error>> {
         {
             let fi = info.lock().unwrap();
             print!("pos:{:3} - row:{:3} \r", fi.pos, fi.row);
+            if fi.end {
+                println!();
+                break;
+            }
         }
         stdout().flush().unwrap();
         std::thread::sleep(Duration::from_millis(50));
@@ -39,7 +39,7 @@ impl<'a> AudioCallback for ModPlayer<'a> {
             let mut fi = self.data.lock().unwrap();
             self.oxdz.frame_info(&mut fi);
         }
-        self.oxdz.fill_buffer(&mut out, 0);
+        self.oxdz.fill_buffer(&mut out, 1);
     }
 }
 
@@ -86,6 +86,10 @@ fn run(args: Vec<String>) -> Result<(), Box<Error>> {
         {
             let fi = data.lock().unwrap();
             print!("pos:{:3} - row:{:3} \r", fi.pos, fi.row);
+            if fi.end {
+                println!();
+                break;
+            }
         }
         stdout().flush().unwrap();
         std::thread::sleep(Duration::from_millis(50));
@@ -11,44 +11,72 @@ use proc_macro::TokenStream;
 pub fn save_restore(input: TokenStream) -> TokenStream {
     // Construct a string representation of the type definition
     let s = input.to_string();
-    
+
     // Parse the string representation
     let ast = syn::parse_derive_input(&s).unwrap();
 
     // Build the impl
     let gen = impl_save_restore(&ast);
-    
+
     // Return the generated impl
     gen.parse().unwrap()
 }
 
 fn impl_save_restore(ast: &syn::DeriveInput) -> quote::Tokens {
     let name = &ast.ident;
-    quote! {
-        use std::ptr;
-        use std::mem;
 
+    let fields = match ast.body {
+        syn::Body::Struct(syn::VariantData::Struct(ref fields)) |
+        syn::Body::Struct(syn::VariantData::Tuple(ref fields))  => fields,
+        syn::Body::Struct(syn::VariantData::Unit)               => panic!("SaveRestore: {} has no fields", name),
+        syn::Body::Enum(_)                                      => panic!("SaveRestore: {} is not a struct", name),
+    };
+
+    // Named fields are accessed by name and tuple fields by index
+    let idents: Vec<syn::Ident> = fields.iter().enumerate().map(|(i, f)| match f.ident {
+        Some(ref ident) => ident.clone(),
+        None            => syn::Ident::new(i),
+    }).collect();
+    let idents2 = idents.clone();
+    let types: Vec<&syn::Ty> = fields.iter().map(|f| &f.ty).collect();
+
+    // The structure layout is part of the version tag, so states saved by
+    // a different build of the player are rejected
+    let layout = idents.iter().zip(types.iter()).map(|(i, t)| format!("{}:{}", i, quote!(#t)))
+                       .collect::<Vec<String>>().join(",");
+    let tag = fnv1a(&format!("{}{{{}}}", name, layout));
+
+    quote! {
         impl SaveRestore for #name {
-            unsafe fn save(&self) -> Vec<u8> {
-                let size = mem::size_of::<Self>();
-                let mut dst: Vec<u8> = Vec::with_capacity(size);
-                dst.set_len(size);
-                ptr::copy(self, mem::transmute(dst.as_mut_ptr()), 1);
-                dst
+            fn save(&self, state: &mut Vec<u8>) {
+                #( SaveRestore::save(&self.#idents, state); )*
+            }
+
+            fn restore(&mut self, state: &mut &[u8]) -> Result<(), ::Error> {
+                #( SaveRestore::restore(&mut self.#idents2, state)?; )*
+                Ok(())
             }
 
-            unsafe fn restore(&mut self, buffer: &Vec<u8>) {
-                let size = buffer.len();
-                ptr::copy(buffer.as_ptr(), mem::transmute(self), size)
+            fn version() -> u32 {
+                let mut tag: u32 = #tag;
+                #( tag = tag.rotate_left(5) ^ <#types as SaveRestore>::version(); )*
+                tag
             }
         }
     }
 }
 
+fn fnv1a(s: &str) -> u32 {
+    s.bytes().fold(0x811c9dc5_u32, |h, b| (h ^ b as u32).wrapping_mul(0x01000193))
+}
+
 #[cfg(test)]
 mod tests {
+    use super::fnv1a;
+
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(""), 0x811c9dc5);
+        assert_eq!(fnv1a("a"), 0xe40c292c);
     }
 }
@@ -0,0 +1,204 @@
+use format::{ProbeInfo, Format, Loader};
+use format::c669::{C669Data, C669Patterns, C669Instrument};
+use module::{Module, Sample};
+use module::sample::SampleType;
+use util::BinaryRead;
+use ::*;
+
+/// Composer 669 and UNIS 669 module loader
+pub struct C669Loader;
+
+impl Loader for C669Loader {
+    fn name(&self) -> &'static str {
+        "Composer 669"
+    }
+
+    fn probe(&self, b: &[u8], player_id: &str) -> Result<ProbeInfo, Error> {
+        if b.len() < 0x1f1 {
+            return Err(Error::Format(format!("file too short ({})", b.len())));
+        }
+
+        player::check_accepted(player_id, "669")?;
+
+        let magic = b.read_string(0, 2)?;
+        if magic != "if" && magic != "JN" {
+            return Err(Error::Format(format!("bad magic {:?}", magic)));
+        }
+
+        // The magic is too short, also check header values
+        let num_samples = b.read8(0x6e)?;
+        let num_patterns = b.read8(0x6f)?;
+        let loop_order = b.read8(0x70)?;
+        if num_samples > 64 || num_patterns > 128 || loop_order >= 128 {
+            return Err(Error::Format("invalid header".to_owned()));
+        }
+        for i in 0..128 {
+            let ord = b.read8(0x71 + i)?;
+            if (ord >= num_patterns && ord != 0xff) || b.read8(0x171 + i)? >= 64 {
+                return Err(Error::Format("invalid orders".to_owned()));
+            }
+        }
+
+        Ok(ProbeInfo{format: Format::C669, title: b.read_string(2, 36)?})
+    }
+
+    fn load(self: Box<Self>, b: &[u8], info: ProbeInfo) -> Result<Module, Error> {
+
+        if info.format != Format::C669 {
+            return Err(Error::Format("unsupported format".to_owned()));
+        }
+
+        let magic = b.read_string(0, 2)?;
+        let message = (0..3).map(|i| b.read_string(2 + i * 36, 36)).collect::<Result<Vec<String>, Error>>()?;
+        let num_samples = b.read8(0x6e)?;
+        let num_patterns = b.read8(0x6f)?;
+        let loop_order = b.read8(0x70)?;
+
+        // Load instruments
+        let mut instruments = Vec::<C669Instrument>::new();
+        for i in 0..num_samples as usize {
+            instruments.push(load_instrument(b, i)?);
+        }
+
+        // Load patterns
+        let mut ofs = 0x1f1 + 25 * num_samples as usize;
+        let size = num_patterns as usize * 64 * 8 * 3;
+        let patterns = C669Patterns::from_slice(num_patterns as usize, b.slice(ofs, size)?);
+        ofs += size;
+
+        // Load samples
+        let mut samples = Vec::<Sample>::new();
+        for i in 0..num_samples as usize {
+            let size = instruments[i].size as usize;
+            let smp = load_sample(b.slice(ofs, size)?, ofs, i, &instruments[i]);
+            samples.push(smp);
+            ofs += size;
+        }
+
+        let mut data = C669Data{
+            magic,
+            message,
+            num_samples,
+            num_patterns,
+            loop_order,
+            orders: [0; 128],
+            tempo: [0; 128],
+            breaks: [0; 128],
+            instruments,
+            patterns,
+            samples,
+        };
+
+        data.orders.copy_from_slice(b.slice(0x71, 128)?);
+        data.tempo.copy_from_slice(b.slice(0xf1, 128)?);
+        data.breaks.copy_from_slice(b.slice(0x171, 128)?);
+
+        let unis = data.is_unis();
+
+        let m = Module {
+            format_id  : "669",
+            description: if unis { "UNIS 669" } else { "Composer 669" }.to_owned(),
+            creator    : if unis { "UNIS 669" } else { "Composer 669" }.to_owned(),
+            channels   : 8,
+            player     : "669",
+            data       : Box::new(data),
+        };
+
+        Ok(m)
+    }
+}
+
+fn load_instrument(b: &[u8], i: usize) -> Result<C669Instrument, Error> {
+    let mut ins = C669Instrument::new();
+
+    let ofs = 0x1f1 + i * 25;
+    ins.name = b.read_string(ofs, 13)?;
+    ins.size = b.read32l(ofs + 13)?;
+    ins.loop_start = b.read32l(ofs + 17)?;
+    ins.loop_end = b.read32l(ofs + 21)?;
+
+    Ok(ins)
+}
+
+fn load_sample(b: &[u8], ofs: usize, i: usize, ins: &C669Instrument) -> Sample {
+    let mut smp = Sample::new();
+
+    smp.num = i + 1;
+    smp.address = ofs as u32;
+    smp.name = ins.name.to_owned();
+    smp.size = ins.size;
+    if smp.size > 0 {
+        smp.sample_type = SampleType::Sample8;
+    }
+    smp.store(b);
+    smp.to_signed();
+
+    smp
+}
+
+
+// Composer 669 module used by the loader and player tests
+#[cfg(test)]
+pub fn c669_module(magic: &str) -> Vec<u8> {
+    use util::BinaryWrite;
+
+    let mut b: Vec<u8> = Vec::new();
+    b.write_string(magic, 2);
+    b.write_string("test", 108);
+    b.write8(1);                        // samples
+    b.write8(2);                        // patterns
+    b.write8(1);                        // loop order
+    let mut orders = [0xff; 128];
+    orders[1] = 1;
+    orders[0] = 0;
+    b.extend_from_slice(&orders);
+    b.extend_from_slice(&[4, 6]);       // tempo
+    b.extend_from_slice(&[0; 126]);
+    b.extend_from_slice(&[15, 31]);     // break location
+    b.extend_from_slice(&[0; 126]);
+
+    b.write_string("sample", 13);
+    for &x in &[16, 4, 16] { b.write32l(x) }
+
+    for p in 0..2 {
+        for r in 0..64 {
+            for c in 0..8 {
+                if p == 0 && r == 0 && c == 1 {
+                    b.extend_from_slice(&[0x60, 0x0f, 0x02]);
+                } else {
+                    b.extend_from_slice(&[0xff, 0, 0xff]);
+                }
+            }
+        }
+    }
+    b.extend((0..16).map(|x| x * 16));
+    b
+}
+
+
+#[cfg(test)]
+mod tests {
+    use format;
+    use format::c669::C669Data;
+    use super::c669_module;
+
+    #[test]
+    fn test_load_669() {
+        let b = c669_module("if");
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "669");
+        assert_eq!(m.title().trim_end(), "test");
+        assert_eq!(m.len(), 2);
+        assert_eq!(m.rows(1), 32);
+        let data = m.data.as_any().downcast_ref::<C669Data>().unwrap();
+        assert!(!data.is_unis());
+        let e = data.patterns.event(0, 0, 1);
+        assert_eq!((e.note, e.ins, e.vol, e.cmd, e.param), (24, 0, 15, 0, 2));
+        assert!(!data.patterns.event(0, 1, 1).has_note());
+        assert_eq!(data.samples[0].data[1], 0x90);
+
+        let m = format::load(&c669_module("JN"), "").unwrap();
+        assert_eq!(m.description, "UNIS 669");
+        assert!(format::load(&c669_module("XX"), "").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+pub mod load;
+
+pub use self::load::*;
+
+use std::any::Any;
+use std::fmt;
+use module::{event, ModuleData, Sample};
+use util::NOTES;
+
+// 669 module layout
+//
+//  0000: Magic ("if" for Composer 669, "JN" for UNIS 669)
+//  0002: Song message, 3 lines of 36 characters
+//  006e: Number of samples
+//  006f: Number of patterns
+//  0070: Loop order
+//  0071: Orders, 128 bytes, 0xff ends the song
+//  00f1: Tempo of each pattern, 128 bytes
+//  0171: Break location (last row) of each pattern, 128 bytes
+//  01f1: Instruments, 25 bytes each
+//  xxxx: Patterns, 64 rows of 8 channels with 3 bytes per event
+//  xxxx: Unsigned 8-bit sample data
+
+pub struct C669Data {
+    pub magic       : String,
+    pub message     : Vec<String>,
+    pub num_samples : u8,
+    pub num_patterns: u8,
+    pub loop_order  : u8,
+    pub orders      : [u8; 128],
+    pub tempo       : [u8; 128],
+    pub breaks      : [u8; 128],
+    pub instruments : Vec<C669Instrument>,
+    pub patterns    : C669Patterns,
+    pub samples     : Vec<Sample>,
+}
+
+impl C669Data {
+    /// True if the module was created by UNIS 669, which adds balance and
+    /// retrig effects.
+    pub fn is_unis(&self) -> bool {
+        self.magic == "JN"
+    }
+}
+
+impl ModuleData for C669Data {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    // The first line of the song message is used as title.
+    fn title(&self) -> &str {
+        &self.message[0]
+    }
+
+    fn patterns(&self) -> usize {
+        self.num_patterns as usize
+    }
+
+    fn len(&self) -> usize {
+        for i in 0..128 {
+            if self.orders[i] >= self.num_patterns {
+                return i
+            }
+        }
+        128
+    }
+
+    fn pattern_in_position(&self, pos: usize) -> Option<usize> {
+        if pos >= self.len() {
+            None
+        } else {
+            Some(self.orders[pos] as usize)
+        }
+    }
+
+    fn instruments(&self) -> Vec<String> {
+        self.instruments.iter().map(|x| x.name.to_owned()).collect::<Vec<String>>()
+    }
+
+    fn rows(&self, pat: usize) -> usize {
+        if pat >= self.num_patterns as usize {
+            0
+        } else {
+            self.breaks[pat] as usize + 1
+        }
+    }
+
+    fn pattern_data(&self, pat: usize, num: usize, buffer: &mut [u8]) -> usize {
+        let rows = self.rows(pat);
+        let mut i = 0;
+        for _ in 0..num {
+            let (row, ch) = (i / 8, i % 8);
+            if row >= rows {
+                break
+            }
+            let ofs = i * 6;
+            let e = self.patterns.event(pat, row, ch);
+
+            let mut flags = 0;
+            if e.has_note() { flags |= event::HAS_NOTE | event::HAS_INS; buffer[ofs+1] = e.note + 36; buffer[ofs+2] = e.ins + 1 }
+            if e.has_vol()  { flags |= event::HAS_VOL ; buffer[ofs+3] = e.vol }
+            if e.has_cmd()  { flags |= event::HAS_CMD ; buffer[ofs+4] = e.cmd; buffer[ofs+5] = e.param }
+            buffer[ofs] = flags;
+
+            i += 1;
+        }
+        i
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        self.samples.to_owned()
+    }
+}
+
+
+/// C669Instrument defines the sample header fields of a 669 module.
+#[derive(Debug,Default)]
+pub struct C669Instrument {
+    pub name      : String,
+    pub size      : u32,
+    pub loop_start: u32,
+    pub loop_end  : u32,
+}
+
+impl C669Instrument {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn has_loop(&self) -> bool {
+        self.loop_end <= self.size && self.loop_end > self.loop_start
+    }
+}
+
+
+/// C669Event defines the event format used in 669 patterns. Notes and
+/// instruments are always set together, and volumes range from 0 to 15.
+#[derive(Default)]
+pub struct C669Event {
+    pub note : u8,      // 255 = no note
+    pub ins  : u8,
+    pub vol  : u8,      // 255 = no volume
+    pub cmd  : u8,      // 255 = no command
+    pub param: u8,
+}
+
+impl C669Event {
+    fn new() -> Self {
+        Default::default()
+    }
+
+    fn from_slice(b: &[u8]) -> Self {
+        let mut e = C669Event::new();
+        match b[0] {
+            0xff => { e.note = 255; e.vol = 255 },
+            0xfe => { e.note = 255; e.vol = b[1] & 0x0f },
+            _    => {
+                e.note = b[0] >> 2;
+                e.ins = (b[0] & 0x03) << 4 | b[1] >> 4;
+                e.vol = b[1] & 0x0f;
+            },
+        }
+        if b[2] == 0xff {
+            e.cmd = 255;
+        } else {
+            e.cmd = b[2] >> 4;
+            e.param = b[2] & 0x0f;
+        }
+        e
+    }
+
+    pub fn has_note(&self) -> bool {
+        self.note != 255
+    }
+
+    pub fn has_vol(&self) -> bool {
+        self.vol != 255
+    }
+
+    pub fn has_cmd(&self) -> bool {
+        self.cmd != 255
+    }
+}
+
+impl fmt::Display for C669Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (note, ins) = if self.has_note() {
+            let n = self.note as usize + 24;
+            (format!("{}{}", NOTES[n%12], n/12), format!("{:02X}", self.ins))
+        } else {
+            ("---".to_owned(), "--".to_owned())
+        };
+
+        let vol = if self.has_vol() {
+            format!("{:X}", self.vol)
+        } else {
+            "-".to_owned()
+        };
+
+        let cmd = if self.has_cmd() {
+            format!("{}{:X}", (b'a' + self.cmd) as char, self.param)
+        } else {
+            "..".to_owned()
+        };
+
+        write!(f, "{} {} {} {}", note, ins, vol, cmd)
+    }
+}
+
+
+pub struct C669Patterns {
+    data: Vec<C669Event>,
+}
+
+impl C669Patterns {
+    fn from_slice(num: usize, b: &[u8]) -> Self {
+        let mut pat = C669Patterns{
+            data: Vec::new(),
+        };
+
+        for i in 0..num * 64 * 8 {
+            pat.data.push(C669Event::from_slice(&b[i * 3..i * 3 + 3]));
+        }
+
+        pat
+    }
+
+    pub fn event(&self, pat: usize, row: usize, chn: usize) -> &C669Event {
+        &self.data[pat * 512 + row * 8 + chn]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        let e = C669Event::from_slice(&[0xff, 0x00, 0xff]);
+        assert_eq!(format!("{}", e), "--- -- - ..");
+
+        let e = C669Event::from_slice(&[0x61, 0x2c, 0x53]);
+        assert_eq!(format!("{}", e), "C 4 12 C f3");
+
+        let e = C669Event::from_slice(&[0xfe, 0x07, 0x21]);
+        assert_eq!(format!("{}", e), "--- -- 7 c1");
+    }
+}
@@ -0,0 +1,413 @@
+mod mod_s3m;
+mod mod_xm;
+mod mtm_s3m;
+mod s3m_xm;
+mod stm_s3m;
+
+use std::fmt;
+use format::s3m::S3mPattern;
+use module::Module;
+use ::*;
+
+// Module conversion
+//
+// Converters rebuild the format-specific data of a module in a different
+// format so that it can be saved or played by a player for the target
+// format. Pattern effects and notes that can't be represented in the target
+// format are dropped and listed in the conversion report.
+
+pub struct Conversion {
+    pub module     : Module,
+    pub unconverted: Vec<Unconverted>,
+}
+
+/// An effect or note that couldn't be converted to the target format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unconverted {
+    pub pattern: usize,
+    pub row    : usize,
+    pub channel: usize,
+    pub effect : String,
+    pub reason : &'static str,
+}
+
+impl fmt::Display for Unconverted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pattern {} row {} channel {}: {} ({})", self.pattern, self.row, self.channel, self.effect, self.reason)
+    }
+}
+
+struct Report {
+    list: Vec<Unconverted>,
+}
+
+impl Report {
+    fn new() -> Self {
+        Report{ list: Vec::new() }
+    }
+
+    fn add(&mut self, pattern: usize, row: usize, channel: usize, effect: String, reason: &'static str) {
+        self.list.push(Unconverted{ pattern, row, channel, effect, reason });
+    }
+}
+
+/// Convert a module to the format identified by `format_id`. Supported
+/// conversions are M.K./xCHN/xxCH to S3M and XM, S3M to XM, and STM and
+/// MTM to S3M.
+pub fn convert(module: &Module, format_id: &str) -> Result<Conversion, Error> {
+    debug!("Convert {} to {}", module.format_id, format_id);
+
+    match (module.format_id, format_id) {
+        ("m.k.", "s3m") | ("xchn", "s3m") | ("xxch", "s3m") => mod_s3m::convert(module),
+        ("m.k.", "xm")  | ("xchn", "xm")  | ("xxch", "xm")  => mod_xm::convert(module),
+        ("s3m", "xm")                                       => s3m_xm::convert(module),
+        ("stm", "s3m")                                      => stm_s3m::convert(module),
+        ("mtm", "s3m")                                      => mtm_s3m::convert(module),
+        _ => Err(Error::Format(format!("can't convert {} module to {}", module.format_id, format_id))),
+    }
+}
+
+
+// Scream Tracker 3 pattern helpers shared by the S3M converters
+
+#[derive(Clone, Copy)]
+struct S3mEvent {
+    note: u8,   // 255 = empty
+    ins : u8,
+    vol : u8,   // 255 = empty
+    cmd : u8,
+    info: u8,
+}
+
+impl S3mEvent {
+    fn new() -> Self {
+        S3mEvent{ note: 255, ins: 0, vol: 255, cmd: 0, info: 0 }
+    }
+}
+
+fn encode_s3m_pattern(events: &[S3mEvent], chn: usize) -> S3mPattern {
+    let mut data = vec![0, 0];  // make room for pattern size
+
+    for r in 0..64 {
+        for c in 0..chn {
+            let e = &events[r * chn + c];
+            let mut b = 0_u8;
+            if e.note != 255 || e.ins != 0 {
+                b |= 0x20;  // note and instrument follow
+            }
+            if e.vol != 255 {
+                b |= 0x40;  // volume follows
+            }
+            if e.cmd != 0 {
+                b |= 0x80;  // command and info follow
+            }
+            if b == 0 {
+                continue
+            }
+            data.push(b | c as u8);
+            if b & 0x20 != 0 {
+                data.push(e.note);
+                data.push(e.ins);
+            }
+            if b & 0x40 != 0 {
+                data.push(e.vol);
+            }
+            if b & 0x80 != 0 {
+                data.push(e.cmd);
+                data.push(e.info);
+            }
+        }
+        data.push(0);
+    }
+
+    let size = data.len();
+    data[0] = (size & 0xff) as u8;
+    data[1] = (size >> 8) as u8;
+
+    S3mPattern{
+        size,
+        data,
+    }
+}
+
+fn decode_s3m_pattern(pat: &S3mPattern, chn: usize) -> Vec<S3mEvent> {
+    let mut events = vec![S3mEvent::new(); 64 * chn];
+    let p = &pat.data;
+
+    let mut row = 0;
+    let mut i = 2;
+    while row < 64 && i < p.len() {
+        let b = p[i]; i += 1;
+        if b == 0 {
+            row += 1;
+            continue
+        }
+
+        let mut e = S3mEvent::new();
+        if b & 0x20 != 0 && i + 1 < p.len() {
+            e.note = p[i];
+            e.ins = p[i + 1];
+            i += 2;
+        }
+        if b & 0x40 != 0 && i < p.len() {
+            e.vol = p[i];
+            i += 1;
+        }
+        if b & 0x80 != 0 && i + 1 < p.len() {
+            e.cmd = p[i];
+            e.info = p[i + 1];
+            i += 2;
+        }
+
+        let c = (b & 0x1f) as usize;
+        if c < chn {
+            events[row * chn + c] = e;
+        }
+    }
+
+    events
+}
+
+// Map channels to the left (0-7) and right (8-15) ST3 channel slots using
+// the Amiga LRRL layout.
+fn s3m_channel_settings(chn: usize) -> Result<[u8; 32], Error> {
+    if chn > 16 {
+        return Err(Error::Format(format!("can't convert {} channels to S3M", chn)));
+    }
+
+    let mut settings = [0xff; 32];
+    let mut used = [false; 16];
+    for c in 0..chn {
+        let right = c % 4 == 1 || c % 4 == 2;
+        let base = if right { 8 } else { 0 };
+        let mut slot = base + c % 8;
+        if used[slot] {
+            slot = (base..base + 8).find(|&x| !used[x]).unwrap_or(slot);
+        }
+        used[slot] = true;
+        settings[c] = slot as u8;
+    }
+
+    Ok(settings)
+}
+
+fn s3m_effect_name(cmd: u8, info: u8) -> String {
+    format!("{}{:02X}", b'@'.wrapping_add(cmd) as char, info)
+}
+
+fn s3m_cmd(c: char) -> u8 {
+    c as u8 - b'@'
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use format::{self, Format};
+    use format::xm::XmData;
+    use util::BinaryWrite;
+
+    fn mod_module(events: &[(usize, [u8; 4])]) -> Module {
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string("test", 20);
+        for i in 0..31 {
+            b.write_string("", 22);
+            b.write16b(if i == 0 { 8 } else { 0 });
+            b.extend_from_slice(&[0, 64, 0, 0, 0, 1]);
+        }
+        b.write8(1);
+        b.write8(127);
+        b.extend_from_slice(&[0; 128]);
+        b.write_string("M.K.", 4);
+        let ofs = b.len();
+        b.extend_from_slice(&[0; 1024]);
+        for &(i, ref e) in events {
+            b[ofs + i*4..ofs + i*4 + 4].copy_from_slice(e);
+        }
+        b.extend_from_slice(&[0; 16]);
+        format::load(&b, "").unwrap()
+    }
+
+    fn stm_module(events: &[(usize, [u8; 4])]) -> Module {
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string("test", 20);
+        b.write_string("!Scream!\x1a\x02", 10);
+        b.extend_from_slice(&[2, 21, 0x60, 1, 64]);
+        b.extend_from_slice(&[0; 13]);
+        for i in 0..31 {
+            b.write_string("", 16);
+            b.write16l(if i == 0 { 16 } else { 0 });
+            b.write16l(0);
+            b.write16l(0xffff);
+            b.extend_from_slice(&[64, 0]);
+            b.write16l(8448);
+            b.extend_from_slice(&[0; 6]);
+        }
+        b.write8(0);
+        b.extend_from_slice(&[99; 127]);
+        let ofs = b.len();
+        for _ in 0..256 { b.extend_from_slice(&[255, 1, 0x80, 0]) }
+        for &(i, ref e) in events {
+            b[ofs + i*4..ofs + i*4 + 4].copy_from_slice(e);
+        }
+        b.extend((0..16).map(|x| x * 16));
+        format::load(&b, "").unwrap()
+    }
+
+    fn s3m_module(events: &[(usize, S3mEvent)]) -> Module {
+        let mut ev = vec![S3mEvent::new(); 64 * 4];
+        for &(i, e) in events {
+            ev[i] = e;
+        }
+
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string("test", 28);
+        b.extend_from_slice(&[0x1a, 16, 0, 0]);
+        for &x in &[2, 1, 1, 0, 0x1320, 2] { b.write16l(x) }
+        b.write_string("SCRM", 4);
+        b.extend_from_slice(&[64, 6, 125, 0xb0, 0, 0]);
+        b.extend_from_slice(&[0; 10]);
+        b.extend_from_slice(&[0, 8, 9, 1]);
+        b.extend_from_slice(&[0xff; 28]);
+        b.extend_from_slice(&[0, 255]);     // orders
+        b.write16l(0x70 >> 4);
+        b.write16l(0xc0 >> 4);
+        b.pad_to(16);
+
+        // instrument with sample data at 0x200
+        b.write8(1);
+        b.write_string("", 12);
+        b.extend_from_slice(&[0, 0x20, 0]);
+        for &x in &[16, 0, 0] { b.write32l(x) }
+        b.extend_from_slice(&[64, 0, 0, 0]);
+        b.write32l(8363);
+        b.extend_from_slice(&[0; 12]);
+        b.write_string("sample", 28);
+        b.write_string("SCRS", 4);
+
+        b.extend_from_slice(&encode_s3m_pattern(&ev, 4).data);
+        b.resize(0x200, 0);
+        b.extend((0..16).map(|x| x * 16));
+        format::load(&b, "").unwrap()
+    }
+
+    #[test]
+    fn test_channel_settings() {
+        assert_eq!(&s3m_channel_settings(4).unwrap()[..5], &[0, 9, 10, 3, 255]);
+        let s = s3m_channel_settings(16).unwrap();
+        let mut used = s[..16].to_vec();
+        used.sort();
+        assert_eq!(used, (0..16).collect::<Vec<u8>>());
+        assert!(s3m_channel_settings(17).is_err());
+    }
+
+    #[test]
+    fn test_mod_to_xm() {
+        let m = mod_module(&[(0, [0x01, 0xac, 0x1c, 0x40]), (1, [0, 0, 0x0e, 0x01]), (6, [0, 0, 0x0f, 0]), (7, [0, 0x0e, 0x10, 0])]);
+        let c = m.convert_to("xm").unwrap();
+        assert_eq!(c.module.format_id, "xm");
+        assert_eq!(c.unconverted.len(), 3);
+        assert_eq!(c.unconverted[0], Unconverted{ pattern: 0, row: 0, channel: 1, effect: "E01".to_owned(), reason: "set filter" });
+        assert_eq!(format!("{}", c.unconverted[1]), "pattern 0 row 1 channel 2: F00 (stop song)");
+
+        // periods above the FastTracker 2 note range are dropped
+        assert_eq!(format!("{}", c.unconverted[2]), "pattern 0 row 1 channel 3: period 14 (note out of range)");
+
+        let data = c.module.data.as_any().downcast_ref::<XmData>().unwrap();
+        let e = data.patterns[0].event(0, 0);
+        assert_eq!((e.ton, e.instr, e.eff_typ, e.eff), (49, 1, 0x0c, 0x40));
+        assert_eq!(data.instruments[0].samp[0].len, 16);
+
+        // converted modules can be saved and loaded back
+        let b = format::save(&c.module, Format::Xm).unwrap();
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "xm");
+        assert_eq!(m.title().trim_end(), "test");
+    }
+
+    #[test]
+    fn test_mod_to_s3m() {
+        let m = mod_module(&[(0, [0x01, 0xac, 0x1c, 0x40]), (1, [0, 0, 0x0e, 0xf1]), (4, [0, 0, 0x0a, 0xf2])]);
+        let c = m.convert_to("s3m").unwrap();
+        assert_eq!(c.unconverted.len(), 1);
+        assert_eq!(c.unconverted[0].reason, "invert loop");
+
+        let b = format::save(&c.module, Format::S3m).unwrap();
+        let m = format::load(&b, "").unwrap();
+        let data = m.data.as_any().downcast_ref::<format::s3m::S3mData>().unwrap();
+        let events = decode_s3m_pattern(&data.patterns[0], 4);
+        let e = events[0];
+        assert_eq!((e.note, e.ins, e.vol, e.cmd), (0x40, 1, 64, 0));
+        let e = events[4];
+        assert_eq!((e.cmd, e.info), (s3m_cmd('D'), 0xf0));
+    }
+
+    #[test]
+    fn test_s3m_to_xm() {
+        let ev = |note, cmd, info| S3mEvent{ note, ins: if note < 255 { 1 } else { 0 }, vol: 32, cmd: s3m_cmd(cmd), info };
+        let m = s3m_module(&[(0, ev(0x40, 'A', 3)), (1, ev(255, 'U', 0x44)), (4, ev(255, 'D', 0x2f)), (8, ev(255, 'D', 0)), (9, ev(255, 'T', 0x10))]);
+        let c = m.convert_to("xm").unwrap();
+        assert_eq!(c.module.format_id, "xm");
+        assert_eq!(c.unconverted.len(), 2);
+        assert_eq!(c.unconverted[0], Unconverted{ pattern: 0, row: 0, channel: 1, effect: "U44".to_owned(), reason: "fine vibrato" });
+        assert_eq!(format!("{}", c.unconverted[1]), "pattern 0 row 2 channel 1: T10 (tempo too low)");
+
+        // converted modules can be saved and loaded back
+        let b = format::save(&c.module, Format::Xm).unwrap();
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "xm");
+        assert_eq!(m.channels, 4);
+        assert_eq!(m.title().trim_end(), "test");
+        let data = m.data.as_any().downcast_ref::<XmData>().unwrap();
+        assert_eq!(data.header.song_tab, vec![0]);
+        assert_eq!(data.instruments[0].samp[0].len, 16);
+        let e = data.patterns[0].event(0, 0);
+        assert_eq!((e.ton, e.instr, e.vol, e.eff_typ, e.eff), (49, 1, 0x30, 0x0f, 3));
+        let e = data.patterns[0].event(0, 1);
+        assert_eq!((e.ton, e.eff_typ, e.eff), (0, 0, 0));
+
+        // fine volume slides use the effect memory
+        let e = data.patterns[0].event(1, 0);
+        assert_eq!((e.eff_typ, e.eff), (0x0e, 0xa2));
+        let e = data.patterns[0].event(2, 0);
+        assert_eq!((e.eff_typ, e.eff), (0x0e, 0xa2));
+    }
+
+    #[test]
+    fn test_stm_to_s3m() {
+        let m = stm_module(&[(0, [0x22, 0x08, 0x81, 0x40]), (1, [255, 1, 0x85, 0xf0]), (4, [255, 1, 0x84, 0x12]), (5, [255, 1, 0x8b, 0x01])]);
+        let c = m.convert_to("s3m").unwrap();
+        assert_eq!(c.module.format_id, "s3m");
+        assert_eq!(c.unconverted.len(), 2);
+        assert_eq!(c.unconverted[0], Unconverted{ pattern: 0, row: 0, channel: 1, effect: "EF0".to_owned(), reason: "slide too fast" });
+        assert_eq!(format!("{}", c.unconverted[1]), "pattern 0 row 1 channel 1: K01 (unsupported command)");
+
+        // converted modules can be saved and loaded back
+        let b = format::save(&c.module, Format::S3m).unwrap();
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "s3m");
+        assert_eq!(m.channels, 4);
+        assert_eq!(m.title().trim_end(), "test");
+        let data = m.data.as_any().downcast_ref::<format::s3m::S3mData>().unwrap();
+        assert_eq!(&data.orders[..2], &[0, 255]);
+        assert_eq!(data.i_s, 6);
+        assert_eq!(data.instruments[0].length, 16);
+        assert_eq!(data.samples[0].data[1], 16);
+
+        let events = decode_s3m_pattern(&data.patterns[0], 4);
+        let e = events[0];
+        assert_eq!((e.note, e.ins, e.vol, e.cmd, e.info), (0x22, 1, 64, s3m_cmd('A'), 4));
+        let e = events[1];
+        assert_eq!((e.note, e.cmd), (255, 0));
+
+        // volume slide down has priority
+        let e = events[4];
+        assert_eq!((e.cmd, e.info), (s3m_cmd('D'), 0x02));
+    }
+
+    #[test]
+    fn test_unsupported_conversion() {
+        let m = mod_module(&[]);
+        assert!(m.convert_to("it").is_err());
+    }
+}
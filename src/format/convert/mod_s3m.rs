@@ -0,0 +1,281 @@
+use format::mk::{self, ModData};
+use format::s3m::{S3mData, S3mInstrument, S3mPattern};
+use format::convert::{Conversion, Report, S3mEvent, encode_s3m_pattern, s3m_channel_settings, s3m_cmd};
+use module::Module;
+use ::*;
+
+pub static FINETUNE_TABLE: [u16; 16] = [
+    8363, 8413, 8463, 8529, 8581, 8651, 8723, 8757,
+    7895, 7941, 7985, 8046, 8107, 8169, 8232, 8280
+];
+
+
+pub fn convert(module: &Module) -> Result<Conversion, Error> {
+
+    let data = module.data.as_any().downcast_ref::<ModData>().unwrap();
+    let mut report = Report::new();
+
+    let mut ins_num = 0;
+    for i in 0..31 {
+        if data.instruments[i].size > 0 {
+            ins_num = i + 1
+        }
+    }
+
+    let mut instruments = Vec::<S3mInstrument>::new();
+    for i in 0..ins_num {
+        instruments.push(S3mInstrument{
+            typ     : 1,
+            filename: String::new(),
+            memseg  : 0,
+            length  : data.instruments[i].size as u32 * 2,
+            loop_beg: data.instruments[i].repeat as u32 * 2,
+            loop_end: (data.instruments[i].repeat as u32 + data.instruments[i].replen as u32) * 2,
+            vol     : data.instruments[i].volume as i8,
+            flags   : if data.instruments[i].replen > 1 { 1 } else { 0 },
+            c2spd   : FINETUNE_TABLE[(data.instruments[i].finetune & 0x0f) as usize] as u32,
+            name    : data.instruments[i].name.clone(),
+        });
+    }
+
+    let ch = module.channels;
+    let ch_settings = s3m_channel_settings(ch)?;
+
+    let pat_num = data.patterns.num();
+    let mut patterns = Vec::<S3mPattern>::new();
+
+    for i in 0..pat_num {
+        patterns.push(convert_pattern(data, i, ch, &mut report))
+    }
+
+    let new_data = S3mData{
+        song_name  : data.song_name.clone(),
+        ord_num    : data.song_length as u16,
+        ins_num    : ins_num as u16,
+        pat_num    : pat_num as u16,
+        flags      : 0,
+        cwt_v      : 0x1320,  // Scream Tracker 3.20
+        ffi        : 1,       // signed samples
+        g_v        : 64,
+        i_s        : 6,
+        i_t        : 125,
+        m_v        : 0xb0,
+        d_p        : 0xd2,    // not 0xfc
+        ch_settings,
+        orders     : data.orders[..data.song_length as usize].to_vec(),
+        instrum_pp : vec![0xd2; ins_num],   // != 0
+        pattern_pp : vec![0xd2; pat_num],   // != 0
+        ch_pan     : [0; 32],
+        instruments,
+        patterns,
+        samples    : data.samples.clone(),
+
+        channels   : ch,
+    };
+
+    Ok(Conversion{
+        module: Module{
+            format_id  : "s3m",
+            description: format!("Scream Tracker 3 S3M (converted from {})", data.magic),
+            creator    : module.creator.clone(),
+            channels   : ch,
+            player     : "st3",
+            data       : Box::new(new_data),
+        },
+        unconverted: report.list,
+    })
+}
+
+fn convert_pattern(data: &ModData, num: usize, ch: usize, report: &mut Report) -> S3mPattern {
+    let mut events = vec![S3mEvent::new(); 64 * ch];
+
+    for r in 0..64 {
+        for c in 0..ch {
+            let e = data.patterns.event(num, r as u8, c);
+            let out = &mut events[r * ch + c];
+
+            let note = mk::period_to_note(e.note & 0xfff);
+            if note != 0 {
+                out.note = ((note/12)-1)<<4 | note%12;  // hi=oct, lo=note
+            }
+            out.ins = ((e.note&0xf000) >> 8) as u8 | (e.cmd&0xf0) >> 4;
+
+            let cmd = e.cmd & 0x0f;
+            if cmd == 0x0c {
+                out.vol = if e.cmdlo > 64 { 64 } else { e.cmdlo };
+            } else if cmd != 0 || e.cmdlo != 0 {
+                match convert_cmd(cmd, e.cmdlo) {
+                    Ok((new_cmd, new_info)) => { out.cmd = new_cmd; out.info = new_info; },
+                    Err(reason) => report.add(num, r, c, format!("{:X}{:02X}", cmd, e.cmdlo), reason),
+                }
+            }
+        }
+    }
+
+    encode_s3m_pattern(&events, ch)
+}
+
+// Protracker volume slides give priority to the slide up nibble, and
+// ST3 uses DxF/DFx for fine slides.
+fn volslide(info: u8) -> u8 {
+    if info & 0xf0 != 0 { info & 0xf0 } else { info & 0x0f }
+}
+
+// Convert a Protracker effect to Scream Tracker 3. Effects that do nothing
+// in Protracker are mapped to an empty command.
+pub fn convert_cmd(cmd: u8, info: u8) -> Result<(u8, u8), &'static str> {
+    let mut new_info = info;
+
+    let x = match cmd {
+        0  => {     // Normal play or Arpeggio
+            'J'
+        },
+        1  => {     // Slide Up
+            if info == 0 {
+                '@'
+            } else if info >= 0xe0 {
+                return Err("slide too fast")
+            } else {
+                'F'
+            }
+        },
+        2  => {     // Slide Down
+            if info == 0 {
+                '@'
+            } else if info >= 0xe0 {
+                return Err("slide too fast")
+            } else {
+                'E'
+            }
+        },
+        3  => {     // Tone Portamento
+            'G'
+        },
+        4  => {     // Vibrato
+            'H'
+        },
+        5  => {     // Tone Portamento + Volume Slide
+            if info == 0 {
+                'G'
+            } else {
+                new_info = volslide(info);
+                'L'
+            }
+        },
+        6  => {     // Vibrato + Volume Slide
+            if info == 0 {
+                'H'
+            } else {
+                new_info = volslide(info);
+                'K'
+            }
+        },
+        7  => {     // Tremolo
+            'R'
+        },
+        8  => {     // Set Panning
+            new_info = 0x80 | info >> 4;
+            'S'
+        },
+        9  => {     // Set SampleOffset
+            'O'
+        },
+        10 => {     // VolumeSlide
+            if info == 0 {
+                '@'
+            } else {
+                new_info = volslide(info);
+                'D'
+            }
+        },
+        11 => {     // Position Jump
+            'B'
+        },
+        13 => {     // Pattern Break
+            'C'
+        },
+        14 => {     // E-Commands
+            let val = info & 0x0f;
+            match info >> 4 {
+                0  => {     // Set Filter
+                    'S'
+                },
+                1  => {     // E1- FineSlide Up
+                    new_info = 0xf0 | val;
+                    if val == 0 { '@' } else { 'F' }
+                },
+                2  => {     // E2- FineSlide Down
+                    new_info = 0xf0 | val;
+                    if val == 0 { '@' } else { 'E' }
+                },
+                3  => {     // E3- Glissando Control
+                    new_info = 0x10 | val;
+                    'S'
+                },
+                4  => {     // E4- Set Vibrato Waveform
+                    new_info = 0x30 | val;
+                    'S'
+                },
+                5  => {     // E5- Set Finetune
+                    new_info = 0x20 | (val ^ 8);
+                    'S'
+                },
+                6  => {     // E6- Jump to Loop
+                    new_info = 0xb0 | val;
+                    'S'
+                },
+                7  => {     // E7- Set Tremolo Waveform
+                    new_info = 0x40 | val;
+                    'S'
+                },
+                8  => {     // E8- Set Panning
+                    new_info = 0x80 | val;
+                    'S'
+                },
+                9  => {     // E9- Retrig Note
+                    new_info = val;
+                    if val == 0 { '@' } else { 'Q' }
+                }
+                10 => {     // EA- Fine VolumeSlide Up
+                    new_info = val << 4 | 0x0f;
+                    if val == 0 { '@' } else { 'D' }
+                },
+                11 => {     // EB- Fine VolumeSlide Down
+                    new_info = 0xf0 | val;
+                    if val == 0 { '@' } else { 'D' }
+                },
+                12 => {     // EC- NoteCut
+                    'S'
+                },
+                13 => {     // ED- NoteDelay
+                    'S'
+                },
+                14 => {     // EE- PatternDelay
+                    'S'
+                },
+                _  => {     // EF- Invert Loop
+                    return Err("invert loop")
+                },
+            }
+        },
+        15 => {     // Set Speed
+            if info == 0 {
+                return Err("stop song")
+            } else if info < 0x20 {
+                'A'
+            } else {
+                'T'
+            }
+        }
+        _  => {
+            '@'
+        },
+    };
+
+    let new_cmd = s3m_cmd(x);
+    if new_cmd == 0 {
+        new_info = 0;
+    }
+
+    Ok((new_cmd, new_info))
+}
@@ -0,0 +1,157 @@
+use format::mk::{self, ModData};
+use format::xm::{XmData, SongHeaderTyp, InstrHeaderTyp, SampleHeaderTyp, PatternHeaderTyp};
+use format::convert::{Conversion, Report};
+use module::Module;
+use ::*;
+
+
+pub fn convert(module: &Module) -> Result<Conversion, Error> {
+
+    let data = module.data.as_any().downcast_ref::<ModData>().unwrap();
+    let mut report = Report::new();
+
+    // XM channels come in pairs
+    let ch = module.channels;
+    let xm_ch = (ch + 1) & !1;
+    let pat_num = data.patterns.num();
+    let len = data.song_length as u16;
+
+    let header = SongHeaderTyp{
+        sig        : "Extended Module: ".to_owned(),
+        name       : data.song_name.clone(),
+        prog_name  : "FastTracker v2.00   ".to_owned(),
+        ver        : 0x0104,
+        header_size: 276,
+        len,
+        rep_s      : if (data.restart as u16) < len { data.restart as u16 } else { 0 },
+        ant_chn    : xm_ch as u16,
+        ant_ptn    : pat_num as u16 + 1,  // extra empty pattern, as in the loader
+        ant_instrs : 31,
+        flags      : 0,     // Amiga frequency table
+        def_tempo  : 6,     // ticks per row
+        def_speed  : 125,   // BPM
+        song_tab   : data.orders[..len as usize].to_vec(),
+    };
+
+    // Instruments and samples
+    let mut instruments = Vec::<InstrHeaderTyp>::new();
+    let mut samples = Vec::new();
+    for i in 0..31 {
+        let mi = &data.instruments[i];
+        let mut ins = InstrHeaderTyp::new();
+        ins.instr_size = 263;
+        ins.name = mi.name.clone();
+        if mi.size > 0 {
+            ins.ant_samp = 1;
+            ins.sample_size = 40;
+            ins.ta = vec![0; 96];
+            ins.env_vp = vec![(0, 0); 12];
+            ins.env_pp = vec![(0, 0); 12];
+
+            // FastTracker 2 clips loops to the sample length, and loops
+            // of one word are not played
+            let len = mi.size as u32 * 2;
+            let mut rep_s = mi.repeat as u32 * 2;
+            let mut rep_l = mi.replen as u32 * 2;
+            if rep_s + rep_l > len {
+                if rep_s >= len {
+                    rep_s = 0;
+                    rep_l = 0;
+                } else {
+                    rep_l = len - rep_s;
+                }
+            }
+            if rep_l <= 2 {
+                rep_s = 0;
+                rep_l = 0;
+            }
+
+            let mut samp = SampleHeaderTyp::new();
+            samp.len = len;
+            samp.rep_s = rep_s;
+            samp.rep_l = rep_l;
+            samp.vol = if mi.volume > 64 { 64 } else { mi.volume };
+            samp.fine = ((mi.finetune & 0x0f) << 4) as i8;  // Protracker finetune steps are 1/8 semitone
+            samp.typ = if rep_l > 0 { 1 } else { 0 };
+            samp.pan = 128;
+            samp.name = mi.name.clone();
+            samp.smp_num = samples.len() as u32 + 1;
+            ins.samp.push(samp);
+
+            let mut smp = data.samples[i].clone();
+            smp.num = samples.len() + 1;
+            samples.push(smp);
+        }
+        instruments.push(ins);
+    }
+
+    // Patterns
+    let mut patterns = Vec::<PatternHeaderTyp>::new();
+    for p in 0..pat_num {
+        let mut pat = PatternHeaderTyp::new(64, xm_ch);
+        for r in 0..64 {
+            for c in 0..ch {
+                let e = data.patterns.event(p, r as u8, c);
+                let out = &mut pat.data[r * xm_ch + c];
+
+                let period = e.note & 0xfff;
+                let note = mk::period_to_note(period);
+                if note > 11 && note < 108 {
+                    out.ton = note - 11;  // Protracker C-1 is FastTracker C-3
+                } else if period != 0 {
+                    report.add(p, r, c, format!("period {}", period), "note out of range");
+                }
+                out.instr = ((e.note&0xf000) >> 8) as u8 | (e.cmd&0xf0) >> 4;
+
+                let cmd = e.cmd & 0x0f;
+                if cmd != 0 || e.cmdlo != 0 {
+                    match convert_cmd(cmd, e.cmdlo) {
+                        Ok((eff_typ, eff)) => { out.eff_typ = eff_typ; out.eff = eff; },
+                        Err(reason) => report.add(p, r, c, format!("{:X}{:02X}", cmd, e.cmdlo), reason),
+                    }
+                }
+            }
+        }
+        patterns.push(pat);
+    }
+    patterns.push(PatternHeaderTyp::new_empty(xm_ch));
+
+    let new_data = XmData{
+        header,
+        instruments,
+        patterns,
+        samples,
+    };
+
+    Ok(Conversion{
+        module: Module{
+            format_id  : "xm",
+            description: format!("Extended module v1.04 (converted from {})", data.magic),
+            creator    : module.creator.clone(),
+            channels   : xm_ch,
+            player     : "ft2",
+            data       : Box::new(new_data),
+        },
+        unconverted: report.list,
+    })
+}
+
+// FastTracker 2 effects are a superset of the Protracker effects, except
+// for the Amiga-specific commands.
+fn convert_cmd(cmd: u8, info: u8) -> Result<(u8, u8), &'static str> {
+    match cmd {
+        0x0e => match info >> 4 {
+            0x0 => Err("set filter"),
+            0x8 => Err("unused command"),
+            0xf => Err("invert loop"),
+            _   => Ok((cmd, info)),
+        },
+        0x0c => Ok((cmd, if info > 64 { 64 } else { info })),
+        0x0f => if info == 0 {
+            Err("stop song")
+        } else {
+            Ok((cmd, info))
+        },
+        _    => Ok((cmd, info)),
+    }
+}
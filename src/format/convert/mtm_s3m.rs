@@ -0,0 +1,127 @@
+use format::mtm::MtmData;
+use format::s3m::{S3mData, S3mInstrument, S3mPattern};
+use format::convert::{Conversion, Report, S3mEvent, encode_s3m_pattern, s3m_channel_settings, s3m_cmd};
+use format::convert::mod_s3m::{FINETUNE_TABLE, convert_cmd};
+use module::Module;
+use ::*;
+
+
+pub fn convert(module: &Module) -> Result<Conversion, Error> {
+
+    let data = module.data.as_any().downcast_ref::<MtmData>().unwrap();
+    let mut report = Report::new();
+
+    let mut instruments = Vec::<S3mInstrument>::new();
+    for mi in &data.instruments {
+        // sample sizes are in bytes
+        let shift = if mi.is_16bit() { 1 } else { 0 };
+        let length = mi.size >> shift;
+        let loop_end = if mi.loop_end > mi.size { mi.size } else { mi.loop_end } >> shift;
+        let loop_beg = mi.loop_start >> shift;
+        let has_loop = loop_end > loop_beg + 2;
+        instruments.push(S3mInstrument{
+            typ     : 1,
+            filename: String::new(),
+            memseg  : 0,
+            length,
+            loop_beg,
+            loop_end,
+            vol     : if mi.volume > 64 { 64 } else { mi.volume as i8 },
+            flags   : (if has_loop { 1 } else { 0 }) | (if mi.is_16bit() { 4 } else { 0 }),
+            c2spd   : FINETUNE_TABLE[(mi.finetune & 0x0f) as usize] as u32,
+            name    : mi.name.clone(),
+        });
+    }
+    let ins_num = instruments.len();
+
+    let ch = data.channels as usize;
+    let ch_settings = s3m_channel_settings(ch)?;
+
+    // MultiTracker pan positions range from 0 to 15, as in S3M
+    let mut ch_pan = [0; 32];
+    for c in 0..ch {
+        ch_pan[c] = 0x20 | (data.pan[c] & 0x0f);
+    }
+
+    let pat_num = data.last_pattern as usize + 1;
+    let mut patterns = Vec::<S3mPattern>::new();
+    for i in 0..pat_num {
+        patterns.push(convert_pattern(data, i, &mut report));
+    }
+
+    let new_data = S3mData{
+        song_name  : data.name.clone(),
+        ord_num    : data.last_order as u16 + 1,
+        ins_num    : ins_num as u16,
+        pat_num    : pat_num as u16,
+        flags      : 0,
+        cwt_v      : 0x1320,  // Scream Tracker 3.20
+        ffi        : 1,       // signed samples
+        g_v        : 64,
+        i_s        : 6,
+        i_t        : 125,
+        m_v        : 0xb0,
+        d_p        : 0xfc,    // channel pan positions follow
+        ch_settings,
+        orders     : data.orders[..data.last_order as usize + 1].to_vec(),
+        instrum_pp : vec![0xd2; ins_num],   // != 0
+        pattern_pp : vec![0xd2; pat_num],   // != 0
+        ch_pan,
+        instruments,
+        patterns,
+        samples    : data.samples.clone(),
+
+        channels   : ch,
+    };
+
+    Ok(Conversion{
+        module: Module{
+            format_id  : "s3m",
+            description: "Scream Tracker 3 S3M (converted from MTM)".to_owned(),
+            creator    : module.creator.clone(),
+            channels   : ch,
+            player     : "st3",
+            data       : Box::new(new_data),
+        },
+        unconverted: report.list,
+    })
+}
+
+fn convert_pattern(data: &MtmData, num: usize, report: &mut Report) -> S3mPattern {
+    let ch = data.channels as usize;
+    let rows = data.rows as usize;
+    let mut events = vec![S3mEvent::new(); 64 * ch];
+
+    for r in 0..rows {
+        for c in 0..ch {
+            let e = data.event(num, r, c);
+            let out = &mut events[r * ch + c];
+
+            if e.note != 0 {
+                let note = e.note + 24;
+                out.note = (note/12)<<4 | note%12;  // hi=oct, lo=note
+            }
+            out.ins = e.ins;
+
+            if e.cmd == 0x0c {
+                out.vol = if e.param > 64 { 64 } else { e.param };
+            } else if e.cmd != 0 || e.param != 0 {
+                match convert_cmd(e.cmd, e.param) {
+                    Ok((new_cmd, new_info)) => { out.cmd = new_cmd; out.info = new_info; },
+                    Err(reason) => report.add(num, r, c, format!("{:X}{:02X}", e.cmd, e.param), reason),
+                }
+            }
+        }
+    }
+
+    // Short patterns end with a pattern break in a free effect slot
+    if rows < 64 {
+        let last = &mut events[(rows - 1) * ch..rows * ch];
+        match last.iter_mut().find(|x| x.cmd == 0) {
+            Some(e) => { e.cmd = s3m_cmd('C'); e.info = 0 },
+            None    => report.add(num, rows - 1, 0, format!("{} rows", rows), "no room for pattern break"),
+        }
+    }
+
+    encode_s3m_pattern(&events, ch)
+}
@@ -0,0 +1,284 @@
+use format::s3m::S3mData;
+use format::xm::{XmData, SongHeaderTyp, InstrHeaderTyp, SampleHeaderTyp, PatternHeaderTyp};
+use format::convert::{Conversion, Report, decode_s3m_pattern, s3m_effect_name};
+use module::Module;
+use ::*;
+
+// FastTracker 2 effect numbers
+const FX_GLOBAL_VOLUME: u8 = 16;
+const FX_TREMOR       : u8 = 29;
+const FX_EXTRA_FINE   : u8 = 33;
+
+const KEY_OFF: u8 = 97;
+
+
+pub fn convert(module: &Module) -> Result<Conversion, Error> {
+
+    let data = module.data.as_any().downcast_ref::<S3mData>().unwrap();
+    let mut report = Report::new();
+
+    // XM channels come in pairs
+    let ch = data.channels;
+    let xm_ch = (ch + 1) & !1;
+
+    // Drop the skip markers and everything after the end marker, and keep
+    // track of the new position of each order for position jumps
+    let mut song_tab = Vec::<u8>::new();
+    let mut ord_map = Vec::<u8>::new();
+    for &o in data.orders.iter().take(data.ord_num as usize) {
+        ord_map.push(song_tab.len() as u8);
+        match o {
+            255 => break,
+            254 => continue,
+            _   => song_tab.push(o),
+        }
+    }
+    let len = song_tab.len() as u16;
+
+    let header = SongHeaderTyp{
+        sig        : "Extended Module: ".to_owned(),
+        name       : data.song_name.clone(),
+        prog_name  : "FastTracker v2.00   ".to_owned(),
+        ver        : 0x0104,
+        header_size: 276,
+        len,
+        rep_s      : 0,
+        ant_chn    : xm_ch as u16,
+        ant_ptn    : data.pat_num + 1,   // extra empty pattern, as in the loader
+        ant_instrs : data.ins_num,
+        flags      : 0,     // Amiga frequency table
+        def_tempo  : if data.i_s == 0 { 6 } else { data.i_s as u16 },      // ticks per row
+        def_speed  : if data.i_t < 32 { 125 } else { data.i_t as u16 },   // BPM
+        song_tab,
+    };
+
+    // Instruments and samples
+    let mut instruments = Vec::<InstrHeaderTyp>::new();
+    let mut samples = Vec::new();
+    for i in 0..data.ins_num as usize {
+        let si = &data.instruments[i];
+        let mut ins = InstrHeaderTyp::new();
+        ins.instr_size = 263;
+        ins.name = si.name.clone();
+        if si.typ == 1 && si.length > 0 {
+            ins.ant_samp = 1;
+            ins.sample_size = 40;
+            ins.ta = vec![0; 96];
+            ins.env_vp = vec![(0, 0); 12];
+            ins.env_pp = vec![(0, 0); 12];
+
+            let sixteen_bit = si.flags & 0x04 != 0;
+            let bps = if sixteen_bit { 2 } else { 1 };
+            let loop_end = if si.loop_end > si.length { si.length } else { si.loop_end };
+            let has_loop = si.flags & 0x01 != 0 && loop_end > si.loop_beg;
+            let (rel_ton, fine) = c2spd_to_relative(si.c2spd);
+
+            let mut samp = SampleHeaderTyp::new();
+            samp.len = si.length * bps;
+            if has_loop {
+                samp.rep_s = si.loop_beg * bps;
+                samp.rep_l = (loop_end - si.loop_beg) * bps;
+            }
+            samp.vol = if si.vol > 64 { 64 } else if si.vol < 0 { 0 } else { si.vol as u8 };
+            samp.fine = fine;
+            samp.rel_ton = rel_ton;
+            samp.typ = if has_loop { 1 } else { 0 } | if sixteen_bit { 16 } else { 0 };
+            samp.pan = 128;
+            samp.name = si.name.clone();
+            samp.smp_num = samples.len() as u32 + 1;
+            ins.samp.push(samp);
+
+            let mut smp = data.samples[i].clone();
+            smp.num = samples.len() + 1;
+            samples.push(smp);
+        }
+        instruments.push(ins);
+    }
+
+    // Patterns
+    let mut patterns = Vec::<PatternHeaderTyp>::new();
+    for p in 0..data.pat_num as usize {
+        let events = decode_s3m_pattern(&data.patterns[p], ch);
+        let mut pat = PatternHeaderTyp::new(64, xm_ch);
+
+        // ST3 effects share a single parameter memory per channel
+        let mut last_info = vec![0_u8; ch];
+
+        for r in 0..64 {
+            for c in 0..ch {
+                let e = &events[r * ch + c];
+                let out = &mut pat.data[r * xm_ch + c];
+
+                out.ton = match e.note {
+                    255 => 0,
+                    254 => KEY_OFF,
+                    n   => {
+                        let ton = (n >> 4) * 12 + (n & 0x0f) + 1;
+                        if ton <= 96 { ton } else { 0 }
+                    }
+                };
+                out.instr = e.ins;
+                if e.vol <= 64 {
+                    out.vol = 0x10 + e.vol;
+                }
+
+                if e.cmd == 0 {
+                    continue
+                }
+
+                let mut info = e.info;
+                if info == 0 && uses_memory(e.cmd) {
+                    info = last_info[c];
+                } else if info != 0 {
+                    last_info[c] = info;
+                }
+
+                match convert_cmd(e.cmd, info, &ord_map) {
+                    Ok((eff_typ, eff)) => { out.eff_typ = eff_typ; out.eff = eff; },
+                    Err(reason) => report.add(p, r, c, s3m_effect_name(e.cmd, e.info), reason),
+                }
+            }
+        }
+        patterns.push(pat);
+    }
+    patterns.push(PatternHeaderTyp::new_empty(xm_ch));
+
+    let new_data = XmData{
+        header,
+        instruments,
+        patterns,
+        samples,
+    };
+
+    Ok(Conversion{
+        module: Module{
+            format_id  : "xm",
+            description: "Extended module v1.04 (converted from S3M)".to_owned(),
+            creator    : module.creator.clone(),
+            channels   : xm_ch,
+            player     : "ft2",
+            data       : Box::new(new_data),
+        },
+        unconverted: report.list,
+    })
+}
+
+// Split the sample rate for middle C in FastTracker 2 relative note and
+// finetune (in 1/128 semitone units).
+fn c2spd_to_relative(c2spd: u32) -> (i8, i8) {
+    let c2spd = if c2spd == 0 { 8363 } else { c2spd };
+    let val = (1536.0 * (c2spd as f64 / 8363.0).log(2.0)).round() as i32;
+    let rel = if val < 0 { -((-val + 127) / 128) } else { val / 128 };
+    let rel = if rel < -48 { -48 } else if rel > 71 { 71 } else { rel };
+    let fine = val - rel * 128;
+    let fine = if fine < -128 { -128 } else if fine > 127 { 127 } else { fine };
+    (rel as i8, fine as i8)
+}
+
+fn uses_memory(cmd: u8) -> bool {
+    match b'@'.wrapping_add(cmd) as char {
+        'D' | 'E' | 'F' | 'I' | 'J' | 'K' | 'L' | 'Q' | 'R' => true,
+        _ => false,
+    }
+}
+
+// Convert a ST3 volume slide to FastTracker 2: normal slides use Axy, and
+// fine slides use EAx and EBx.
+fn volslide(info: u8) -> (u8, u8) {
+    let hi = info >> 4;
+    let lo = info & 0x0f;
+    if lo == 0x0f && hi != 0 {
+        (0x0e, 0xa0 | hi)
+    } else if hi == 0x0f && lo != 0 {
+        (0x0e, 0xb0 | lo)
+    } else if lo == 0 {
+        (0x0a, hi << 4)
+    } else {
+        (0x0a, lo)
+    }
+}
+
+fn convert_cmd(cmd: u8, info: u8, ord_map: &[u8]) -> Result<(u8, u8), &'static str> {
+    match b'@'.wrapping_add(cmd) as char {
+        'A' => {    // Set speed
+            if info == 0 {
+                Ok((0, 0))
+            } else if info < 0x20 {
+                Ok((0x0f, info))
+            } else {
+                Err("speed too high")
+            }
+        },
+        'B' => {    // Jump to order
+            match ord_map.get(info as usize) {
+                Some(&pos) => Ok((0x0b, pos)),
+                None       => Err("invalid order"),
+            }
+        },
+        'C' => Ok((0x0d, info)),    // Break pattern
+        'D' => Ok(volslide(info)),  // Volume slide
+        'E' => {    // Slide down
+            match info >> 4 {
+                0x0f => Ok((0x0e, 0x20 | info & 0x0f)),
+                0x0e => Ok((FX_EXTRA_FINE, 0x20 | info & 0x0f)),
+                _    => Ok((0x02, info)),
+            }
+        },
+        'F' => {    // Slide up
+            match info >> 4 {
+                0x0f => Ok((0x0e, 0x10 | info & 0x0f)),
+                0x0e => Ok((FX_EXTRA_FINE, 0x10 | info & 0x0f)),
+                _    => Ok((0x01, info)),
+            }
+        },
+        'G' => Ok((0x03, info)),    // Tone portamento
+        'H' => Ok((0x04, info)),    // Vibrato
+        'I' => Ok((FX_TREMOR, info)),
+        'J' => {    // Arpeggio
+            if info == 0 {
+                Ok((0, 0))
+            } else {
+                Ok((0x00, info))
+            }
+        },
+        'K' | 'L' => {    // Vibrato/Tone portamento + volume slide
+            let fx = if cmd == 11 { 0x06 } else { 0x05 };
+            match volslide(info) {
+                (0x0a, val) => Ok((fx, val)),
+                _           => Err("fine volume slide"),
+            }
+        },
+        'O' => Ok((0x09, info)),    // Set sample offset
+        'Q' => Ok((0x1b, info)),    // Retrig + volume slide
+        'R' => Ok((0x07, info)),    // Tremolo
+        'S' => {
+            let val = info & 0x0f;
+            match info >> 4 {
+                0x1 => Ok((0x0e, 0x30 | val)),      // Glissando control
+                0x2 => Ok((0x0e, 0x50 | val)),      // Set finetune
+                0x3 => Ok((0x0e, 0x40 | val)),      // Vibrato waveform
+                0x4 => Ok((0x0e, 0x70 | val)),      // Tremolo waveform
+                0x8 => Ok((0x08, val * 0x11)),      // Set panning
+                0xb => Ok((0x0e, 0x60 | val)),      // Pattern loop
+                0xc => Ok((0x0e, 0xc0 | val)),      // Note cut
+                0xd => Ok((0x0e, 0xd0 | val)),      // Note delay
+                0xe => Ok((0x0e, 0xe0 | val)),      // Pattern delay
+                0x0 => Err("set filter"),
+                0xa => Err("stereo control"),
+                0xf => Err("funk repeat"),
+                _   => Err("unsupported command"),
+            }
+        },
+        'T' => {    // Set tempo
+            if info < 0x20 {
+                Err("tempo too low")
+            } else {
+                Ok((0x0f, info))
+            }
+        },
+        'U' => Err("fine vibrato"),
+        'V' => Ok((FX_GLOBAL_VOLUME, if info > 64 { 64 } else { info })),
+        'X' => Ok((0x08, if info >= 0x80 { 0xff } else { info * 2 })),
+        _   => Err("unsupported command"),
+    }
+}
@@ -0,0 +1,154 @@
+use format::stm::StmData;
+use format::s3m::{S3mData, S3mInstrument, S3mPattern};
+use format::convert::{Conversion, Report, S3mEvent, encode_s3m_pattern, s3m_channel_settings, s3m_effect_name, s3m_cmd};
+use module::Module;
+use ::*;
+
+
+pub fn convert(module: &Module) -> Result<Conversion, Error> {
+
+    let data = module.data.as_any().downcast_ref::<StmData>().unwrap();
+    let mut report = Report::new();
+
+    let mut ins_num = 0;
+    for i in 0..31 {
+        if data.instruments[i].size > 0 {
+            ins_num = i + 1
+        }
+    }
+
+    let mut instruments = Vec::<S3mInstrument>::new();
+    for i in 0..ins_num {
+        let si = &data.instruments[i];
+        let loop_end = if si.loop_end > si.size { si.size } else { si.loop_end };
+        let has_loop = si.loop_end != 0xffff && loop_end > si.loop_start;
+        instruments.push(S3mInstrument{
+            typ     : 1,
+            filename: String::new(),
+            memseg  : 0,
+            length  : si.size as u32,
+            loop_beg: si.loop_start as u32,
+            loop_end: loop_end as u32,
+            vol     : if si.volume > 64 { 64 } else { si.volume as i8 },
+            flags   : if has_loop { 1 } else { 0 },
+            c2spd   : si.c2spd as u32,
+            name    : si.name.clone(),
+        });
+    }
+
+    // Orders end at the first invalid pattern, S3M order lists have even length
+    let mut orders: Vec<u8> = data.orders.iter().take_while(|&&x| x < data.num_patterns).cloned().collect();
+    orders.push(255);
+    if orders.len() & 1 != 0 {
+        orders.push(255);
+    }
+
+    let pat_num = data.num_patterns as usize;
+    let mut patterns = Vec::<S3mPattern>::new();
+    for i in 0..pat_num {
+        patterns.push(convert_pattern(data, i, &mut report));
+    }
+
+    let new_data = S3mData{
+        song_name  : data.name.clone(),
+        ord_num    : orders.len() as u16,
+        ins_num    : ins_num as u16,
+        pat_num    : pat_num as u16,
+        flags      : 0,
+        cwt_v      : 0x1320,  // Scream Tracker 3.20
+        ffi        : 1,       // signed samples
+        g_v        : if data.global_vol > 64 { 64 } else { data.global_vol },
+        i_s        : if data.speed >> 4 == 0 { 6 } else { data.speed >> 4 },
+        i_t        : 125,
+        m_v        : 0xb0,
+        d_p        : 0,
+        ch_settings: s3m_channel_settings(4)?,
+        orders,
+        instrum_pp : vec![0xd2; ins_num],   // != 0
+        pattern_pp : vec![0xd2; pat_num],   // != 0
+        ch_pan     : [0; 32],
+        instruments,
+        patterns,
+        samples    : data.samples.clone(),
+
+        channels   : 4,
+    };
+
+    Ok(Conversion{
+        module: Module{
+            format_id  : "s3m",
+            description: "Scream Tracker 3 S3M (converted from STM)".to_owned(),
+            creator    : module.creator.clone(),
+            channels   : 4,
+            player     : "st3",
+            data       : Box::new(new_data),
+        },
+        unconverted: report.list,
+    })
+}
+
+fn convert_pattern(data: &StmData, num: usize, report: &mut Report) -> S3mPattern {
+    let mut events = vec![S3mEvent::new(); 64 * 4];
+
+    for r in 0..64 {
+        for c in 0..4 {
+            let e = data.patterns.event(num as u16, r as u16, c);
+            let out = &mut events[r * 4 + c];
+
+            out.note = if e.note < 251 || e.note == 254 { e.note } else { 255 };
+            out.ins = e.smp;
+            if e.volume <= 64 {
+                out.vol = e.volume;
+            }
+
+            if e.cmd != 0 {
+                match convert_cmd(e.cmd, e.infobyte) {
+                    Ok((cmd, info)) => { out.cmd = cmd; out.info = info; },
+                    Err(reason) => report.add(num, r, c, s3m_effect_name(e.cmd, e.infobyte), reason),
+                }
+            }
+        }
+    }
+
+    encode_s3m_pattern(&events, 4)
+}
+
+// Scream Tracker 2 has no effect memory, so effects with a zero parameter
+// do nothing and are mapped to an empty command.
+fn convert_cmd(cmd: u8, info: u8) -> Result<(u8, u8), &'static str> {
+    let mut new_info = info;
+
+    let x = match b'@'.wrapping_add(cmd) as char {
+        'A' => {    // Set tempo, ticks per row in the high nibble
+            new_info = info >> 4;
+            if new_info == 0 { '@' } else { 'A' }
+        },
+        'B' => 'B',
+        'C' => {    // Pattern break, always to the first row
+            new_info = 0;
+            'C'
+        },
+        'D' => {    // Volume slide, slide down has priority
+            new_info = if info & 0x0f != 0 { info & 0x0f } else { info & 0xf0 };
+            if info == 0 { '@' } else { 'D' }
+        },
+        'E' | 'F' => {
+            if info >= 0xe0 {
+                return Err("slide too fast")
+            }
+            if info == 0 { '@' } else { b'@'.wrapping_add(cmd) as char }
+        },
+        'G' => 'G',
+        'H' => if info == 0 { '@' } else { 'H' },
+        'I' => 'I',
+        'J' => if info == 0 { '@' } else { 'J' },
+        _   => return Err("unsupported command"),
+    };
+
+    let new_cmd = s3m_cmd(x);
+    if new_cmd == 0 {
+        new_info = 0;
+    }
+
+    Ok((new_cmd, new_info))
+}
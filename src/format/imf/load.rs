@@ -0,0 +1,314 @@
+use std::cmp;
+use format::{ProbeInfo, Format, Loader};
+use format::imf::*;
+use module::{Module, Sample};
+use module::sample::SampleType;
+use util::BinaryRead;
+use ::*;
+
+/// Imago Orpheus module loader
+pub struct ImfLoader;
+
+impl Loader for ImfLoader {
+    fn name(&self) -> &'static str {
+        "Imago Orpheus"
+    }
+
+    fn probe(&self, b: &[u8], player_id: &str) -> Result<ProbeInfo, Error> {
+        if b.len() < 0x340 {
+            return Err(Error::Format(format!("file too short ({})", b.len())));
+        }
+
+        player::check_accepted(player_id, "imf")?;
+
+        let magic = b.read_string(0x3c, 4)?;
+        if magic != "IM10" {
+            return Err(Error::Format(format!("bad magic {:?}", magic)));
+        }
+
+        Ok(ProbeInfo{format: Format::Imf, title: b.read_string(0, 32)?})
+    }
+
+    fn load(self: Box<Self>, b: &[u8], info: ProbeInfo) -> Result<Module, Error> {
+
+        if info.format != Format::Imf {
+            return Err(Error::Format("unsupported format".to_owned()));
+        }
+
+        let title = b.read_string(0, 32)?;
+        let num_orders = b.read16l(0x20)?;
+        let num_patterns = b.read16l(0x22)?;
+        let num_instruments = b.read16l(0x24)?;
+        let flags = b.read16l(0x26)?;
+
+        if num_orders > 256 {
+            return Err(Error::Load(format!("invalid number of orders {}", num_orders)));
+        }
+
+        // Channel settings
+        let mut channels = Vec::<ImfChannel>::new();
+        for i in 0..32 {
+            let ofs = 0x40 + i * 16;
+            channels.push(ImfChannel{
+                name  : b.read_string(ofs, 12)?,
+                chorus: b.read8(ofs + 12)?,
+                reverb: b.read8(ofs + 13)?,
+                pan   : b.read8(ofs + 14)?,
+                status: b.read8(ofs + 15)?,
+            });
+        }
+
+        let orders = b.slice(0x240, num_orders as usize)?.to_vec();
+
+        // Load patterns
+        let mut ofs = 0x340;
+        let mut patterns = Vec::<ImfPattern>::new();
+        for _ in 0..num_patterns {
+            let size = b.read16l(ofs)? as usize;
+            let rows = b.read16l(ofs + 2)? as usize;
+            if size < 4 {
+                return Err(Error::Load(format!("invalid pattern size {}", size)));
+            }
+            patterns.push(ImfPattern::from_slice(rows, b.slice(ofs + 4, size - 4)?));
+            ofs += size;
+        }
+
+        // Load instruments and samples
+        let mut instruments = Vec::<ImfInstrument>::new();
+        let mut smp_headers = Vec::<ImfSample>::new();
+        let mut samples = Vec::<Sample>::new();
+        for _ in 0..num_instruments {
+            let mut ins = load_instrument(b, ofs)?;
+            ins.first_sample = smp_headers.len();
+            ofs += 384;
+
+            for _ in 0..ins.nsmp {
+                let sh = load_sample_header(b, ofs)?;
+                ofs += 64;
+                let smp = load_sample(b, ofs, samples.len(), &sh)?;
+                ofs += smp.size as usize * if sh.flags & IMF_SMP_16BIT != 0 { 2 } else { 1 };
+                smp_headers.push(sh);
+                samples.push(smp);
+            }
+
+            instruments.push(ins);
+        }
+
+        // Disabled channels at the end of the list are not used
+        let num_channels = cmp::max(1, 32 - channels.iter().rev().take_while(|x| x.status == IMF_CHN_DISABLED).count());
+
+        let data = ImfData{
+            title,
+            num_orders,
+            num_patterns,
+            num_instruments,
+            flags,
+            speed     : b.read8(0x30)?,
+            tempo     : b.read8(0x31)?,
+            master_vol: b.read8(0x32)?,
+            amp       : b.read8(0x33)?,
+            channels,
+            orders,
+            patterns,
+            instruments,
+            smp_headers,
+            samples,
+        };
+
+        let m = Module {
+            format_id  : "imf",
+            description: "Imago Orpheus module".to_owned(),
+            creator    : "Imago Orpheus".to_owned(),
+            channels   : num_channels,
+            player     : "imf",
+            data       : Box::new(data),
+        };
+
+        Ok(m)
+    }
+}
+
+fn load_envelope(b: &[u8], ofs: usize, info: usize) -> Result<ImfEnvelope, Error> {
+    let mut env = ImfEnvelope::new();
+
+    env.num = cmp::min(16, b.read8(info)?);
+    env.sus = b.read8(info + 1)?;
+    env.lpb = b.read8(info + 2)?;
+    env.lpe = b.read8(info + 3)?;
+    env.flg = b.read8(info + 4)?;
+    for i in 0..env.num as usize {
+        env.node.push((b.read16l(ofs + i * 4)?, b.read16l(ofs + i * 4 + 2)?));
+    }
+
+    Ok(env)
+}
+
+fn load_instrument(b: &[u8], ofs: usize) -> Result<ImfInstrument, Error> {
+    let magic = b.read_string(ofs + 0x17c, 4)?;
+    if magic != "II10" {
+        return Err(Error::Load(format!("bad instrument magic {:?}", magic)));
+    }
+
+    let mut ins = ImfInstrument::new();
+
+    ins.name    = b.read_string(ofs, 32)?;
+    ins.map     = b.slice(ofs + 0x20, 120)?.to_vec();
+    ins.vol_env = load_envelope(b, ofs + 0xa0, ofs + 0x160)?;
+    ins.pan_env = load_envelope(b, ofs + 0xe0, ofs + 0x168)?;
+    ins.pit_env = load_envelope(b, ofs + 0x120, ofs + 0x170)?;
+    ins.fadeout = b.read16l(ofs + 0x178)?;
+    ins.nsmp    = b.read16l(ofs + 0x17a)?;
+
+    Ok(ins)
+}
+
+fn load_sample_header(b: &[u8], ofs: usize) -> Result<ImfSample, Error> {
+    let magic = b.read_string(ofs + 0x3c, 4)?;
+    if magic != "IS10" {
+        return Err(Error::Load(format!("bad sample magic {:?}", magic)));
+    }
+
+    let mut sh = ImfSample::new();
+
+    sh.name       = b.read_string(ofs, 13)?;
+    sh.length     = b.read32l(ofs + 0x10)?;
+    sh.loop_start = b.read32l(ofs + 0x14)?;
+    sh.loop_end   = b.read32l(ofs + 0x18)?;
+    sh.c5speed    = b.read32l(ofs + 0x1c)?;
+    sh.volume     = b.read8(ofs + 0x20)?;
+    sh.pan        = b.read8(ofs + 0x21)?;
+    sh.flags      = b.read8(ofs + 0x30)?;
+
+    // lengths are stored in bytes
+    if sh.flags & IMF_SMP_16BIT != 0 {
+        sh.length /= 2;
+        sh.loop_start /= 2;
+        sh.loop_end /= 2;
+    }
+
+    Ok(sh)
+}
+
+fn load_sample(b: &[u8], ofs: usize, i: usize, sh: &ImfSample) -> Result<Sample, Error> {
+    let mut smp = Sample::new();
+
+    smp.num = i + 1;
+    smp.address = ofs as u32;
+    smp.name = sh.name.to_owned();
+    smp.size = sh.length;
+    smp.loop_bidir = sh.flags & IMF_SMP_BIDI != 0;
+
+    if smp.size > 0 {
+        if sh.flags & IMF_SMP_16BIT != 0 {
+            smp.sample_type = SampleType::Sample16;
+            smp.store(b.slice(ofs, smp.size as usize * 2)?);
+        } else {
+            smp.sample_type = SampleType::Sample8;
+            smp.store(b.slice(ofs, smp.size as usize)?);
+        }
+    }
+
+    Ok(smp)
+}
+
+
+// Imago Orpheus module used by the loader and player tests
+#[cfg(test)]
+pub fn imf_module() -> Vec<u8> {
+    use util::BinaryWrite;
+
+    let mut b: Vec<u8> = Vec::new();
+    b.write_string("test", 32);
+    b.write16l(2);                      // orders
+    b.write16l(2);                      // patterns
+    b.write16l(1);                      // instruments
+    b.write16l(1);                      // linear slides
+    b.write_string("", 8);
+    b.extend_from_slice(&[6, 125, 64, 48]);
+    b.write_string("", 8);
+    b.write_string("IM10", 4);
+    for i in 0..32 {
+        b.write_string("", 12);
+        b.extend_from_slice(&[0, 0, 128, if i < 4 { 0 } else { 2 }]);
+    }
+    let mut orders = [0_u8; 256];
+    orders[1] = 1;
+    b.extend_from_slice(&orders);
+
+    // pattern 0: C-5 instrument 1 in channel 1 with slide up 2 and
+    // volume slide down 2
+    b.write16l(4 + 10);
+    b.write16l(64);
+    b.extend_from_slice(&[0xe1, 0x40, 0x01, 0x12, 0x02, 0x0d, 0x02, 0x00]);
+    b.extend_from_slice(&[0x00; 2]);
+    // pattern 1: 32 empty rows with a pattern break in the last one
+    b.write16l(4 + 35);
+    b.write16l(64);
+    b.extend_from_slice(&[0x00; 31]);
+    b.extend_from_slice(&[0x40, 0x1e, 0x00, 0x00]);
+
+    // instrument 1, one sample with volume and pitch envelopes
+    b.write_string("instrument", 32);
+    b.extend_from_slice(&[0; 120]);
+    b.write_string("", 8);
+    for &(x, y) in &[(0, 64), (4, 32)] {
+        b.write16l(x);
+        b.write16l(y);
+    }
+    b.extend_from_slice(&[0; 56 + 64]);
+    for &(x, y) in &[(0, 128), (4, 160)] {
+        b.write16l(x);
+        b.write16l(y);
+    }
+    b.extend_from_slice(&[0; 56]);
+    b.extend_from_slice(&[2, 0, 0, 0, 1, 0, 0, 0]);
+    b.extend_from_slice(&[0; 8]);
+    b.extend_from_slice(&[2, 0, 0, 0, 1, 0, 0, 0]);
+    b.write16l(0);                      // fadeout
+    b.write16l(1);                      // samples
+    b.write_string("II10", 4);
+
+    b.write_string("sample", 16);
+    b.write32l(16);                     // length
+    b.write32l(0);
+    b.write32l(16);
+    b.write32l(8363);
+    b.extend_from_slice(&[64, 128]);
+    b.extend_from_slice(&[0; 14]);
+    b.write8(0x01);                     // loop
+    b.extend_from_slice(&[0; 11]);
+    b.write_string("IS10", 4);
+    b.extend((0..16).map(|x| x * 8));
+
+    b
+}
+
+
+#[cfg(test)]
+mod tests {
+    use format;
+    use format::imf::ImfData;
+    use super::imf_module;
+
+    #[test]
+    fn test_load_imf() {
+        let b = imf_module();
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "imf");
+        assert_eq!(m.title().trim_end(), "test");
+        assert_eq!(m.channels, 4);
+        assert_eq!(m.len(), 2);
+
+        let data = m.data.as_any().downcast_ref::<ImfData>().unwrap();
+        assert!(data.has_linear_slides());
+        let e = data.patterns[0].event(0, 1);
+        assert_eq!((e.note, e.ins, e.fxt, e.fxp, e.fxt2, e.fxp2), (49, 1, 0x12, 0x02, 0x0d, 0x02));
+        assert_eq!(data.patterns[1].event(31, 0).fxt, 0x1e);
+        assert_eq!(data.instruments[0].vol_env.node, vec![(0, 64), (4, 32)]);
+        assert!(data.instruments[0].vol_env.is_on());
+        assert!(!data.instruments[0].pan_env.is_on());
+        assert_eq!(data.instruments[0].pit_env.node, vec![(0, 128), (4, 160)]);
+        assert_eq!(data.instruments[0].sample(49), Some(0));
+        assert_eq!(data.samples[0].data[2], 16);
+    }
+}
@@ -0,0 +1,330 @@
+pub mod load;
+
+pub use self::load::*;
+
+use std::any::Any;
+use std::fmt;
+use module::{event, ModuleData, Sample};
+use util::NOTES;
+
+// Imago Orpheus module layout
+//
+//  0000: Song name, 32 bytes
+//  0020: Number of orders, patterns and instruments, flags (16-bit each)
+//  0030: Initial speed, BPM, master volume and amplification
+//  003c: Magic ("IM10")
+//  0040: Channel settings, 32 channels of 16 bytes each
+//  0240: Orders, 256 bytes
+//  0340: Patterns, each with a 16-bit size and number of rows
+//  xxxx: Instruments, each followed by its sample headers and data
+
+pub const IMF_NOTE_OFF : u8 = 255;
+
+pub const IMF_LINEAR   : u16 = 0x01;
+
+pub struct ImfData {
+    pub title          : String,
+    pub num_orders     : u16,
+    pub num_patterns   : u16,
+    pub num_instruments: u16,
+    pub flags          : u16,
+    pub speed          : u8,
+    pub tempo          : u8,
+    pub master_vol     : u8,
+    pub amp            : u8,
+    pub channels       : Vec<ImfChannel>,
+    pub orders         : Vec<u8>,
+    pub patterns       : Vec<ImfPattern>,
+    pub instruments    : Vec<ImfInstrument>,
+    pub smp_headers    : Vec<ImfSample>,      // samples of all instruments
+    pub samples        : Vec<Sample>,
+}
+
+impl ImfData {
+    pub fn has_linear_slides(&self) -> bool {
+        self.flags & IMF_LINEAR != 0
+    }
+}
+
+impl ModuleData for ImfData {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn title(&self) -> &str {
+        &self.title
+    }
+
+    fn patterns(&self) -> usize {
+        self.patterns.len()
+    }
+
+    fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    fn pattern_in_position(&self, pos: usize) -> Option<usize> {
+        if pos >= self.orders.len() {
+            None
+        } else {
+            Some(self.orders[pos] as usize)
+        }
+    }
+
+    fn instruments(&self) -> Vec<String> {
+        self.instruments.iter().map(|x| x.name.to_owned()).collect::<Vec<String>>()
+    }
+
+    fn rows(&self, pat: usize) -> usize {
+        if pat >= self.patterns.len() {
+            0
+        } else {
+            self.patterns[pat].rows
+        }
+    }
+
+    // Only the first effect column is shown.
+    fn pattern_data(&self, pat: usize, num: usize, buffer: &mut [u8]) -> usize {
+        let rows = self.rows(pat);
+        let mut i = 0;
+        for _ in 0..num {
+            let (row, ch) = (i / 32, i % 32);
+            if row >= rows {
+                break
+            }
+            let ofs = i * 6;
+            let e = self.patterns[pat].event(row, ch);
+
+            let mut flags = 0;
+            if e.note != 0 { flags |= event::HAS_NOTE; buffer[ofs+1] = e.note }
+            if e.ins  != 0 { flags |= event::HAS_INS ; buffer[ofs+2] = e.ins }
+            if e.fxt != 0 || e.fxp != 0 { flags |= event::HAS_CMD; buffer[ofs+4] = e.fxt; buffer[ofs+5] = e.fxp }
+            buffer[ofs] = flags;
+
+            i += 1;
+        }
+        i
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        self.samples.to_owned()
+    }
+}
+
+
+pub const IMF_CHN_ENABLED : u8 = 0;
+pub const IMF_CHN_MUTED   : u8 = 1;
+pub const IMF_CHN_DISABLED: u8 = 2;
+
+#[derive(Debug, Default, Clone)]
+pub struct ImfChannel {
+    pub name  : String,
+    pub chorus: u8,
+    pub reverb: u8,
+    pub pan   : u8,
+    pub status: u8,
+}
+
+
+/// ImfEvent defines a pattern event. Notes are numbered from 1, IMF
+/// events have two effect columns with the same effect set.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ImfEvent {
+    pub note: u8,
+    pub ins : u8,
+    pub fxt : u8,
+    pub fxp : u8,
+    pub fxt2: u8,
+    pub fxp2: u8,
+}
+
+impl ImfEvent {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+const FX_NAMES: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+fn fx_name(fxt: u8, fxp: u8) -> String {
+    if fxt == 0 && fxp == 0 {
+        "...".to_owned()
+    } else {
+        format!("{}{:02X}", *FX_NAMES.get(fxt as usize).unwrap_or(&b'?') as char, fxp)
+    }
+}
+
+impl fmt::Display for ImfEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let note = match self.note {
+            0            => "---".to_owned(),
+            IMF_NOTE_OFF => "===".to_owned(),
+            n            => { let n = n as usize + 11; format!("{}{}", NOTES[n % 12], n / 12) },
+        };
+
+        let ins = if self.ins == 0 {
+            "--".to_owned()
+        } else {
+            format!("{:02X}", self.ins)
+        };
+
+        write!(f, "{} {} {} {}", note, ins, fx_name(self.fxt, self.fxp), fx_name(self.fxt2, self.fxp2))
+    }
+}
+
+
+pub struct ImfPattern {
+    pub rows: usize,
+    data    : Vec<ImfEvent>,
+}
+
+impl ImfPattern {
+    // Each event starts with a mask byte with the channel number in the
+    // low 5 bits. Bit 5 is set if note and instrument follow, bits 6 and
+    // 7 are set if the first and second effects follow. A zero mask ends
+    // the row.
+    fn from_slice(rows: usize, b: &[u8]) -> Self {
+        let mut data = vec![ImfEvent::new(); rows * 32];
+        let mut i = 0;
+        let mut row = 0;
+
+        while row < rows && i < b.len() {
+            let mask = b[i];
+            i += 1;
+            if mask == 0 {
+                row += 1;
+                continue
+            }
+
+            let mut e = ImfEvent::new();
+            let mut read = || -> u8 {
+                i += 1;
+                *b.get(i - 1).unwrap_or(&0)
+            };
+            if mask & 0x20 != 0 {
+                e.note = match read() {
+                    0xa0 => IMF_NOTE_OFF,
+                    0xff => 0,
+                    n    => (n >> 4) * 12 + (n & 0x0f) + 1,
+                };
+                e.ins = read();
+            }
+            if mask & 0x40 != 0 {
+                e.fxt = read();
+                e.fxp = read();
+            }
+            if mask & 0x80 != 0 {
+                e.fxt2 = read();
+                e.fxp2 = read();
+            }
+            data[row * 32 + (mask & 0x1f) as usize] = e;
+        }
+
+        ImfPattern{ rows, data }
+    }
+
+    pub fn event(&self, row: usize, chn: usize) -> ImfEvent {
+        self.data.get(row * 32 + chn).cloned().unwrap_or_default()
+    }
+}
+
+
+pub const IMF_ENV_ON     : u8 = 0x01;
+pub const IMF_ENV_SUSTAIN: u8 = 0x02;
+pub const IMF_ENV_LOOP   : u8 = 0x04;
+
+/// An Imago Orpheus envelope. Volume envelope values range from 0 to 64,
+/// pan and pitch envelope values range from 0 to 255 centered at 128.
+#[derive(Debug, Default, Clone)]
+pub struct ImfEnvelope {
+    pub num : u8,
+    pub sus : u8,
+    pub lpb : u8,
+    pub lpe : u8,
+    pub flg : u8,
+    pub node: Vec<(u16, u16)>,  // (tick, y)
+}
+
+impl ImfEnvelope {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.flg & IMF_ENV_ON != 0 && self.num > 0
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub struct ImfInstrument {
+    pub name        : String,
+    pub map         : Vec<u8>,      // sample for each note, 120 entries
+    pub vol_env     : ImfEnvelope,
+    pub pan_env     : ImfEnvelope,
+    pub pit_env     : ImfEnvelope,
+    pub fadeout     : u16,
+    pub nsmp        : u16,
+    pub first_sample: usize,        // index of the first sample in the module
+}
+
+impl ImfInstrument {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The module sample index used to play a note.
+    pub fn sample(&self, note: u8) -> Option<usize> {
+        match self.map.get(note as usize - 1) {
+            Some(&s) if (s as u16) < self.nsmp => Some(self.first_sample + s as usize),
+            _                                   => None,
+        }
+    }
+}
+
+
+pub const IMF_SMP_LOOP   : u8 = 0x01;
+pub const IMF_SMP_BIDI   : u8 = 0x02;
+pub const IMF_SMP_16BIT  : u8 = 0x04;
+pub const IMF_SMP_PAN    : u8 = 0x08;
+
+#[derive(Debug, Default, Clone)]
+pub struct ImfSample {
+    pub name      : String,
+    pub length    : u32,
+    pub loop_start: u32,
+    pub loop_end  : u32,
+    pub c5speed   : u32,
+    pub volume    : u8,
+    pub pan       : u8,
+    pub flags     : u8,
+}
+
+impl ImfSample {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn has_loop(&self) -> bool {
+        self.flags & IMF_SMP_LOOP != 0 && self.loop_end > self.loop_start
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        // C-5 instrument 1 in channel 2, key off with effect Cxx in
+        // channel 0 of row 1
+        let p = ImfPattern::from_slice(2, &[0x62, 0x40, 0x01, 0x0c, 0x20, 0x00, 0xe0, 0xa0, 0x00, 0x12, 0x04, 0x0d, 0x40, 0x00]);
+        let e = p.event(0, 2);
+        assert_eq!((e.note, e.ins, e.fxt, e.fxp), (49, 1, 0x0c, 0x20));
+        assert_eq!(format!("{}", e), "C 5 01 C20 ...");
+        let e = p.event(1, 0);
+        assert_eq!(format!("{}", e), "=== -- I04 D40");
+        assert_eq!(p.event(1, 1), ImfEvent::new());
+    }
+}
@@ -0,0 +1,221 @@
+// Impulse Tracker IT214/IT215 sample decompression
+//
+// Compressed samples are stored in blocks of 0x8000 8-bit samples or
+// 0x4000 16-bit samples. Each block starts with a 16-bit length followed
+// by a bitstream of variable-width deltas. IT215 samples use a second
+// integration stage.
+
+use std::cmp;
+use util::BinaryRead;
+use ::*;
+
+struct BitReader<'a> {
+    b   : &'a [u8],
+    pos : usize,
+    buf : u32,
+    num : u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(b: &'a [u8]) -> Self {
+        BitReader {
+            b,
+            pos: 0,
+            buf: 0,
+            num: 0,
+        }
+    }
+
+    fn read_bits(&mut self, n: u32) -> Result<u32, Error> {
+        let mut val = 0_u32;
+        for i in 0..n {
+            if self.num == 0 {
+                if self.pos >= self.b.len() {
+                    return Err(Error::Load("compressed sample truncated".to_owned()))
+                }
+                self.buf = self.b[self.pos] as u32;
+                self.pos += 1;
+                self.num = 8;
+            }
+            val |= (self.buf & 1) << i;
+            self.buf >>= 1;
+            self.num -= 1;
+        }
+        Ok(val)
+    }
+}
+
+fn next_width(val: u32, width: u32) -> u32 {
+    if val < width { val } else { val + 1 }
+}
+
+pub fn decompress8(b: &[u8], len: usize, it215: bool) -> Result<(Vec<u8>, usize), Error> {
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    let mut ofs = 0;
+
+    while out.len() < len {
+        let block_size = b.read16l(ofs)? as usize;
+        let mut br = BitReader::new(b.slice(ofs + 2, block_size)?);
+        ofs += 2 + block_size;
+
+        let block_len = cmp::min(0x8000, len - out.len());
+        let mut width = 9;
+        let mut d1 = 0_i8;
+        let mut d2 = 0_i8;
+
+        let mut pos = 0;
+        while pos < block_len {
+            if width == 0 || width > 9 {
+                return Err(Error::Load(format!("invalid bit width {}", width)))
+            }
+
+            let val = br.read_bits(width)?;
+
+            if width < 7 {
+                // method 1 (1-6 bits)
+                if val == 1 << (width - 1) {
+                    let v = br.read_bits(3)? + 1;
+                    width = next_width(v, width);
+                    continue
+                }
+            } else if width < 9 {
+                // method 2 (7-8 bits)
+                let border = (0xff >> (9 - width)) - 4;
+                if val > border && val <= border + 8 {
+                    width = next_width(val - border, width);
+                    continue
+                }
+            } else {
+                // method 3 (9 bits)
+                if val & 0x100 != 0 {
+                    width = (val + 1) & 0xff;
+                    continue
+                }
+            }
+
+            // sign-extend
+            let v = if width < 8 {
+                let shift = 8 - width;
+                ((val << shift) as u8 as i8) >> shift
+            } else {
+                val as u8 as i8
+            };
+
+            d1 = d1.wrapping_add(v);
+            d2 = d2.wrapping_add(d1);
+            out.push(if it215 { d2 } else { d1 } as u8);
+            pos += 1;
+        }
+    }
+
+    Ok((out, ofs))
+}
+
+pub fn decompress16(b: &[u8], len: usize, it215: bool) -> Result<(Vec<i16>, usize), Error> {
+    let mut out: Vec<i16> = Vec::with_capacity(len);
+    let mut ofs = 0;
+
+    while out.len() < len {
+        let block_size = b.read16l(ofs)? as usize;
+        let mut br = BitReader::new(b.slice(ofs + 2, block_size)?);
+        ofs += 2 + block_size;
+
+        let block_len = cmp::min(0x4000, len - out.len());
+        let mut width = 17;
+        let mut d1 = 0_i16;
+        let mut d2 = 0_i16;
+
+        let mut pos = 0;
+        while pos < block_len {
+            if width == 0 || width > 17 {
+                return Err(Error::Load(format!("invalid bit width {}", width)))
+            }
+
+            let val = br.read_bits(width)?;
+
+            if width < 7 {
+                // method 1 (1-6 bits)
+                if val == 1 << (width - 1) {
+                    let v = br.read_bits(4)? + 1;
+                    width = next_width(v, width);
+                    continue
+                }
+            } else if width < 17 {
+                // method 2 (7-16 bits)
+                let border = (0xffff >> (17 - width)) - 8;
+                if val > border && val <= border + 16 {
+                    width = next_width(val - border, width);
+                    continue
+                }
+            } else {
+                // method 3 (17 bits)
+                if val & 0x10000 != 0 {
+                    width = (val + 1) & 0xff;
+                    continue
+                }
+            }
+
+            // sign-extend
+            let v = if width < 16 {
+                let shift = 16 - width;
+                ((val << shift) as u16 as i16) >> shift
+            } else {
+                val as u16 as i16
+            };
+
+            d1 = d1.wrapping_add(v);
+            d2 = d2.wrapping_add(d1);
+            out.push(if it215 { d2 } else { d1 });
+            pos += 1;
+        }
+    }
+
+    Ok((out, ofs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pack values LSB-first with the given bit widths
+    fn pack(vals: &[(u32, u32)]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut acc = 0_u64;
+        let mut num = 0;
+        for &(val, width) in vals {
+            acc |= (val as u64) << num;
+            num += width;
+            while num >= 8 {
+                out.push(acc as u8);
+                acc >>= 8;
+                num -= 8;
+            }
+        }
+        if num > 0 {
+            out.push(acc as u8);
+        }
+        let mut block = vec![out.len() as u8, (out.len() >> 8) as u8];
+        block.extend(out);
+        block
+    }
+
+    #[test]
+    fn test_decompress8() {
+        // 9-bit deltas 1, 1, then switch to 4 bits (method 3), delta -2
+        let b = pack(&[(1, 9), (1, 9), (0x103, 9), (0xe, 4)]);
+        let (out, size) = decompress8(&b, 3, false).unwrap();
+        assert_eq!(out, vec![1, 2, 0]);
+        assert_eq!(size, b.len());
+
+        let (out, _) = decompress8(&b, 3, true).unwrap();
+        assert_eq!(out, vec![1, 3, 3]);
+    }
+
+    #[test]
+    fn test_decompress16() {
+        // 17-bit deltas 1000, -1000
+        let b = pack(&[(1000, 17), (-1000_i16 as u16 as u32, 17)]);
+        let (out, _) = decompress16(&b, 2, false).unwrap();
+        assert_eq!(out, vec![1000, 0]);
+    }
+}
@@ -0,0 +1,580 @@
+use std::cmp;
+use format::{ProbeInfo, Format, Loader};
+use format::it::*;
+use format::it::itsex;
+use module::{Module, Sample};
+use module::sample::SampleType;
+use util::{BinaryRead, SliceConvert};
+use ::*;
+
+/// Impulse Tracker module loader
+pub struct ItLoader;
+
+impl Loader for ItLoader {
+    fn name(&self) -> &'static str {
+        "Impulse Tracker"
+    }
+
+    fn probe(&self, b: &[u8], player_id: &str) -> Result<ProbeInfo, Error> {
+        if b.len() < 0xc0 {
+            return Err(Error::Format(format!("file too short ({})", b.len())));
+        }
+
+        let magic = b.read_string(0, 4)?;
+        if magic == "IMPM" {
+            player::check_accepted(player_id, "it")?;
+            Ok(ProbeInfo{format: Format::It, title: b.read_string(4, 26)?})
+        } else {
+            Err(Error::Format(format!("bad magic {:?}", magic)))
+        }
+    }
+
+    fn load(self: Box<Self>, b: &[u8], info: ProbeInfo) -> Result<Module, Error> {
+
+        if info.format != Format::It {
+            return Err(Error::Format("unsupported format".to_owned()));
+        }
+
+        let song_name = b.read_string(4, 26)?;
+        let ord_num = b.read16l(0x20)?;
+        let ins_num = b.read16l(0x22)?;
+        let smp_num = b.read16l(0x24)?;
+        let pat_num = b.read16l(0x26)?;
+        let cwt_v = b.read16l(0x28)?;
+        let cmwt = b.read16l(0x2a)?;
+        let flags = b.read16l(0x2c)?;
+        let special = b.read16l(0x2e)?;
+        let g_v = b.read8(0x30)?;
+        let m_v = b.read8(0x31)?;
+        let i_s = b.read8(0x32)?;
+        let i_t = b.read8(0x33)?;
+        let sep = b.read8(0x34)?;
+        let pwd = b.read8(0x35)?;
+        let msg_len = b.read16l(0x36)? as usize;
+        let msg_ofs = b.read32l(0x38)? as usize;
+
+        // Orders
+        let orders = b.slice(0xc0, ord_num as usize)?.to_vec();
+
+        // Instrument, sample and pattern offsets
+        let mut ofs = 0xc0 + ord_num as usize;
+        let mut ins_ofs = Vec::<usize>::new();
+        for _ in 0..ins_num { ins_ofs.push(b.read32l(ofs)? as usize); ofs += 4; }
+        let mut smp_ofs = Vec::<usize>::new();
+        for _ in 0..smp_num { smp_ofs.push(b.read32l(ofs)? as usize); ofs += 4; }
+        let mut pat_ofs = Vec::<usize>::new();
+        for _ in 0..pat_num { pat_ofs.push(b.read32l(ofs)? as usize); ofs += 4; }
+
+        // Song message
+        let message = if special & 0x01 != 0 && msg_len > 0 {
+            b.read_string(msg_ofs, msg_len)?.replace("\r", "\n")
+        } else {
+            "".to_owned()
+        };
+
+        // Load instruments
+        let mut instruments = Vec::<ItInstrument>::new();
+        for i in 0..ins_num as usize {
+            let ins = if cmwt < 0x200 {
+                load_old_instrument(b, ins_ofs[i])?
+            } else {
+                load_instrument(b, ins_ofs[i])?
+            };
+            instruments.push(ins);
+        }
+
+        // Load samples
+        let mut smp_headers = Vec::<ItSample>::new();
+        let mut samples = Vec::<Sample>::new();
+        for i in 0..smp_num as usize {
+            let sh = load_sample_header(b, smp_ofs[i])?;
+            let smp = load_sample(b, i, &sh)?;
+            smp_headers.push(sh);
+            samples.push(smp);
+        }
+
+        // Load patterns
+        let mut patterns = Vec::<ItPattern>::new();
+        for i in 0..pat_num as usize {
+            let pat = if pat_ofs[i] == 0 {
+                ItPattern::new_empty(64, 64)
+            } else {
+                load_pattern(b, pat_ofs[i])?
+            };
+            patterns.push(pat);
+        }
+
+        // Orders may reference patterns that are not stored in the file
+        let max_pat = orders.iter().filter(|&&x| x < 200).map(|&x| x as usize + 1).max().unwrap_or(0);
+        while patterns.len() < max_pat {
+            patterns.push(ItPattern::new_empty(64, 64));
+        }
+
+        // Find the number of channels in use
+        let num_chn = {
+            let mut chn = 0;
+            for p in &patterns {
+                chn = cmp::max(chn, p.used_channels());
+            }
+            cmp::max(chn, 1)
+        };
+
+        let patterns = patterns.iter().map(|p| p.compact(num_chn)).collect::<Vec<ItPattern>>();
+
+        let mut data = ItData{
+            song_name,
+            ord_num,
+            ins_num,
+            smp_num,
+            pat_num: patterns.len() as u16,
+            cwt_v,
+            cmwt,
+            flags,
+            special,
+            g_v,
+            m_v,
+            i_s,
+            i_t,
+            sep,
+            pwd,
+            message,
+            chnl_pan: [0; 64],
+            chnl_vol: [0; 64],
+            orders,
+            instruments,
+            smp_headers,
+            patterns,
+            samples,
+
+            channels: num_chn,
+        };
+
+        data.chnl_pan.copy_from_slice(b.slice(0x40, 64)?);
+        data.chnl_vol.copy_from_slice(b.slice(0x80, 64)?);
+
+        let ver_major = (cwt_v & 0xf00) >> 8;
+        let ver_minor = cwt_v & 0x0ff;
+
+        let m = Module {
+            format_id  : "it",
+            description: format!("Impulse Tracker IT {}.{:02x}", (cmwt & 0xf00) >> 8, cmwt & 0xff),
+            creator    : match cwt_v >> 12 {
+                             0 => format!("Impulse Tracker {}.{:02x}", ver_major, ver_minor),
+                             1 => "Schism Tracker".to_owned(),
+                             5 => format!("OpenMPT {}.{:02x}", ver_major, ver_minor),
+                             _ => format!("unknown ({:04x})", cwt_v),
+                         },
+            channels   : num_chn,
+            player     : "it",
+            data       : Box::new(data),
+        };
+
+        Ok(m)
+    }
+}
+
+fn load_old_instrument(b: &[u8], ofs: usize) -> Result<ItInstrument, Error> {
+    let mut ins = ItInstrument::new();
+
+    ins.filename = b.read_string(ofs + 0x04, 12)?;
+    let flg      = b.read8(ofs + 0x11)?;
+    let vls      = b.read8(ofs + 0x12)?;
+    let vle      = b.read8(ofs + 0x13)?;
+    let sls      = b.read8(ofs + 0x14)?;
+    let sle      = b.read8(ofs + 0x15)?;
+    ins.fadeout  = b.read16l(ofs + 0x18)?.saturating_mul(2);
+    ins.nna      = b.read8(ofs + 0x1a)?;
+    ins.dct      = b.read8(ofs + 0x1b)?;
+    ins.trkvers  = b.read16l(ofs + 0x1c)?;
+    ins.nos      = b.read8(ofs + 0x1e)?;
+    ins.name     = b.read_string(ofs + 0x20, 26)?;
+    ins.keyboard = load_keyboard(b, ofs + 0x40)?;
+
+    // Fields not present in old format instruments
+    ins.gbv = 128;
+    ins.dfp = 0x80 | 32;
+    ins.ppc = 60;
+
+    // Volume envelope node points
+    let mut env = ItEnvelope::new();
+    env.flg = flg & (IT_ENV_ON | IT_ENV_LOOP | IT_ENV_SUSTAIN);
+    env.lpb = vls;
+    env.lpe = vle;
+    env.slb = sls;
+    env.sle = sle;
+    for i in 0..25 {
+        let tick = b.read8(ofs + 0x1f8 + i * 2)?;
+        if tick == 0xff {
+            break
+        }
+        let y = b.read8(ofs + 0x1f8 + i * 2 + 1)?;
+        env.node.push((y as i8, tick as u16));
+    }
+    env.num = env.node.len() as u8;
+    ins.vol_env = env;
+
+    Ok(ins)
+}
+
+fn load_instrument(b: &[u8], ofs: usize) -> Result<ItInstrument, Error> {
+    let mut ins = ItInstrument::new();
+
+    ins.filename = b.read_string(ofs + 0x04, 12)?;
+    ins.nna      = b.read8(ofs + 0x11)?;
+    ins.dct      = b.read8(ofs + 0x12)?;
+    ins.dca      = b.read8(ofs + 0x13)?;
+    ins.fadeout  = b.read16l(ofs + 0x14)?;
+    ins.pps      = b.read8i(ofs + 0x16)?;
+    ins.ppc      = b.read8(ofs + 0x17)?;
+    ins.gbv      = b.read8(ofs + 0x18)?;
+    ins.dfp      = b.read8(ofs + 0x19)?;
+    ins.rv       = b.read8(ofs + 0x1a)?;
+    ins.rp       = b.read8(ofs + 0x1b)?;
+    ins.trkvers  = b.read16l(ofs + 0x1c)?;
+    ins.nos      = b.read8(ofs + 0x1e)?;
+    ins.name     = b.read_string(ofs + 0x20, 26)?;
+    ins.ifc      = b.read8(ofs + 0x3a)?;
+    ins.ifr      = b.read8(ofs + 0x3b)?;
+    ins.mch      = b.read8(ofs + 0x3c)?;
+    ins.mpr      = b.read8(ofs + 0x3d)?;
+    ins.midibnk  = b.read16l(ofs + 0x3e)?;
+    ins.keyboard = load_keyboard(b, ofs + 0x40)?;
+    ins.vol_env  = load_envelope(b, ofs + 0x130)?;
+    ins.pan_env  = load_envelope(b, ofs + 0x182)?;
+    ins.pit_env  = load_envelope(b, ofs + 0x1d4)?;
+
+    Ok(ins)
+}
+
+fn load_keyboard(b: &[u8], ofs: usize) -> Result<Vec<(u8, u8)>, Error> {
+    let mut keyboard = Vec::<(u8, u8)>::with_capacity(120);
+    for i in 0..120 {
+        keyboard.push((b.read8(ofs + i * 2)?, b.read8(ofs + i * 2 + 1)?));
+    }
+    Ok(keyboard)
+}
+
+fn load_envelope(b: &[u8], ofs: usize) -> Result<ItEnvelope, Error> {
+    let mut env = ItEnvelope::new();
+
+    env.flg = b.read8(ofs)?;
+    env.num = cmp::min(b.read8(ofs + 1)?, 25);
+    env.lpb = b.read8(ofs + 2)?;
+    env.lpe = b.read8(ofs + 3)?;
+    env.slb = b.read8(ofs + 4)?;
+    env.sle = b.read8(ofs + 5)?;
+    for i in 0..env.num as usize {
+        let y = b.read8i(ofs + 6 + i * 3)?;
+        let tick = b.read16l(ofs + 6 + i * 3 + 1)?;
+        env.node.push((y, tick));
+    }
+
+    Ok(env)
+}
+
+fn load_sample_header(b: &[u8], ofs: usize) -> Result<ItSample, Error> {
+    let mut sh = ItSample::new();
+
+    sh.filename       = b.read_string(ofs + 0x04, 12)?;
+    sh.gvl            = b.read8(ofs + 0x11)?;
+    sh.flg            = b.read8(ofs + 0x12)?;
+    sh.vol            = b.read8(ofs + 0x13)?;
+    sh.name           = b.read_string(ofs + 0x14, 26)?;
+    sh.cvt            = b.read8(ofs + 0x2e)?;
+    sh.dfp            = b.read8(ofs + 0x2f)?;
+    sh.length         = b.read32l(ofs + 0x30)?;
+    sh.loop_begin     = b.read32l(ofs + 0x34)?;
+    sh.loop_end       = b.read32l(ofs + 0x38)?;
+    sh.c5speed        = b.read32l(ofs + 0x3c)?;
+    sh.sus_loop_begin = b.read32l(ofs + 0x40)?;
+    sh.sus_loop_end   = b.read32l(ofs + 0x44)?;
+    sh.sample_pointer = b.read32l(ofs + 0x48)?;
+    sh.vis            = b.read8(ofs + 0x4c)?;
+    sh.vid            = b.read8(ofs + 0x4d)?;
+    sh.vir            = b.read8(ofs + 0x4e)?;
+    sh.vit            = b.read8(ofs + 0x4f)?;
+
+    Ok(sh)
+}
+
+fn load_sample(b: &[u8], i: usize, sh: &ItSample) -> Result<Sample, Error> {
+    let mut smp = Sample::new();
+
+    smp.num  = i + 1;
+    smp.address = sh.sample_pointer;
+    smp.name = sh.name.to_owned();
+
+    if sh.flg & IT_SMP_SAMPLE == 0 || sh.length == 0 {
+        return Ok(smp)
+    }
+
+    smp.size = sh.length;
+    smp.loop_bidir = sh.flg & IT_SMP_BIDI_LOOP != 0;
+    let ofs = sh.sample_pointer as usize;
+    let it215 = sh.cvt & 0x04 != 0;
+
+    if ofs > b.len() {
+        return Err(Error::Load("invalid sample pointer".to_owned()));
+    }
+
+    // Stereo samples store the left channel first, we only use that one
+    if sh.flg & IT_SMP_16BIT != 0 {
+        smp.sample_type = SampleType::Sample16;
+        if sh.flg & IT_SMP_COMPRESSED != 0 {
+            let (buf, _) = itsex::decompress16(b.slice(ofs, b.len() - ofs)?, smp.size as usize, it215)?;
+            let buf = buf.iter().map(|&x| x as u16).collect::<Vec<u16>>();
+            smp.store(&buf[..].as_slice_u8());
+        } else {
+            smp.store(b.slice(ofs, smp.size as usize * 2)?);
+        }
+    } else {
+        smp.sample_type = SampleType::Sample8;
+        if sh.flg & IT_SMP_COMPRESSED != 0 {
+            let (buf, _) = itsex::decompress8(b.slice(ofs, b.len() - ofs)?, smp.size as usize, it215)?;
+            smp.store(&buf[..]);
+        } else {
+            smp.store(b.slice(ofs, smp.size as usize)?);
+        }
+    }
+
+    if sh.flg & IT_SMP_COMPRESSED == 0 && sh.cvt & 0x01 == 0 {
+        smp.to_signed();
+    }
+
+    Ok(smp)
+}
+
+fn load_pattern(b: &[u8], ofs: usize) -> Result<ItPattern, Error> {
+    let len = b.read16l(ofs)? as usize;
+    let rows = b.read16l(ofs + 2)? as usize;
+    let data = b.slice(ofs + 8, len)?;
+
+    if rows == 0 {
+        return Ok(ItPattern::new_empty(64, 64))
+    }
+
+    let mut pat = ItPattern::new_empty(rows, 64);
+    let mut last_mask = [0_u8; 64];
+    let mut last = vec![ItEvent::new(); 64];
+
+    let mut i = 0;
+    let mut row = 0;
+    while row < rows && i < len {
+        let chn_var = data[i]; i += 1;
+        if chn_var == 0 {
+            row += 1;
+            continue
+        }
+
+        let ch = (chn_var as usize - 1) & 63;
+        if chn_var & 0x80 != 0 {
+            last_mask[ch] = data.read8(i)?; i += 1;
+        }
+        let mask = last_mask[ch];
+
+        let e = pat.event_mut(row, ch);
+        if mask & 0x01 != 0 { last[ch].note = data.read8(i)?; i += 1; }
+        if mask & 0x02 != 0 { last[ch].ins = data.read8(i)?; i += 1; }
+        if mask & 0x04 != 0 { last[ch].vol = data.read8(i)?; i += 1; }
+        if mask & 0x08 != 0 { last[ch].cmd = data.read8(i)?; last[ch].info = data.read8(i + 1)?; i += 2; }
+
+        if mask & 0x11 != 0 { e.mask |= IT_MASK_NOTE; e.note = last[ch].note }
+        if mask & 0x22 != 0 { e.mask |= IT_MASK_INS; e.ins = last[ch].ins }
+        if mask & 0x44 != 0 { e.mask |= IT_MASK_VOL; e.vol = last[ch].vol }
+        if mask & 0x88 != 0 { e.mask |= IT_MASK_CMD; e.cmd = last[ch].cmd; e.info = last[ch].info }
+    }
+
+    Ok(pat)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use format;
+    use format::it::*;
+    use util::BinaryWrite;
+
+    const INS_OFS: usize = 0xd0;
+    const SMP_OFS: usize = INS_OFS + 0x230;
+    const PAT_OFS: usize = SMP_OFS + 0x50;
+
+    // Row 0 sets all fields in channels 1 and 3, row 1 reuses the values
+    // of channel 1 with a new mask, row 2 reuses the mask of channel 1 and
+    // sets a note off in channel 3
+    const PATTERN: &[u8] = &[
+        0x81, 0x0f, 60, 1, 64, 1, 6, 0x83, 0x03, 48, 1, 0,
+        0x81, 0xf0, 0,
+        0x01, 0x83, 0x01, 255, 0,
+    ];
+
+    fn it_module(cmwt: u16) -> Vec<u8> {
+        let msg_ofs = PAT_OFS + 8 + PATTERN.len();
+        let data_ofs = msg_ofs + 12;
+
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string("IMPM", 4);
+        b.write_string("test", 26);
+        b.write16l(0x1004);
+        for &x in &[2, 1, 1, 1, 0x0214, cmwt] { b.write16l(x) }
+        b.write16l(IT_STEREO | IT_USE_INSTRUMENTS | IT_LINEAR_SLIDES);
+        b.write16l(0x01);                   // special: song message
+        b.extend_from_slice(&[128, 48, 6, 125, 128, 0]);
+        b.write16l(11);
+        b.write32l(msg_ofs as u32);
+        b.write32l(0);
+        b.extend((0..64).map(|x| x as u8));  // channel pan
+        b.extend_from_slice(&[64; 64]);     // channel volume
+        b.extend_from_slice(&[0, 255]);     // orders
+        for &x in &[INS_OFS, SMP_OFS, PAT_OFS] { b.write32l(x as u32) }
+
+        // Instrument
+        b.resize(INS_OFS, 0);
+        b.write_string("IMPI", 4);
+        b.write_string("ins.iti", 12);
+        if cmwt < 0x200 {
+            b.extend_from_slice(&[0, 0x05, 1, 3, 0, 0, 0, 0]);
+            b.write16l(100);                // fadeout
+            b.extend_from_slice(&[2, 1]);   // nna, dnc
+            b.write16l(0x0214);
+            b.extend_from_slice(&[1, 0]);   // nos
+        } else {
+            b.extend_from_slice(&[0, 2, 1, 0]);
+            b.write16l(256);                // fadeout
+            b.extend_from_slice(&[0xfc, 48, 100, 0x80 | 16, 10, 20]);
+            b.write16l(0x0214);
+            b.extend_from_slice(&[1, 0]);   // nos
+        }
+        b.write_string("instrument", 26);
+        b.extend_from_slice(&[0; 6]);
+        for n in 0..120 { b.extend_from_slice(&[n, 1]) }
+        if cmwt < 0x200 {
+            b.resize(INS_OFS + 0x1f8, 0);
+            b.extend_from_slice(&[0, 64, 10, 32, 20, 0, 0xff, 0xff]);
+        } else {
+            // volume envelope on with a sustain loop, empty pan and pitch
+            b.extend_from_slice(&[0x05, 3, 0, 0, 1, 1]);
+            for &(y, tick) in &[(64, 0), (32, 10), (0, 20)] {
+                b.write8(y);
+                b.write16l(tick);
+            }
+        }
+
+        // Sample header
+        b.resize(SMP_OFS, 0);
+        b.write_string("IMPS", 4);
+        b.write_string("smp.its", 12);
+        b.extend_from_slice(&[0, 64, IT_SMP_SAMPLE | IT_SMP_LOOP, 48]);
+        b.write_string("sample", 26);
+        b.extend_from_slice(&[0, 0x80 | 32]);
+        for &x in &[16, 4, 12, 22050, 0, 0, data_ofs as u32] { b.write32l(x) }
+        b.extend_from_slice(&[1, 2, 3, 4]);
+
+        // Pattern
+        b.write16l(PATTERN.len() as u16);
+        b.write16l(4);
+        b.write32l(0);
+        b.extend_from_slice(PATTERN);
+
+        b.write_string("hello\rworld", 12);
+        b.extend((0..16).map(|x| x * 16));
+        b
+    }
+
+    #[test]
+    fn test_load_it() {
+        let b = it_module(0x214);
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "it");
+        assert_eq!(m.description, "Impulse Tracker IT 2.14");
+        assert_eq!(m.creator, "Impulse Tracker 2.14");
+        assert_eq!(m.title().trim_end(), "test");
+        assert_eq!(m.channels, 3);
+        let data = m.data.as_any().downcast_ref::<ItData>().unwrap();
+        assert_eq!(data.orders, vec![0, 255]);
+        assert!(data.use_instruments());
+        assert_eq!((data.g_v, data.m_v, data.i_s, data.i_t, data.sep), (128, 48, 6, 125, 128));
+        assert_eq!((data.chnl_pan[5], data.chnl_vol[5]), (5, 64));
+        assert_eq!(data.message, "hello\nworld");
+
+        assert!(format::load(&b[..0xc0], "").is_err());
+    }
+
+    #[test]
+    fn test_load_it_instrument() {
+        let m = format::load(&it_module(0x214), "").unwrap();
+        let data = m.data.as_any().downcast_ref::<ItData>().unwrap();
+        let ins = &data.instruments[0];
+        assert_eq!(ins.name.trim_end(), "instrument");
+        assert_eq!((ins.nna, ins.dct, ins.dca, ins.fadeout), (2, 1, 0, 256));
+        assert_eq!((ins.pps, ins.ppc, ins.gbv, ins.dfp, ins.rv, ins.rp), (-4, 48, 100, 0x80 | 16, 10, 20));
+        assert_eq!(ins.keyboard[60], (60, 1));
+        assert!(ins.vol_env.is_on());
+        assert_eq!((ins.vol_env.slb, ins.vol_env.sle), (1, 1));
+        assert_eq!(ins.vol_env.node, vec![(64, 0), (32, 10), (0, 20)]);
+        assert!(!ins.pan_env.is_on());
+        assert!(!ins.pit_env.is_on());
+    }
+
+    #[test]
+    fn test_load_it_old_instrument() {
+        let m = format::load(&it_module(0x100), "").unwrap();
+        assert_eq!(m.description, "Impulse Tracker IT 1.00");
+        let data = m.data.as_any().downcast_ref::<ItData>().unwrap();
+        let ins = &data.instruments[0];
+        assert_eq!(ins.name.trim_end(), "instrument");
+        assert_eq!((ins.nna, ins.dct, ins.fadeout, ins.nos), (2, 1, 200, 1));
+        assert_eq!((ins.gbv, ins.dfp, ins.ppc), (128, 0x80 | 32, 60));
+        assert_eq!(ins.keyboard[119], (119, 1));
+
+        // envelope nodes are (tick, y) pairs ending at tick 0xff
+        let env = &ins.vol_env;
+        assert_eq!(env.flg, IT_ENV_ON | IT_ENV_SUSTAIN);
+        assert_eq!((env.lpb, env.lpe, env.slb, env.sle), (1, 3, 0, 0));
+        assert_eq!(env.node, vec![(64, 0), (32, 10), (0, 20)]);
+        assert_eq!(env.num, 3);
+    }
+
+    #[test]
+    fn test_load_it_sample() {
+        let b = it_module(0x214);
+        let m = format::load(&b, "").unwrap();
+        let data = m.data.as_any().downcast_ref::<ItData>().unwrap();
+        let sh = &data.smp_headers[0];
+        assert_eq!(sh.name.trim_end(), "sample");
+        assert_eq!((sh.gvl, sh.flg, sh.vol, sh.dfp), (64, IT_SMP_SAMPLE | IT_SMP_LOOP, 48, 0x80 | 32));
+        assert_eq!((sh.length, sh.loop_begin, sh.loop_end, sh.c5speed), (16, 4, 12, 22050));
+        assert_eq!((sh.vis, sh.vid, sh.vir, sh.vit), (1, 2, 3, 4));
+
+        // unsigned sample data is converted to signed
+        assert_eq!(data.samples[0].size, 16);
+        assert_eq!(data.samples[0].data[1], 0x90);
+
+        // sample data past the end of the file
+        let mut b = b;
+        let ptr = b.len() + 1;
+        b[SMP_OFS + 0x48..SMP_OFS + 0x4c].copy_from_slice(&[ptr as u8, (ptr >> 8) as u8, 0, 0]);
+        assert!(format::load(&b, "").is_err());
+    }
+
+    #[test]
+    fn test_load_it_pattern() {
+        let m = format::load(&it_module(0x214), "").unwrap();
+        let data = m.data.as_any().downcast_ref::<ItData>().unwrap();
+        assert_eq!(data.patterns.len(), 1);
+        let pat = &data.patterns[0];
+        assert_eq!(pat.rows, 4);
+
+        let all = IT_MASK_NOTE | IT_MASK_INS | IT_MASK_VOL | IT_MASK_CMD;
+        for row in 0..3 {
+            let e = pat.event(row, 0);
+            assert_eq!((e.mask, e.note, e.ins, e.vol, e.cmd, e.info), (all, 60, 1, 64, 1, 6));
+        }
+        let e = pat.event(0, 2);
+        assert_eq!((e.mask, e.note, e.ins), (IT_MASK_NOTE | IT_MASK_INS, 48, 1));
+        assert_eq!(pat.event(1, 2).mask, 0);
+        let e = pat.event(2, 2);
+        assert_eq!((e.mask, e.note), (IT_MASK_NOTE, IT_NOTE_OFF));
+        assert_eq!(pat.event(3, 0).mask, 0);
+        assert_eq!(pat.event(0, 1).mask, 0);
+    }
+}
@@ -0,0 +1,350 @@
+pub mod load;
+mod itsex;
+
+pub use self::load::*;
+
+use std::any::Any;
+use std::cmp;
+use module::{event, ModuleData, Sample};
+
+//                              Impulse Tracker header
+//          0   1   2   3   4   5   6   7   8   9   A   B   C   D   E   F
+//        ,---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---.
+//  0000: |'I'|'M'|'P'|'M'| Song Name, max 26 characters, includes NULL   |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0010: |.......................................................|PHiligt|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0020: |OrdNum |InsNum |SmpNum |PatNum | Cwt/v | Cmwt  | Flags |Special|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0030: |GV |MV |IS |IT |Sep|PWD|MsgLgth|Message Offset |   Reserved    |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0040: | Chnl Pan (64 bytes)...                                        |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0080: | Chnl Vol (64 bytes)...                                        |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  00C0: | Orders, Length = OrdNum                                       |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  xxxx: | 'Long' Offset of instruments, Length = InsNum*4               |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  xxxx: | 'Long' Offset of samples headers, Length = SmpNum*4           |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  xxxx: | 'Long' Offset of patterns, Length = PatNum*4                  |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+
+pub const IT_STEREO         : u16 = 0x0001;
+pub const IT_VOL0_OPT       : u16 = 0x0002;
+pub const IT_USE_INSTRUMENTS: u16 = 0x0004;
+pub const IT_LINEAR_SLIDES  : u16 = 0x0008;
+pub const IT_OLD_EFFECTS    : u16 = 0x0010;
+pub const IT_LINK_GXX       : u16 = 0x0020;
+
+pub const IT_NOTE_OFF       : u8 = 255;
+pub const IT_NOTE_CUT       : u8 = 254;
+
+pub struct ItData {
+    pub song_name  : String,
+    pub ord_num    : u16,
+    pub ins_num    : u16,
+    pub smp_num    : u16,
+    pub pat_num    : u16,
+    pub cwt_v      : u16,
+    pub cmwt       : u16,
+    pub flags      : u16,
+    pub special    : u16,
+    pub g_v        : u8,
+    pub m_v        : u8,
+    pub i_s        : u8,
+    pub i_t        : u8,
+    pub sep        : u8,
+    pub pwd        : u8,
+    pub message    : String,
+    pub chnl_pan   : [u8; 64],
+    pub chnl_vol   : [u8; 64],
+    pub orders     : Vec<u8>,
+    pub instruments: Vec<ItInstrument>,
+    pub smp_headers: Vec<ItSample>,
+    pub patterns   : Vec<ItPattern>,
+    pub samples    : Vec<Sample>,
+
+    pub channels   : usize,
+}
+
+impl ItData {
+    pub fn use_instruments(&self) -> bool {
+        self.flags & IT_USE_INSTRUMENTS != 0
+    }
+}
+
+impl ModuleData for ItData {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn title(&self) -> &str {
+        &self.song_name
+    }
+
+    fn patterns(&self) -> usize {
+        self.pat_num as usize
+    }
+
+    fn len(&self) -> usize {
+        self.ord_num as usize
+    }
+
+    fn pattern_in_position(&self, pos: usize) -> Option<usize> {
+        if pos >= self.orders.len() {
+            None
+        } else {
+            Some(self.orders[pos] as usize)
+        }
+    }
+
+    fn instruments(&self) -> Vec<String> {
+        if self.use_instruments() {
+            self.instruments.iter().map(|x| x.name.to_owned()).collect::<Vec<String>>()
+        } else {
+            self.smp_headers.iter().map(|x| x.name.to_owned()).collect::<Vec<String>>()
+        }
+    }
+
+    fn rows(&self, pat: usize) -> usize {
+        if pat >= self.patterns.len() {
+            0
+        } else {
+            self.patterns[pat].rows
+        }
+    }
+
+    fn pattern_data(&self, pat: usize, num: usize, buffer: &mut [u8]) -> usize {
+        let pattern = &self.patterns[pat];
+        let chn = self.channels;
+
+        let mut i = 0;
+        for _ in 0..num {
+            let (row, ch) = (i / chn, i % chn);
+            if row >= pattern.rows {
+                break
+            }
+            let ofs = i * 6;
+            let e = pattern.event(row, ch);
+
+            let mut flags = 0;
+            if e.mask & IT_MASK_NOTE != 0 && e.note < 120 { flags |= event::HAS_NOTE; buffer[ofs+1] = e.note }
+            if e.mask & IT_MASK_INS  != 0 { flags |= event::HAS_INS; buffer[ofs+2] = e.ins }
+            if e.mask & IT_MASK_VOL  != 0 { flags |= event::HAS_VOL; buffer[ofs+3] = e.vol }
+            if e.mask & IT_MASK_CMD  != 0 { flags |= event::HAS_CMD; buffer[ofs+4] = e.cmd; buffer[ofs+5] = e.info }
+            buffer[ofs] = flags;
+
+            i += 1;
+        }
+        i
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        self.samples.to_owned()
+    }
+}
+
+
+/// An Impulse Tracker envelope, as stored in new format instruments.
+#[derive(Debug, Default, Clone)]
+pub struct ItEnvelope {
+    pub flg : u8,
+    pub num : u8,
+    pub lpb : u8,
+    pub lpe : u8,
+    pub slb : u8,
+    pub sle : u8,
+    pub node: Vec<(i8, u16)>,  // (y, tick)
+}
+
+pub const IT_ENV_ON      : u8 = 0x01;
+pub const IT_ENV_LOOP    : u8 = 0x02;
+pub const IT_ENV_SUSTAIN : u8 = 0x04;
+pub const IT_ENV_CARRY   : u8 = 0x08;
+pub const IT_ENV_FILTER  : u8 = 0x80;
+
+impl ItEnvelope {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.flg & IT_ENV_ON != 0 && self.num > 0
+    }
+}
+
+
+//                        Impulse Instrument Format
+//          0   1   2   3   4   5   6   7   8   9   A   B   C   D   E   F
+//        ,---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---.
+//  0000: |'I'|'M'|'P'|'I'| DOS FileName (12345678.123)                   |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0010: |00h|NNA|DCT|DCA|FadeOut|PPS|PPC|GbV|DfP|RV |RP |TrkVers|NoS| x |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0020: | Instrument Name, max 26 bytes, includes NUL...................|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0030: |.......................................|IFC|IFR|MCh|MPr|MIDIBnk|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0040: | Note-Sample/Keyboard Table, Length = 240 bytes................|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0130: | Envelopes.....................................................|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+
+#[derive(Debug, Default)]
+pub struct ItInstrument {
+    pub filename: String,
+    pub nna     : u8,
+    pub dct     : u8,
+    pub dca     : u8,
+    pub fadeout : u16,
+    pub pps     : i8,
+    pub ppc     : u8,
+    pub gbv     : u8,
+    pub dfp     : u8,
+    pub rv      : u8,
+    pub rp      : u8,
+    pub trkvers : u16,
+    pub nos     : u8,
+    pub name    : String,
+    pub ifc     : u8,
+    pub ifr     : u8,
+    pub mch     : u8,
+    pub mpr     : u8,
+    pub midibnk : u16,
+    pub keyboard: Vec<(u8, u8)>,  // (note, sample)
+    pub vol_env : ItEnvelope,
+    pub pan_env : ItEnvelope,
+    pub pit_env : ItEnvelope,
+}
+
+impl ItInstrument {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+
+//                          Impulse Sample Format
+//          0   1   2   3   4   5   6   7   8   9   A   B   C   D   E   F
+//        ,---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---.
+//  0000: |'I'|'M'|'P'|'S'| DOS Filename (12345678.123)                   |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0010: |00h|GvL|Flg|Vol| Sample Name, max 26 bytes, includes NUL.......|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0020: |.......................................................|Cvt|DfP|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0030: | Length        | Loop Begin    | Loop End      | C5Speed       |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0040: | SusLoop Begin | SusLoop End   | SamplePointer |ViS|ViD|ViR|ViT|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+
+pub const IT_SMP_SAMPLE    : u8 = 0x01;
+pub const IT_SMP_16BIT     : u8 = 0x02;
+pub const IT_SMP_STEREO    : u8 = 0x04;
+pub const IT_SMP_COMPRESSED: u8 = 0x08;
+pub const IT_SMP_LOOP      : u8 = 0x10;
+pub const IT_SMP_SUSLOOP   : u8 = 0x20;
+pub const IT_SMP_BIDI_LOOP : u8 = 0x40;
+pub const IT_SMP_BIDI_SUS  : u8 = 0x80;
+
+#[derive(Debug, Default)]
+pub struct ItSample {
+    pub filename      : String,
+    pub gvl           : u8,
+    pub flg           : u8,
+    pub vol           : u8,
+    pub name          : String,
+    pub cvt           : u8,
+    pub dfp           : u8,
+    pub length        : u32,
+    pub loop_begin    : u32,
+    pub loop_end      : u32,
+    pub c5speed       : u32,
+    pub sus_loop_begin: u32,
+    pub sus_loop_end  : u32,
+    pub sample_pointer: u32,
+    pub vis           : u8,
+    pub vid           : u8,
+    pub vir           : u8,
+    pub vit           : u8,
+}
+
+impl ItSample {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+
+pub const IT_MASK_NOTE: u8 = 0x01;
+pub const IT_MASK_INS : u8 = 0x02;
+pub const IT_MASK_VOL : u8 = 0x04;
+pub const IT_MASK_CMD : u8 = 0x08;
+
+/// An unpacked pattern event. Values reused from previous events
+/// (mask bits 4 to 7) are already resolved, so `mask` only carries
+/// bits 0 to 3.
+#[derive(Debug, Default, Clone)]
+pub struct ItEvent {
+    pub mask: u8,
+    pub note: u8,
+    pub ins : u8,
+    pub vol : u8,
+    pub cmd : u8,
+    pub info: u8,
+}
+
+impl ItEvent {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+
+pub struct ItPattern {
+    pub rows: usize,
+    chn     : usize,
+    data    : Vec<ItEvent>,
+}
+
+impl ItPattern {
+    pub fn new_empty(rows: usize, chn: usize) -> Self {
+        ItPattern {
+            rows,
+            chn,
+            data: vec![ItEvent::new(); rows * chn],
+        }
+    }
+
+    pub fn event(&self, row: usize, chn: usize) -> &ItEvent {
+        &self.data[row * self.chn + chn]
+    }
+
+    pub fn event_mut(&mut self, row: usize, chn: usize) -> &mut ItEvent {
+        &mut self.data[row * self.chn + chn]
+    }
+
+    // Number of channels up to the last one with events
+    fn used_channels(&self) -> usize {
+        let mut num = 0;
+        for (i, e) in self.data.iter().enumerate() {
+            if e.mask != 0 {
+                num = cmp::max(num, i % self.chn + 1);
+            }
+        }
+        num
+    }
+
+    // Copy the pattern keeping only the first chn channels
+    fn compact(&self, chn: usize) -> Self {
+        let mut pat = ItPattern::new_empty(self.rows, chn);
+        for row in 0..self.rows {
+            for ch in 0..cmp::min(chn, self.chn) {
+                *pat.event_mut(row, ch) = self.event(row, ch).clone();
+            }
+        }
+        pat
+    }
+}
@@ -0,0 +1,510 @@
+use std::cmp;
+use format::{ProbeInfo, Format, Loader};
+use format::mdl::*;
+use format::mdl::unpack;
+use module::{Module, Sample};
+use module::sample::SampleType;
+use util::{BinaryRead, SliceConvert};
+use ::*;
+
+/// Digitrakker MDL module loader
+pub struct MdlLoader;
+
+impl Loader for MdlLoader {
+    fn name(&self) -> &'static str {
+        "Digitrakker"
+    }
+
+    fn probe(&self, b: &[u8], player_id: &str) -> Result<ProbeInfo, Error> {
+        if b.len() < 5 {
+            return Err(Error::Format(format!("file too short ({})", b.len())));
+        }
+
+        player::check_accepted(player_id, "mdl")?;
+
+        let magic = b.read_string(0, 4)?;
+        if magic != "DMDL" {
+            return Err(Error::Format(format!("bad magic {:?}", magic)));
+        }
+
+        let chunks = chunk_list(b)?;
+        let title = match find_chunk(b, &chunks, "IN") {
+            Some(c) => c.read_string(0, 32)?,
+            None    => return Err(Error::Format("no info chunk".to_owned())),
+        };
+
+        Ok(ProbeInfo{format: Format::Mdl, title})
+    }
+
+    fn load(self: Box<Self>, b: &[u8], info: ProbeInfo) -> Result<Module, Error> {
+
+        if info.format != Format::Mdl {
+            return Err(Error::Format("unsupported format".to_owned()));
+        }
+
+        let version = b.read8(4)?;
+        let chunks = chunk_list(b)?;
+
+        // Song information
+        let c = match find_chunk(b, &chunks, "IN") {
+            Some(c) => c,
+            None    => return Err(Error::Load("no info chunk".to_owned())),
+        };
+        let title = c.read_string(0, 32)?;
+        let composer = c.read_string(32, 20)?;
+        let num_orders = c.read16l(52)? as usize;
+        let restart = c.read16l(54)?;
+        let global_vol = c.read8(56)?;
+        let speed = c.read8(57)?;
+        let tempo = c.read8(58)?;
+        let mut chn_pan = [0_u8; 32];
+        chn_pan.copy_from_slice(c.slice(59, 32)?);
+        let orders = c.slice(91, num_orders)?.to_vec();
+
+        let message = match find_chunk(b, &chunks, "ME") {
+            Some(c) => c.read_string(0, c.len())?,
+            None    => "".to_owned(),
+        };
+
+        // Patterns
+        let mut patterns = Vec::<MdlPattern>::new();
+        if let Some(c) = find_chunk(b, &chunks, "PA") {
+            let num = c.read8(0)? as usize;
+            let mut ofs = 1;
+            for _ in 0..num {
+                let pat = if version >= 0x10 {
+                    let chn = c.read8(ofs)? as usize;
+                    let rows = c.read8(ofs + 1)? as usize + 1;
+                    let name = c.read_string(ofs + 2, 16)?;
+                    let tracks = (0..chn).map(|i| c.read16l(ofs + 18 + i * 2)).collect::<Result<Vec<u16>, Error>>()?;
+                    ofs += 18 + chn * 2;
+                    MdlPattern{ name, rows, tracks }
+                } else {
+                    let tracks = (0..32).map(|i| c.read16l(ofs + i * 2)).collect::<Result<Vec<u16>, Error>>()?;
+                    ofs += 64;
+                    MdlPattern{ name: "".to_owned(), rows: 64, tracks }
+                };
+                patterns.push(pat);
+            }
+        }
+
+        if let Some(&p) = orders.iter().find(|&&x| x as usize >= patterns.len()) {
+            return Err(Error::Load(format!("invalid pattern {} in orders", p)));
+        }
+
+        // Tracks, track 0 is always empty
+        let mut tracks = vec![MdlTrack::new()];
+        if let Some(c) = find_chunk(b, &chunks, "TR") {
+            let num = c.read16l(0)? as usize;
+            let mut ofs = 2;
+            for _ in 0..num {
+                let size = c.read16l(ofs)? as usize;
+                tracks.push(MdlTrack::from_slice(c.slice(ofs + 2, size)?));
+                ofs += 2 + size;
+            }
+        }
+
+        // Envelopes
+        let vol_env = load_envelopes(b, &chunks, "VE")?;
+        let pan_env = load_envelopes(b, &chunks, "PE")?;
+        let freq_env = load_envelopes(b, &chunks, "FE")?;
+
+        // Sample headers
+        let mut smp_headers = Vec::<MdlSample>::new();
+        let mut smp_order = Vec::<usize>::new();
+        if let Some(c) = find_chunk(b, &chunks, "IS") {
+            let num = c.read8(0)? as usize;
+            let mut ofs = 1;
+            for _ in 0..num {
+                let n = c.read8(ofs)? as usize;
+                if n == 0 {
+                    return Err(Error::Load("invalid sample number".to_owned()));
+                }
+                let mut sh = MdlSample::new();
+                sh.name = c.read_string(ofs + 1, 32)?;
+                sh.filename = c.read_string(ofs + 33, 8)?;
+                ofs += 41;
+                if version >= 0x10 {
+                    sh.c4speed = c.read32l(ofs)?;
+                    ofs += 4;
+                } else {
+                    sh.c4speed = c.read16l(ofs)? as u32;
+                    ofs += 2;
+                }
+                sh.length = c.read32l(ofs)?;
+                sh.loop_start = c.read32l(ofs + 4)?;
+                sh.loop_len = c.read32l(ofs + 8)?;
+                ofs += 12;
+                if version < 0x10 {
+                    sh.volume = c.read8(ofs)?;
+                    ofs += 1;
+                }
+                sh.flags = c.read8(ofs)?;
+                ofs += 1;
+
+                // lengths are stored in bytes
+                if sh.is_16bit() {
+                    sh.length /= 2;
+                    sh.loop_start /= 2;
+                    sh.loop_len /= 2;
+                }
+
+                if smp_headers.len() < n {
+                    smp_headers.resize(n, MdlSample::new());
+                }
+                smp_headers[n - 1] = sh;
+                smp_order.push(n);
+            }
+        }
+
+        // Sample data, stored in the same order as the sample headers
+        let mut samples = (0..smp_headers.len()).map(|i| {
+            let mut smp = Sample::new();
+            smp.num = i + 1;
+            smp.name = smp_headers[i].name.to_owned();
+            smp
+        }).collect::<Vec<Sample>>();
+        if let Some(c) = find_chunk(b, &chunks, "SA") {
+            let base = chunks.iter().find(|x| x.0 == "SA").map_or(0, |x| x.1);
+            let mut ofs = 0;
+            for &n in &smp_order {
+                samples[n - 1].address = (base + ofs) as u32;
+                ofs += load_sample(c, ofs, &smp_headers[n - 1], &mut samples[n - 1])?;
+            }
+        }
+
+        // Instruments. Versions before 1.0 have no instrument chunk and
+        // use sample numbers as instruments.
+        let mut instruments = Vec::<MdlInstrument>::new();
+        match find_chunk(b, &chunks, "II") {
+            Some(c) => {
+                let num = c.read8(0)? as usize;
+                let mut ofs = 1;
+                for _ in 0..num {
+                    let n = c.read8(ofs)? as usize;
+                    let nsmp = c.read8(ofs + 1)? as usize;
+                    let mut ins = MdlInstrument::new();
+                    ins.name = c.read_string(ofs + 2, 32)?;
+                    ofs += 34;
+                    for _ in 0..nsmp {
+                        ins.samples.push(load_sample_map(c, ofs)?);
+                        ofs += 14;
+                    }
+                    if n == 0 {
+                        continue
+                    }
+                    while instruments.len() < n {
+                        instruments.push(MdlInstrument::new());
+                    }
+                    instruments[n - 1] = ins;
+                }
+            },
+            None => {
+                for sh in &smp_headers {
+                    let mut ins = MdlInstrument::new();
+                    ins.name = sh.name.to_owned();
+                    ins.samples.push(MdlSampleMap{
+                        sample   : instruments.len() as u8 + 1,
+                        last_note: 120,
+                        volume   : sh.volume,
+                        vol_env  : 0x40,
+                        pan      : 64,
+                        ..Default::default()
+                    });
+                    instruments.push(ins);
+                }
+            },
+        }
+
+        let channels = cmp::min(32, cmp::max(1, patterns.iter().map(|x| x.tracks.len()).max().unwrap_or(0)));
+
+        let data = MdlData{
+            version,
+            title,
+            composer,
+            restart,
+            global_vol,
+            speed,
+            tempo,
+            chn_pan,
+            orders,
+            message,
+            patterns,
+            tracks,
+            instruments,
+            vol_env,
+            pan_env,
+            freq_env,
+            smp_headers,
+            samples,
+            channels,
+        };
+
+        let m = Module {
+            format_id  : "mdl",
+            description: format!("Digitrakker {}.{} MDL", version >> 4, version & 0x0f),
+            creator    : "Digitrakker".to_owned(),
+            channels,
+            player     : "mdl",
+            data       : Box::new(data),
+        };
+
+        Ok(m)
+    }
+}
+
+// List the offset, size and id of each chunk
+fn chunk_list(b: &[u8]) -> Result<Vec<(String, usize, usize)>, Error> {
+    let mut chunks = Vec::new();
+    let mut ofs = 5;
+    while ofs + 6 <= b.len() {
+        let id = b.read_string(ofs, 2)?;
+        let size = b.read32l(ofs + 2)? as usize;
+        if ofs + 6 + size > b.len() {
+            return Err(Error::Load(format!("truncated {} chunk", id)));
+        }
+        chunks.push((id, ofs + 6, size));
+        ofs += 6 + size;
+    }
+    Ok(chunks)
+}
+
+fn find_chunk<'a>(b: &'a [u8], chunks: &[(String, usize, usize)], id: &str) -> Option<&'a [u8]> {
+    match chunks.iter().find(|x| x.0 == id) {
+        Some(&(_, ofs, size)) => b.get(ofs..ofs + size),
+        None                  => None,
+    }
+}
+
+// Envelopes are stored with their number, 15 nodes with the tick delta
+// from the previous node and the node value, flags and loop points.
+fn load_envelopes(b: &[u8], chunks: &[(String, usize, usize)], id: &str) -> Result<Vec<MdlEnvelope>, Error> {
+    let mut env = vec![MdlEnvelope::new(); 64];
+
+    if let Some(c) = find_chunk(b, chunks, id) {
+        let num = c.read8(0)? as usize;
+        for i in 0..num {
+            let ofs = 1 + i * 33;
+            let n = c.read8(ofs)? as usize & 0x3f;
+            let e = &mut env[n];
+            let mut tick = 0;
+            for j in 0..15 {
+                let x = c.read8(ofs + 1 + j * 2)? as u16;
+                if j > 0 && x == 0 {
+                    break
+                }
+                tick += x;
+                e.node.push((tick, c.read8(ofs + 2 + j * 2)? & 0x3f));
+            }
+            e.flags = c.read8(ofs + 31)?;
+            let lp = c.read8(ofs + 32)?;
+            e.lpb = lp & 0x0f;
+            e.lpe = lp >> 4;
+        }
+    }
+
+    Ok(env)
+}
+
+fn load_sample_map(b: &[u8], ofs: usize) -> Result<MdlSampleMap, Error> {
+    Ok(MdlSampleMap{
+        sample   : b.read8(ofs)?,
+        last_note: b.read8(ofs + 1)?,
+        volume   : b.read8(ofs + 2)?,
+        vol_env  : b.read8(ofs + 3)?,
+        pan      : b.read8(ofs + 4)?,
+        pan_env  : b.read8(ofs + 5)?,
+        fadeout  : b.read16l(ofs + 6)?,
+        vib_speed: b.read8(ofs + 8)?,
+        vib_depth: b.read8(ofs + 9)?,
+        vib_sweep: b.read8(ofs + 10)?,
+        vib_type : b.read8(ofs + 11)?,
+        freq_env : b.read8(ofs + 13)?,
+    })
+}
+
+// Load sample data and return the number of bytes used
+fn load_sample(b: &[u8], ofs: usize, sh: &MdlSample, smp: &mut Sample) -> Result<usize, Error> {
+    if sh.length == 0 {
+        return Ok(0)
+    }
+
+    smp.size = sh.length;
+    smp.rate = 1.0;
+    smp.loop_bidir = sh.flags & MDL_SMP_BIDI != 0;
+
+    let len = sh.length as usize;
+    let used = match sh.pack_type() {
+        0 => {
+            if sh.is_16bit() {
+                smp.sample_type = SampleType::Sample16;
+                smp.store(b.slice(ofs, len * 2)?);
+                len * 2
+            } else {
+                smp.sample_type = SampleType::Sample8;
+                smp.store(b.slice(ofs, len)?);
+                len
+            }
+        },
+        1 => {
+            let size = b.read32l(ofs)? as usize;
+            let buf = unpack::unpack8(b.slice(ofs + 4, size)?, len)?;
+            smp.sample_type = SampleType::Sample8;
+            smp.store(&buf[..]);
+            size + 4
+        },
+        2 => {
+            let size = b.read32l(ofs)? as usize;
+            let buf = unpack::unpack16(b.slice(ofs + 4, size)?, len)?;
+            let buf = buf.iter().map(|&x| x as u16).collect::<Vec<u16>>();
+            smp.sample_type = SampleType::Sample16;
+            smp.store(&buf[..].as_slice_u8());
+            size + 4
+        },
+        _ => return Err(Error::Load(format!("unsupported sample packing {}", sh.pack_type()))),
+    };
+
+    Ok(used)
+}
+
+
+// Digitrakker 1.1 module used by the loader and player tests
+#[cfg(test)]
+pub fn mdl_module() -> Vec<u8> {
+    use util::BinaryWrite;
+
+    fn chunk(b: &mut Vec<u8>, id: &str, data: &[u8]) {
+        b.write_string(id, 2);
+        b.write32l(data.len() as u32);
+        b.extend_from_slice(data);
+    }
+
+    let mut b: Vec<u8> = Vec::new();
+    b.write_string("DMDL", 4);
+    b.write8(0x11);
+
+    let mut c: Vec<u8> = Vec::new();
+    c.write_string("test", 32);
+    c.write_string("composer", 20);
+    c.write16l(2);                      // orders
+    c.write16l(0);                      // restart
+    c.write8(255);                      // global volume
+    c.write8(6);                        // speed
+    c.write8(125);                      // tempo
+    c.extend_from_slice(&[64; 2]);      // channel pan
+    c.extend_from_slice(&[0x80; 30]);
+    c.extend_from_slice(&[0, 1]);
+    chunk(&mut b, "IN", &c);
+
+    let mut c: Vec<u8> = Vec::new();
+    c.write8(2);
+    for &(rows, t) in &[(63, 1), (15, 2)] {
+        c.write8(2);
+        c.write8(rows);
+        c.write_string("pattern", 16);
+        c.write16l(t);
+        c.write16l(0);
+    }
+    chunk(&mut b, "PA", &c);
+
+    // track 1: C-4 instrument 1, porta up 2 in the first column and
+    // volume slide down 8 in the second column
+    // track 2: key off in row 2
+    let mut c: Vec<u8> = Vec::new();
+    c.write16l(2);
+    c.write16l(7);
+    c.extend_from_slice(&[0xff, 49, 1, 0xc0, 0x21, 0x02, 0x08]);
+    c.write16l(3);
+    c.extend_from_slice(&[0x04, 0x03 | 0x01 << 2, 255]);
+    chunk(&mut b, "TR", &c);
+
+    let mut c: Vec<u8> = Vec::new();
+    c.write8(1);
+    c.write8(1);                        // instrument number
+    c.write8(1);                        // samples
+    c.write_string("instrument", 32);
+    c.extend_from_slice(&[1, 120, 255, 0x80, 64, 0, 0, 1, 0, 0, 0, 0, 0, 0x80]);
+    chunk(&mut b, "II", &c);
+
+    // volume envelope 0: 64 to 32 in 4 ticks
+    let mut c: Vec<u8> = Vec::new();
+    c.write8(1);
+    c.write8(0);
+    c.extend_from_slice(&[0, 63, 4, 32]);
+    c.extend_from_slice(&[0; 26]);
+    c.write8(0);
+    c.write8(0);
+    chunk(&mut b, "VE", &c);
+
+    // frequency envelope 0: up 4 semitones in 4 ticks
+    let mut c: Vec<u8> = Vec::new();
+    c.write8(1);
+    c.write8(0);
+    c.extend_from_slice(&[0, 32, 4, 48]);
+    c.extend_from_slice(&[0; 26]);
+    c.write8(0);
+    c.write8(0);
+    chunk(&mut b, "FE", &c);
+
+    let mut c: Vec<u8> = Vec::new();
+    c.write8(1);
+    c.write8(1);
+    c.write_string("sample", 32);
+    c.write_string("sample", 8);
+    c.write32l(8363);
+    c.write32l(16);                     // length
+    c.write32l(0);
+    c.write32l(16);                     // loop length
+    c.write8(0x04);                     // 8-bit packed
+    chunk(&mut b, "IS", &c);
+
+    // 16 deltas of +5 (sign 0, short 1, value 5)
+    let mut c: Vec<u8> = Vec::new();
+    c.write32l(10);
+    c.extend_from_slice(&[0xd6, 0x5a, 0x6b, 0xad, 0xb5, 0xd6, 0x5a, 0x6b, 0xad, 0xb5]);
+    chunk(&mut b, "SA", &c);
+
+    b
+}
+
+
+#[cfg(test)]
+mod tests {
+    use format;
+    use format::mdl::MdlData;
+    use super::mdl_module;
+
+    #[test]
+    fn test_load_mdl() {
+        let b = mdl_module();
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "mdl");
+        assert_eq!(m.description, "Digitrakker 1.1 MDL");
+        assert_eq!(m.title().trim_end(), "test");
+        assert_eq!(m.channels, 2);
+        assert_eq!(m.len(), 2);
+        assert_eq!(m.rows(1), 16);
+
+        let data = m.data.as_any().downcast_ref::<MdlData>().unwrap();
+        let e = data.event(0, 0, 0);
+        assert_eq!((e.note, e.ins, e.vol, e.e1, e.e2, e.p1, e.p2), (49, 1, 0xc0, 1, 2, 0x02, 0x08));
+        assert_eq!(data.event(1, 2, 0).note, 255);
+        assert_eq!(data.vol_env[0].node, vec![(0, 63), (4, 32)]);
+        assert_eq!(data.instruments[0].map(49).unwrap().sample, 1);
+        assert_eq!(data.samples[0].size, 16);
+        assert_eq!((data.samples[0].data[1], data.samples[0].data[3]), (10, 20));
+    }
+
+    #[test]
+    fn test_load_truncated_mdl() {
+        let b = mdl_module();
+        for size in 0..b.len() {
+            if let Ok(m) = format::load(&b[..size], "") {
+                for pos in 0..m.len() {
+                    assert!(m.pattern_in_position(pos).unwrap() < m.patterns());
+                }
+            }
+        }
+        assert!(format::load(&b[..110], "").is_err());
+    }
+}
@@ -0,0 +1,360 @@
+pub mod load;
+mod unpack;
+
+pub use self::load::*;
+
+use std::any::Any;
+use std::fmt;
+use module::{event, ModuleData, Sample};
+use util::NOTES;
+
+// Digitrakker modules start with the "DMDL" magic and a version byte,
+// followed by chunks made of a 2-character id and a 32-bit length:
+//
+//  IN: song information, orders and channel settings
+//  ME: song message
+//  PA: patterns, a list of track numbers for each channel
+//  TR: packed tracks
+//  II: instruments, mapping note ranges to samples
+//  VE, PE, FE: volume, pan and frequency envelopes
+//  IS: sample headers
+//  SA: sample data, raw or packed
+
+pub const MDL_NOTE_OFF: u8 = 255;
+
+pub struct MdlData {
+    pub version    : u8,
+    pub title      : String,
+    pub composer   : String,
+    pub restart    : u16,
+    pub global_vol : u8,
+    pub speed      : u8,
+    pub tempo      : u8,
+    pub chn_pan    : [u8; 32],
+    pub orders     : Vec<u8>,
+    pub message    : String,
+    pub patterns   : Vec<MdlPattern>,
+    pub tracks     : Vec<MdlTrack>,
+    pub instruments: Vec<MdlInstrument>,   // indexed by instrument number - 1
+    pub vol_env    : Vec<MdlEnvelope>,     // indexed by envelope number
+    pub pan_env    : Vec<MdlEnvelope>,
+    pub freq_env   : Vec<MdlEnvelope>,
+    pub smp_headers: Vec<MdlSample>,       // indexed by sample number - 1
+    pub samples    : Vec<Sample>,
+
+    pub channels   : usize,
+}
+
+impl MdlData {
+    /// The event played by channel `chn` in a pattern row.
+    pub fn event(&self, pat: usize, row: usize, chn: usize) -> MdlEvent {
+        let p = &self.patterns[pat];
+        match p.tracks.get(chn) {
+            Some(&t) if (t as usize) < self.tracks.len() => self.tracks[t as usize].event(row),
+            _ => MdlEvent::new(),
+        }
+    }
+}
+
+impl ModuleData for MdlData {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn title(&self) -> &str {
+        &self.title
+    }
+
+    fn patterns(&self) -> usize {
+        self.patterns.len()
+    }
+
+    fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    fn pattern_in_position(&self, pos: usize) -> Option<usize> {
+        if pos >= self.orders.len() {
+            None
+        } else {
+            Some(self.orders[pos] as usize)
+        }
+    }
+
+    fn instruments(&self) -> Vec<String> {
+        self.instruments.iter().map(|x| x.name.to_owned()).collect::<Vec<String>>()
+    }
+
+    fn rows(&self, pat: usize) -> usize {
+        if pat >= self.patterns.len() {
+            0
+        } else {
+            self.patterns[pat].rows
+        }
+    }
+
+    // Only the first effect column is shown.
+    fn pattern_data(&self, pat: usize, num: usize, buffer: &mut [u8]) -> usize {
+        let rows = self.rows(pat);
+        let mut i = 0;
+        for _ in 0..num {
+            let (row, ch) = (i / self.channels, i % self.channels);
+            if row >= rows {
+                break
+            }
+            let ofs = i * 6;
+            let e = self.event(pat, row, ch);
+
+            let mut flags = 0;
+            if e.note != 0 { flags |= event::HAS_NOTE; buffer[ofs+1] = e.note }
+            if e.ins  != 0 { flags |= event::HAS_INS ; buffer[ofs+2] = e.ins }
+            if e.vol  != 0 { flags |= event::HAS_VOL ; buffer[ofs+3] = e.vol }
+            if e.e1 != 0 || e.p1 != 0 { flags |= event::HAS_CMD; buffer[ofs+4] = e.e1; buffer[ofs+5] = e.p1 }
+            buffer[ofs] = flags;
+
+            i += 1;
+        }
+        i
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        self.samples.to_owned()
+    }
+}
+
+
+pub struct MdlPattern {
+    pub name  : String,
+    pub rows  : usize,
+    pub tracks: Vec<u16>,
+}
+
+
+/// MdlEvent defines a track event. Each event has two effect columns, the
+/// first one with pitch effects and the second one with volume effects.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MdlEvent {
+    pub note: u8,
+    pub ins : u8,
+    pub vol : u8,
+    pub e1  : u8,
+    pub e2  : u8,
+    pub p1  : u8,
+    pub p2  : u8,
+}
+
+impl MdlEvent {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl fmt::Display for MdlEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let note = match self.note {
+            0            => "---".to_owned(),
+            MDL_NOTE_OFF => "===".to_owned(),
+            n            => format!("{}{}", NOTES[(n as usize - 1) % 12], (n as usize - 1) / 12),
+        };
+
+        let ins = if self.ins == 0 {
+            "--".to_owned()
+        } else {
+            format!("{:02X}", self.ins)
+        };
+
+        let vol = if self.vol == 0 {
+            "--".to_owned()
+        } else {
+            format!("{:02X}", self.vol)
+        };
+
+        write!(f, "{} {} {} {:X}{:X}{:02X}{:02X}", note, ins, vol, self.e1, self.e2, self.p1, self.p2)
+    }
+}
+
+
+/// MdlTrack holds the unpacked events of a track. Track 0 is always empty
+/// and is not stored in the module file.
+pub struct MdlTrack {
+    events: Vec<MdlEvent>,
+}
+
+impl MdlTrack {
+    pub fn new() -> Self {
+        MdlTrack{ events: Vec::new() }
+    }
+
+    // Each row starts with a byte with the command in the low 2 bits and
+    // the command argument in the high 6 bits.
+    fn from_slice(b: &[u8]) -> Self {
+        let mut events = Vec::<MdlEvent>::new();
+        let mut i = 0;
+
+        while i < b.len() && events.len() < 256 {
+            let x = (b[i] >> 2) as usize;
+            let cmd = b[i] & 0x03;
+            i += 1;
+
+            match cmd {
+                0 => {  // skip x+1 empty rows
+                    for _ in 0..x + 1 {
+                        events.push(MdlEvent::new());
+                    }
+                },
+                1 => {  // repeat the previous event x+1 times
+                    let e = events.last().cloned().unwrap_or_default();
+                    for _ in 0..x + 1 {
+                        events.push(e);
+                    }
+                },
+                2 => {  // copy the event in row x
+                    let e = events.get(x).cloned().unwrap_or_default();
+                    events.push(e);
+                },
+                _ => {  // new event, x has the fields present
+                    let mut e = MdlEvent::new();
+                    let mut read = |flag: usize| -> u8 {
+                        if x & flag != 0 && i < b.len() {
+                            i += 1;
+                            b[i - 1]
+                        } else {
+                            0
+                        }
+                    };
+                    e.note = read(0x01);
+                    e.ins = read(0x02);
+                    e.vol = read(0x04);
+                    let fx = read(0x08);
+                    e.e1 = fx & 0x0f;
+                    e.e2 = fx >> 4;
+                    e.p1 = read(0x10);
+                    e.p2 = read(0x20);
+                    events.push(e);
+                },
+            }
+        }
+
+        MdlTrack{ events }
+    }
+
+    pub fn event(&self, row: usize) -> MdlEvent {
+        self.events.get(row).cloned().unwrap_or_default()
+    }
+}
+
+
+pub const MDL_ENV_SUSTAIN: u8 = 0x10;
+pub const MDL_ENV_LOOP   : u8 = 0x20;
+
+/// MdlEnvelope defines a volume, pan or frequency envelope. Node values
+/// range from 0 to 63, pan and frequency envelopes are centered at 32.
+#[derive(Debug, Default, Clone)]
+pub struct MdlEnvelope {
+    pub node : Vec<(u16, u8)>,  // (tick, y)
+    pub flags: u8,
+    pub lpb  : u8,
+    pub lpe  : u8,
+}
+
+impl MdlEnvelope {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn sustain(&self) -> Option<usize> {
+        if self.flags & MDL_ENV_SUSTAIN != 0 { Some((self.flags & 0x0f) as usize) } else { None }
+    }
+
+    pub fn has_loop(&self) -> bool {
+        self.flags & MDL_ENV_LOOP != 0 && (self.lpb as usize) < self.node.len()
+    }
+}
+
+
+/// MdlSampleMap assigns a sample and its playback parameters to a note
+/// range of an instrument.
+#[derive(Debug, Default, Clone)]
+pub struct MdlSampleMap {
+    pub sample   : u8,
+    pub last_note: u8,
+    pub volume   : u8,
+    pub vol_env  : u8,      // bit 7: envelope on, bit 6: use volume
+    pub pan      : u8,
+    pub pan_env  : u8,      // bit 7: envelope on, bit 6: use pan
+    pub fadeout  : u16,
+    pub vib_speed: u8,
+    pub vib_depth: u8,
+    pub vib_sweep: u8,
+    pub vib_type : u8,
+    pub freq_env : u8,      // bit 7: envelope on
+}
+
+#[derive(Debug, Default)]
+pub struct MdlInstrument {
+    pub name   : String,
+    pub samples: Vec<MdlSampleMap>,
+}
+
+impl MdlInstrument {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The sample mapping used to play a note.
+    pub fn map(&self, note: u8) -> Option<&MdlSampleMap> {
+        self.samples.iter().find(|x| note <= x.last_note)
+    }
+}
+
+
+pub const MDL_SMP_16BIT   : u8 = 0x01;
+pub const MDL_SMP_BIDI    : u8 = 0x02;
+
+#[derive(Debug, Default, Clone)]
+pub struct MdlSample {
+    pub name      : String,
+    pub filename  : String,
+    pub c4speed   : u32,
+    pub length    : u32,
+    pub loop_start: u32,
+    pub loop_len  : u32,
+    pub volume    : u8,
+    pub flags     : u8,
+}
+
+impl MdlSample {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_16bit(&self) -> bool {
+        self.flags & MDL_SMP_16BIT != 0
+    }
+
+    /// Sample packing: 0 for raw data, 1 for 8-bit and 2 for 16-bit packed data.
+    pub fn pack_type(&self) -> u8 {
+        (self.flags >> 2) & 0x03
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track() {
+        // C-4 with instrument 1 and both effects, 2 empty rows, repeat,
+        // copy row 0
+        let t = MdlTrack::from_slice(&[0xff, 49, 1, 0xc0, 0x21, 0x10, 0x20, 0x04, 0x01, 0x02]);
+        let e = t.event(0);
+        assert_eq!((e.note, e.ins, e.vol, e.e1, e.e2, e.p1, e.p2), (49, 1, 0xc0, 1, 2, 0x10, 0x20));
+        assert_eq!(format!("{}", e), "C 4 01 C0 121020");
+        assert_eq!(t.event(1), MdlEvent::new());
+        assert_eq!(t.event(2), MdlEvent::new());
+        assert_eq!(t.event(3), MdlEvent::new());
+        assert_eq!(t.event(4), e);
+        assert_eq!(t.event(100), MdlEvent::new());
+    }
+}
@@ -0,0 +1,129 @@
+// Digitrakker packed sample decompression
+//
+// Packed samples are stored as a bitstream of sign-magnitude deltas, read
+// LSB-first. Each delta starts with a sign bit followed by a flag: if set,
+// the magnitude is stored in 3 bits, otherwise it is 8 plus 16 for each
+// zero bit until a set bit, plus 4 more bits. 16-bit samples store the
+// low byte verbatim before the delta of the high byte.
+
+use ::*;
+
+struct BitReader<'a> {
+    b   : &'a [u8],
+    pos : usize,
+    buf : u32,
+    num : u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(b: &'a [u8]) -> Self {
+        BitReader {
+            b,
+            pos: 0,
+            buf: 0,
+            num: 0,
+        }
+    }
+
+    fn read_bits(&mut self, n: u32) -> Result<u32, Error> {
+        let mut val = 0_u32;
+        for i in 0..n {
+            if self.num == 0 {
+                if self.pos >= self.b.len() {
+                    return Err(Error::Load("packed sample truncated".to_owned()))
+                }
+                self.buf = self.b[self.pos] as u32;
+                self.pos += 1;
+                self.num = 8;
+            }
+            val |= (self.buf & 1) << i;
+            self.buf >>= 1;
+            self.num -= 1;
+        }
+        Ok(val)
+    }
+
+    fn read_delta(&mut self) -> Result<u8, Error> {
+        let sign = self.read_bits(1)?;
+        let mut val = if self.read_bits(1)? != 0 {
+            self.read_bits(3)?
+        } else {
+            let mut v = 8;
+            while self.read_bits(1)? == 0 {
+                v += 0x10;
+            }
+            v + self.read_bits(4)?
+        } as u8;
+        if sign != 0 {
+            val = !val;
+        }
+        Ok(val)
+    }
+}
+
+pub fn unpack8(b: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    let mut br = BitReader::new(b);
+    let mut d = 0_u8;
+
+    for _ in 0..len {
+        d = d.wrapping_add(br.read_delta()?);
+        out.push(d);
+    }
+
+    Ok(out)
+}
+
+pub fn unpack16(b: &[u8], len: usize) -> Result<Vec<i16>, Error> {
+    let mut out: Vec<i16> = Vec::with_capacity(len);
+    let mut br = BitReader::new(b);
+    let mut d = 0_u8;
+
+    for _ in 0..len {
+        let lo = br.read_bits(8)? as u16;
+        d = d.wrapping_add(br.read_delta()?);
+        out.push(((d as u16) << 8 | lo) as i16);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pack values LSB-first with the given bit widths
+    fn pack(vals: &[(u32, u32)]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut acc = 0_u64;
+        let mut num = 0;
+        for &(val, width) in vals {
+            acc |= (val as u64) << num;
+            num += width;
+            while num >= 8 {
+                out.push(acc as u8);
+                acc >>= 8;
+                num -= 8;
+            }
+        }
+        if num > 0 {
+            out.push(acc as u8);
+        }
+        out
+    }
+
+    #[test]
+    fn test_unpack8() {
+        // +5 (short), +0x2a (8 + 2*16 + 2), -3 (sign, short 2)
+        let b = pack(&[(0, 1), (1, 1), (5, 3), (0, 1), (0, 1), (0, 1), (0, 1), (1, 1), (2, 4), (1, 1), (1, 1), (2, 3)]);
+        assert_eq!(unpack8(&b, 3).unwrap(), vec![5, 0x2f, 0x2c]);
+        assert!(unpack8(&b, 4).is_err());
+    }
+
+    #[test]
+    fn test_unpack16() {
+        // low byte 0x34, high byte delta +1
+        let b = pack(&[(0x34, 8), (0, 1), (1, 1), (1, 3), (0x78, 8), (1, 1), (1, 1), (0, 3)]);
+        assert_eq!(unpack16(&b, 2).unwrap(), vec![0x0134, 0x0078]);
+    }
+}
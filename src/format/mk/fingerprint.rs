@@ -19,6 +19,7 @@ pub enum TrackerID {
     ConvertedST,
     UnknownOrConverted,
     ProtrackerClone,
+    Startrekker,
 }
 
 struct Magic {
@@ -29,7 +30,7 @@ struct Magic {
 }
 
 lazy_static! {
-    static ref MAGIC: Box<[Magic; 13]> = Box::new([
+    static ref MAGIC: Box<[Magic; 15]> = Box::new([
         Magic{magic:"M.K.", flag:false, id:TrackerID::Protracker,     ch:4},
         Magic{magic:"M!K!", flag:true,  id:TrackerID::Protracker,     ch:4},
         Magic{magic:"M&K!", flag:true,  id:TrackerID::Noisetracker,   ch:4},
@@ -43,6 +44,8 @@ lazy_static! {
         Magic{magic:"FA06", flag:true,  id:TrackerID::DigitalTracker, ch:6},  // Atari Falcon
         Magic{magic:"FA08", flag:true,  id:TrackerID::DigitalTracker, ch:8},  // Atari Falcon
         Magic{magic:"NSMS", flag:true,  id:TrackerID::Unknown,        ch:4},  // in Kingdom.mod
+        Magic{magic:"FLT4", flag:true,  id:TrackerID::Startrekker,    ch:4},
+        Magic{magic:"FLT8", flag:true,  id:TrackerID::Startrekker,    ch:8},
     ]);
 
     static ref STANDARD_NOTES: Box<[u16; 36]> = Box::new([
@@ -36,8 +36,11 @@ impl Loader for ModLoader {
             } else {
                 Err(Error::Format(format!("bad magic {:?}", magic)))
             }
-        } else if magic == magic4!('F','L','T','4') || magic == magic4!('F','L','T','8') {
-            player::check_accepted(player_id, "flt")?;
+        } else if magic == magic4!('F','L','T','4') {
+            player::check_accepted(player_id, "m.k.")?;
+            Ok(ProbeInfo{format: Format::Flt, title: b.read_string(0, 20)?})
+        } else if magic == magic4!('F','L','T','8') {
+            player::check_accepted(player_id, "xchn")?;
             Ok(ProbeInfo{format: Format::Flt, title: b.read_string(0, 20)?})
         } else {
             Err(Error::Format(format!("bad magic {:?}", magic)))
@@ -46,7 +49,7 @@ impl Loader for ModLoader {
 
     fn load(self: Box<Self>, b: &[u8], info: ProbeInfo) -> Result<Module, Error> {
 
-        if info.format != Format::Mk && info.format != Format::Xchn && info.format != Format::Xxch {
+        if info.format != Format::Mk && info.format != Format::Xchn && info.format != Format::Xxch && info.format != Format::Flt {
             return Err(Error::Format("unsupported format".to_owned()));
         }
 
@@ -65,11 +68,19 @@ impl Loader for ModLoader {
         // Load orders
         let song_length = b.read8(950)?;
         let restart = b.read8(951)?;
-        let orders = b.slice(952, 128)?;
+        let mut orders = [0; 128];
+        orders.copy_from_slice(b.slice(952, 128)?);
         let magic = b.read_string(1080, 4)?;
 
         let mut chn = channels_from_magic(&magic);
 
+        // Startrekker 8-channel patterns are stored as two 4-channel patterns,
+        // and orders refer to the first pattern of the pair
+        let flt8 = magic == "FLT8";
+        if flt8 {
+            orders.iter_mut().for_each(|x| *x >>= 1);
+        }
+
         let mut pat = 0;
         orders[..song_length as usize].iter().for_each(|x| { pat = cmp::max(pat, *x as usize); } );
         pat += 1;
@@ -102,7 +113,11 @@ impl Loader for ModLoader {
         }
 
         // Load patterns
-        let patterns = ModPatterns::from_slice(pat, b.slice(1084, 256*chn*pat)?, chn)?;
+        let patterns = if flt8 {
+            ModPatterns::from_slice(pat, &flt8_patterns(b.slice(1084, 256*chn*pat)?, pat), chn)?
+        } else {
+            ModPatterns::from_slice(pat, b.slice(1084, 256*chn*pat)?, chn)?
+        };
 
         // Load samples
         let mut ofs = 1084 + 256*chn*pat;
@@ -113,19 +128,17 @@ impl Loader for ModLoader {
             ofs += size;
         }
 
-        let mut data = ModData{
+        let data = ModData{
             song_name,
             instruments,
             song_length,
             restart,
-            orders: [0; 128],
+            orders,
             magic : magic.clone(),
             patterns,
             samples,
         };
 
-        data.orders.copy_from_slice(orders);
-
         if tracker_id == TrackerID::Unknown {
             tracker_id = Fingerprint::id(&data)
         }
@@ -148,6 +161,7 @@ impl Loader for ModLoader {
             TrackerID::ConvertedST        => ("Converted 15-ins", "nt"),
             TrackerID::UnknownOrConverted => ("Unknown tracker",  "pt2"),
             TrackerID::ProtrackerClone    => ("Protracker clone", "pt2"),
+            TrackerID::Startrekker        => ("Startrekker",      "pt2"),
         };
 
         debug!("Tracker: {} => player: {}", creator, player_id);
@@ -163,15 +177,26 @@ impl Loader for ModLoader {
 
         // set format ID
         let mut format_id = "m.k.";
-        if tracker_id == TrackerID::FastTracker {
+        if tracker_id == TrackerID::FastTracker || tracker_id == TrackerID::Startrekker {
             if chn == 6 || chn == 8 {
                 format_id = "xchn";
             }
         }
 
+        // Startrekker AM synth instruments are stored in the companion .nt file
+        // and have no sample data in the module, so they're played as silence
+        let mut description = format!("{} module ", magic);
+        if tracker_id == TrackerID::Startrekker {
+            let am = unsampled_instruments(&data, chn);
+            if !am.is_empty() {
+                debug!("AM synth instruments not supported: {:?}", am);
+                description = format!("{} module ({} AM synth instruments not supported)", magic, am.len());
+            }
+        }
+
         let m = Module {
             format_id,
-            description: format!("{} module ", magic),
+            description,
             creator    : creator.to_owned(),
             channels   : chn,
             player     : player_id,
@@ -212,6 +237,37 @@ fn load_sample(b: &[u8], ofs: usize, i: usize, ins: &ModInstrument) -> Sample {
     smp
 }
 
+fn flt8_patterns(b: &[u8], pat: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(b.len());
+    for p in 0..pat {
+        for r in 0..64 {
+            for half in 0..2 {
+                let ofs = (p*2 + half) * 1024 + r * 16;
+                data.extend_from_slice(&b[ofs..ofs+16]);
+            }
+        }
+    }
+    data
+}
+
+// Find instruments used in patterns that have no sample data
+fn unsampled_instruments(data: &ModData, chn: usize) -> Vec<usize> {
+    let mut list = Vec::new();
+    for p in 0..data.patterns.num() {
+        for r in 0..64 {
+            for c in 0..chn {
+                let e = data.patterns.event(p, r, c);
+                let ins = (((e.note&0xf000) >> 8) as u8 | (e.cmd&0xf0) >> 4) as usize;
+                if ins > 0 && data.instruments[ins - 1].size == 0 && !list.contains(&ins) {
+                    list.push(ins);
+                }
+            }
+        }
+    }
+    list.sort();
+    list
+}
+
 fn channels_from_magic(magic: &str) -> usize {
     if magic == "FLT8" {
         8
@@ -226,3 +282,63 @@ fn channels_from_magic(magic: &str) -> usize {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use format;
+    use format::mk::ModData;
+    use util::BinaryWrite;
+
+    // Startrekker module with the given number of 4 channel patterns,
+    // events store the pattern number and the event index
+    fn flt_module(magic: &str, num: u8) -> Vec<u8> {
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string("test", 20);
+        for _ in 0..31 {
+            b.write_string("", 22);
+            b.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        }
+        b.write8(2);
+        b.write8(127);
+        b.write8(2);
+        b.write8(0);
+        b.extend_from_slice(&[0; 126]);
+        b.write_string(magic, 4);
+        for p in 0..num {
+            for r in 0..64 {
+                for c in 0..4 {
+                    b.extend_from_slice(&[0, 0, p, (r * 4 + c) as u8]);
+                }
+            }
+        }
+        b
+    }
+
+    #[test]
+    fn test_load_flt4() {
+        let b = flt_module("FLT4", 3);
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "m.k.");
+        assert_eq!(m.channels, 4);
+        assert_eq!(m.creator, "Startrekker");
+        let data = m.data.as_any().downcast_ref::<ModData>().unwrap();
+        assert_eq!(&data.orders[..2], &[2, 0]);
+        assert_eq!(data.patterns.num(), 3);
+        let e = data.patterns.event(2, 10, 2);
+        assert_eq!((e.cmd, e.cmdlo), (2, 42));
+    }
+
+    #[test]
+    fn test_load_flt8() {
+        let b = flt_module("FLT8", 4);
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "xchn");
+        assert_eq!(m.channels, 8);
+        let data = m.data.as_any().downcast_ref::<ModData>().unwrap();
+        assert_eq!(&data.orders[..2], &[1, 0]);
+        assert_eq!(data.patterns.num(), 2);
+        let e = data.patterns.event(1, 10, 6);
+        assert_eq!((e.cmd, e.cmdlo), (3, 42));
+    }
+}
@@ -1,6 +1,8 @@
 pub mod load;
+pub mod save;
 
 pub use self::load::*;
+pub use self::save::*;
 
 use std::any::Any;
 use module::{event, ModuleData, Sample};
@@ -0,0 +1,66 @@
+use format::Format;
+use format::mk::ModData;
+use util::BinaryWrite;
+use ::*;
+
+/// Write a Protracker module. `Format::Mk` keeps the original 4-channel magic,
+/// `Format::Xchn` and `Format::Xxch` write the FastTracker channel count ID.
+pub fn save(data: &ModData, fmt: Format) -> Result<Vec<u8>, Error> {
+    let chn = data.patterns.chn;
+
+    let magic = match fmt {
+        Format::Mk => {
+            if chn != 4 {
+                return Err(Error::Format(format!("can't save {} channels as M.K.", chn)));
+            }
+            match data.magic.as_ref() {
+                "M.K." | "M!K!" | "M&K!" | "NSMS" => data.magic.to_owned(),
+                _                                 => "M.K.".to_owned(),
+            }
+        },
+        Format::Xchn | Format::Xxch => {
+            if chn < 10 && fmt == Format::Xchn {
+                format!("{}CHN", chn)
+            } else if chn < 100 {
+                format!("{:02}CH", chn)
+            } else {
+                return Err(Error::Format(format!("can't save {} channels", chn)));
+            }
+        },
+        _ => return Err(Error::Format("unsupported format".to_owned())),
+    };
+
+    let mut b: Vec<u8> = Vec::new();
+
+    b.write_string(data.song_name.trim_end(), 20);
+
+    for ins in &data.instruments {
+        b.write_string(ins.name.trim_end(), 22);
+        b.write16b(ins.size);
+        b.write8(ins.finetune);
+        b.write8(ins.volume);
+        b.write16b(ins.repeat);
+        b.write16b(ins.replen);
+    }
+
+    b.write8(data.song_length);
+    b.write8(data.restart);
+    b.extend_from_slice(&data.orders);
+    b.write_string(&magic, 4);
+
+    for e in &data.patterns.data {
+        b.write16b(e.note);
+        b.write8(e.cmd);
+        b.write8(e.cmdlo);
+    }
+
+    for (ins, smp) in data.instruments.iter().zip(&data.samples) {
+        let size = ins.size as usize * 2;
+        let raw = smp.data.as_slice_u8();
+        let n = if raw.len() < size { raw.len() } else { size };
+        b.extend_from_slice(&raw[..n]);
+        b.extend(std::iter::repeat(0).take(size - n));
+    }
+
+    Ok(b)
+}
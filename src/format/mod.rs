@@ -7,6 +7,13 @@ pub mod stm;
 pub mod s3m;
 pub mod xm;
 pub mod fest;
+pub mod it;
+pub mod mtm;
+pub mod c669;
+pub mod mdl;
+pub mod imf;
+pub mod sfx;
+pub mod convert;
 
 // Supported formats
 
@@ -22,6 +29,13 @@ pub enum Format {
     S3m,
     Stm,
     Xm,
+    It,
+    Mtm,
+    C669,
+    Mdl,
+    Imf,
+    Sfx,
+    Sfx31,
 }
 
 pub struct ProbeInfo {
@@ -44,8 +58,14 @@ pub trait Loader: Sync {
 fn loader_list() -> Vec<Box<Loader>> {
     vec![
         Box::new(xm::XmLoader),
+        Box::new(it::ItLoader),
         Box::new(s3m::S3mLoader),
         Box::new(stm::StmLoader),
+        Box::new(mtm::MtmLoader),
+        Box::new(c669::C669Loader),
+        Box::new(mdl::MdlLoader),
+        Box::new(imf::ImfLoader),
+        Box::new(sfx::SfxLoader),
         Box::new(mk::ModLoader),
         Box::new(st::StLoader),
         Box::new(fest::FestLoader),
@@ -72,3 +92,133 @@ pub fn load(b: &[u8], player_id: &str) -> Result<Module, Error> {
 
     Err(Error::Format("unsupported module format".to_owned()))
 }
+
+pub fn save(module: &Module, fmt: Format) -> Result<Vec<u8>, Error> {
+
+    let data = module.data.as_any();
+
+    debug!("Save format {:?}", fmt);
+
+    match fmt {
+        Format::Mk | Format::Xchn | Format::Xxch => match data.downcast_ref::<mk::ModData>() {
+            Some(d) => mk::save(d, fmt),
+            None    => Err(Error::Format(format!("can't save {} module as {:?}", module.format_id, fmt))),
+        },
+        Format::S3m => match data.downcast_ref::<s3m::S3mData>() {
+            Some(d) => s3m::save(d),
+            None    => Err(Error::Format(format!("can't save {} module as {:?}", module.format_id, fmt))),
+        },
+        Format::Xm => match data.downcast_ref::<xm::XmData>() {
+            Some(d) => xm::save(d),
+            None    => Err(Error::Format(format!("can't save {} module as {:?}", module.format_id, fmt))),
+        },
+        _ => Err(Error::Format(format!("unsupported save format {:?}", fmt))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::BinaryWrite;
+
+    fn round_trip(b: &[u8], fmt: Format) {
+        let m = load(b, "").unwrap();
+        let out = save(&m, fmt).unwrap();
+        assert_eq!(&out[..], b);
+    }
+
+    #[test]
+    fn test_save_mod() {
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string("test", 20);
+        b.write_string("sample", 22);
+        b.write16b(8);
+        b.write8(0);
+        b.write8(64);
+        b.write16b(0);
+        b.write16b(1);
+        for _ in 1..31 {
+            b.write_string("", 22);
+            b.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        }
+        b.write8(1);
+        b.write8(127);
+        b.extend_from_slice(&[0; 128]);
+        b.write_string("M.K.", 4);
+        b.extend_from_slice(&[0x11, 0xac, 0x1c, 0x40]);
+        b.extend_from_slice(&[0; 1020]);
+        b.extend((0..16).map(|x| x * 8));
+
+        round_trip(&b, Format::Mk);
+    }
+
+    #[test]
+    fn test_save_s3m() {
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string("test", 28);
+        b.extend_from_slice(&[0x1a, 16, 0, 0]);
+        for &x in &[2, 1, 1, 0, 0x1320, 2] { b.write16l(x) }
+        b.write_string("SCRM", 4);
+        b.extend_from_slice(&[64, 6, 125, 0xb0, 0, 252]);
+        b.extend_from_slice(&[0; 10]);
+        b.extend_from_slice(&[0, 8]);
+        b.extend_from_slice(&[255; 30]);
+        b.extend_from_slice(&[0, 255]);         // orders
+        b.write16l(0x09);                       // instrument parapointer
+        b.write16l(0x0e);                       // pattern parapointer
+        b.extend_from_slice(&[0x23; 32]);       // channel pan
+        b.pad_to(16);
+
+        b.write8(1);
+        b.write_string("SAMPLE.SMP", 12);
+        b.write8(0);
+        b.write16l(0x13);                       // memseg
+        for &x in &[16, 0, 0] { b.write32l(x) }
+        b.extend_from_slice(&[64, 0, 0, 0]);
+        b.write32l(8363);
+        b.extend_from_slice(&[0; 12]);
+        b.write_string("sample", 28);
+        b.write_string("SCRS", 4);
+
+        b.write16l(67);
+        b.extend_from_slice(&[0x20, 0x40, 1]);
+        b.extend_from_slice(&[0; 64]);
+        b.pad_to(16);
+
+        b.extend((0..16).map(|x| x * 8 + 0x80));
+
+        round_trip(&b, Format::S3m);
+    }
+
+    #[test]
+    fn test_save_xm() {
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string("Extended Module: test", 37);
+        b.write8(0x1a);
+        b.write_string("tracker name        ", 20);
+        b.write16l(0x0104);
+        b.write32l(276);
+        for &x in &[1, 0, 2, 1, 1, 1, 6, 125] { b.write16l(x) }
+        b.extend_from_slice(&[0; 256]);
+
+        b.write32l(9);
+        b.write8(0);
+        b.write16l(2);
+        b.write16l(6);
+        b.extend_from_slice(&[0x83, 49, 1, 0x80, 0x80, 0x80]);
+
+        b.write32l(263);
+        b.write_string("instrument", 22);
+        b.write8(0);
+        b.write16l(1);
+        b.write32l(40);
+        b.extend_from_slice(&[0; 230]);
+        for &x in &[4, 0, 0] { b.write32l(x) }
+        b.extend_from_slice(&[64, 0, 0, 128, 0, 0]);
+        b.write_string("sample", 22);
+        b.extend_from_slice(&[10, 10, 246, 246]);
+
+        round_trip(&b, Format::Xm);
+    }
+}
@@ -0,0 +1,281 @@
+use format::{ProbeInfo, Format, Loader};
+use format::mtm::{MtmData, MtmTracks, MtmInstrument};
+use module::{Module, Sample};
+use module::sample::SampleType;
+use util::BinaryRead;
+use ::*;
+
+/// MultiTracker module loader
+pub struct MtmLoader;
+
+impl Loader for MtmLoader {
+    fn name(&self) -> &'static str {
+        "MultiTracker"
+    }
+
+    fn probe(&self, b: &[u8], player_id: &str) -> Result<ProbeInfo, Error> {
+        if b.len() < 194 {
+            return Err(Error::Format(format!("file too short ({})", b.len())));
+        }
+
+        player::check_accepted(player_id, "mtm")?;
+
+        let magic = b.read_string(0, 3)?;
+        if magic == "MTM" {
+            Ok(ProbeInfo{format: Format::Mtm, title: b.read_string(4, 20)?})
+        } else {
+            Err(Error::Format(format!("bad magic {:?}", magic)))
+        }
+    }
+
+    fn load(self: Box<Self>, b: &[u8], info: ProbeInfo) -> Result<Module, Error> {
+
+        if info.format != Format::Mtm {
+            return Err(Error::Format("unsupported format".to_owned()));
+        }
+
+        let version = b.read8(3)?;
+        let name = b.read_string(4, 20)?;
+        let num_tracks = b.read16l(24)?;
+        let last_pattern = b.read8(26)?;
+        let last_order = b.read8(27)?;
+        let comment_size = b.read16l(28)? as usize;
+        let num_samples = b.read8(30)?;
+        let rows = b.read8(32)?;
+        let channels = b.read8(33)?;
+        let pan = b.slice(34, 32)?;
+
+        if last_order >= 128 {
+            return Err(Error::Format(format!("invalid song length {}", last_order as usize + 1)));
+        }
+        if rows == 0 || rows > 64 {
+            return Err(Error::Format(format!("invalid number of rows {}", rows)));
+        }
+        if channels == 0 || channels > 32 {
+            return Err(Error::Format(format!("invalid number of channels {}", channels)));
+        }
+
+        // Load instruments
+        let mut instruments = Vec::<MtmInstrument>::new();
+        for i in 0..num_samples as usize {
+            instruments.push(load_instrument(b, i)?);
+        }
+
+        // Load orders
+        let mut ofs = 66 + 37 * num_samples as usize;
+        let orders = b.slice(ofs, 128)?;
+        ofs += 128;
+
+        // Load tracks
+        let size = num_tracks as usize * rows as usize * 3;
+        let tracks = MtmTracks::from_slice(num_tracks as usize, rows as usize, b.slice(ofs, size)?);
+        ofs += size;
+
+        // Load track sequencing
+        let mut sequence = Vec::<u16>::new();
+        for _ in 0..(last_pattern as usize + 1) * 32 {
+            sequence.push(b.read16l(ofs)?);
+            ofs += 2;
+        }
+
+        if let Some(&p) = orders[..=last_order as usize].iter().find(|&&x| x > last_pattern) {
+            return Err(Error::Load(format!("invalid pattern {} in orders", p)));
+        }
+        if let Some(&t) = sequence.iter().find(|&&x| x > num_tracks) {
+            return Err(Error::Load(format!("invalid track {} in pattern sequence", t)));
+        }
+
+        // Load comment
+        let comment = b.slice(ofs, comment_size)?.to_vec();
+        ofs += comment_size;
+
+        // Load samples
+        let mut samples = Vec::<Sample>::new();
+        for i in 0..num_samples as usize {
+            let size = instruments[i].size as usize;
+            let smp = load_sample(b.slice(ofs, size)?, ofs, i, &instruments[i]);
+            samples.push(smp);
+            ofs += size;
+        }
+
+        let mut data = MtmData{
+            name,
+            version,
+            num_tracks,
+            last_pattern,
+            last_order,
+            num_samples,
+            rows,
+            channels,
+            pan: [0; 32],
+            comment,
+            instruments,
+            orders: [0; 128],
+            tracks,
+            sequence,
+            samples,
+        };
+
+        data.pan.copy_from_slice(pan);
+        data.orders.copy_from_slice(orders);
+
+        let m = Module {
+            format_id  : "mtm",
+            description: "MultiTracker MTM".to_owned(),
+            creator    : format!("MultiTracker {}.{:02}", version >> 4, version & 0x0f),
+            channels   : channels as usize,
+            player     : "st3",
+            data       : Box::new(data),
+        };
+
+        Ok(m)
+    }
+}
+
+fn load_instrument(b: &[u8], i: usize) -> Result<MtmInstrument, Error> {
+    let mut ins = MtmInstrument::new();
+
+    let ofs = 66 + i * 37;
+    ins.name = b.read_string(ofs, 22)?;
+    ins.size = b.read32l(ofs + 22)?;
+    ins.loop_start = b.read32l(ofs + 26)?;
+    ins.loop_end = b.read32l(ofs + 30)?;
+    ins.finetune = b.read8i(ofs + 34)?;
+    ins.volume = b.read8(ofs + 35)?;
+    ins.attr = b.read8(ofs + 36)?;
+
+    Ok(ins)
+}
+
+// MultiTracker samples are unsigned, 16-bit sample sizes are in bytes.
+fn load_sample(b: &[u8], ofs: usize, i: usize, ins: &MtmInstrument) -> Sample {
+    let mut smp = Sample::new();
+
+    smp.num = i + 1;
+    smp.address = ofs as u32;
+    smp.name = ins.name.to_owned();
+    smp.size = if ins.is_16bit() { ins.size / 2 } else { ins.size };
+    if smp.size > 0 {
+        smp.sample_type = if ins.is_16bit() { SampleType::Sample16 } else { SampleType::Sample8 };
+    }
+    smp.store(b);
+    smp.to_signed();
+
+    smp
+}
+
+
+#[cfg(test)]
+mod tests {
+    use format;
+    use format::mtm::MtmData;
+    use util::BinaryWrite;
+    use ::*;
+
+    fn mtm_module(rows: usize) -> Vec<u8> {
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string("MTM", 3);
+        b.write8(0x10);
+        b.write_string("test", 20);
+        b.write16l(2);                      // tracks
+        b.write8(0);                        // last pattern
+        b.write8(1);                        // last order
+        b.write16l(4);                      // comment size
+        b.write8(1);                        // samples
+        b.write8(0);
+        b.write8(rows as u8);
+        b.write8(4);                        // channels
+        b.extend_from_slice(&[3, 12, 12, 3]);
+        b.extend_from_slice(&[0; 28]);
+
+        b.write_string("sample", 22);
+        for &x in &[16, 4, 16] { b.write32l(x) }
+        b.extend_from_slice(&[0, 48, 0]);
+
+        b.extend_from_slice(&[0; 128]);     // orders
+        for t in 0..2 {
+            for r in 0..rows {
+                b.extend_from_slice(&[if r == 0 { 0x60 } else { 0 }, 0x10 | t, r as u8 & 0x0f]);
+            }
+        }
+        b.write16l(2);                      // sequence
+        b.write16l(0);
+        b.write16l(1);
+        for _ in 3..32 { b.write16l(0) }
+        b.write_string("text", 4);
+        b.extend((0..16).map(|x| x * 16));
+        b
+    }
+
+    #[test]
+    fn test_load_mtm() {
+        let b = mtm_module(64);
+        let m = format::load(&b, "").unwrap();
+        assert_eq!(m.format_id, "mtm");
+        assert_eq!(m.channels, 4);
+        assert_eq!(m.len(), 2);
+        let data = m.data.as_any().downcast_ref::<MtmData>().unwrap();
+        assert_eq!(data.comment, b"text");
+        let e = data.event(0, 3, 0);
+        assert_eq!((e.note, e.ins, e.cmd, e.param), (0, 1, 1, 3));
+        let e = data.event(0, 0, 2);
+        assert_eq!((e.note, e.ins, e.cmd, e.param), (24, 1, 0, 0));
+        let e = data.event(0, 3, 1);
+        assert_eq!((e.note, e.ins, e.cmd, e.param), (0, 0, 0, 0));
+        assert_eq!(data.samples[0].data[1], 0x90);
+    }
+
+    #[test]
+    fn test_load_invalid_mtm() {
+        // order 1 points to pattern 1, but the last pattern is 0
+        let mut b = mtm_module(64);
+        b[66 + 37 + 1] = 1;
+        assert!(format::load(&b, "").is_err());
+
+        // the first channel of pattern 0 uses track 3 of 2
+        let mut b = mtm_module(64);
+        b[66 + 37 + 128 + 2 * 64 * 3] = 3;
+        assert!(format::load(&b, "").is_err());
+    }
+
+    #[test]
+    fn test_jump_past_end_mtm() {
+        // B04 in row 4 of track 1 jumps past the last order
+        let mut b = mtm_module(64);
+        let ofs = 66 + 37 + 128 + 4 * 3;
+        b[ofs + 1..ofs + 3].copy_from_slice(&[0x1b, 4]);
+        let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+
+        // the jump row is played and the song restarts from the first order
+        let mut mi = ModuleInfo::new();
+        ox.module_info(&mut mi);
+        assert_eq!(mi.total_time, 5 * 6 * 20);
+        for _ in 0..5 * 6 {
+            ox.play_frame();
+        }
+        assert_eq!(ox.player.loop_count(), 0);
+        ox.play_frame();
+        assert_eq!(ox.player.loop_count(), 1);
+
+        // B00 in the first row jumps to itself and never reaches a new row
+        let mut b = mtm_module(64);
+        let ofs = 66 + 37 + 128;
+        b[ofs + 1..ofs + 3].copy_from_slice(&[0x1b, 0]);
+        assert!(Oxdz::new(&b, 44100, "").is_ok());
+    }
+
+    #[test]
+    fn test_play_mtm() {
+        for &rows in &[64, 32] {
+            let b = mtm_module(rows);
+            let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+            assert_eq!(ox.player_info().unwrap().id, "st3");
+            let mut mi = ModuleInfo::new();
+            ox.module_info(&mut mi);
+            assert_eq!(mi.total_time, 2 * rows as u32 * 6 * 20);
+
+            ox.play_frame();
+            assert!(ox.buffer().iter().any(|&x| x != 0));
+        }
+    }
+}
@@ -0,0 +1,235 @@
+pub mod load;
+
+pub use self::load::*;
+
+use std::any::Any;
+use std::fmt;
+use module::{event, ModuleData, Sample};
+use util::NOTES;
+
+//                              MTM module header
+//          0   1   2   3   4   5   6   7   8   9   A   B   C   D   E   F
+//        ,---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---.
+//  0000: |'M'|'T'|'M'|Ver| Song name, 20 chars                           |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0010: |               |Tracks |LPa|LOr|Comment|Smp|Atr|Row|Chn| Pan   |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0020: | Pan positions for 32 channels                                 |
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0030: |                                                       |Smp ...|
+//        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//  0042: Instruments (37 bytes each), orders (128 bytes), tracks (3 bytes
+//        per row), track sequencing (32 words per pattern), comment and
+//        sample data.
+
+pub struct MtmData {
+    pub name        : String,
+    pub version     : u8,
+    pub num_tracks  : u16,
+    pub last_pattern: u8,
+    pub last_order  : u8,
+    pub num_samples : u8,
+    pub rows        : u8,
+    pub channels    : u8,
+    pub pan         : [u8; 32],
+    pub comment     : Vec<u8>,
+    pub instruments : Vec<MtmInstrument>,
+    pub orders      : [u8; 128],
+    pub tracks      : MtmTracks,
+    pub sequence    : Vec<u16>,
+    pub samples     : Vec<Sample>,
+}
+
+impl MtmData {
+    /// The event played by channel `chn` in a pattern row. Channels using
+    /// track 0 are empty.
+    pub fn event(&self, pat: usize, row: usize, chn: usize) -> &MtmEvent {
+        let track = self.sequence[pat * 32 + chn] as usize;
+        self.tracks.event(track, row)
+    }
+}
+
+impl ModuleData for MtmData {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn title(&self) -> &str {
+        &self.name
+    }
+
+    fn patterns(&self) -> usize {
+        self.last_pattern as usize + 1
+    }
+
+    fn len(&self) -> usize {
+        self.last_order as usize + 1
+    }
+
+    fn pattern_in_position(&self, pos: usize) -> Option<usize> {
+        if pos >= self.len() {
+            None
+        } else {
+            Some(self.orders[pos] as usize)
+        }
+    }
+
+    fn instruments(&self) -> Vec<String> {
+        self.instruments.iter().map(|x| x.name.to_owned()).collect::<Vec<String>>()
+    }
+
+    fn rows(&self, pat: usize) -> usize {
+        if pat >= self.patterns() {
+            0
+        } else {
+            self.rows as usize
+        }
+    }
+
+    fn pattern_data(&self, pat: usize, num: usize, buffer: &mut [u8]) -> usize {
+        let chn = self.channels as usize;
+        let mut i = 0;
+        for _ in 0..num {
+            let (row, ch) = (i / chn, i % chn);
+            if row >= self.rows as usize {
+                break
+            }
+            let ofs = i * 6;
+            let e = self.event(pat, row, ch);
+
+            let mut flags = 0;
+            if e.note != 0 { flags |= event::HAS_NOTE; buffer[ofs+1] = e.note + 36 }
+            if e.ins  != 0 { flags |= event::HAS_INS ; buffer[ofs+2] = e.ins }
+            if e.cmd != 0 || e.param != 0 { flags |= event::HAS_CMD; buffer[ofs+4] = e.cmd; buffer[ofs+5] = e.param }
+            buffer[ofs] = flags;
+
+            i += 1;
+        }
+        i
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        self.samples.to_owned()
+    }
+}
+
+
+/// MtmInstrument defines the sample header fields of a MultiTracker module.
+#[derive(Debug,Default)]
+pub struct MtmInstrument {
+    pub name      : String,
+    pub size      : u32,
+    pub loop_start: u32,
+    pub loop_end  : u32,
+    pub finetune  : i8,
+    pub volume    : u8,
+    pub attr      : u8,
+}
+
+impl MtmInstrument {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_16bit(&self) -> bool {
+        self.attr & 0x01 != 0
+    }
+}
+
+
+/// MtmEvent defines the event format used in MultiTracker tracks.
+#[derive(Default)]
+pub struct MtmEvent {
+    pub note : u8,
+    pub ins  : u8,
+    pub cmd  : u8,
+    pub param: u8,
+}
+
+impl MtmEvent {
+    fn new() -> Self {
+        Default::default()
+    }
+
+    fn from_slice(b: &[u8]) -> Self {
+        let mut e = MtmEvent::new();
+        e.note = b[0] >> 2;
+        e.ins = (b[0] & 0x03) << 4 | b[1] >> 4;
+        e.cmd = b[1] & 0x0f;
+        e.param = b[2];
+        e
+    }
+}
+
+impl fmt::Display for MtmEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let note = if self.note == 0 {
+            "---".to_owned()
+        } else {
+            let n = self.note as usize + 24;
+            format!("{}{}", NOTES[n%12], n/12)
+        };
+
+        let ins = if self.ins == 0 {
+            "--".to_owned()
+        } else {
+            format!("{:02X}", self.ins)
+        };
+
+        write!(f, "{} {} {:X}{:02X}", note, ins, self.cmd, self.param)
+    }
+}
+
+
+/// MtmTracks holds the track data. Track 0 is always empty and is not
+/// stored in the module file.
+pub struct MtmTracks {
+    rows: usize,
+    data: Vec<MtmEvent>,
+}
+
+impl MtmTracks {
+    fn from_slice(num: usize, rows: usize, b: &[u8]) -> Self {
+        let mut tracks = MtmTracks{
+            rows,
+            data: Vec::new(),
+        };
+
+        tracks.data.extend((0..rows).map(|_| MtmEvent::new()));
+        for t in 0..num {
+            for r in 0..rows {
+                let ofs = (t * rows + r) * 3;
+                tracks.data.push(MtmEvent::from_slice(&b[ofs..ofs+3]));
+            }
+        }
+
+        tracks
+    }
+
+    pub fn num(&self) -> usize {
+        self.data.len() / self.rows - 1
+    }
+
+    pub fn event(&self, track: usize, row: usize) -> &MtmEvent {
+        let track = if track > self.num() { 0 } else { track };
+        &self.data[track * self.rows + row]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        let e = MtmEvent::from_slice(&[0, 0, 0]);
+        assert_eq!(format!("{}", e), "--- -- 000");
+
+        let e = MtmEvent::from_slice(&[0x61, 0x2c, 0x40]);
+        assert_eq!(format!("{}", e), "C 4 12 C40");
+
+        let e = MtmEvent::from_slice(&[0x02, 0x1f, 0x06]);
+        assert_eq!(format!("{}", e), "--- 21 F06");
+    }
+}
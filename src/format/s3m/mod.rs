@@ -1,6 +1,8 @@
 pub mod load;
+pub mod save;
 
 pub use self::load::*;
+pub use self::save::*;
 
 use std::any::Any;
 use module::{event, ModuleData, Sample};
@@ -149,6 +151,7 @@ impl ModuleData for S3mData {
 #[derive(Debug, Default)]
 pub struct S3mInstrument {
     pub typ     : u8,
+    pub filename: String,
     pub memseg  : u32,
     pub length  : u32,
     pub loop_beg: u32,
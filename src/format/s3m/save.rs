@@ -0,0 +1,121 @@
+use byteorder::{ByteOrder, LittleEndian};
+use format::s3m::{S3mData, S3mInstrument};
+use module::Sample;
+use util::BinaryWrite;
+use ::*;
+
+/// Write a Scream Tracker 3 module. Instruments, patterns and sample data
+/// are laid out sequentially after the header and their parapointers are
+/// recomputed.
+pub fn save(data: &S3mData) -> Result<Vec<u8>, Error> {
+    let mut b: Vec<u8> = Vec::new();
+
+    b.write_string(data.song_name.trim_end(), 28);
+    b.write8(0x1a);
+    b.write8(16);
+    b.write16l(0);
+    b.write16l(data.ord_num);
+    b.write16l(data.ins_num);
+    b.write16l(data.pat_num);
+    b.write16l(data.flags);
+    b.write16l(data.cwt_v);
+    b.write16l(data.ffi);
+    b.write_string("SCRM", 4);
+    b.write8(data.g_v);
+    b.write8(data.i_s);
+    b.write8(data.i_t);
+    b.write8(data.m_v);
+    b.write8(0);            // ultraclick removal
+    b.write8(data.d_p);
+    b.extend_from_slice(&[0; 10]);
+    b.extend_from_slice(&data.ch_settings);
+    b.extend_from_slice(&data.orders);
+
+    // Parapointers are filled in later
+    let pp_ofs = b.len();
+    b.extend(std::iter::repeat(0).take(2 * (data.ins_num as usize + data.pat_num as usize)));
+
+    if data.d_p == 252 {
+        b.extend_from_slice(&data.ch_pan);
+    }
+    b.pad_to(16);
+
+    // Instrument headers
+    let mut ins_ofs = Vec::<usize>::new();
+    for i in 0..data.ins_num as usize {
+        ins_ofs.push(b.len());
+        patch16l(&mut b, pp_ofs + 2*i, ins_ofs[i])?;
+        save_instrument(&mut b, &data.instruments[i]);
+    }
+
+    // Patterns
+    for i in 0..data.pat_num as usize {
+        let ofs = b.len();
+        patch16l(&mut b, pp_ofs + 2*(data.ins_num as usize + i), ofs)?;
+        b.extend_from_slice(&data.patterns[i].data);
+        b.pad_to(16);
+    }
+
+    // Sample data
+    for i in 0..data.ins_num as usize {
+        let ins = &data.instruments[i];
+        if ins.typ != 1 || ins.length == 0 {
+            continue
+        }
+
+        let ofs = b.len();
+        let memseg = ofs >> 4;
+        if memseg > 0xffffff {
+            return Err(Error::Format("module too large".to_owned()));
+        }
+        b[ins_ofs[i] + 0x0d] = (memseg >> 16) as u8;
+        b[ins_ofs[i] + 0x0e] = memseg as u8;
+        b[ins_ofs[i] + 0x0f] = (memseg >> 8) as u8;
+
+        save_sample(&mut b, ins, &data.samples[i], data.ffi != 1);
+        b.pad_to(16);
+    }
+
+    Ok(b)
+}
+
+fn patch16l(b: &mut Vec<u8>, ofs: usize, val: usize) -> Result<(), Error> {
+    let para = val >> 4;
+    if para > 0xffff {
+        return Err(Error::Format("module too large".to_owned()));
+    }
+    LittleEndian::write_u16(&mut b[ofs..ofs+2], para as u16);
+    Ok(())
+}
+
+fn save_instrument(b: &mut Vec<u8>, ins: &S3mInstrument) {
+    b.write8(ins.typ);
+    b.write_string(ins.filename.trim_end(), 12);
+    b.extend_from_slice(&[0; 3]);   // memseg
+    b.write32l(ins.length);
+    b.write32l(ins.loop_beg);
+    b.write32l(ins.loop_end);
+    b.write8(ins.vol as u8);
+    b.write8(0);
+    b.write8(0);                    // packing
+    b.write8(ins.flags as u8 & !0x02);  // stereo data is not kept
+    b.write32l(ins.c2spd);
+    b.extend_from_slice(&[0; 12]);
+    b.write_string(ins.name.trim_end(), 28);
+    b.write_string(if ins.typ == 1 { "SCRS" } else if ins.typ >= 2 { "SCRI" } else { "" }, 4);
+}
+
+fn save_sample(b: &mut Vec<u8>, ins: &S3mInstrument, smp: &Sample, cvt: bool) {
+    let size = if ins.flags & 0x04 != 0 { ins.length as usize * 2 } else { ins.length as usize };
+
+    let mut smp = smp.clone();
+    if cvt {
+        // the signed conversion is its own inverse
+        smp.to_signed();
+    }
+
+    let raw = smp.data.as_slice_u8();
+    let n = if raw.len() < size { raw.len() } else { size };
+    b.extend_from_slice(&raw[..n]);
+    b.extend(std::iter::repeat(0).take(size - n));
+}
@@ -0,0 +1,209 @@
+use std::cmp;
+use format::{ProbeInfo, Format, Loader};
+use format::sfx::*;
+use format::mk::ModPatterns;
+use module::{Module, Sample};
+use module::sample::SampleType;
+use util::BinaryRead;
+use ::*;
+
+/// SoundFX module loader
+pub struct SfxLoader;
+
+impl Loader for SfxLoader {
+    fn name(&self) -> &'static str {
+        "SoundFX"
+    }
+
+    fn probe(&self, b: &[u8], player_id: &str) -> Result<ProbeInfo, Error> {
+        if b.len() < 0x294 {
+            return Err(Error::Format(format!("file too short ({})", b.len())));
+        }
+
+        player::check_accepted(player_id, "sfx")?;
+
+        let format = if b.read_string(60, 4)? == "SONG" {
+            Format::Sfx
+        } else if b.read_string(124, 4)? == "SO31" {
+            Format::Sfx31
+        } else {
+            return Err(Error::Format("bad magic".to_owned()));
+        };
+
+        let num_ins = if format == Format::Sfx { 15 } else { 31 };
+        let ofs = num_ins * 4 + 20 + num_ins * 30;
+        let len = b.read8(ofs)?;
+        if len == 0 || len > 0x7f {
+            return Err(Error::Format(format!("invalid length {}", len)));
+        }
+
+        // SoundFX modules have no title
+        Ok(ProbeInfo{format, title: "".to_owned()})
+    }
+
+    fn load(self: Box<Self>, b: &[u8], info: ProbeInfo) -> Result<Module, Error> {
+
+        let num_ins = match info.format {
+            Format::Sfx   => 15,
+            Format::Sfx31 => 31,
+            _             => return Err(Error::Format("unsupported format".to_owned())),
+        };
+
+        let delay = b.read16b(num_ins * 4 + 4)?;
+
+        // Load instruments
+        let mut instruments: Vec<SfxInstrument> = Vec::new();
+        for i in 0..num_ins {
+            let ins = load_instrument(b, num_ins, i)?;
+            instruments.push(ins);
+        }
+
+        let ofs = num_ins * 4 + 20 + num_ins * 30;
+        let song_length = b.read8(ofs)?;
+        let restart = b.read8(ofs + 1)?;
+
+        // Load orders
+        let orders = b.slice(ofs + 2, 128)?;
+
+        let mut pat = 0_usize;
+        orders.iter().for_each(|x| { pat = cmp::max(pat, *x as usize); } );
+        pat += 1;
+
+        // Load patterns
+        let mut ofs = ofs + 130;
+        let patterns = ModPatterns::from_slice(pat, b.slice(ofs, 1024*pat)?, 4)?;
+
+        // Load samples
+        ofs += 1024*pat;
+        let mut samples: Vec<Sample> = Vec::new();
+        for i in 0..num_ins {
+            let size = instruments[i].size as usize;
+            let smp = load_sample(b.slice(ofs, size)?, ofs, i, &instruments[i]);
+            samples.push(smp);
+            ofs += size;
+        }
+
+        let mut data = SfxData{
+            song_name: "".to_owned(),
+            instruments,
+            delay,
+            song_length,
+            restart,
+            orders: [0; 128],
+            patterns,
+            samples,
+        };
+
+        data.orders.copy_from_slice(orders);
+
+        let m = Module {
+            format_id  : "sfx",
+            description: format!("{} instrument SoundFX module", num_ins),
+            creator    : if num_ins == 15 { "SoundFX 1.3" } else { "SoundFX 2.0" }.to_owned(),
+            channels   : 4,
+            player     : "sfx",
+            data       : Box::new(data),
+        };
+
+        Ok(m)
+    }
+}
+
+fn load_instrument(b: &[u8], num_ins: usize, i: usize) -> Result<SfxInstrument, Error> {
+    let mut ins = SfxInstrument::new();
+
+    let ofs = num_ins * 4 + 20 + i * 30;
+    ins.name   = b.read_string(ofs, 22)?;
+    ins.size   = b.read32b(i * 4)?;
+    ins.volume = cmp::min(64, b.read8(ofs + 25)?);
+    ins.repeat = b.read16b(ofs + 26)?;
+    ins.replen = b.read16b(ofs + 28)?;
+
+    Ok(ins)
+}
+
+fn load_sample(b: &[u8], ofs: usize, i: usize, ins: &SfxInstrument) -> Sample {
+    let mut smp = Sample::new();
+
+    smp.num  = i + 1;
+    smp.name = ins.name.to_owned();
+    smp.address = ofs as u32;
+    smp.size = ins.size;
+    if smp.size > 0 {
+        smp.sample_type = SampleType::Sample8;
+    }
+    smp.store(b);
+
+    smp
+}
+
+
+// SoundFX module used by the loader and player tests
+#[cfg(test)]
+pub fn sfx_module(num_ins: usize) -> Vec<u8> {
+    use util::BinaryWrite;
+
+    let mut b: Vec<u8> = Vec::new();
+    b.write32b(32);                     // sample sizes
+    for _ in 1..num_ins {
+        b.write32b(0);
+    }
+    b.write_string(if num_ins == 15 { "SONG" } else { "SO31" }, 4);
+    b.write16b(14565);                  // 122 bpm
+    b.write_string("", 14);
+
+    b.write_string("instrument", 22);
+    b.write16b(16);
+    b.extend_from_slice(&[0, 64]);
+    b.write16b(0);
+    b.write16b(16);
+    for _ in 1..num_ins {
+        b.write_string("", 22);
+        b.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    b.extend_from_slice(&[2, 0]);
+    let mut orders = [0_u8; 128];
+    orders[1] = 1;
+    b.extend_from_slice(&orders);
+
+    // pattern 0: C-2 instrument 1 in channel 1 with pitchbend up 2
+    let mut pat = [0_u8; 2048];
+    pat[4..8].copy_from_slice(&[0x01, 0xac, 0x12, 0x02]);
+    // pattern 1: break after 32 rows
+    pat[1024 + 31*16..1024 + 31*16 + 4].copy_from_slice(&[0xff, 0xfc, 0x00, 0x00]);
+    b.extend_from_slice(&pat);
+
+    b.extend((0..32).map(|x| x * 4));
+
+    b
+}
+
+
+#[cfg(test)]
+mod tests {
+    use format;
+    use format::sfx::SfxData;
+    use super::sfx_module;
+
+    #[test]
+    fn test_load_sfx() {
+        for &n in &[15, 31] {
+            let b = sfx_module(n);
+            let m = format::load(&b, "").unwrap();
+            assert_eq!(m.format_id, "sfx");
+            assert_eq!(m.channels, 4);
+            assert_eq!(m.len(), 2);
+
+            let data = m.data.as_any().downcast_ref::<SfxData>().unwrap();
+            assert_eq!(data.instruments.len(), n);
+            assert_eq!(data.instruments[0].name.trim_end(), "instrument");
+            assert_eq!(data.instruments[0].size, 32);
+            assert_eq!(data.instruments[0].replen, 16);
+            assert_eq!(data.patterns.event(0, 0, 1).note, 0x1ac);
+            assert_eq!(data.patterns.event(1, 31, 0).note, 0xfffc);
+            assert_eq!(data.samples[0].data[2], 8);
+            assert!((data.tempo() - 122.0).abs() < 0.01);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+pub mod load;
+
+pub use self::load::*;
+
+use std::any::Any;
+use format::mk::{self, ModPatterns};
+use module::{event, ModuleData, Sample};
+
+// SoundFX module layout
+//
+//  0000: Sample sizes, 15 (or 31) 32-bit values
+//  003c: Magic ("SONG"), or "SO31" at 007c in 31-instrument modules
+//  0040: CIA timer value (16-bit), followed by 14 unused bytes
+//  0050: Instruments, 30 bytes each
+//  0212: Song length, restart position and orders (128 bytes)
+//  0294: Patterns, 1024 bytes each, followed by sample data
+
+// Special notes
+pub const SFX_NOTE_PIC: u16 = 0xfffd;
+pub const SFX_NOTE_STP: u16 = 0xfffe;
+pub const SFX_NOTE_BRK: u16 = 0xfffc;
+pub const SFX_NOTE_UNK: u16 = 0xfffb;
+
+pub struct SfxData {
+    pub song_name  : String,
+    pub instruments: Vec<SfxInstrument>,
+    pub delay      : u16,
+    pub song_length: u8,
+    pub restart    : u8,
+    pub orders     : [u8; 128],
+    pub patterns   : ModPatterns,
+    pub samples    : Vec<Sample>,
+}
+
+impl SfxData {
+    /// The replay tempo in BPM, derived from the CIA timer value.
+    pub fn tempo(&self) -> f32 {
+        if self.delay == 0 {
+            125.0
+        } else {
+            (14565.0 * 122.0 / self.delay as f64) as f32
+        }
+    }
+}
+
+impl ModuleData for SfxData {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn title(&self) -> &str {
+        &self.song_name
+    }
+
+    fn patterns(&self) -> usize {
+        self.patterns.num()
+    }
+
+    fn len(&self) -> usize {
+        self.song_length as usize
+    }
+
+    fn pattern_in_position(&self, pos: usize) -> Option<usize> {
+        if pos >= self.orders.len() {
+            None
+        } else {
+            Some(self.orders[pos] as usize)
+        }
+    }
+
+    fn instruments(&self) -> Vec<String> {
+        self.instruments.iter().map(|x| x.name.to_owned()).collect::<Vec<String>>()
+    }
+
+    fn rows(&self, pat: usize) -> usize {
+        if pat >= self.patterns.num() {
+            0
+        } else {
+            64
+        }
+    }
+
+    // Special notes are not shown.
+    fn pattern_data(&self, pat: usize, num: usize, buffer: &mut [u8]) -> usize {
+        let mut i = 0;
+        for _ in 0..num {
+            let (row, ch) = (i / 4, i % 4);
+            if row >= 64 {
+                break
+            }
+            let ofs = i * 6;
+            let e = self.patterns.event(pat, row as u8, ch);
+
+            let mut flags = 0;
+            if e.note < SFX_NOTE_UNK {
+                let note = e.note & 0xfff;
+                let ins = (((e.note & 0x1000) >> 8) | ((e.cmd as u16 & 0xf0) >> 4)) as u8;
+                if note != 0 { flags |= event::HAS_NOTE; buffer[ofs+1] = mk::period_to_note(note) }
+                if ins  != 0 { flags |= event::HAS_INS ; buffer[ofs+2] = ins }
+                if e.cmd & 0x0f != 0 || e.cmdlo != 0 { flags |= event::HAS_CMD; buffer[ofs+4] = e.cmd & 0x0f; buffer[ofs+5] = e.cmdlo }
+            }
+            buffer[ofs] = flags;
+
+            i += 1;
+        }
+        i
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        self.samples.to_owned()
+    }
+}
+
+
+/// A SoundFX instrument. Unlike Protracker, the loop start is stored in
+/// bytes and the sample size comes from the table at the start of the file.
+#[derive(Debug, Default)]
+pub struct SfxInstrument {
+    pub name    : String,
+    pub size    : u32,
+    pub volume  : u8,
+    pub repeat  : u16,
+    pub replen  : u16,
+}
+
+impl SfxInstrument {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
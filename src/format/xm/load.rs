@@ -131,6 +131,7 @@ fn load_sample(samp: &SampleHeaderTyp, smp_num: usize, b: &[u8], offset: &mut us
     smp.num = smp_num;
     smp.name = samp.name.to_owned();
     smp.size = samp.len as u32;
+    smp.loop_bidir = samp.typ & 3 == 2;
     let byte_size = samp.len as usize;
     smp.sample_type = if samp.typ & 16 != 0 {
         let buf = diff_decode_16l(b.slice(*offset, byte_size)?);
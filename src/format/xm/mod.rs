@@ -1,6 +1,8 @@
 pub mod load;
+pub mod save;
 
 pub use self::load::*;
+pub use self::save::*;
 
 use std::any::Any;
 use module::{event, ModuleData, Sample};
@@ -10,11 +12,11 @@ use ::*;
 
 #[derive(Debug)]
 pub struct SongHeaderTyp {
-    sig        : String,
-    name       : String,
-    prog_name  : String,
-    pub ver    : u16,
-    header_size: u32,
+    pub sig        : String,
+    pub name       : String,
+    pub prog_name  : String,
+    pub ver        : u16,
+    pub header_size: u32,
     pub len        : u16,
     pub rep_s      : u16,
     pub ant_chn    : u16,
@@ -76,7 +78,7 @@ pub struct SampleHeaderTyp {
     pub typ    : u8,
     pub pan    : u8,
     pub rel_ton: i8,
-    skrap  : u8,
+    pub skrap  : u8,
     pub name   : String,
 
     pub smp_num: u32,
@@ -112,11 +114,11 @@ impl SampleHeaderTyp {
 
 #[derive(Default)]
 pub struct InstrHeaderTyp {
-    instr_size  : u32,
-    name        : String,
-    typ         : u8,
+    pub instr_size  : u32,
+    pub name        : String,
+    pub typ         : u8,
     pub ant_samp    : u16,
-    sample_size : i32,
+    pub sample_size : i32,
     pub ta          : Vec<u8>, //[u8; 96],
     pub env_vp      : Vec<(i16, i16)>, //[[i16; 2]; 12],
     pub env_pp      : Vec<(i16, i16)>, //[[i16; 2]; 12],
@@ -221,20 +223,24 @@ pub struct PatternHeaderTyp {
     _typ               : u8,
     pub patt_len       : u16,
     data_len           : u16,
-    num_chn            : usize,
-    data               : Vec<TonTyp>,
+    pub num_chn        : usize,
+    pub data           : Vec<TonTyp>,
 }
 
 
 impl PatternHeaderTyp {
     pub fn new_empty(num_chn: usize) -> Self {
+        Self::new(64, num_chn)
+    }
+
+    pub fn new(patt_len: u16, num_chn: usize) -> Self {
         PatternHeaderTyp{
             pattern_header_size: 0,
             _typ: 0,
-            patt_len: 64,
+            patt_len,
             data_len: 0,
             num_chn,
-            data: vec![TonTyp::new(); 64 * num_chn],
+            data: vec![TonTyp::new(); patt_len as usize * num_chn],
         }
     }
 
@@ -0,0 +1,170 @@
+use format::xm::{XmData, InstrHeaderTyp, PatternHeaderTyp, TonTyp};
+use module::Sample;
+use util::BinaryWrite;
+use ::*;
+
+const XM_VERSION: u16 = 0x0104;
+
+/// Write a FastTracker II module. Modules are always saved as version 1.04,
+/// with sample data following each instrument.
+pub fn save(data: &XmData) -> Result<Vec<u8>, Error> {
+    let mut b: Vec<u8> = Vec::new();
+    let header = &data.header;
+
+    let tab_size = if header.len as usize > 256 { header.len as usize } else { 256 };
+
+    b.write_string("Extended Module: ", 17);
+    b.write_string(header.name.trim_end(), 20);
+    b.write8(0x1a);
+    b.write_string(&header.prog_name, 20);
+    b.write16l(XM_VERSION);
+    b.write32l(20 + tab_size as u32);
+    b.write16l(header.len);
+    b.write16l(header.rep_s);
+    b.write16l(header.ant_chn);
+    b.write16l(header.ant_ptn - 1);    // the loader adds an extra empty pattern
+    b.write16l(header.ant_instrs);
+    b.write16l(header.flags);
+    b.write16l(header.def_tempo);
+    b.write16l(header.def_speed);
+    b.extend_from_slice(&header.song_tab);
+    b.extend(std::iter::repeat(0).take(tab_size - header.song_tab.len()));
+
+    for pat in data.patterns.iter().take(header.ant_ptn as usize - 1) {
+        save_pattern(&mut b, pat);
+    }
+
+    let mut smp_num = 0;
+    for ins in &data.instruments {
+        let num = ins.samp.len();
+        let samples = match data.samples.get(smp_num..smp_num+num) {
+            Some(val) => val,
+            None      => return Err(Error::Format("missing sample data".to_owned())),
+        };
+        save_instrument(&mut b, ins, samples)?;
+        smp_num += num;
+    }
+
+    Ok(b)
+}
+
+fn save_pattern(b: &mut Vec<u8>, pat: &PatternHeaderTyp) {
+    let mut packed: Vec<u8> = Vec::new();
+    for e in &pat.data {
+        pack_event(&mut packed, e);
+    }
+
+    b.write32l(9);
+    b.write8(0);
+    b.write16l(pat.patt_len);
+    b.write16l(packed.len() as u16);
+    b.extend_from_slice(&packed);
+}
+
+fn pack_event(b: &mut Vec<u8>, e: &TonTyp) {
+    let fields = [e.ton, e.instr, e.vol, e.eff_typ, e.eff];
+    let mut mask = 0;
+    for i in 0..5 {
+        if fields[i] != 0 {
+            mask |= 1 << i;
+        }
+    }
+
+    if mask == 0x1f {
+        b.extend_from_slice(&fields);
+    } else {
+        b.write8(0x80 | mask);
+        for i in 0..5 {
+            if mask & (1 << i) != 0 {
+                b.write8(fields[i]);
+            }
+        }
+    }
+}
+
+fn save_instrument(b: &mut Vec<u8>, ins: &InstrHeaderTyp, samples: &[Sample]) -> Result<(), Error> {
+    let start = b.len();
+    let ant_samp = ins.samp.len();
+    let min_size = if ant_samp > 0 { 263 } else { 29 };
+    let instr_size = if ins.instr_size as usize >= min_size { ins.instr_size as usize } else { min_size };
+
+    b.write32l(instr_size as u32);
+    b.write_string(ins.name.trim_end(), 22);
+    b.write8(ins.typ);
+    b.write16l(ant_samp as u16);
+
+    if ant_samp > 0 {
+        b.write32l(40);
+        b.extend_from_slice(&ins.ta);
+        for &(x, y) in ins.env_vp.iter().chain(ins.env_pp.iter()) {
+            b.write16l(x as u16);
+            b.write16l(y as u16);
+        }
+        b.write8(ins.env_vp_ant);
+        b.write8(ins.env_pp_ant);
+        b.write8(ins.env_v_sust);
+        b.write8(ins.env_v_rep_s);
+        b.write8(ins.env_v_rep_e);
+        b.write8(ins.env_p_sust);
+        b.write8(ins.env_p_rep_s);
+        b.write8(ins.env_p_rep_e);
+        b.write8(ins.env_v_typ);
+        b.write8(ins.env_p_typ);
+        b.write8(ins.vib_typ);
+        b.write8(ins.vib_sweep);
+        b.write8(ins.vib_depth);
+        b.write8(ins.vib_rate);
+        b.write16l(ins.fade_out);
+    }
+
+    let size = b.len() - start;
+    b.extend(std::iter::repeat(0).take(instr_size - size));
+
+    for samp in &ins.samp {
+        b.write32l(samp.len);
+        b.write32l(samp.rep_s);
+        b.write32l(samp.rep_l);
+        b.write8(samp.vol);
+        b.write8(samp.fine as u8);
+        b.write8(samp.typ);
+        b.write8(samp.pan);
+        b.write8(samp.rel_ton as u8);
+        b.write8(samp.skrap);
+        b.write_string(samp.name.trim_end(), 22);
+    }
+
+    for (samp, smp) in ins.samp.iter().zip(samples) {
+        let raw = smp.data.as_slice_u8();
+        let size = samp.len as usize;
+        if raw.len() < size {
+            return Err(Error::Format(format!("sample {} too short", smp.num)));
+        }
+        if samp.typ & 16 != 0 {
+            diff_encode_16l(b, &raw[..size]);
+        } else {
+            diff_encode_8(b, &raw[..size]);
+        }
+    }
+
+    Ok(())
+}
+
+fn diff_encode_8(b: &mut Vec<u8>, data: &[u8]) {
+    let mut old = 0_u8;
+    for &new in data {
+        b.write8(new.wrapping_sub(old));
+        old = new;
+    }
+}
+
+fn diff_encode_16l(b: &mut Vec<u8>, data: &[u8]) {
+    let mut old = 0_u16;
+    for i in 0..data.len() / 2 {
+        let new = ((data[i*2+1] as u16) << 8) | data[i*2] as u16;
+        b.write16l(new.wrapping_sub(old));
+        old = new;
+    }
+    if data.len() & 1 != 0 {
+        b.write8(0);
+    }
+}
@@ -27,12 +27,17 @@ mod util;
 
 mod player;
 mod mixer;
+mod stream;
 
 pub mod format;
 pub mod module;
+pub mod render;
 pub use player::FrameInfo;
+pub use player::SongInfo;
 pub use player::PlayerInfo;
+pub use player::{OutputSample, I24};
 pub use format::FormatInfo;
+pub use stream::{Stream, SampleFormat};
 pub use module::Module;
 
 use std::error;
@@ -54,6 +59,8 @@ pub const MAX_KEYS     : usize = 128;
 pub const MAX_CHANNELS : usize = 64;
 pub const MAX_SEQUENCES: usize = 16;
 
+const SNAPSHOT_MAGIC: &'static [u8] = b"OXSN";
+
 #[derive(Default)]
 pub struct ModuleInfo {
     /// The module title.
@@ -136,11 +143,28 @@ impl<'a> Oxdz<'a> {
         self
     }
 
-    pub fn fill_buffer(&mut self, mut buffer: &mut [i16], loops: usize) -> &mut Self {
+    /// Fill the buffer with interleaved stereo samples in any output format
+    /// (`i16`, `I24`, `i32` or `f32`). Samples are rendered from the 32-bit
+    /// mixer accumulator, so wider formats don't lose headroom. Replay ends
+    /// after `loops` loops, or never if `loops` is 0; once it ends, the rest
+    /// of the buffer is filled with silence and `end()` returns true.
+    pub fn fill_buffer<T: OutputSample>(&mut self, mut buffer: &mut [T], loops: usize) -> &mut Self {
         self.player.fill_buffer(&mut buffer, loops);
         self
     }
 
+    /// Set the length in ms of the fade-out played by `fill_buffer()` after
+    /// the last loop.
+    pub fn set_fade_out(&mut self, ms: u32) -> &mut Self {
+        self.player.set_fade_out(ms);
+        self
+    }
+
+    /// True when replay ended in `fill_buffer()`.
+    pub fn end(&self) -> bool {
+        self.player.end()
+    }
+
     /// Play a module frame and renders the output on an internal buffer.
     /// The buffer can be retrieved using the `buffer()` function.
     pub fn play_frame(&mut self) -> &mut Self {
@@ -152,6 +176,25 @@ impl<'a> Oxdz<'a> {
         self.player.buffer()
     }
 
+    /// Play a module frame and return the output of each module channel.
+    /// The mix of all channels is still available with `buffer()`.
+    pub fn play_frame_stems(&mut self) -> Vec<&[i16]> {
+        self.player.play_frame_stems()
+    }
+
+    /// Turn the player into a stream of rendered audio in the given sample
+    /// format. The stream ends after `loops` loops, or never if `loops` is 0.
+    pub fn into_stream(self, format: SampleFormat, loops: usize) -> Stream<'a> {
+        Stream::new(self, format, loops)
+    }
+
+    /// Retrieve the frame rendered by `play_frame()` in the output format
+    /// of `out`.
+    pub fn buffer_as<T: OutputSample>(&self, out: &mut Vec<T>) -> &Self {
+        self.player.buffer_as(out);
+        self
+    }
+
     pub fn set_mute(&mut self, chn: usize, val: bool) -> &mut Self {
         self.player.set_mute(chn, val);
         self
@@ -167,11 +210,75 @@ impl<'a> Oxdz<'a> {
         self
     }
 
+    /// Seek to a time in milliseconds. Replay continues from the exact
+    /// sample when the buffer is filled with `fill_buffer()`.
+    pub fn seek_ms(&mut self, ms: u32) -> &mut Self {
+        self.player.seek_ms(ms);
+        self
+    }
+
+    /// List the songs found in the module. The first song is the main
+    /// song, other songs start at orders not played by previous songs.
+    pub fn subsongs(&self) -> Vec<SongInfo> {
+        self.player.songs()
+    }
+
+    /// Select a song and restart replay from its first order.
+    pub fn select_song(&mut self, song: usize) -> Result<&mut Self, Error> {
+        self.player.set_song(song)?;
+        Ok(self)
+    }
+
+    /// Seek to a row in an order. Seeking is faster when checkpoints
+    /// were recorded with `set_checkpoints()`.
+    pub fn seek_to(&mut self, pos: usize, row: usize) -> Result<&mut Self, Error> {
+        self.player.seek_to(pos, row)?;
+        Ok(self)
+    }
+
+    /// Rescan the module recording a checkpoint every `rows` rows, 0
+    /// disables checkpoints. Replay restarts from the beginning.
+    pub fn set_checkpoints(&mut self, rows: usize) -> &mut Self {
+        self.player.set_checkpoint_rows(rows);
+        self.player.scan();
+        self
+    }
+
+    /// Capture the playback state in a byte blob that can be stored and
+    /// later restored with `restore()`, even by a different process. The
+    /// snapshot is tied to the module data and to the sampling rate.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(SNAPSHOT_MAGIC);
+        b.extend_from_slice(&self.md5sum);
+        b.extend(self.player.snapshot());
+        b
+    }
+
+    /// Resume playback from a snapshot created by `snapshot()`.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<&mut Self, Error> {
+        if snapshot.len() < 20 || &snapshot[..4] != SNAPSHOT_MAGIC {
+            return Err(Error::Player("invalid snapshot".to_owned()))
+        }
+        if snapshot[4..20] != self.md5sum {
+            return Err(Error::Player("snapshot belongs to a different module".to_owned()))
+        }
+        self.player.restore_snapshot(&snapshot[20..])?;
+        Ok(self)
+    }
+
     pub fn set_interpolator(&mut self, name: &str) -> Result<&mut Self, Error> {
         self.player.set_interpolator(name)?;
         Ok(self)
     }
 
+    /// Set the volume ramp length in samples, 0 disables ramping. Players
+    /// emulating the Amiga hardware never ramp.
+    pub fn set_ramp_length(&mut self, samples: usize) -> &mut Self {
+        self.player.set_ramp_length(samples);
+        self
+    }
+
 /*
     pub fn player(&'a mut self) -> &'a mut player::Player {
         &mut self.player
@@ -233,3 +340,253 @@ impl From<io::Error> for Error {
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::BinaryWrite;
+
+    pub fn test_mod(title: &str) -> Vec<u8> {
+        let mut b: Vec<u8> = Vec::new();
+        b.write_string(title, 20);
+        b.write_string("square", 22);
+        b.write16b(16);
+        b.extend_from_slice(&[0, 64, 0, 0, 0, 16]);
+        for _ in 1..31 {
+            b.write_string("", 22);
+            b.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        }
+        b.write8(1);
+        b.write8(127);
+        b.extend_from_slice(&[0; 128]);
+        b.write_string("M.K.", 4);
+        for r in 0..64 {
+            b.extend_from_slice(if r & 1 == 0 { &[0x01, 0xac, 0x10, 0] } else { &[0x00, 0xd6, 0x10, 0] });
+            b.extend_from_slice(&[0; 12]);
+        }
+        b.extend((0..32).map(|x| if x < 16 { 0x40 } else { 0xc0 }));
+        b
+    }
+
+    // Position 0 loops on itself, position 1 is a second song
+    fn test_songs_mod() -> Vec<u8> {
+        let mut b = test_mod("songs");
+        b[950] = 2;
+        b[953] = 1;
+        let pat = b[1084..2108].to_vec();
+        b.splice(2108..2108, pat);
+        b[1084 + 15*16 + 6] = 0x0b;         // B00 in row 15 of pattern 0
+        b[2108 + 31*16 + 6] = 0x0b;         // B01 D08 in row 31 of pattern 1
+        b[2108 + 31*16 + 7] = 0x01;
+        b[2108 + 31*16 + 10] = 0x0d;
+        b[2108 + 31*16 + 11] = 0x08;
+        b
+    }
+
+    fn render(ox: &mut Oxdz, frames: usize) -> Vec<i16> {
+        let mut out = vec![0_i16; 1000];
+        let mut v = Vec::new();
+        for _ in 0..frames {
+            ox.fill_buffer(&mut out, 0);
+            v.extend_from_slice(&out);
+        }
+        v
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let b = test_mod("test");
+        for player in &["pt2", "st3", "ft2"] {
+            let mut ox = Oxdz::new(&b, 44100, player).unwrap();
+            render(&mut ox, 30);
+            let snapshot = ox.snapshot();
+            let expected = render(&mut ox, 30);
+            assert!(expected.iter().any(|&x| x != 0));
+
+            let mut ox = Oxdz::new(&b, 44100, player).unwrap();
+            ox.restore(&snapshot).unwrap();
+            assert_eq!(render(&mut ox, 30), expected);
+        }
+
+        let snapshot = Oxdz::new(&b, 44100, "").unwrap().snapshot();
+
+        // snapshots are tied to the module and sampling rate
+        let mut ox = Oxdz::new(&test_mod("other"), 44100, "").unwrap();
+        assert!(ox.restore(&snapshot).is_err());
+        let mut ox = Oxdz::new(&b, 48000, "").unwrap();
+        assert!(ox.restore(&snapshot).is_err());
+        assert!(ox.restore(&snapshot[..30]).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_interpolator() {
+        // a snapshot taken with the default spline interpolator can be
+        // restored into a player using a different one
+        let b = test_mod("test");
+        let mut ox = Oxdz::new(&b, 44100, "st3").unwrap();
+        render(&mut ox, 30);
+        let snapshot = ox.snapshot();
+
+        let mut ox = Oxdz::new(&b, 44100, "st3").unwrap();
+        ox.set_interpolator("sinc64").unwrap();
+        ox.restore(&snapshot).unwrap();
+        assert!(render(&mut ox, 30).iter().any(|&x| x != 0));
+    }
+
+    #[test]
+    fn test_snapshot_truncated() {
+        // a truncated snapshot leaves the player unchanged
+        let b = test_mod("test");
+        let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+        render(&mut ox, 30);
+        let snapshot = ox.snapshot();
+
+        let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+        render(&mut ox, 10);
+        let current = ox.snapshot();
+        assert!(ox.restore(&snapshot[..snapshot.len() - 8]).is_err());
+        assert_eq!(ox.snapshot(), current);
+    }
+
+    #[test]
+    fn test_seek_ms() {
+        let b = test_mod("test");
+        for player in &["pt2", "st3"] {
+            let mut ox = Oxdz::new(&b, 44100, player).unwrap();
+            let full = render(&mut ox, 200);
+
+            // the seek point is in the middle of a frame
+            let mut ox = Oxdz::new(&b, 44100, player).unwrap();
+            ox.seek_ms(1234);
+            let seek = render(&mut ox, 20);
+            let ofs = 1234 * 441 / 10 * 2;
+            assert_eq!(&seek[200..], &full[ofs+200..ofs+seek.len()]);
+        }
+    }
+
+    #[test]
+    fn test_subsongs() {
+        let b = test_songs_mod();
+        for player in &["pt2", "st3", "ft2"] {
+            let mut ox = Oxdz::new(&b, 44100, player).unwrap();
+            let songs = ox.subsongs();
+            assert_eq!(songs.len(), 2);
+            assert_eq!(songs[0], SongInfo{ entry: 0, time: 1920, loop_pos: 0, loop_row: 0, loop_time: Some(0) });
+            assert_eq!(songs[1], SongInfo{ entry: 1, time: 3840, loop_pos: 1, loop_row: 8, loop_time: Some(960) });
+
+            ox.select_song(1).unwrap();
+            let mut mi = ModuleInfo::new();
+            ox.module_info(&mut mi);
+            assert_eq!(mi.total_time, 3840);
+            render(&mut ox, 10);
+            let mut fi = FrameInfo::new();
+            ox.frame_info(&mut fi);
+            assert_eq!(fi.pos, 1);
+
+            assert!(ox.select_song(2).is_err());
+        }
+    }
+
+    #[test]
+    fn test_play_frame_stems() {
+        let mut b = test_mod("test");
+        b[1084 + 8..1084 + 12].copy_from_slice(&[0x01, 0x40, 0x10, 0]);
+        for player in &["pt2", "st3", "ft2"] {
+            let mut ox = Oxdz::new(&b, 44100, player).unwrap();
+            render(&mut ox, 10);
+            for _ in 0..10 {
+                let mix = ox.play_frame_stems().iter().map(|x| x.iter().any(|&v| v != 0)).collect::<Vec<bool>>();
+                assert_eq!(mix, [true, false, true, false]);
+
+                // stems add up to the main mix
+                let stems = ox.player.stem_mix_buffers();
+                let sum = (0..stems[0].len()).map(|i| stems.iter().map(|x| x[i]).sum()).collect::<Vec<i32>>();
+                assert_eq!(&sum[..], ox.player.mix_buffer());
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream() {
+        use std::io::Read;
+
+        let b = test_mod("test");
+        let frame_size = 44100 * 2 / 50;
+        let mut stream = Oxdz::new(&b, 44100, "").unwrap().into_stream(SampleFormat::S16, 1);
+        let frames: Vec<Vec<u8>> = Iterator::by_ref(&mut stream).collect();
+        assert_eq!(frames.len(), 64 * 6);
+        assert!(frames.iter().all(|x| x.len() == frame_size * 2));
+        assert_eq!(stream.loops(), 1);
+        assert!(stream.next().is_none());
+
+        let mut data = Vec::new();
+        let mut stream = Oxdz::new(&b, 44100, "").unwrap().into_stream(SampleFormat::S16, 1);
+        stream.read_to_end(&mut data).unwrap();
+        assert_eq!(data, frames.concat());
+
+        let mut stream = Oxdz::new(&b, 44100, "").unwrap().into_stream(SampleFormat::S24, 2);
+        assert_eq!(stream.next().unwrap().len(), frame_size * 3);
+        assert_eq!(stream.count(), 64 * 6 * 2 - 1);
+    }
+
+    #[test]
+    fn test_fill_buffer_end() {
+        let b = test_mod("test");
+        let frame_size = 44100 * 2 / 50;
+        let song_size = 64 * 6 * frame_size;
+
+        for &fade in &[0, 500] {
+            let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+            ox.set_fade_out(fade);
+            let size = song_size + fade as usize * 441 / 10 * 2;
+            let mut out = vec![1_i16; size + 1000];
+            let filled = ox.player.fill_buffer(&mut out, 1);
+            assert_eq!(filled, size);
+            assert!(ox.end());
+            assert!(out[size..].iter().all(|&x| x == 0));
+            if fade > 0 {
+                assert!(out[size - 20..size].iter().all(|&x| x.abs() < 10));
+            }
+
+            // nothing else is played after the end
+            assert_eq!(ox.player.fill_buffer(&mut out, 1), 0);
+            let mut fi = FrameInfo::new();
+            ox.frame_info(&mut fi);
+            assert!(fi.end);
+
+            // seeking restarts replay
+            ox.seek_ms(0);
+            assert!(!ox.end());
+            assert_eq!(ox.player.fill_buffer(&mut out[..100], 1), 100);
+        }
+    }
+
+    #[test]
+    fn test_seek_to() {
+        let b = test_mod("test");
+        for player in &["pt2", "st3"] {
+            let mut ox = Oxdz::new(&b, 44100, player).unwrap();
+            let full = render(&mut ox, 200);
+
+            // with and without checkpoints
+            for &rows in &[0, 4] {
+                let mut ox = Oxdz::new(&b, 44100, player).unwrap();
+                ox.set_checkpoints(rows);
+                ox.seek_to(0, 17).unwrap();
+                let mut fi = FrameInfo::new();
+                ox.frame_info(&mut fi);
+                assert_eq!((fi.pos, fi.row), (0, 17));
+                let ofs = fi.time as usize * 441 / 10 * 2;
+                let seek = render(&mut ox, 20);
+
+                // skip the volume ramp of the first notes
+                assert_eq!(&seek[500..], &full[ofs+500..ofs+seek.len()]);
+            }
+
+            let mut ox = Oxdz::new(&b, 44100, player).unwrap();
+            assert!(ox.seek_to(0, 64).is_err());
+            assert!(ox.seek_to(1, 0).is_err());
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::f64::consts::PI;
+use player::scan::SaveRestore;
+
+// Resonant low-pass filter as used by Impulse Tracker 2.14 and later.
+// Cutoff is in the 0..254 range (127 is the top of the regular range,
+// higher values are reached with filter envelopes), resonance in 0..127.
+
+#[derive(Clone, Default, SaveRestore)]
+pub struct Filter {
+    fg : f64,
+    fb0: f64,
+    fb1: f64,
+    y1 : f64,
+    y2 : f64,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn set(&mut self, rate: u32, cutoff: usize, resonance: usize) {
+        let rate = rate as f64;
+
+        let mut fc = 110.0 * 2.0_f64.powf(0.25 + cutoff as f64 / 24.0);
+        if fc > rate / 2.0 {
+            fc = rate / 2.0;
+        }
+
+        let r = rate / (2.0 * PI * fc);
+        let dmpfac = 10.0_f64.powf(-((24.0 / 128.0) * resonance as f64) / 20.0);
+
+        let d = dmpfac * r + dmpfac - 1.0;
+        let e = r * r;
+
+        self.fg  = 1.0 / (1.0 + d + e);
+        self.fb0 = (d + e + e) / (1.0 + d + e);
+        self.fb1 = -e / (1.0 + d + e);
+    }
+
+    pub fn reset(&mut self) {
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
+
+    pub fn apply(&mut self, x: i32) -> i32 {
+        let mut y = x as f64 * self.fg + self.y1 * self.fb0 + self.y2 * self.fb1;
+        if y > 65535.0 {
+            y = 65535.0;
+        } else if y < -65536.0 {
+            y = -65536.0;
+        }
+        self.y2 = self.y1;
+        self.y1 = y;
+        y as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+
+    #[test]
+    fn test_filter_dc() {
+        // a low-pass filter must converge to the input DC level
+        let mut f = Filter::new();
+        f.set(44100, 100, 0);
+        let mut y = 0;
+        for _ in 0..10000 {
+            y = f.apply(1000);
+        }
+        assert!((y - 1000).abs() <= 1);
+    }
+}
@@ -1,9 +1,24 @@
+use std::cmp;
+use std::f64::consts::PI;
 use mixer::SMIX_SHIFT;
 
 pub trait Interpolator: Send + Sync {
     fn name(&self) -> &'static str;
     fn bsize(&self) -> usize;
     fn get_sample(&self, &[i32], i32) -> i32;
+
+    // Number of samples read ahead of the current position. Interpolators
+    // with lookahead get a buffer centered on the current position, with
+    // every input sample in it even when the step is larger than one.
+    fn lookahead(&self) -> usize {
+        0
+    }
+
+    // Interpolate knowing the resampling step (16.16 fixed point), so the
+    // signal can be band-limited when downsampling.
+    fn get_sample_step(&self, i: &[i32], frac: i32, _step: usize) -> i32 {
+        self.get_sample(i, frac)
+    }
 }
 
 // Nearest neighbor interpolator
@@ -638,6 +653,120 @@ lazy_static! {
 }
 
 
+// Windowed sinc interpolator
+//
+// Blackman-windowed sinc with a configurable number of taps. When
+// downsampling, the kernel is stretched by the resampling ratio (up to
+// SINC_MAX_RATIO) to move the cutoff down to the output Nyquist frequency.
+pub struct Sinc {
+    taps : usize,
+    table: Vec<f32>,  // kernel from 0 to taps/2, SINC_RES points per zero crossing
+}
+
+const SINC_RES: usize = 256;
+const SINC_MAX_RATIO: usize = 4;
+
+impl Sinc {
+    pub fn new(taps: usize) -> Self {
+        let half = taps / 2;
+        let num = half * SINC_RES;
+        let mut table = vec![0.0_f32; num + 2];
+        for k in 0..num + 1 {
+            let x = k as f64 / SINC_RES as f64;
+            let sinc = if k == 0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            let w = 0.42 + 0.5 * (PI * x / half as f64).cos() + 0.08 * (2.0 * PI * x / half as f64).cos();
+            table[k] = (sinc * w) as f32;
+        }
+        Sinc {
+            taps,
+            table,
+        }
+    }
+
+    fn kernel(&self, x: f32) -> f32 {
+        let x = x.abs() * SINC_RES as f32;
+        let k = x as usize;
+        if k + 1 >= self.table.len() {
+            return 0.0
+        }
+        let f = x - k as f32;
+        self.table[k] + (self.table[k + 1] - self.table[k]) * f
+    }
+}
+
+impl Interpolator for Sinc {
+    fn name(&self) -> &'static str {
+        match self.taps {
+            8  => "windowed sinc (8 taps)",
+            16 => "windowed sinc (16 taps)",
+            32 => "windowed sinc (32 taps)",
+            64 => "windowed sinc (64 taps)",
+            _  => "windowed sinc",
+        }
+    }
+
+    fn bsize(&self) -> usize {
+        self.taps * SINC_MAX_RATIO
+    }
+
+    fn lookahead(&self) -> usize {
+        self.bsize() / 2
+    }
+
+    fn get_sample(&self, i: &[i32], frac: i32) -> i32 {
+        self.get_sample_step(i, frac, 1 << SMIX_SHIFT)
+    }
+
+    fn get_sample_step(&self, i: &[i32], frac: i32, step: usize) -> i32 {
+        let mut ratio = step as f32 / (1 << SMIX_SHIFT) as f32;
+        if ratio < 1.0 {
+            ratio = 1.0;
+        } else if ratio > SINC_MAX_RATIO as f32 {
+            ratio = SINC_MAX_RATIO as f32;
+        }
+
+        // i[c] is the sample at the current position
+        let c = (self.bsize() / 2) as isize;
+        let f = frac as f32 / (1 << SMIX_SHIFT) as f32;
+        let radius = (self.taps / 2) as f32 * ratio;
+        let lo = cmp::max((f - radius).ceil() as isize, -c);
+        let hi = cmp::min((f + radius).floor() as isize, c - 1);
+
+        let mut sum = 0.0_f32;
+        let mut wsum = 0.0_f32;
+        for j in lo..hi+1 {
+            let w = self.kernel((j as f32 - f) / ratio);
+            sum += i[(c + j) as usize] as f32 * w;
+            wsum += w;
+        }
+
+        if wsum > 0.0 { (sum / wsum) as i32 } else { 0 }
+    }
+}
+
+lazy_static! {
+    pub static ref SINC8 : Sinc = Sinc::new(8);
+    pub static ref SINC16: Sinc = Sinc::new(16);
+    pub static ref SINC32: Sinc = Sinc::new(32);
+    pub static ref SINC64: Sinc = Sinc::new(64);
+}
+
+/// Tap counts available for the windowed sinc interpolator.
+pub const SINC_TAPS: &[usize] = &[8, 16, 32, 64];
+
+/// The windowed sinc interpolator with the given number of taps, or None
+/// if the tap count is not one of `SINC_TAPS`.
+pub fn sinc(taps: usize) -> Option<&'static Sinc> {
+    match taps {
+        8  => Some(&*SINC8),
+        16 => Some(&*SINC16),
+        32 => Some(&*SINC32),
+        64 => Some(&*SINC64),
+        _  => None,
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -668,4 +797,41 @@ mod tests {
         assert_eq!(interp.get_sample(i, 32767), 0x59f3);
         assert_eq!(interp.get_sample(i, 65535), 0x6ff8);
     }
+
+    fn sine(len: usize, period: f64, amp: f64) -> Vec<i32> {
+        (0..len).map(|x| (amp * (2.0 * PI * x as f64 / period).sin()) as i32).collect()
+    }
+
+    #[test]
+    fn test_interpolate_sinc_dc() {
+        let interp = Sinc::new(16);
+        let i = vec![0x1000; interp.bsize()];
+        assert_eq!(interp.get_sample(&i, 0), 0x1000);
+        assert_eq!(interp.get_sample(&i, 32768), 0x1000);
+        assert_eq!(interp.get_sample_step(&i, 12345, 3 << SMIX_SHIFT), 0x1000);
+    }
+
+    #[test]
+    fn test_interpolate_sinc() {
+        let interp = Sinc::new(16);
+        let c = interp.bsize() / 2;
+        let i = sine(interp.bsize(), 32.0, 16384.0);
+
+        // on a sample point
+        assert!((interp.get_sample(&i, 0) - i[c]).abs() <= 1);
+
+        // halfway between samples
+        let expected = 16384.0 * (2.0 * PI * (c as f64 + 0.5) / 32.0).sin();
+        assert!((interp.get_sample(&i, 32768) as f64 - expected).abs() < 16.0);
+    }
+
+    #[test]
+    fn test_interpolate_sinc_downsample() {
+        // a tone at 3/4 of the input Nyquist frequency must be filtered
+        // out when downsampling by 2
+        let interp = Sinc::new(32);
+        let i: Vec<i32> = (0..interp.bsize()).map(|x| (16384.0 * (0.75 * PI * x as f64).cos()) as i32).collect();
+        assert!(interp.get_sample(&i, 0).abs() > 8000);
+        assert!(interp.get_sample_step(&i, 0, 2 << SMIX_SHIFT).abs() < 500);
+    }
 }
@@ -1,11 +1,16 @@
 use module::sample::{Sample, SampleType};
 use mixer::interpolator::Interpolator;
 use mixer::paula::Paula;
-use util::MemOpExt;
+use mixer::filter::Filter;
+use player::scan::SaveRestore;
 use ::*;
 
+pub use mixer::output::{OutputSample, I24};
+
 mod interpolator;
 mod paula;
+mod filter;
+mod output;
 
 
 const C4_PAL_RATE : f64 = 8287.0;   // 7093789.2 / period (C4) * 2
@@ -21,6 +26,7 @@ const SMIX_MASK    : usize = 0xffff;
 const LIM16_HI     : i32 = 32767;
 const LIM16_LO     : i32 = -32768;
 const DOWNMIX_SHIFT: usize = 12;
+const RAMP_SHIFT   : usize = 8;
 
 macro_rules! try_voice {
     ( $a:expr, $b: expr ) => {
@@ -45,6 +51,10 @@ pub struct Mixer<'a> {
     buffer    : Vec<i16>,
     pub interp: &'a interpolator::Interpolator,
     sample    : Vec<Sample>,
+    ramp      : bool,   // volume ramping enabled by the player
+    ramp_len  : usize,  // volume ramp length in samples
+    stem32    : Vec<Vec<i32>>,  // per-channel accumulators when rendering stems
+    stem_buf  : Vec<Vec<i16>>,
 }
 
 
@@ -60,10 +70,17 @@ impl<'a> Mixer<'a> {
             buffer   : vec![0; MAX_FRAMESIZE],
             interp   : &interpolator::Spline,
             sample,
+            ramp     : false,
+            ramp_len : rate as usize / 200,  // 5ms
+            stem32   : Vec::new(),
+            stem_buf : Vec::new(),
         };
 
+        let bsize = mixer.interp.bsize();
         for i in 0..num {
             mixer.voices[i].num = i;
+            mixer.voices[i].chn = i;
+            mixer.voices[i].i_buffer = vec![0; bsize];
         }
 
         mixer
@@ -75,8 +92,24 @@ impl<'a> Mixer<'a> {
             "nearest" => &interpolator::Nearest,
             "linear"  => &interpolator::Linear,
             "spline"  => &interpolator::Spline,
-            _         => return Err(Error::Player(format!(r#"unknown interpolator "{}""#, name))),
+            "sinc"    => &*interpolator::SINC32,
+            _         => {
+                // "sincN" selects a sinc interpolator with N taps
+                let taps = match name.trim_start_matches("sinc").parse::<usize>() {
+                    Ok(val) if name.starts_with("sinc") => val,
+                    _ => return Err(Error::Player(format!(r#"unknown interpolator "{}""#, name))),
+                };
+                match interpolator::sinc(taps) {
+                    Some(interp) => interp,
+                    None         => return Err(Error::Player(format!("unsupported sinc tap count {} (available: {:?})", taps, interpolator::SINC_TAPS))),
+                }
+            }
         };
+        let bsize = self.interp.bsize();
+        for v in &mut self.voices {
+            v.i_buffer = vec![0; bsize];
+            v.refill = true;
+        }
         Ok(())
     }
 
@@ -84,6 +117,34 @@ impl<'a> Mixer<'a> {
         self.voices.len()
     }
 
+    pub fn set_num_voices(&mut self, num: usize) {
+        let mut voice = Voice::new();
+        voice.i_buffer = vec![0; self.interp.bsize()];
+        self.voices.resize(num, voice);
+        for i in 0..num {
+            self.voices[i].num = i;
+            self.voices[i].chn = i;
+        }
+    }
+
+    // Set the module channel played by a voice, for players using virtual
+    // voices.
+    pub fn set_voice_channel(&mut self, voice: usize, chn: usize) {
+        try_voice!(voice, self.voices);
+        self.voices[voice].chn = chn;
+    }
+
+    // Mix each channel to its own buffer in addition to the main mix, 0
+    // channels disables stems.
+    pub fn set_stems(&mut self, channels: usize) {
+        self.stem32 = vec![vec![0; MAX_FRAMESIZE]; channels];
+        self.stem_buf = vec![vec![0; MAX_FRAMESIZE]; channels];
+    }
+
+    pub fn num_stems(&self) -> usize {
+        self.stem32.len()
+    }
+
     pub fn enable_paula(&mut self, enable: bool) {
         for v in &mut self.voices {
             v.paula = if enable {
@@ -94,6 +155,20 @@ impl<'a> Mixer<'a> {
         }
     }
 
+    // Volume ramping is enabled by players that don't emulate the Amiga
+    // hardware, Paula-based players keep the raw volume changes.
+    pub fn enable_ramp(&mut self, enable: bool) {
+        self.ramp = enable;
+    }
+
+    pub fn set_ramp_length(&mut self, samples: usize) {
+        self.ramp_len = samples;
+    }
+
+    fn ramp_len(&self) -> usize {
+        if self.ramp { self.ramp_len } else { 0 }
+    }
+
     pub fn enable_filter(&mut self, val: bool) {
         for v in &mut self.voices {
             match v.paula {
@@ -108,7 +183,9 @@ impl<'a> Mixer<'a> {
     }
 
     pub fn reset_voice(&mut self, voice: usize) {
+        let ramp_len = self.ramp_len();
         let v = &mut self.voices[voice];
+        v.anticlick(ramp_len);
         v.pos = 0.0;
         v.period = 0.0;
         v.note = 0;
@@ -119,37 +196,38 @@ impl<'a> Mixer<'a> {
         v.loop_start = 0;
         v.loop_end = 0;
         v.has_loop = false;
+        v.bidir_loop = false;
+        v.reverse = false;
         v.mute = false;
         v.active = false;
-        v.i_buffer = [0; 4];
+        for x in &mut v.i_buffer {
+            *x = 0;
+        }
+        v.refill = true;
+        v.filter = None;
     }
 
     pub fn reset(&mut self) {
         self.buf32 = vec![0; MAX_FRAMESIZE];
         self.buffer = vec![0; MAX_FRAMESIZE];
         for voice in 0..self.voices.len() {
-            self.reset_voice(voice)
+            self.reset_voice(voice);
+            self.voices[voice].tail_cnt = 0;
         }
     }
 
     pub fn voicepos(&self, voice: usize) -> f64 {
         try_voice!(voice, self.voices, 0_f64);
 
-        let v = &self.voices[voice];
-
-/*
-        let sample = &self.sample[v.smp];
-        if v.has_loop && sample.loop_bidir {
-            // TODO: handle bidirectional loop
-        }
-*/
-
-        v.pos
+        // Backwards stretches of bidirectional loops are mixed from the
+        // actual sample position, so no translation is needed here.
+        self.voices[voice].pos
     }
 
     pub fn set_voicepos(&mut self, voice: usize, pos: f64) {
         try_voice!(voice, self.voices);
 
+        let ramp_len = self.ramp_len();
         let v = &mut self.voices[voice];
 
         if v.smp >= self.sample.len() {
@@ -157,23 +235,33 @@ impl<'a> Mixer<'a> {
             return
         }
 
+        v.anticlick(ramp_len);
         v.pos = pos;
+        v.reverse = false;
+        v.refill = true;
 
         let sample = &self.sample[v.smp];
 
         if v.pos >= v.end as f64 {
             if v.has_loop {
-                v.pos = v.loop_start as f64;
+                if v.bidir_loop && v.loop_end > v.loop_start {
+                    // Fold the position into the forward/backward loop cycle
+                    let loop_size = (v.loop_end - v.loop_start) as f64;
+                    let ofs = (v.pos - v.loop_start as f64) % (2.0 * loop_size);
+                    if ofs < loop_size {
+                        v.pos = v.loop_start as f64 + ofs;
+                    } else {
+                        v.pos = v.loop_end as f64 - (ofs - loop_size);
+                        v.reverse = true;
+                    }
+                    v.end = v.loop_end;
+                } else {
+                    v.pos = v.loop_start as f64;
+                }
             } else {
                 v.pos = sample.size as f64;
             }
         }
-
-        // TODO: handle bidirectional loop
-
-        //if ac {
-        //    v.anticlick();
-        //}
     }
 
     pub fn volume(&self, voice: usize) -> usize {
@@ -218,14 +306,22 @@ impl<'a> Mixer<'a> {
             return
         }
 
+        let ramp_len = self.ramp_len();
         let v = &mut self.voices[voice];
+        v.anticlick(ramp_len);
         v.active = true;
         v.smp = smp - 1;
         v.pos = 0_f64;
         v.end = self.sample[smp - 1].size;
         v.has_loop = false;
+        v.bidir_loop = self.sample[smp - 1].loop_bidir;
+        v.reverse = false;
+        v.refill = true;
         v.sample_end = true;
         v.fix_loop();
+        if let Some(ref mut f) = v.filter {
+            f.reset();
+        }
     }
 
     pub fn set_sample_ptr(&mut self, voice: usize, addr: u32) {
@@ -239,6 +335,9 @@ impl<'a> Mixer<'a> {
                 v.smp = s.num - 1;
                 v.pos = (addr - s.address) as f64;
                 v.end = s.size;
+                v.bidir_loop = s.loop_bidir;
+                v.reverse = false;
+                v.refill = true;
                 v.fix_loop();
                 return
             }
@@ -261,6 +360,60 @@ impl<'a> Mixer<'a> {
         self.voices[voice].has_loop = val;
     }
 
+    // Unlike enable_loop(), the loop end is honored before the sample end
+    // is reached. Used to switch between sustain and regular loops.
+    pub fn set_loop(&mut self, voice: usize, start: u32, end: u32, enable: bool, bidir: bool) {
+        try_voice!(voice, self.voices);
+
+        let v = &mut self.voices[voice];
+        if v.smp >= self.sample.len() {
+            return
+        }
+
+        // loop points past the sample end are clamped to the sample
+        let size = self.sample[v.smp].size;
+        let end = if end > size { size } else { end };
+        let start = if start > end { end } else { start };
+
+        v.loop_start = start;
+        v.loop_end = end;
+        v.has_loop = enable;
+        v.bidir_loop = bidir;
+        v.end = self.sample[v.smp].size;
+        if enable && v.pos < end as f64 {
+            v.end = end;
+        }
+        if !(enable && bidir) || v.pos < start as f64 {
+            v.reverse = false;
+        } else if v.reverse && v.pos > end as f64 {
+            v.pos = end as f64;
+        }
+        v.fix_loop();
+    }
+
+    pub fn voice_active(&self, voice: usize) -> bool {
+        try_voice!(voice, self.voices, false);
+        let v = &self.voices[voice];
+        v.active && (v.has_loop || v.pos < v.end as f64)
+    }
+
+    pub fn set_filter(&mut self, voice: usize, cutoff: usize, resonance: usize) {
+        try_voice!(voice, self.voices);
+        let rate = self.rate;
+        let v = &mut self.voices[voice];
+        if v.filter.is_none() {
+            v.filter = Some(Filter::new());
+        }
+        if let Some(ref mut f) = v.filter {
+            f.set(rate, cutoff, resonance);
+        }
+    }
+
+    pub fn reset_filter(&mut self, voice: usize) {
+        try_voice!(voice, self.voices);
+        self.voices[voice].filter = None;
+    }
+
     pub fn set_mute(&mut self, voice: usize, val: bool) {
         try_voice!(voice, self.voices);
         self.voices[voice].mute = val;
@@ -273,6 +426,16 @@ impl<'a> Mixer<'a> {
     }
 
     pub fn mix(&mut self) {
+        self.mix_frame(true)
+    }
+
+    // Advance the voices by one frame without rendering, used to fast
+    // forward to a seek point.
+    pub fn skip(&mut self) {
+        self.mix_frame(false)
+    }
+
+    fn mix_frame(&mut self, render: bool) {
 
         let mut md = MixerData{
             pos    : 0.0_f64,
@@ -281,12 +444,55 @@ impl<'a> Mixer<'a> {
             size   : 0,
             vol_r  : 0,
             vol_l  : 0,
+            reverse: false,
+            ramp   : 0,
+            ramp_r : 0,
+            ramp_l : 0,
+            delta_r: 0,
+            delta_l: 0,
+            last_r : 0,
+            last_l : 0,
+            end       : 0,
+            loop_start: 0,
+            loop_end  : 0,
+            has_loop  : false,
+            bidir_loop: false,
+            refill    : false,
         };
 
-        self.buf32[..].fill(0, self.framesize);
+        for x in &mut self.buf32[..self.framesize * 2] {
+            *x = 0
+        }
+        for b in &mut self.stem32 {
+            for x in &mut b[..self.framesize * 2] {
+                *x = 0
+            }
+        }
+
+        let ramp_len = self.ramp_len();
 
         for v in &mut self.voices {
+            if !render {
+                v.tail_cnt = 0;
+                v.ramp_cnt = 0;
+                v.refill = true;
+            }
+
+            // Voices are mixed to the stem of their channel, and stems are
+            // added to the main mix when the frame is complete
+            let buf32 = match self.stem32.get_mut(v.chn) {
+                Some(b) => b,
+                None    => &mut self.buf32,
+            };
+
+            // Fade out the tail of a replaced note
+            if v.tail_cnt > 0 {
+                v.mix_tail(&mut buf32[..self.framesize * 2], ramp_len);
+            }
+
             if v.mute || v.period < 1.0 || !v.active {
+                v.last_l = 0;
+                v.last_r = 0;
                 continue
             }
 
@@ -295,6 +501,19 @@ impl<'a> Mixer<'a> {
             let vol_r = v.vol * (0x80 - v.pan) as usize;
             let vol_l = v.vol * (0x80 + v.pan) as usize;
 
+            // Ramp from the current volume to the new one
+            let target_r = ((vol_r >> 8) << RAMP_SHIFT) as i32;
+            let target_l = ((vol_l >> 8) << RAMP_SHIFT) as i32;
+            if ramp_len > 0 && (v.ramp_r != target_r || v.ramp_l != target_l) {
+                v.ramp_cnt = ramp_len;
+                v.delta_r = (target_r - v.ramp_r) / ramp_len as i32;
+                v.delta_l = (target_l - v.ramp_l) / ramp_len as i32;
+            } else {
+                v.ramp_cnt = 0;
+                v.ramp_r = target_r;
+                v.ramp_l = target_l;
+            }
+
             let sample = &self.sample[v.smp];
             let step = C4_PERIOD * C4_PAL_RATE * sample.rate / self.rate as f64 / v.period;
             if step < 0.001 {
@@ -315,7 +534,17 @@ impl<'a> Mixer<'a> {
 
                 // How many samples we can write before the loop break or sample end...
                 let mut samples = 0;
-                if v.pos > v.end as f64 {
+                if v.reverse {
+                    // ...or before the loop start if we're playing backwards
+                    let mut s = ((v.pos - v.loop_start as f64) / step).ceil() as isize;
+                    if s > size {
+                       s = size;
+                    }
+                    if s > 0 {
+                        samples = s;
+                        usmp = 0;
+                    }
+                } else if v.pos > v.end as f64 {
                     usmp = 1;
                 } else {
                     let mut s = ((v.end as f64 - v.pos) / step).ceil() as isize;
@@ -330,10 +559,23 @@ impl<'a> Mixer<'a> {
                 }
 
                 if samples == 0 {
+                    // Change direction at the bidirectional loop boundaries,
+                    // an empty loop can't be bounced off and ends the sample
+                    if v.has_loop && v.bidir_loop && v.loop_end > v.loop_start {
+                        v.loop_reposition();
+                        continue;
+                    }
                     break;
                 }
 
-                if v.vol > 0 {
+                if !render {
+                    // Keep the Paula clock phase when skipping frames
+                    if v.vol > 0 {
+                        if let Some(ref mut paula) = v.paula {
+                            paula.skip(samples as usize);
+                        }
+                    }
+                } else if v.vol > 0 || v.ramp_cnt > 0 {
                     let mix_size = samples * 2;
 
                     if samples > 0 {
@@ -343,28 +585,62 @@ impl<'a> Mixer<'a> {
                         md.size = samples;
                         md.vol_l = vol_l >> 8;
                         md.vol_r = vol_r >> 8;
+                        md.reverse = v.reverse;
+                        md.ramp = v.ramp_cnt;
+                        md.ramp_r = v.ramp_r;
+                        md.ramp_l = v.ramp_l;
+                        md.delta_r = v.delta_r;
+                        md.delta_l = v.delta_l;
+                        md.end = v.end;
+                        md.loop_start = v.loop_start;
+                        md.loop_end = v.loop_end;
+                        md.has_loop = v.has_loop;
+                        md.bidir_loop = v.bidir_loop;
+                        md.refill = v.refill;
 
                         match v.paula {
-                            Some(ref mut val) => md.mix_paula(&sample.data.as_slice_i8(), &mut self.buf32, val),
+                            Some(ref mut val) => md.mix_paula(&sample.data.as_slice_i8(), buf32, val),
                             None          => {
                                 match sample.sample_type {
                                     SampleType::Empty    => {},
-                                    SampleType::Sample8  => md.mix::<i8>(self.interp, &sample.data.as_slice_i8(), &mut self.buf32, &mut v.i_buffer),
-                                    SampleType::Sample16 => md.mix::<i16>(self.interp, &sample.data.as_slice_i16(), &mut self.buf32, &mut v.i_buffer),
+                                    SampleType::Sample8  => md.mix::<i8>(self.interp, &sample.data.as_slice_i8(), buf32, &mut v.i_buffer, &mut v.filter),
+                                    SampleType::Sample16 => md.mix::<i16>(self.interp, &sample.data.as_slice_i16(), buf32, &mut v.i_buffer, &mut v.filter),
                                 };
                             }
                         }
 
+                        v.ramp_cnt = md.ramp;
+                        v.ramp_r = md.ramp_r;
+                        v.ramp_l = md.ramp_l;
+                        v.last_r = md.last_r;
+                        v.last_l = md.last_l;
+                        v.refill = md.refill;
+
                         buf_pos += mix_size as usize;
                     }
+                } else {
+                    v.last_l = 0;
+                    v.last_r = 0;
+                }
+                if v.reverse {
+                    v.pos -= step * samples as f64;
+                } else {
+                    v.pos += step * samples as f64;
                 }
-                v.pos += step * samples as f64;
                 size -= samples + usmp;
 
                 // No more samples in this frame
                 if size <= 0 {
                     if v.has_loop {
-                        if v.pos + step >= v.end as f64 {
+                        if v.reverse {
+                            if v.pos <= v.loop_start as f64 {
+                                v.loop_reposition();
+                            }
+                        } else if v.bidir_loop {
+                            if v.pos >= v.end as f64 {
+                                v.loop_reposition();
+                            }
+                        } else if v.pos + step >= v.end as f64 {
                             v.pos += step;
                             v.loop_reposition();
                         }
@@ -384,8 +660,17 @@ impl<'a> Mixer<'a> {
             }
         }
 
+        let size = self.framesize * 2;
+        for b in &self.stem32 {
+            for (x, y) in self.buf32[..size].iter_mut().zip(b[..size].iter()) {
+                *x += *y;
+            }
+        }
+
         // Render final frame
-        self.downmix();
+        if render {
+            self.downmix();
+        }
     }
 
 
@@ -398,29 +683,151 @@ impl<'a> Mixer<'a> {
                 break;
             }
 
-            let smp = self.buf32[i] >> DOWNMIX_SHIFT;
-            if smp > LIM16_HI {
-                self.buffer[i] = LIM16_HI as i16;
-            } else if smp < LIM16_LO {
-                self.buffer[i] = LIM16_LO as i16;
-            } else {
-                self.buffer[i] = smp as i16;
-            }
+            self.buffer[i] = i16::from_mix(self.buf32[i]);
 
             i += 1;
         }
+
+        for (b, b32) in self.stem_buf.iter_mut().zip(self.stem32.iter()) {
+            for (x, y) in b[..size].iter_mut().zip(b32[..size].iter()) {
+                *x = i16::from_mix(*y);
+            }
+        }
     }
 
     pub fn buffer(&self) -> &[i16] {
         // *2 because we're stereo
         &self.buffer[..self.framesize*2]
     }
+
+    // The last mixed frame in the 32-bit accumulator
+    pub fn frame(&self) -> &[i32] {
+        &self.buf32[..self.framesize*2]
+    }
+
+    pub fn stem_buffers(&self) -> Vec<&[i16]> {
+        self.stem_buf.iter().map(|b| &b[..self.framesize*2]).collect()
+    }
+
+    pub fn stem_frames(&self) -> Vec<&[i32]> {
+        self.stem32.iter().map(|b| &b[..self.framesize*2]).collect()
+    }
+
+    // Apply a linear fade to the last mixed frame. The fade is len sample
+    // frames long and this frame starts pos frames into it.
+    pub fn fade(&mut self, pos: usize, len: usize) {
+        let size = self.framesize * 2;
+        let gain = |i: usize| if pos + i >= len { 0.0 } else { 1.0 - (pos + i) as f32 / len as f32 };
+        for buf in Some(&mut self.buf32).into_iter().chain(self.stem32.iter_mut()) {
+            for (i, smp) in buf[..size].chunks_mut(2).enumerate() {
+                let g = gain(i);
+                for val in smp {
+                    *val = (*val as f32 * g) as i32;
+                }
+            }
+        }
+        self.downmix();
+    }
+
+    pub fn set_frame(&mut self, frame: &[i32]) -> Result<(), Error> {
+        if frame.len() != self.framesize * 2 {
+            return Err(Error::Player("invalid frame size".to_owned()))
+        }
+        self.buf32[..frame.len()].copy_from_slice(frame);
+        self.downmix();
+        Ok(())
+    }
+
+    // Render the last mixed frame starting at offset ofs in the output
+    // format, directly from the 32-bit accumulator.
+    pub fn render<T: OutputSample>(&self, out: &mut [T], ofs: usize) {
+        render_frame(&self.buf32[..self.framesize * 2], out, ofs);
+    }
+
+    // Same as render(), for the stem of a module channel.
+    pub fn render_stem<T: OutputSample>(&self, chn: usize, out: &mut [T], ofs: usize) {
+        if let Some(buf) = self.stem32.get(chn) {
+            render_frame(&buf[..self.framesize * 2], out, ofs);
+        }
+    }
 }
 
+fn render_frame<T: OutputSample>(frame: &[i32], out: &mut [T], ofs: usize) {
+    if ofs >= frame.len() {
+        return
+    }
+    for (o, val) in out.iter_mut().zip(frame[ofs..].iter()) {
+        *o = T::from_mix(*val);
+    }
+}
 
-#[derive(Clone,Default)]
+// Only the playback state is saved, the sampling rate, interpolator and
+// volume ramp settings are part of the mixer configuration.
+impl<'a> SaveRestore for Mixer<'a> {
+    fn save(&self, state: &mut Vec<u8>) {
+        self.factor.save(state);
+        self.framesize.save(state);
+        self.voices.save(state);
+    }
+
+    fn restore(&mut self, state: &mut &[u8]) -> Result<(), Error> {
+        let ms = MixerState::decode(state)?;
+        self.apply_state(ms);
+        Ok(())
+    }
+
+    fn version() -> u32 {
+        Voice::version().rotate_left(5) ^ f64::version() ^ 0x6d6978  // "mix"
+    }
+}
+
+
+/// A decoded and validated mixer state, applied with `Mixer::apply_state`.
+pub struct MixerState {
+    factor   : f64,
+    framesize: usize,
+    voices   : Vec<Voice>,
+}
+
+impl MixerState {
+    pub fn decode(state: &mut &[u8]) -> Result<Self, Error> {
+        let mut ms = MixerState{ factor: 0.0, framesize: 0, voices: Vec::new() };
+        ms.factor.restore(state)?;
+        ms.framesize.restore(state)?;
+        ms.voices.restore(state)?;
+        if ms.framesize > MAX_FRAMESIZE / 2 {
+            return Err(Error::Player("invalid mixer state".to_owned()))
+        }
+        Ok(ms)
+    }
+
+    pub fn framesize(&self) -> usize {
+        self.framesize
+    }
+}
+
+impl<'a> Mixer<'a> {
+    // The lookahead buffers were saved for the interpolator in use at the
+    // time, voices are refilled if the buffer size doesn't match ours.
+    pub fn apply_state(&mut self, ms: MixerState) {
+        let bsize = self.interp.bsize();
+        self.factor = ms.factor;
+        self.framesize = ms.framesize;
+        self.voices = ms.voices;
+        for v in &mut self.voices {
+            if v.i_buffer.len() != bsize {
+                v.i_buffer = vec![0; bsize];
+                v.refill = true;
+            }
+        }
+    }
+}
+
+
+#[derive(Clone,Default,SaveRestore)]
 struct Voice {
     num       : usize,
+    chn       : usize,  // module channel, for stems
     pos       : f64,
     period    : f64,
     note      : usize,
@@ -432,13 +839,29 @@ struct Voice {
     loop_start: u32,
     loop_end  : u32,
     has_loop  : bool,
+    bidir_loop: bool,
+    reverse   : bool,  // playing the backwards stretch of a bidirectional loop
     sample_end: bool,
     mute      : bool,
     active    : bool,
 
-    i_buffer  : [i32; 4],
+    // volume ramping
+    ramp_cnt  : usize,
+    ramp_r    : i32,
+    ramp_l    : i32,
+    delta_r   : i32,
+    delta_l   : i32,
+    last_r    : i32,   // last mixed output, used to fade out replaced notes
+    last_l    : i32,
+    tail_cnt  : usize,
+    tail_r    : i32,
+    tail_l    : i32,
+
+    i_buffer  : Vec<i32>,
+    refill    : bool,  // position changed, lookahead interpolators reload the buffer
 
     paula     : Option<Paula>,
+    filter    : Option<Filter>,
 }
 
 impl Voice {
@@ -448,6 +871,28 @@ impl Voice {
     }
 
     pub fn loop_reposition(&mut self) {
+        if self.bidir_loop && self.loop_end > self.loop_start {
+            // Bounce off the loop boundary and change direction
+            if self.reverse {
+                self.pos = 2.0 * self.loop_start as f64 - self.pos;
+            } else {
+                self.pos = 2.0 * self.loop_end as f64 - self.pos;
+            }
+            self.reverse = !self.reverse;
+            self.end = self.loop_end;
+            self.has_loop = true;
+
+            // sanity check
+            if self.pos < self.loop_start as f64 {
+                self.pos = self.loop_start as f64;
+            } else if self.pos > self.loop_end as f64 {
+                self.pos = self.loop_end as f64;
+            }
+            return
+        }
+
+        self.reverse = false;
+
         // sanity check
         if self.pos > self.loop_end as f64 {
             self.pos = self.loop_end as f64;
@@ -464,9 +909,6 @@ impl Voice {
         if self.pos < 0.0 {
             self.pos = 0.0;
         }
-
-        //if self.bidir_loop {
-        //}
     }
 
     // sample loop sanity checks
@@ -480,8 +922,48 @@ impl Voice {
         }
     }
 
-    //pub fn anticlick(&self) {
-    //}
+    // Move the current output to a tail that fades out in the next frame,
+    // and fade in whatever the voice plays next.
+    pub fn anticlick(&mut self, ramp_len: usize) {
+        if ramp_len == 0 {
+            return
+        }
+
+        // remaining value of an unfinished tail
+        if self.tail_cnt > 0 {
+            self.tail_r = (self.tail_r as i64 * self.tail_cnt as i64 / ramp_len as i64) as i32;
+            self.tail_l = (self.tail_l as i64 * self.tail_cnt as i64 / ramp_len as i64) as i32;
+        } else {
+            self.tail_r = 0;
+            self.tail_l = 0;
+        }
+
+        self.tail_r += self.last_r;
+        self.tail_l += self.last_l;
+        self.tail_cnt = if self.tail_r != 0 || self.tail_l != 0 { ramp_len } else { 0 };
+
+        self.last_r = 0;
+        self.last_l = 0;
+        self.ramp_r = 0;
+        self.ramp_l = 0;
+        self.ramp_cnt = 0;
+    }
+
+    fn mix_tail(&mut self, buf32: &mut [i32], ramp_len: usize) {
+        if ramp_len == 0 {
+            self.tail_cnt = 0;
+            return
+        }
+
+        let mut bpos = 0;
+        while self.tail_cnt > 0 && bpos < buf32.len() {
+            let cnt = self.tail_cnt as i64;
+            buf32[bpos    ] += (self.tail_r as i64 * cnt / ramp_len as i64) as i32;
+            buf32[bpos + 1] += (self.tail_l as i64 * cnt / ramp_len as i64) as i32;
+            bpos += 2;
+            self.tail_cnt -= 1;
+        }
+    }
 }
 
 
@@ -492,12 +974,36 @@ struct MixerData {
     pub size   : isize,
     pub vol_l  : usize,
     pub vol_r  : usize,
+    pub reverse: bool,
+    pub ramp   : usize,  // samples left in the volume ramp
+    pub ramp_r : i32,
+    pub ramp_l : i32,
+    pub delta_r: i32,
+    pub delta_l: i32,
+    pub last_r : i32,
+    pub last_l : i32,
+
+    // sample limits for lookahead interpolators
+    pub end       : u32,
+    pub loop_start: u32,
+    pub loop_end  : u32,
+    pub has_loop  : bool,
+    pub bidir_loop: bool,
+    pub refill    : bool,
 }
 
 impl MixerData {
-    fn mix<T>(&mut self, interp: &Interpolator, data: &[T], buf32: &mut [i32], ibuf: &mut [i32])
+    fn mix<T>(&mut self, interp: &Interpolator, data: &[T], buf32: &mut [i32], ibuf: &mut [i32], filter: &mut Option<Filter>)
     where Sampler: SamplerOperations<T>
     {
+        if interp.lookahead() > 0 {
+            return self.mix_lookahead::<T>(interp, data, buf32, ibuf, filter)
+        }
+
+        if self.reverse {
+            return self.mix_reverse::<T>(interp, data, buf32, ibuf, filter)
+        }
+
         let mut pos = self.pos as usize;
         let mut frac = ((1 << SMIX_SHIFT) as f64 * (self.pos - pos as f64)) as usize;
         let mut bpos = self.buf_pos;
@@ -518,13 +1024,159 @@ impl MixerData {
                 pos += istep;
             }
 
-            let smp = interp.get_sample(&ibuf, frac as i32);
+            let mut smp = interp.get_sample(&ibuf, frac as i32);
+            if let Some(ref mut f) = *filter {
+                smp = f.apply(smp);
+            }
 
-            // Store stereo
-            buf32[bpos    ] += smp * self.vol_r as i32;
-            buf32[bpos + 1] += smp * self.vol_l as i32;
+            self.store(buf32, bpos, smp);
             bpos += 2;
+        }
+    }
+
+    // Backwards playback: position p reads the sample at ceil(p) - 1, mirroring
+    // the forward case. The interpolation buffer holds samples in playing order,
+    // so all interpolators work unchanged.
+    fn mix_reverse<T>(&mut self, interp: &Interpolator, data: &[T], buf32: &mut [i32], ibuf: &mut [i32], filter: &mut Option<Filter>)
+    where Sampler: SamplerOperations<T>
+    {
+        let start = self.pos.ceil();
+        let mut pos = start as isize - 1;
+        let mut frac = ((1 << SMIX_SHIFT) as f64 * (start - self.pos)) as usize;
+        let mut bpos = self.buf_pos;
+
+        let bmax = interp.bsize() - 1;
+        let last = data.len() as isize - 1;
+
+        for _ in 0..self.size {
+            frac += self.step;
+            let istep = frac >> SMIX_SHIFT;
+            frac &= SMIX_MASK;
 
+            // add sample to interpolation buffer
+            if istep > 0 {
+                for i in 0..bmax {
+                    ibuf[i] = ibuf[i+1]
+                }
+                ibuf[bmax] = Sampler::get(&data[clamp_index(pos, last)]);
+                pos -= istep as isize;
+            }
+
+            let mut smp = interp.get_sample(&ibuf, frac as i32);
+            if let Some(ref mut f) = *filter {
+                smp = f.apply(smp);
+            }
+
+            self.store(buf32, bpos, smp);
+            bpos += 2;
+        }
+    }
+
+    // Store stereo, ramping the volume if needed
+    fn store(&mut self, buf32: &mut [i32], bpos: usize, smp: i32) {
+        let (vol_r, vol_l) = if self.ramp > 0 {
+            self.ramp -= 1;
+            if self.ramp == 0 {
+                self.ramp_r = (self.vol_r << RAMP_SHIFT) as i32;
+                self.ramp_l = (self.vol_l << RAMP_SHIFT) as i32;
+            } else {
+                self.ramp_r += self.delta_r;
+                self.ramp_l += self.delta_l;
+            }
+            (self.ramp_r >> RAMP_SHIFT, self.ramp_l >> RAMP_SHIFT)
+        } else {
+            (self.vol_r as i32, self.vol_l as i32)
+        };
+
+        self.last_r = smp * vol_r;
+        self.last_l = smp * vol_l;
+        buf32[bpos    ] += self.last_r;
+        buf32[bpos + 1] += self.last_l;
+    }
+
+    // Mixing for interpolators that read ahead of the current position. The
+    // buffer is centered on the current position, holds every input sample
+    // (so the interpolator can band-limit when downsampling), and follows
+    // loops in either direction.
+    fn mix_lookahead<T>(&mut self, interp: &Interpolator, data: &[T], buf32: &mut [i32], ibuf: &mut [i32], filter: &mut Option<Filter>)
+    where Sampler: SamplerOperations<T>
+    {
+        let bsize = interp.bsize();
+        let ahead = interp.lookahead() as isize;
+        let dir: isize = if self.reverse { -1 } else { 1 };
+
+        // integer position and fraction in the playing direction
+        let (mut pos, mut frac) = if self.reverse {
+            let start = self.pos.ceil();
+            (start as isize - 1, ((1 << SMIX_SHIFT) as f64 * (start - self.pos)) as usize)
+        } else {
+            let start = self.pos.floor();
+            (start as isize, ((1 << SMIX_SHIFT) as f64 * (self.pos - start)) as usize)
+        };
+
+        // ibuf[ahead + j] is the sample j steps away from the current position
+        if self.refill {
+            for n in 0..bsize {
+                let j = n as isize - ahead;
+                ibuf[n] = self.sample_at(data, pos + dir * j);
+            }
+            self.refill = false;
+        }
+
+        let mut bpos = self.buf_pos;
+
+        for _ in 0..self.size {
+            frac += self.step;
+            let istep = frac >> SMIX_SHIFT;
+            frac &= SMIX_MASK;
+
+            for _ in 0..istep {
+                ibuf.copy_within(1..bsize, 0);
+                ibuf[bsize - 1] = self.sample_at(data, pos + dir * ahead);
+                pos += dir;
+            }
+
+            let mut smp = interp.get_sample_step(&ibuf, frac as i32, self.step);
+            if let Some(ref mut f) = *filter {
+                smp = f.apply(smp);
+            }
+
+            self.store(buf32, bpos, smp);
+            bpos += 2;
+        }
+    }
+
+    // Sample value at a position reached by moving in the playing direction,
+    // wrapping around the loop as the mixer does
+    fn sample_at<T>(&self, data: &[T], idx: isize) -> i32
+    where Sampler: SamplerOperations<T>
+    {
+        let end = self.end as isize;
+        let ls = self.loop_start as isize;
+        let le = self.loop_end as isize;
+        let len = le - ls;
+
+        let mut i = idx;
+        if self.has_loop && len > 0 {
+            if !self.reverse && i >= end {
+                let e = i - end;
+                i = if self.bidir_loop {
+                    let k = e % (2 * len);
+                    if k < len { le - 1 - k } else { ls + k - len }
+                } else {
+                    end - len + e % len
+                };
+            } else if self.reverse && i < ls {
+                let e = ls - 1 - i;
+                let k = e % (2 * len);
+                i = if k < len { ls + k } else { le - 1 - (k - len) };
+            }
+        }
+
+        if i < 0 || i >= end || i as usize >= data.len() {
+            0
+        } else {
+            Sampler::get(&data[i as usize])
         }
     }
 
@@ -589,3 +1241,195 @@ impl SamplerOperations<i8> for Sampler {
         (*i as i32) << 8
     }
 }
+
+fn clamp_index(pos: isize, last: isize) -> usize {
+    if pos < 0 {
+        0
+    } else if pos > last {
+        last as usize
+    } else {
+        pos as usize
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use module::sample::{Sample, SampleType};
+    use super::Mixer;
+
+    fn ramp_sample() -> Sample {
+        let mut smp = Sample::new();
+        smp.num = 1;
+        smp.size = 32;
+        smp.sample_type = SampleType::Sample8;
+        let data: Vec<u8> = (0..32).map(|x| x as u8 * 4).collect();
+        smp.store(&data);
+        smp
+    }
+
+    #[test]
+    fn test_bidir_loop() {
+        let mut mixer = Mixer::new(1, 44100, vec![ramp_sample()]);
+        mixer.set_interpolator("nearest").unwrap();
+        mixer.set_sample(0, 1);
+        mixer.set_loop(0, 8, 16, true, true);
+        mixer.set_volume(0, 1024);
+        mixer.set_period(0, 428.0 * 8287.0 / 44100.0 / 0.75);
+        mixer.set_tempo(125.0);
+
+        let mut up = false;
+        let mut down = false;
+        for _ in 0..4 {
+            mixer.mix();
+            let pos = mixer.voicepos(0);
+            assert!(pos >= 8.0 && pos <= 16.0);
+
+            let buffer = mixer.buffer();
+            for i in 1..buffer.len() / 2 {
+                let (prev, cur) = (buffer[i * 2 - 2], buffer[i * 2]);
+                up |= cur > prev;
+                down |= cur < prev;
+            }
+        }
+        assert!(up && down);
+
+        // sample values inside the loop only
+        let buffer = mixer.buffer();
+        let min = buffer.iter().min().unwrap();
+        let max = buffer.iter().max().unwrap();
+        assert!(*min >= 8 * 4 * 256 / 8 && *max < 16 * 4 * 256 / 8);
+
+        // an empty loop at the sample end plays as no loop
+        let mut mixer = Mixer::new(1, 44100, vec![ramp_sample()]);
+        mixer.set_sample(0, 1);
+        mixer.set_loop(0, 32, 32, true, true);
+        mixer.set_volume(0, 1024);
+        mixer.set_period(0, 428.0);
+        mixer.set_tempo(125.0);
+        for _ in 0..4 {
+            mixer.mix();
+        }
+        assert_eq!(mixer.voicepos(0), 32.0);
+        assert!(mixer.buffer().iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn test_loop_past_end() {
+        for &bidir in &[false, true] {
+            for &(start, end) in &[(16, 64), (48, 64)] {
+                let mut mixer = Mixer::new(1, 44100, vec![ramp_sample()]);
+                mixer.set_sample(0, 1);
+                mixer.set_loop(0, start, end, true, bidir);
+                mixer.set_volume(0, 1024);
+                mixer.set_period(0, 428.0 * 8287.0 / 44100.0 / 0.75);
+                mixer.set_tempo(125.0);
+                for _ in 0..4 {
+                    mixer.mix();
+                    assert!(mixer.voicepos(0) <= 32.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_volume_ramp() {
+        let mut smp = Sample::new();
+        smp.num = 1;
+        smp.size = 16;
+        smp.sample_type = SampleType::Sample8;
+        smp.store(&[64; 16]);
+
+        let mut mixer = Mixer::new(1, 44100, vec![smp]);
+        mixer.enable_ramp(true);
+        mixer.set_ramp_length(100);
+        mixer.set_interpolator("nearest").unwrap();
+        mixer.set_sample(0, 1);
+        mixer.set_loop(0, 0, 16, true, false);
+        mixer.set_volume(0, 1024);
+        mixer.set_period(0, 428.0 * 8287.0 / 44100.0);
+        mixer.set_tempo(125.0);
+
+        // fade in
+        mixer.mix();
+        {
+            let buffer = mixer.buffer();
+            assert!(buffer[2] < buffer[100]);
+            assert!(buffer[100] < buffer[200]);
+            assert_eq!(buffer[300], buffer[400]);
+        }
+
+        // fade out the tail of a cut note
+        let level = mixer.buffer()[400];
+        mixer.reset_voice(0);
+        mixer.mix();
+        let buffer = mixer.buffer();
+        assert!(buffer[0] > 0 && buffer[0] <= level);
+        assert!(buffer[100] < buffer[0]);
+        assert_eq!(buffer[200], 0);
+    }
+
+    #[test]
+    fn test_sinc_taps() {
+        let mut mixer = Mixer::new(1, 44100, vec![]);
+        for taps in super::interpolator::SINC_TAPS {
+            mixer.set_interpolator(&format!("sinc{}", taps)).unwrap();
+            assert_eq!(mixer.interp.bsize(), taps * 4);
+        }
+        assert!(mixer.set_interpolator("sinc12").is_err());
+        assert!(mixer.set_interpolator("sincx").is_err());
+    }
+
+    #[test]
+    fn test_sinc_loop() {
+        // a constant looped sample must stay constant across loop points
+        // in both directions, at any step
+        for &bidir in &[false, true] {
+            for &period in &[1.0, 0.7, 2.5] {
+                let mut smp = Sample::new();
+                smp.num = 1;
+                smp.size = 32;
+                smp.sample_type = SampleType::Sample8;
+                smp.store(&[64; 32]);
+
+                let mut mixer = Mixer::new(1, 44100, vec![smp]);
+                mixer.set_interpolator("sinc16").unwrap();
+                mixer.set_sample(0, 1);
+                mixer.set_loop(0, 8, 24, true, bidir);
+                mixer.set_voicepos(0, 16.0);
+                mixer.set_volume(0, 1024);
+                mixer.set_period(0, 428.0 * 8287.0 / 44100.0 * period);
+                mixer.set_tempo(125.0);
+
+                for _ in 0..3 {
+                    mixer.mix();
+                    let buffer = mixer.buffer();
+                    for x in buffer {
+                        assert!((*x as i32 - 64 * 32).abs() <= 1);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let mut mixer = Mixer::new(1, 44100, vec![ramp_sample()]);
+        mixer.set_sample(0, 1);
+        mixer.set_loop(0, 0, 32, true, false);
+        mixer.set_volume(0, 1024);
+        mixer.set_period(0, 428.0 * 8287.0 / 44100.0);
+        mixer.set_tempo(125.0);
+        mixer.mix();
+
+        let size = mixer.buffer().len();
+        let mut out16 = vec![0_i16; size];
+        let mut outf = vec![0.0_f32; size];
+        mixer.render(&mut out16, 0);
+        mixer.render(&mut outf, 0);
+        assert_eq!(&out16[..], mixer.buffer());
+        for i in 0..size {
+            assert!((outf[i] * 32768.0 - out16[i] as f32).abs() < 1.0);
+        }
+    }
+}
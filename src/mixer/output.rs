@@ -0,0 +1,140 @@
+use byteorder::{ByteOrder, LittleEndian};
+use mixer::{DOWNMIX_SHIFT, LIM16_HI, LIM16_LO};
+
+// Output sample formats rendered from the 32-bit mixer accumulator. The
+// accumulator full scale (16-bit full scale before the downmix shift) maps
+// to the full scale of each format; only the floating point output keeps
+// the headroom above full scale.
+
+const ACC_FULL_SCALE: f32 = (1_u32 << (15 + DOWNMIX_SHIFT)) as f32;
+
+pub trait OutputSample: Copy + Default {
+    fn from_mix(val: i32) -> Self;
+    /// Append the sample to a byte buffer in little-endian order.
+    fn write_le(&self, out: &mut Vec<u8>);
+}
+
+/// 16-bit signed output, clipped.
+impl OutputSample for i16 {
+    fn from_mix(val: i32) -> Self {
+        let smp = val >> DOWNMIX_SHIFT;
+        if smp > LIM16_HI {
+            LIM16_HI as i16
+        } else if smp < LIM16_LO {
+            LIM16_LO as i16
+        } else {
+            smp as i16
+        }
+    }
+
+    fn write_le(&self, out: &mut Vec<u8>) {
+        let mut b = [0; 2];
+        LittleEndian::write_i16(&mut b, *self);
+        out.extend_from_slice(&b);
+    }
+}
+
+/// 32-bit signed output, clipped.
+impl OutputSample for i32 {
+    fn from_mix(val: i32) -> Self {
+        let smp = (val as i64) << (16 - DOWNMIX_SHIFT);
+        if smp > i32::max_value() as i64 {
+            i32::max_value()
+        } else if smp < i32::min_value() as i64 {
+            i32::min_value()
+        } else {
+            smp as i32
+        }
+    }
+
+    fn write_le(&self, out: &mut Vec<u8>) {
+        let mut b = [0; 4];
+        LittleEndian::write_i32(&mut b, *self);
+        out.extend_from_slice(&b);
+    }
+}
+
+/// Floating point output, 1.0 is full scale. Not clipped.
+impl OutputSample for f32 {
+    fn from_mix(val: i32) -> Self {
+        val as f32 / ACC_FULL_SCALE
+    }
+
+    fn write_le(&self, out: &mut Vec<u8>) {
+        let mut b = [0; 4];
+        LittleEndian::write_f32(&mut b, *self);
+        out.extend_from_slice(&b);
+    }
+}
+
+/// 24-bit signed output, clipped, stored in the low bits of an `i32`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct I24(pub i32);
+
+const LIM24_HI: i32 = 0x7fffff;
+const LIM24_LO: i32 = -0x800000;
+
+impl I24 {
+    /// Little-endian packed representation.
+    pub fn to_le_bytes(&self) -> [u8; 3] {
+        [self.0 as u8, (self.0 >> 8) as u8, (self.0 >> 16) as u8]
+    }
+}
+
+impl OutputSample for I24 {
+    fn from_mix(val: i32) -> Self {
+        let smp = val >> (DOWNMIX_SHIFT - 8);
+        if smp > LIM24_HI {
+            I24(LIM24_HI)
+        } else if smp < LIM24_LO {
+            I24(LIM24_LO)
+        } else {
+            I24(smp)
+        }
+    }
+
+    fn write_le(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_full_scale() {
+        let full = 32767 << DOWNMIX_SHIFT;
+        assert_eq!(i16::from_mix(full), 32767);
+        assert_eq!(I24::from_mix(full), I24(32767 << 8));
+        assert_eq!(i32::from_mix(full), 32767 << 16);
+        assert!((f32::from_mix(full) - 32767.0 / 32768.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_output_headroom() {
+        let over = 65536 << DOWNMIX_SHIFT;
+        assert_eq!(i16::from_mix(over), 32767);
+        assert_eq!(i16::from_mix(-over), -32768);
+        assert_eq!(I24::from_mix(over), I24(0x7fffff));
+        assert_eq!(i32::from_mix(-over), i32::min_value());
+        assert_eq!(f32::from_mix(over), 2.0);
+    }
+
+    #[test]
+    fn test_i24_bytes() {
+        assert_eq!(I24(-2).to_le_bytes(), [0xfe, 0xff, 0xff]);
+        assert_eq!(I24(0x123456).to_le_bytes(), [0x56, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn test_write_le() {
+        let mut b = Vec::new();
+        (-2_i16).write_le(&mut b);
+        I24(0x123456).write_le(&mut b);
+        0x01020304_i32.write_le(&mut b);
+        1.0_f32.write_le(&mut b);
+        assert_eq!(b, [0xfe, 0xff, 0x56, 0x34, 0x12, 4, 3, 2, 1, 0, 0, 0x80, 0x3f]);
+    }
+}
@@ -1,4 +1,6 @@
 
+use player::scan::SaveRestore;
+
 // 131072 to 0, 2048 entries
 pub const PAULA_HZ        : f64   = 3546895.0;
 pub const MINIMUM_INTERVAL: usize = 16;
@@ -8,13 +10,13 @@ const BLEP_SIZE : usize = 2048;
 const MAX_BLEPS : usize = (BLEP_SIZE / MINIMUM_INTERVAL);
 
 // the structure that holds data of bleps
-#[derive(Copy,Clone,Default)]
+#[derive(Copy,Clone,Default,SaveRestore)]
 struct Blep {
     level: i16,
     age  : i16,
 }
 
-#[derive(Clone)]
+#[derive(Clone,SaveRestore)]
 pub struct Paula {
     // the instantenous value of Paula output
     global_output_level: i16,
@@ -97,11 +99,28 @@ impl Paula {
         }
     }
 
+    // advance the clock without producing output
+    pub fn skip(&mut self, samples: usize) {
+        for _ in 0..samples {
+            let num_in = self.remainder as usize / MINIMUM_INTERVAL;
+            self.remainder -= (num_in * MINIMUM_INTERVAL) as f64;
+            self.remainder += self.fdiv;
+        }
+        self.active_bleps = 0;
+    }
+
     pub fn enable_filter(&mut self, val: bool) {
         self.filter = val
     }
 }
 
+// Placeholder for restored states, all fields are overwritten
+impl Default for Paula {
+    fn default() -> Self {
+        Paula::new(44100)
+    }
+}
+
 
 //
 // Table generated by compute-blep.py (a1200 and vanilla tables removed)
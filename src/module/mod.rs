@@ -5,7 +5,8 @@ pub use self::sample::Sample;
 
 use std::any::Any;
 use std::marker::{Sync, Send};
-use util::MemOpExt;
+use format::convert::{self, Conversion};
+use Error;
 
 
 // Module
@@ -46,13 +47,20 @@ impl Module {
 
     pub fn pattern_data(&self, pat: usize, mut buffer: &mut [u8]) -> usize {
         let length = buffer.len();
-        buffer[..].fill(0, length);
+        for x in buffer.iter_mut() {
+            *x = 0
+        }
         self.data.pattern_data(pat, length / 6, &mut buffer)
     }
 
     pub fn samples(&self) -> Vec<Sample> {
         self.data.samples()
     }
+
+    /// Convert the module to another format, such as "s3m" or "xm".
+    pub fn convert_to(&self, format_id: &str) -> Result<Conversion, Error> {
+        convert::convert(self, format_id)
+    }
 }
 
 pub trait ModuleData: Send + Sync {
@@ -76,6 +76,8 @@ pub struct Sample {
     pub address     : u32,
     pub size        : u32,
     pub rate        : f64,
+    /// Loop is played forwards and backwards.
+    pub loop_bidir  : bool,
     /// The normalized rate used to play this sample.
     pub name        : String,
     /// The raw PCM-encoded sample data.
@@ -90,6 +92,7 @@ impl Sample {
             address     : 0,
             size        : 0,
             rate        : 1.0,
+            loop_bidir  : false,
             name        : "".to_owned(),
             data        : SampleData::new(),
         }
@@ -0,0 +1,27 @@
+mod player;
+
+use module::Module;
+use player::{Options, PlayerListEntry, PlayerInfo, FormatPlayer};
+use ::*;
+
+pub struct C669;
+
+impl PlayerListEntry for C669 {
+   fn info(&self) -> PlayerInfo {
+       PlayerInfo {
+          id         : "669",
+          name       : "Composer 669 replayer",
+          description: "A replayer for Composer 669 and UNIS 669 modules",
+          author     : "Claudio Matsuoka",
+          accepts    : &[ "669" ],
+       }
+   }
+
+   fn player(&self, module: &Module, options: Options) -> Box<FormatPlayer> {
+       Box::new(self::player::C669Player::new(module, options))
+   }
+
+   fn import(&self, module: Module) -> Result<Module, Error> {
+       Ok(module)
+   }
+}
@@ -0,0 +1,357 @@
+use module::{Module, ModuleData};
+use player::{Options, PlayerData, FormatPlayer, State};
+use player::scan::{self, SaveRestore};
+use format::c669::C669Data;
+use mixer::Mixer;
+use Error;
+
+/// Composer 669 replayer
+///
+/// An oxdz player for modules created with Composer 669 by Tran and UNIS
+/// 669 by Jason Nunn. 669 has its own effect semantics: pitch slides are
+/// linear in frequency, slide, portamento and vibrato effects keep running
+/// on the following rows until the channel plays a new note or receives
+/// another effect, and channels have fixed left/right panning.
+
+// Replay runs at about 32 ticks per second
+const TEMPO          : f32 = 78.0;
+
+// Mixer periods are relative to the Amiga C4 rate
+const PERIOD_BASE    : f64 = 428.0 * 8287.0;
+
+const C4_FREQ        : f64 = 8363.0;
+const C4_NOTE        : i32 = 24;
+
+// Frequency change in Hz for each unit of the effect parameter
+const SLIDE_STEP     : i32 = 4;
+const VIBRATO_STEP   : i32 = 8;
+
+const FX_PORTA_UP    : u8 = 0x00;  // a
+const FX_PORTA_DOWN  : u8 = 0x01;  // b
+const FX_TONEPORTA   : u8 = 0x02;  // c
+const FX_FREQ_ADJUST : u8 = 0x03;  // d
+const FX_VIBRATO     : u8 = 0x04;  // e
+const FX_SPEED       : u8 = 0x05;  // f
+const FX_BALANCE     : u8 = 0x06;  // g, UNIS 669 only
+const FX_RETRIG      : u8 = 0x07;  // h, UNIS 669 only
+
+const FX_NONE        : u8 = 0xff;
+
+lazy_static! {
+    static ref VIBRATO_TABLE: Box<[i32; 16]> = Box::new([
+        0, 3, 6, 7, 8, 7, 6, 3, 0, -3, -6, -7, -8, -7, -6, -3
+    ]);
+}
+
+
+#[derive(SaveRestore)]
+pub struct C669Player {
+    options : Options,
+
+    pos     : usize,
+    row     : usize,
+    tick    : usize,
+    speed   : usize,
+    unis    : bool,
+    channels: [C669Channel; 8],
+}
+
+impl C669Player {
+    pub fn new(module: &Module, options: Options) -> Self {
+
+        let module = module.data.as_any().downcast_ref::<C669Data>().unwrap();
+
+        C669Player {
+            options,
+
+            pos     : 0,
+            row     : 0,
+            tick    : 0,
+            speed   : 4,
+            unis    : module.is_unis(),
+            channels: [C669Channel::new(); 8],
+        }
+    }
+
+    fn pattern_speed(&self, module: &C669Data) -> usize {
+        let pat = module.orders[self.pos] as usize;
+        if module.tempo[pat] == 0 { 1 } else { module.tempo[pat] as usize }
+    }
+
+    fn play_row(&mut self, module: &C669Data, mixer: &mut Mixer) {
+        let pat = module.orders[self.pos] as usize;
+        let unis = self.unis;
+        let mut speed = self.speed;
+
+        for chn in 0..8 {
+            let e = module.patterns.event(pat, self.row, chn);
+            let ch = &mut self.channels[chn];
+
+            if e.has_note() {
+                let ins = e.ins as usize + 1;
+                let freq = note_to_freq(e.note);
+                if e.cmd == FX_TONEPORTA && ch.ins != 0 {
+                    ch.target = freq;
+                } else if ins <= module.instruments.len() {
+                    let instrument = &module.instruments[ins - 1];
+                    ch.ins = ins;
+                    ch.freq = freq;
+                    ch.target = freq;
+                    ch.vib_pos = 0;
+                    mixer.set_sample(chn, ins);
+                    mixer.set_voicepos(chn, 0.0);
+                    mixer.set_loop_start(chn, instrument.loop_start);
+                    mixer.set_loop_end(chn, instrument.loop_end);
+                    mixer.enable_loop(chn, instrument.has_loop());
+                }
+
+                // a new note stops the running effect
+                ch.effect = FX_NONE;
+            }
+
+            if e.has_vol() {
+                ch.volume = ((e.vol as u16 * 64 + 8) / 15) as u8;
+            }
+
+            if !e.has_cmd() {
+                continue
+            }
+
+            match e.cmd {
+                FX_SPEED => if e.param != 0 {
+                    speed = e.param as usize;
+                },
+                FX_FREQ_ADJUST => {
+                    ch.freq += e.param as i32;
+                },
+                FX_BALANCE => if unis {
+                    ch.pan = e.param as isize * 17 - 128;
+                    mixer.set_pan(chn, ch.pan);
+                },
+                FX_PORTA_UP | FX_PORTA_DOWN | FX_TONEPORTA | FX_VIBRATO => {
+                    ch.effect = e.cmd;
+                    ch.param = e.param;
+                },
+                FX_RETRIG => if unis {
+                    ch.effect = e.cmd;
+                    ch.param = e.param;
+                },
+                _ => {},
+            }
+        }
+
+        self.speed = speed;
+    }
+
+    // Effects are processed in all ticks, including the first tick of a row
+    fn play_effects(&mut self, mixer: &mut Mixer) {
+        for chn in 0..8 {
+            let tick = self.tick;
+            let ch = &mut self.channels[chn];
+            let param = ch.param as i32;
+
+            ch.vib_delta = 0;
+
+            match ch.effect {
+                FX_PORTA_UP => {
+                    ch.freq += param * SLIDE_STEP;
+                },
+                FX_PORTA_DOWN => {
+                    ch.freq -= param * SLIDE_STEP;
+                    if ch.freq < 1 {
+                        ch.freq = 1;
+                    }
+                },
+                FX_TONEPORTA => {
+                    if ch.freq < ch.target {
+                        ch.freq += param * SLIDE_STEP;
+                        if ch.freq > ch.target {
+                            ch.freq = ch.target;
+                        }
+                    } else if ch.freq > ch.target {
+                        ch.freq -= param * SLIDE_STEP;
+                        if ch.freq < ch.target {
+                            ch.freq = ch.target;
+                        }
+                    }
+                },
+                FX_VIBRATO => {
+                    ch.vib_delta = VIBRATO_TABLE[ch.vib_pos] * param * VIBRATO_STEP / 8;
+                    ch.vib_pos = (ch.vib_pos + 1) % 16;
+                },
+                FX_RETRIG => {
+                    if param != 0 && tick > 0 && tick % param as usize == 0 {
+                        mixer.set_voicepos(chn, 0.0);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn next_tick(&mut self, module: &C669Data) {
+        self.tick += 1;
+        if self.tick < self.speed {
+            return
+        }
+
+        self.tick = 0;
+        self.row += 1;
+        if self.row <= module.breaks[module.orders[self.pos] as usize] as usize {
+            return
+        }
+
+        self.row = 0;
+        self.pos += 1;
+        if self.pos >= module.len() {
+            self.pos = module.loop_order as usize;
+            if self.pos >= module.len() {
+                self.pos = 0;
+            }
+        }
+        self.speed = self.pattern_speed(module);
+    }
+}
+
+fn note_to_freq(note: u8) -> i32 {
+    (C4_FREQ * 2.0_f64.powf((note as i32 - C4_NOTE) as f64 / 12.0)) as i32
+}
+
+
+#[derive(Default,Copy,Clone,SaveRestore)]
+struct C669Channel {
+    ins      : usize,
+    freq     : i32,
+    target   : i32,
+    volume   : u8,
+    effect   : u8,
+    param    : u8,
+    vib_pos  : usize,
+    vib_delta: i32,
+    pan      : isize,
+}
+
+impl C669Channel {
+    pub fn new() -> Self {
+        C669Channel {
+            effect: FX_NONE,
+            ..Default::default()
+        }
+    }
+}
+
+
+impl FormatPlayer for C669Player {
+    fn start(&mut self, data: &mut PlayerData, mdata: &ModuleData, mixer: &mut Mixer) {
+
+        let module = mdata.as_any().downcast_ref::<C669Data>().unwrap();
+
+        self.pos = data.pos;
+        self.row = 0;
+        self.tick = 0;
+        self.speed = self.pattern_speed(&module);
+
+        data.speed = self.speed;
+        data.tempo = TEMPO;
+        data.time  = 0.0;
+
+        data.initial_speed = data.speed;
+        data.initial_tempo = data.tempo;
+
+        let pan = match self.options.option_int("pan") {
+            Some(val) => val,
+            None      => 70,
+        };
+        let panl = -128 * pan / 100;
+        let panr = 127 * pan / 100;
+
+        for chn in 0..8 {
+            self.channels[chn] = C669Channel::new();
+            self.channels[chn].pan = if chn & 1 == 0 { panl } else { panr };
+            mixer.set_pan(chn, self.channels[chn].pan);
+        }
+    }
+
+    fn play(&mut self, data: &mut PlayerData, mdata: &ModuleData, mut mixer: &mut Mixer) {
+
+        let module = mdata.as_any().downcast_ref::<C669Data>().unwrap();
+
+        if self.tick == 0 {
+            self.play_row(&module, &mut mixer);
+        }
+        self.play_effects(&mut mixer);
+
+        for chn in 0..8 {
+            let ch = &self.channels[chn];
+            let freq = ch.freq + ch.vib_delta;
+            if freq > 0 {
+                mixer.set_period(chn, PERIOD_BASE / freq as f64);
+            }
+            mixer.set_volume(chn, ch.volume as usize * 16);
+        }
+
+        // report the position of the next frame, as the other players do
+        self.next_tick(&module);
+
+        data.frame = self.tick;
+        data.row = self.row;
+        data.pos = self.pos;
+        data.speed = self.speed;
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.row = 0;
+        self.tick = 0;
+    }
+
+    fn save_state(&self) -> State {
+        scan::save_state(self)
+    }
+
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use format::c669::c669_module;
+    use player::TestPlayer;
+    use ::*;
+
+    #[test]
+    fn test_play_669() {
+        let b = c669_module("if");
+        let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+        assert_eq!(ox.player_info().unwrap().id, "669");
+
+        // per-pattern tempo and break location, ticks are 2500/78 ms long
+        let songs = ox.subsongs();
+        assert_eq!(songs[0].time, ((16 * 4 + 32 * 6) as f32 * 2500.0 / 78.0) as u32);
+        assert_eq!((songs[0].loop_pos, songs[0].loop_row), (1, 0));
+
+        // ticks are 2500/78 ms long, 1413 sample frames at 44.1 kHz
+        ox.play_frame();
+        assert_eq!(ox.buffer().len(), 2 * 44100 * 250 / 7800);
+        assert!(ox.buffer().iter().any(|&x| x != 0));
+
+        // channels have fixed left/right panning
+        let mut fi = FrameInfo::new();
+        ox.frame_info(&mut fi);
+        let pan = fi.channel_info.iter().take(4).map(|x| x.pan).collect::<Vec<i8>>();
+        assert_eq!(pan, vec![-89, 88, -89, 88]);
+
+        // the slide up runs in every tick, including the first tick of each
+        // row, and keeps running on the following rows: 4 Hz per unit of
+        // the parameter from the C-4 frequency of 8363 Hz
+        let mut p = TestPlayer::new(&b, "669");
+        for tick in 1..13 {
+            p.play();
+            let freq = 8363.0 + 8.0 * tick as f64;
+            assert!((p.mixer.period(1) - 428.0 * 8287.0 / freq).abs() < 1e-6);
+        }
+        assert_eq!(p.data.row, 3);
+    }
+}
@@ -1,8 +1,9 @@
 use module::{Module, ModuleData};
 use player::{Options, PlayerData, FormatPlayer, State};
-use player::scan::SaveRestore;
+use player::scan::{self, SaveRestore};
 use format::mk::ModData;
 use mixer::Mixer;
+use Error;
 
 /// FT101 Replayer
 ///
@@ -785,7 +786,7 @@ lazy_static! {
 }
 
 
-#[derive(Clone,Copy,Default)]
+#[derive(Clone,Copy,Default,SaveRestore)]
 struct ChannelData {
     n_length       : u16,
     n_loopstart    : u16,
@@ -832,6 +833,8 @@ impl FormatPlayer for FtPlayer {
         data.initial_speed = data.speed;
         data.initial_tempo = data.tempo;
 
+        self.ft_song_pos = data.pos as u8;
+
         let pan = match self.options.option_int("pan") {
             Some(val) => val,
             None      => 70,
@@ -877,7 +880,6 @@ impl FormatPlayer for FtPlayer {
         data.pos = self.ft_song_pos as usize;
         data.speed = self.ft_speed as usize;
         data.tempo = self.cia_tempo as f32;
-        data.time += 20.0 * 125.0 / data.tempo as f32;
 
         if self.position_jump_cmd {
             data.pos = self.ft_song_pos.wrapping_add(1) as usize;
@@ -902,12 +904,12 @@ impl FormatPlayer for FtPlayer {
         self.ft_pattern_pos     = 0;
     }
 
-    unsafe fn save_state(&self) -> State {
-        self.save()
+    fn save_state(&self) -> State {
+        scan::save_state(self)
     }
 
-    unsafe fn restore_state(&mut self, state: &State) {
-        self.restore(&state)
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
     }
 }
 
@@ -2,9 +2,10 @@ use std::cmp;
 use std::f64::consts::PI;
 use module::{Module, ModuleData};
 use player::{Options, PlayerData, FormatPlayer, State};
-use player::scan::SaveRestore;
+use player::scan::{self, SaveRestore};
 use format::xm::{XmData, TonTyp};
 use mixer::Mixer;
+use Error;
 
 const IS_VOL     : u8 = 1;
 const IS_PERIOD  : u8 = 2;
@@ -12,7 +13,7 @@ const IS_NYTON   : u8 = 4;
 const IS_PAN     : u8 = 8;
 const IS_QUICKVOL: u8 = 16;
 
-#[derive(Default)]
+#[derive(Default, SaveRestore)]
 struct SongTyp {
     len            : u16,
     rep_s          : u16,
@@ -87,7 +88,7 @@ struct InstrTyp {
 }
 */
 
-#[derive(Default)]
+#[derive(Default, SaveRestore)]
 struct StmTyp {
     out_vol               : i8,
     real_vol              : i8,
@@ -2181,10 +2182,12 @@ impl Ft2Play {
 
 
 impl FormatPlayer for Ft2Play {
-    fn start(&mut self, data: &mut PlayerData, mdata: &ModuleData, _mixer: &mut Mixer) {
+    fn start(&mut self, data: &mut PlayerData, mdata: &ModuleData, mixer: &mut Mixer) {
 
         let module = mdata.as_any().downcast_ref::<XmData>().unwrap();
 
+        mixer.enable_ramp(true);
+
         let h = &module.header;
 
         for p in &module.patterns {
@@ -2235,7 +2238,8 @@ impl FormatPlayer for Ft2Play {
             self.note2period[1919..1936].copy_from_slice(&[16, 8, 0, 22, 16, 32, 24, 16, 8, 0, 26, 32, 24, 16, 8, 0, 0]);
         }
 
-        self.set_pos(0, 0, &module);
+        let pos = data.pos as i16;
+        self.set_pos(pos, 0, &module);
 
         data.speed = self.song.tempo as usize;
         data.tempo = self.song.speed as f32;
@@ -2253,10 +2257,9 @@ impl FormatPlayer for Ft2Play {
 
         data.frame = ((self.song.tempo - self.song.timer + 1) % self.song.tempo) as usize;
         data.row = self.song.patt_pos as usize;
-        data.pos = self.song.song_pos as usize;
+        data.pos = if self.song.song_pos >= 0 { self.song.song_pos as usize } else { 0 };  // song_pos is -1 after B00
         data.speed = self.song.tempo as usize;
         data.tempo = self.song.speed as f32;
-        data.time += 20.0 * 125.0 / data.tempo as f32;
 
         /*if self.position_jump_cmd {
             data.pos = self.ft_song_pos.wrapping_add(1) as usize;
@@ -2273,12 +2276,12 @@ impl FormatPlayer for Ft2Play {
     fn reset(&mut self) {
     }
 
-    unsafe fn save_state(&self) -> State {
-        self.save()
+    fn save_state(&self) -> State {
+        scan::save_state(self)
     }
 
-    unsafe fn restore_state(&mut self, state: &State) {
-        self.restore(&state);
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
     }
 }
 
@@ -0,0 +1,58 @@
+use format::convert;
+use module::Module;
+use ::*;
+
+
+pub fn from_mod(module: Module) -> Result<Module, Error> {
+
+    let c = convert::convert(&module, "xm")?;
+    for u in &c.unconverted {
+        debug!("import: {}", u);
+    }
+
+    let mut m = c.module;
+
+    m.format_id   = module.format_id;
+    m.description = "Imported M.K. module".to_owned();
+    m.creator     = "Fast Tracker 2".to_owned();
+
+    Ok(m)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+    use tests::test_mod;
+    use ::*;
+
+    // The 4 channel test module with 6 empty channels added to each row
+    fn test_xxch() -> Vec<u8> {
+        let mut b = test_mod("test");
+        b[1080..1084].copy_from_slice(b"10CH");
+        let pat = b[1084..2108].chunks(16).flat_map(|r| r.iter().cloned().chain(iter::repeat(0).take(24))).collect::<Vec<u8>>();
+        b.splice(1084..2108, pat);
+        b
+    }
+
+    #[test]
+    fn test_play_mod_ft2() {
+        for &(ref b, chn) in &[(test_mod("test"), 4), (test_xxch(), 10)] {
+            let mut ox = Oxdz::new(b, 44100, "ft2").unwrap();
+            assert_eq!(ox.player_info().unwrap().id, "ft2");
+            let mut mi = ModuleInfo::new();
+            ox.module_info(&mut mi);
+            assert_eq!(mi.channels, chn);
+
+            // the module alternates two notes every row at speed 6
+            assert_eq!(mi.total_time, 64 * 6 * 20);
+            let mut fi = FrameInfo::new();
+            for _ in 0..7 {
+                ox.play_frame();
+            }
+            ox.frame_info(&mut fi);
+            assert_eq!((fi.pos, fi.row), (0, 1));
+            assert!(ox.buffer().iter().any(|&x| x != 0));
+        }
+    }
+}
@@ -1,8 +1,9 @@
 mod ft2play;
+mod import;
 
 use module::Module;
 use player::{Options, PlayerListEntry, PlayerInfo, FormatPlayer};
-use ::Error;
+use ::*;
 
 pub struct Ft2;
 
@@ -13,7 +14,7 @@ impl PlayerListEntry for Ft2 {
           name       : "ft2play(ox) 0.86",
           description: "A port of the Fast Tracker 2.09a replayer",
           author     : r#"Olav "8bitbubsy" Sørensen, Claudio Matsuoka"#,
-          accepts    : &[ "xm", "m.k.", "xxch" ],
+          accepts    : &[ "xm", "m.k.", "xchn", "xxch" ],
        }
    }
 
@@ -22,6 +23,11 @@ impl PlayerListEntry for Ft2 {
    }
 
    fn import(&self, module: Module) -> Result<Module, Error> {
-       Ok(module)
+       match module.format_id {
+           "m.k." => import::from_mod(module),
+           "xchn" => import::from_mod(module),
+           "xxch" => import::from_mod(module),
+           _      => Ok(module),
+       }
    }
 }
@@ -1,8 +1,9 @@
 use module::{Module, ModuleData};
 use player::{Options, PlayerData, FormatPlayer, State};
-use player::scan::SaveRestore;
+use player::scan::{self, SaveRestore};
 use format::mk::ModData;
 use mixer::Mixer;
+use Error;
 
 /// His Master's Noise Replayer
 ///
@@ -527,7 +528,7 @@ impl HmnPlayer {
 }
 
 
-#[derive(Clone,Copy,Default)]
+#[derive(Clone,Copy,Default,SaveRestore)]
 struct ChannelData {
     n_0_note         : u16,
     n_2_cmd          : u8,
@@ -613,6 +614,8 @@ impl FormatPlayer for HmnPlayer {
         data.initial_speed = data.speed;
         data.initial_tempo = data.tempo;
 
+        self.l693_songpos = data.pos as u8;
+
         let pan = match self.options.option_int("pan") {
             Some(val) => val,
             None      => 70,
@@ -638,7 +641,6 @@ impl FormatPlayer for HmnPlayer {
         data.row = self.l692_pattpos as usize;
         data.pos = self.l693_songpos as usize;
         data.speed = self.l642_speed as usize;
-        data.time += 20.0;
     }
 
     fn reset(&mut self) {
@@ -649,12 +651,12 @@ impl FormatPlayer for HmnPlayer {
         self.l692_pattpos = 0;
     }
 
-    unsafe fn save_state(&self) -> State {
-        self.save()
+    fn save_state(&self) -> State {
+        scan::save_state(self)
     }
 
-    unsafe fn restore_state(&mut self, state: &State) {
-        self.restore(&state)
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
     }
 }
 
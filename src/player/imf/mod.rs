@@ -0,0 +1,27 @@
+mod player;
+
+use module::Module;
+use player::{Options, PlayerListEntry, PlayerInfo, FormatPlayer};
+use ::*;
+
+pub struct Imf;
+
+impl PlayerListEntry for Imf {
+   fn info(&self) -> PlayerInfo {
+       PlayerInfo {
+          id         : "imf",
+          name       : "Imago Orpheus replayer",
+          description: "A replayer for Imago Orpheus modules",
+          author     : "Claudio Matsuoka",
+          accepts    : &[ "imf" ],
+       }
+   }
+
+   fn player(&self, module: &Module, options: Options) -> Box<FormatPlayer> {
+       Box::new(self::player::ImfPlayer::new(module, options))
+   }
+
+   fn import(&self, module: Module) -> Result<Module, Error> {
+       Ok(module)
+   }
+}
@@ -0,0 +1,1837 @@
+use std::cmp;
+use std::f64::consts::PI;
+use module::{Module, ModuleData};
+use player::{Options, PlayerData, FormatPlayer, State};
+use player::scan::{self, SaveRestore};
+use format::it::*;
+use mixer::Mixer;
+use Error;
+
+/// IT replayer
+///
+/// An Impulse Tracker 2.14 compatible replayer that follows the structure
+/// of the playroutine in IT_MUSIC.ASM and IT_M_EFF.INC, but is not a
+/// line-by-line port of that code. Pattern data is processed in host
+/// channels, one per pattern channel, and notes are played in slave
+/// (virtual) channels. New note actions and duplicate note checks move
+/// slaves to the background, so several notes can sound on the same host
+/// channel. Each slave channel is a mixer voice.
+///
+/// Differences from the original:
+///
+/// - MIDI output is not supported, Zxx only handles the default filter
+///   macros (SF0 cutoff and the Z80-Z8F resonance table)
+/// - Stereo samples play the left channel only
+/// - Linear slide tables are computed instead of using the hardcoded
+///   tables, so slides can be one unit off from Impulse Tracker
+///
+
+const MAX_HOST_CHANNELS : usize = 64;
+const MAX_SLAVE_CHANNELS: usize = 256;
+const NO_SLAVE          : u16 = 0xffff;
+
+// Slave channel flags
+const SF_ON         : u16 = 0x0001;
+const SF_KEY_OFF    : u16 = 0x0002;
+const SF_NOTE_FADE  : u16 = 0x0004;
+const SF_DISOWNED   : u16 = 0x0008;
+const SF_NEW_NOTE   : u16 = 0x0010;
+const SF_UPDATE_LOOP: u16 = 0x0020;
+const SF_CUT        : u16 = 0x0040;
+const SF_VOL_ENV    : u16 = 0x0100;
+const SF_PAN_ENV    : u16 = 0x0200;
+const SF_PIT_ENV    : u16 = 0x0400;
+
+// New note actions
+const NNA_CUT       : u8 = 0;
+const NNA_OFF       : u8 = 2;
+const NNA_FADE      : u8 = 3;
+
+// Duplicate check actions
+const DCA_CUT       : u8 = 0;
+const DCA_OFF       : u8 = 1;
+
+const PAN_SURROUND  : u8 = 100;
+const NO_VOLUME     : u8 = 0xff;
+
+// The mixer takes Amiga periods (C4 period * C4 rate / frequency)
+const MIXER_PERIOD_BASE: f64 = 428.0 * 8287.0;
+
+// Amiga slides are done in ST3 period units
+const AMIGA_BASE: i64 = 1712 * 8363;
+
+static VOLUME_COLUMN_PORTA: [u8; 10] = [ 0, 1, 4, 8, 16, 32, 64, 96, 128, 255 ];
+
+// PitchTable: 2^((note - 60) / 12) in 16.16 fixed point
+static PITCH_TABLE: [u32; 120] = [
+       2048,    2170,    2299,    2435,    2580,    2734,  // C-0
+       2896,    3069,    3251,    3444,    3649,    3866,  // F#0
+       4096,    4340,    4598,    4871,    5161,    5468,  // C-1
+       5793,    6137,    6502,    6889,    7298,    7732,  // F#1
+       8192,    8679,    9195,    9742,   10321,   10935,  // C-2
+      11585,   12274,   13004,   13777,   14596,   15464,  // F#2
+      16384,   17358,   18390,   19484,   20643,   21870,  // C-3
+      23170,   24548,   26008,   27554,   29193,   30929,  // F#3
+      32768,   34716,   36781,   38968,   41285,   43740,  // C-4
+      46341,   49097,   52016,   55109,   58386,   61858,  // F#4
+      65536,   69433,   73562,   77936,   82570,   87480,  // C-5
+      92682,   98193,  104032,  110218,  116772,  123715,  // F#5
+     131072,  138866,  147123,  155872,  165140,  174960,  // C-6
+     185364,  196386,  208064,  220436,  233544,  247431,  // F#6
+     262144,  277732,  294247,  311744,  330281,  349920,  // C-7
+     370728,  392772,  416128,  440872,  467088,  494862,  // F#7
+     524288,  555464,  588493,  623487,  660561,  699841,  // C-8
+     741455,  785544,  832255,  881744,  934175,  989724,  // F#8
+    1048576, 1110928, 1176987, 1246974, 1321123, 1399681,  // C-9
+    1482910, 1571089, 1664511, 1763488, 1868350, 1979448,  // F#9
+];
+
+
+// TABLES
+lazy_static! {
+    static ref FINE_SINE_DATA: Box<[i8; 256]> = {
+        let mut tab = Box::new([0; 256]);
+        for i in 0..256 {
+            tab[i] = (64.0 * (i as f64 * 2.0 * PI / 256.0).sin()).round() as i8;
+        }
+        tab
+    };
+
+    static ref FINE_RAMP_DOWN_DATA: Box<[i8; 256]> = {
+        let mut tab = Box::new([0; 256]);
+        for i in 0..256 {
+            tab[i] = (64 - (i as i32 + 1) / 2) as i8;
+        }
+        tab
+    };
+
+    static ref FINE_SQUARE_WAVE: Box<[i8; 256]> = {
+        let mut tab = Box::new([0; 256]);
+        for i in 0..128 {
+            tab[i] = 64;
+        }
+        tab
+    };
+
+    // 2^(i/192) * 65536
+    static ref LINEAR_SLIDE_UP_TABLE: Box<[u32; 256]> = {
+        let mut tab = Box::new([0; 256]);
+        for i in 0..256 {
+            tab[i] = (65536.0 * 2.0_f64.powf(i as f64 / 192.0)).round() as u32;
+        }
+        tab
+    };
+
+    // 2^(-i/192) * 65536
+    static ref LINEAR_SLIDE_DOWN_TABLE: Box<[u32; 256]> = {
+        let mut tab = Box::new([0; 256]);
+        for i in 0..256 {
+            tab[i] = cmp::min((65536.0 * 2.0_f64.powf(-(i as f64) / 192.0)).round() as u32, 65535);
+        }
+        tab
+    };
+
+    // 2^(i/768) * 65536
+    static ref FINE_LINEAR_SLIDE_UP_TABLE: Box<[u32; 16]> = {
+        let mut tab = Box::new([0; 16]);
+        for i in 0..16 {
+            tab[i] = (65536.0 * 2.0_f64.powf(i as f64 / 768.0)).round() as u32;
+        }
+        tab
+    };
+
+    // 2^(-i/768) * 65536
+    static ref FINE_LINEAR_SLIDE_DOWN_TABLE: Box<[u32; 16]> = {
+        let mut tab = Box::new([0; 16]);
+        for i in 0..16 {
+            tab[i] = cmp::min((65536.0 * 2.0_f64.powf(-(i as f64) / 768.0)).round() as u32, 65535);
+        }
+        tab
+    };
+}
+
+
+// Slide units are 1/64 of a semitone
+fn linear_slide_up(freq: u32, mut val: u32) -> u32 {
+    let mut f = freq as u64;
+    if val < 16 && val & 3 != 0 {
+        f = (f * FINE_LINEAR_SLIDE_UP_TABLE[val as usize] as u64) >> 16;
+    } else {
+        while val >= 4 {
+            let n = cmp::min(val >> 2, 255);
+            f = (f * LINEAR_SLIDE_UP_TABLE[n as usize] as u64) >> 16;
+            val -= n << 2;
+        }
+        if val > 0 {
+            f = (f * FINE_LINEAR_SLIDE_UP_TABLE[val as usize] as u64) >> 16;
+        }
+    }
+    cmp::min(f, 0x7fff_ffff) as u32
+}
+
+fn linear_slide_down(freq: u32, mut val: u32) -> u32 {
+    let mut f = freq as u64;
+    if val < 16 && val & 3 != 0 {
+        f = (f * FINE_LINEAR_SLIDE_DOWN_TABLE[val as usize] as u64) >> 16;
+    } else {
+        while val >= 4 {
+            let n = cmp::min(val >> 2, 255);
+            f = (f * LINEAR_SLIDE_DOWN_TABLE[n as usize] as u64) >> 16;
+            val -= n << 2;
+        }
+        if val > 0 {
+            f = (f * FINE_LINEAR_SLIDE_DOWN_TABLE[val as usize] as u64) >> 16;
+        }
+    }
+    f as u32
+}
+
+// Positive values slide up (decrease the period)
+fn amiga_slide(freq: u32, delta: i64) -> u32 {
+    if freq == 0 {
+        return 0
+    }
+    let mut period = AMIGA_BASE / freq as i64 - delta;
+    if period < 1 {
+        period = 1;
+    }
+    (AMIGA_BASE / period) as u32
+}
+
+
+// STRUCTS
+
+#[derive(Clone, Copy, Default, SaveRestore)]
+struct EnvState {
+    value: i32,    // 16.16 fixed point
+    delta: i32,
+    tick : i32,
+    next : i32,    // tick of the next node
+    node : usize,  // next node to be processed
+    end  : bool,
+}
+
+impl EnvState {
+    // Return the envelope value for this tick and move to the next tick
+    fn update(&mut self, env: &ItEnvelope, released: bool) -> i32 {
+        let num = env.node.len();
+        if num == 0 {
+            return 0
+        }
+
+        if self.tick < self.next {
+            self.value += self.delta;
+            self.tick += 1;
+            return self.value
+        }
+
+        let mut n = cmp::min(self.node, num - 1);
+
+        // Sustain loop takes precedence over the envelope loop until released
+        let sustain = env.flg & IT_ENV_SUSTAIN != 0 && !released;
+        let (lpb, lpe) = if sustain {
+            (env.slb as usize, env.sle as usize)
+        } else {
+            (env.lpb as usize, env.lpe as usize)
+        };
+
+        if (sustain || env.flg & IT_ENV_LOOP != 0) && lpe < num && lpb <= lpe && n == lpe {
+            n = lpb;
+            if lpb == lpe {
+                self.value = (env.node[n].0 as i32) << 16;
+                self.delta = 0;
+                self.node = n;
+                self.next = self.tick;
+                return self.value
+            }
+        }
+
+        if n >= num - 1 {
+            self.value = (env.node[num - 1].0 as i32) << 16;
+            self.delta = 0;
+            self.node = num - 1;
+            self.next = self.tick;
+            self.end = true;
+            return self.value
+        }
+
+        let (y1, t1) = env.node[n];
+        let (y2, t2) = env.node[n + 1];
+        let dt = cmp::max(t2 as i32 - t1 as i32, 1);
+        self.value = (y1 as i32) << 16;
+        self.delta = ((y2 as i32 - y1 as i32) << 16) / dt;
+        self.node = n + 1;
+        self.next = t2 as i32;
+        self.tick = t1 as i32 + 1;
+        self.value
+    }
+}
+
+#[derive(Clone, Copy, Default, SaveRestore)]
+struct HostChannel {
+    // pattern data for the current row
+    msk         : u8,
+    nte         : u8,
+    ins         : u8,
+    vlc         : u8,
+    cmd         : u8,
+    val         : u8,
+
+    last_ins    : u8,
+    smp         : u8,
+    nt2         : u8,
+    scn         : u16,   // slave channel playing the foreground note
+    freq        : u32,
+    porta_target: u32,
+    vol         : u8,
+    cv          : u8,    // channel volume
+    cp          : u8,    // channel pan
+    disabled    : bool,
+    cutoff      : u8,
+    resonance   : u8,
+    sfx         : u8,    // active MIDI macro
+
+    // effect memory
+    mem_dkl     : u8,
+    mem_ef      : u8,
+    mem_g       : u8,
+    mem_i       : u8,
+    mem_j       : u8,
+    mem_n       : u8,
+    mem_o       : u8,
+    mem_p       : u8,
+    mem_q       : u8,
+    mem_s       : u8,
+    mem_t       : u8,
+    mem_w       : u8,
+    mem_vlc     : u8,
+    high_offset : u8,
+
+    vib_speed   : u8,
+    vib_depth   : u8,
+    vib_wave    : u8,
+    vib_pos     : u8,
+    trm_speed   : u8,
+    trm_depth   : u8,
+    trm_wave    : u8,
+    trm_pos     : u8,
+    pbr_speed   : u8,
+    pbr_depth   : u8,
+    pbr_wave    : u8,
+    pbr_pos     : u8,
+    pbr_val     : i8,
+    tremor_cnt  : u8,
+    tremor_on   : bool,
+    retrig_cnt  : u8,
+    loop_row    : u8,
+    loop_cnt    : u8,
+    note_delay  : u8,
+    note_cut    : u8,
+    note_on     : bool,  // a note was triggered in this row
+
+    // modifiers for the current tick
+    vib_delta   : i32,
+    arp_semi    : u8,
+    trm_delta   : i32,
+    pbr_delta   : i32,
+    tremor_mute : bool,
+}
+
+#[derive(Clone, Copy, Default, SaveRestore)]
+struct SlaveChannel {
+    flags    : u16,
+    hcn      : u8,   // host channel number
+    nna      : u8,
+    ins      : u8,
+    smp      : u8,
+    nte      : u8,
+    vol      : u8,
+    cv       : u8,
+    svl      : u8,   // sample global volume * instrument global volume
+    pan      : u8,
+    pan_ofs  : i8,   // pitch-pan separation and random pan variation
+    freq     : u32,
+    fadeout  : i32,
+    fv       : u32,  // final volume
+    start_pos: u32,
+    cutoff   : u8,
+    resonance: u8,
+    av_pos   : u8,
+    av_depth : u16,
+    vol_env  : EnvState,
+    pan_env  : EnvState,
+    pit_env  : EnvState,
+}
+
+
+#[derive(SaveRestore)]
+pub struct ItPlay {
+    num_channels : usize,
+    speed        : u8,
+    tempo        : u8,
+    tick         : usize,
+    tick_delay   : usize,
+    row_delay    : u8,
+    row_delay_on : bool,
+    gv           : u8,
+    mv           : u8,
+    sep          : u8,
+    cur_order    : usize,
+    cur_pattern  : usize,
+    cur_row      : usize,
+    num_rows     : usize,
+    first_row    : bool,
+    break_flag   : bool,
+    break_row    : usize,
+    jump_flag    : bool,
+    jump_order   : usize,
+    loop_flag    : bool,
+    loop_row     : usize,
+    linear_slides: bool,
+    old_effects  : bool,
+    link_gxx     : bool,
+    stereo       : bool,
+    ins_mode     : bool,
+    seed         : u32,
+
+    hc           : [HostChannel; MAX_HOST_CHANNELS],
+    sc           : [SlaveChannel; MAX_SLAVE_CHANNELS],
+
+    inside_loop  : bool,  // for oxdz scan control
+}
+
+
+// CODE START
+
+impl ItPlay {
+    pub fn new(_module: &Module, _options: Options) -> Self {
+        ItPlay {
+            num_channels : 0,
+            speed        : 6,
+            tempo        : 125,
+            tick         : 0,
+            tick_delay   : 0,
+            row_delay    : 0,
+            row_delay_on : false,
+            gv           : 128,
+            mv           : 48,
+            sep          : 128,
+            cur_order    : 0,
+            cur_pattern  : 0,
+            cur_row      : 0,
+            num_rows     : 0,
+            first_row    : true,
+            break_flag   : false,
+            break_row    : 0,
+            jump_flag    : false,
+            jump_order   : 0,
+            loop_flag    : false,
+            loop_row     : 0,
+            linear_slides: false,
+            old_effects  : false,
+            link_gxx     : false,
+            stereo       : true,
+            ins_mode     : false,
+            seed         : 0x1234,
+            hc           : [HostChannel::default(); MAX_HOST_CHANNELS],
+            sc           : [SlaveChannel::default(); MAX_SLAVE_CHANNELS],
+            inside_loop  : false,
+        }
+    }
+
+    fn random(&mut self) -> u32 {
+        self.seed = self.seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (self.seed >> 16) & 0x7fff
+    }
+
+    fn waveform(&mut self, wave: u8, pos: u8) -> i32 {
+        match wave & 3 {
+            0 => FINE_SINE_DATA[pos as usize] as i32,
+            1 => FINE_RAMP_DOWN_DATA[pos as usize] as i32,
+            2 => FINE_SQUARE_WAVE[pos as usize] as i32,
+            _ => (self.random() & 0x7f) as i32 - 64,
+        }
+    }
+
+    fn pitch_slide_up(&self, freq: u32, val: u32) -> u32 {
+        if self.linear_slides {
+            linear_slide_up(freq, val)
+        } else {
+            amiga_slide(freq, val as i64)
+        }
+    }
+
+    fn pitch_slide_down(&self, freq: u32, val: u32) -> u32 {
+        if self.linear_slides {
+            linear_slide_down(freq, val)
+        } else {
+            amiga_slide(freq, -(val as i64))
+        }
+    }
+
+    fn pitch_slide(&self, freq: u32, delta: i32) -> u32 {
+        if delta > 0 {
+            self.pitch_slide_up(freq, delta as u32)
+        } else if delta < 0 {
+            self.pitch_slide_down(freq, -delta as u32)
+        } else {
+            freq
+        }
+    }
+
+    // Mul C5Speed / ShRD EAX, EDX, 16
+    fn note_freq(&self, note: u8, smp: u8, module: &ItData) -> u32 {
+        let c5speed = module.smp_headers[smp as usize - 1].c5speed as u64;
+        let pitch = PITCH_TABLE[cmp::min(note as usize, 119)] as u64;
+        ((pitch * c5speed) >> 16) as u32
+    }
+
+    fn set_order(&mut self, ord: usize, module: &ItData) {
+        let len = module.orders.len();
+        let mut ord = ord;
+
+        // skip "+++" markers, restart on "---"
+        let mut n = 0;
+        while n <= len {
+            if ord >= len || module.orders[ord] == 255 {
+                ord = 0;
+            }
+            if ord < len && module.orders[ord] == 254 {
+                ord += 1;
+                n += 1;
+                continue
+            }
+            break
+        }
+
+        self.cur_order = ord;
+        self.cur_pattern = if ord < len { module.orders[ord] as usize } else { 0 };
+        self.num_rows = module.rows(self.cur_pattern);
+    }
+
+    fn next_row(&mut self, module: &ItData) {
+        if self.loop_flag {
+            self.loop_flag = false;
+            self.cur_row = self.loop_row;
+            return
+        }
+
+        if self.jump_flag || self.break_flag {
+            let ord = if self.jump_flag { self.jump_order } else { self.cur_order + 1 };
+            let row = if self.break_flag { self.break_row } else { 0 };
+            self.jump_flag = false;
+            self.break_flag = false;
+            self.set_order(ord, module);
+            self.cur_row = if row < self.num_rows { row } else { 0 };
+            return
+        }
+
+        self.cur_row += 1;
+        if self.cur_row >= self.num_rows {
+            let ord = self.cur_order + 1;
+            self.set_order(ord, module);
+            self.cur_row = 0;
+        }
+    }
+
+    fn foreground_slave(&self, ch: usize) -> Option<usize> {
+        let s = self.hc[ch].scn as usize;
+        if s < MAX_SLAVE_CHANNELS {
+            let sc = &self.sc[s];
+            if sc.flags & SF_ON != 0 && sc.flags & SF_DISOWNED == 0 && sc.hcn as usize == ch {
+                return Some(s)
+            }
+        }
+        None
+    }
+
+    fn allocate_slave(&mut self, ch: usize) -> usize {
+        if !self.ins_mode {
+            return ch
+        }
+
+        for i in 0..MAX_SLAVE_CHANNELS {
+            if self.sc[i].flags & SF_ON == 0 {
+                return i
+            }
+        }
+
+        // No free channels, use the quietest background channel
+        let mut found = None;
+        let mut min_vol = u32::max_value();
+        for i in 0..MAX_SLAVE_CHANNELS {
+            let sc = &self.sc[i];
+            if sc.flags & SF_DISOWNED != 0 && sc.fv < min_vol {
+                min_vol = sc.fv;
+                found = Some(i);
+            }
+        }
+
+        match found {
+            Some(i) => i,
+            None    => ch,
+        }
+    }
+
+    fn key_off(&mut self, s: usize, module: &ItData) {
+        let ins_mode = self.ins_mode;
+        let sc = &mut self.sc[s];
+        sc.flags |= SF_KEY_OFF | SF_UPDATE_LOOP;
+        if ins_mode && sc.ins > 0 {
+            let ins = &module.instruments[sc.ins as usize - 1];
+            if sc.flags & SF_VOL_ENV == 0 || ins.vol_env.flg & IT_ENV_LOOP != 0 {
+                sc.flags |= SF_NOTE_FADE;
+            }
+        }
+    }
+
+    fn note_cut(&mut self, s: usize) {
+        self.sc[s].flags = SF_CUT;
+    }
+
+    fn note_fade(&mut self, s: usize) {
+        self.sc[s].flags |= SF_NOTE_FADE;
+    }
+
+    fn trigger_note(&mut self, ch: usize, note: u8, nt2: u8, smp: u8, module: &ItData) {
+        let ins_num = if self.ins_mode { self.hc[ch].last_ins } else { 0 };
+        let old = self.foreground_slave(ch);
+
+        let mut reuse = None;
+        let mut carry = None;
+
+        if self.ins_mode {
+            // New note action
+            if let Some(s) = old {
+                if self.sc[s].ins == ins_num {
+                    carry = Some(self.sc[s]);
+                }
+                match self.sc[s].nna {
+                    NNA_CUT  => reuse = Some(s),
+                    nna      => {
+                        self.sc[s].flags |= SF_DISOWNED;
+                        if nna == NNA_OFF {
+                            self.key_off(s, module);
+                        } else if nna == NNA_FADE {
+                            self.note_fade(s);
+                        }
+                    }
+                }
+            }
+
+            // Duplicate check
+            let instrument = &module.instruments[ins_num as usize - 1];
+            if instrument.dct != 0 {
+                for i in 0..MAX_SLAVE_CHANNELS {
+                    if Some(i) == reuse {
+                        continue
+                    }
+                    let sc = self.sc[i];
+                    if sc.flags & SF_ON == 0 || sc.hcn as usize != ch || sc.ins != ins_num {
+                        continue
+                    }
+                    let dup = match instrument.dct {
+                        1 => sc.nte == note,
+                        2 => sc.smp == smp,
+                        _ => true,
+                    };
+                    if dup {
+                        match instrument.dca {
+                            DCA_CUT => self.note_cut(i),
+                            DCA_OFF => self.key_off(i, module),
+                            _       => self.note_fade(i),
+                        }
+                    }
+                }
+            }
+        } else if let Some(s) = old {
+            reuse = Some(s);
+        }
+
+        let s = match reuse {
+            Some(s) => s,
+            None    => self.allocate_slave(ch),
+        };
+
+        let sh = &module.smp_headers[smp as usize - 1];
+
+        // Sample offset
+        let mut start_pos = 0;
+        if self.hc[ch].cmd == cmd('O') {
+            start_pos = ((self.hc[ch].high_offset as u32) << 16) | ((self.hc[ch].val as u32) << 8);
+            if start_pos >= sh.length {
+                start_pos = if self.old_effects { sh.length } else { 0 };
+            }
+        }
+
+        let mut sc = SlaveChannel::default();
+        sc.flags     = SF_ON | SF_NEW_NOTE;
+        sc.hcn       = ch as u8;
+        sc.ins       = ins_num;
+        sc.smp       = smp;
+        sc.nte       = note;
+        sc.nna       = NNA_CUT;
+        sc.svl       = cmp::min(sh.gvl, 64);
+        sc.fadeout   = 1024;
+        sc.start_pos = start_pos;
+
+        if self.ins_mode {
+            let ins = &module.instruments[ins_num as usize - 1];
+            sc.nna = ins.nna & 3;
+            sc.svl = ((sc.svl as u32 * cmp::min(ins.gbv, 128) as u32) >> 7) as u8;
+
+            if ins.vol_env.is_on() { sc.flags |= SF_VOL_ENV }
+            if ins.pan_env.is_on() { sc.flags |= SF_PAN_ENV }
+            if ins.pit_env.is_on() { sc.flags |= SF_PIT_ENV }
+
+            // Envelope carry
+            if let Some(prev) = carry {
+                if ins.vol_env.flg & IT_ENV_CARRY != 0 { sc.vol_env = prev.vol_env }
+                if ins.pan_env.flg & IT_ENV_CARRY != 0 { sc.pan_env = prev.pan_env }
+                if ins.pit_env.flg & IT_ENV_CARRY != 0 { sc.pit_env = prev.pit_env }
+            }
+
+            // Pitch-pan separation
+            let mut pan_ofs = 0;
+            if ins.pps != 0 {
+                pan_ofs = ((nt2 as i32 - ins.ppc as i32) * ins.pps as i32) / 8;
+            }
+
+            // Random volume and pan variation
+            if ins.rv > 0 {
+                let rv = cmp::min(ins.rv, 100) as i32;
+                let r = (self.random() % (2 * rv as u32 + 1)) as i32 - rv;
+                let svl = sc.svl as i32 + sc.svl as i32 * r / 100;
+                sc.svl = clip(svl, 0, 64) as u8;
+            }
+            if ins.rp > 0 {
+                let rp = cmp::min(ins.rp, 64) as i32;
+                pan_ofs += (self.random() % (2 * rp as u32 + 1)) as i32 - rp;
+            }
+            sc.pan_ofs = clip(pan_ofs, -64, 64) as i8;
+
+            // Instrument filter
+            if ins.ifc & 0x80 != 0 {
+                self.hc[ch].cutoff = ins.ifc & 0x7f;
+            }
+            if ins.ifr & 0x80 != 0 {
+                self.hc[ch].resonance = ins.ifr & 0x7f;
+            }
+        }
+
+        self.sc[s] = sc;
+
+        let freq = self.note_freq(nt2, smp, module);
+        let hc = &mut self.hc[ch];
+        hc.scn = s as u16;
+        hc.smp = smp;
+        hc.nt2 = nt2;
+        hc.freq = freq;
+        hc.porta_target = freq;
+        hc.note_on = true;
+    }
+
+    fn init_note(&mut self, ch: usize, module: &ItData) {
+        let msk = self.hc[ch].msk;
+        let note = self.hc[ch].nte;
+        let ins = self.hc[ch].ins;
+
+        // Resolve sample offset memory before the note is triggered
+        if self.hc[ch].cmd == cmd('O') {
+            let hc = &mut self.hc[ch];
+            if hc.val == 0 { hc.val = hc.mem_o } else { hc.mem_o = hc.val }
+        }
+
+        let porta = {
+            let hc = &self.hc[ch];
+            hc.cmd == cmd('G') || hc.cmd == cmd('L') || (hc.vlc >= 193 && hc.vlc <= 202)
+        };
+
+        let mut ins_valid = false;
+        if msk & IT_MASK_INS != 0 && ins != 0 {
+            let num = if self.ins_mode { module.instruments.len() } else { module.smp_headers.len() };
+            if ins as usize <= num {
+                self.hc[ch].last_ins = ins;
+                ins_valid = true;
+            }
+        }
+
+        let mut smp_used = 0;
+
+        if msk & IT_MASK_NOTE != 0 {
+            if note < 120 {
+                let last_ins = self.hc[ch].last_ins;
+                let (nt2, smp) = if last_ins == 0 {
+                    (note, 0)
+                } else if self.ins_mode {
+                    let k = module.instruments[last_ins as usize - 1].keyboard[note as usize];
+                    (k.0, k.1)
+                } else {
+                    (note, last_ins)
+                };
+
+                if smp != 0 && smp as usize <= module.smp_headers.len() && nt2 < 120 {
+                    smp_used = smp;
+                    match self.foreground_slave(ch) {
+                        Some(s) if porta => {
+                            self.hc[ch].porta_target = self.note_freq(nt2, smp, module);
+                            if self.link_gxx && ins_valid {
+                                let sc = &mut self.sc[s];
+                                sc.vol_env = EnvState::default();
+                                sc.pan_env = EnvState::default();
+                                sc.pit_env = EnvState::default();
+                            }
+                        }
+                        _ => self.trigger_note(ch, note, nt2, smp, module),
+                    }
+                }
+            } else if let Some(s) = self.foreground_slave(ch) {
+                if note == IT_NOTE_OFF {
+                    self.key_off(s, module);
+                } else if note == IT_NOTE_CUT {
+                    self.note_cut(s);
+                } else {
+                    self.note_fade(s);
+                }
+            }
+        }
+
+        // Default volume and pan
+        if ins_valid {
+            let smp = if smp_used != 0 {
+                smp_used
+            } else if self.ins_mode {
+                let nt = if self.hc[ch].nt2 < 120 { self.hc[ch].nt2 } else { 60 };
+                module.instruments[ins as usize - 1].keyboard[nt as usize].1
+            } else {
+                ins
+            };
+
+            if self.ins_mode {
+                let dfp = module.instruments[ins as usize - 1].dfp;
+                if dfp & 0x80 == 0 {
+                    self.hc[ch].cp = cmp::min(dfp, 64);
+                }
+            }
+
+            if smp != 0 && smp as usize <= module.smp_headers.len() {
+                let sh = &module.smp_headers[smp as usize - 1];
+                self.hc[ch].vol = cmp::min(sh.vol, 64);
+                if sh.dfp & 0x80 != 0 {
+                    self.hc[ch].cp = cmp::min(sh.dfp & 0x7f, 64);
+                }
+            }
+        }
+
+        // Volume column volume and pan
+        let v = self.hc[ch].vlc;
+        if v <= 64 {
+            self.hc[ch].vol = v;
+        } else if v >= 128 && v <= 192 {
+            self.hc[ch].cp = v - 128;
+        }
+    }
+
+    // Volume column, first tick
+    fn init_volume(&mut self, ch: usize) {
+        let link_gxx = self.link_gxx;
+        let old_effects = self.old_effects;
+        let v = self.hc[ch].vlc;
+        {
+            let hc = &mut self.hc[ch];
+            match v {
+                65..=74 => {   // fine volume up
+                    let x = v - 65;
+                    if x != 0 { hc.mem_vlc = x }
+                    hc.vol = cmp::min(hc.vol + hc.mem_vlc, 64);
+                }
+                75..=84 => {   // fine volume down
+                    let x = v - 75;
+                    if x != 0 { hc.mem_vlc = x }
+                    hc.vol = hc.vol.saturating_sub(hc.mem_vlc);
+                }
+                85..=94 => {   // volume slide up
+                    let x = v - 85;
+                    if x != 0 { hc.mem_vlc = x }
+                }
+                95..=104 => {  // volume slide down
+                    let x = v - 95;
+                    if x != 0 { hc.mem_vlc = x }
+                }
+                105..=124 => { // pitch slide down/up
+                    let x = (v - 105) % 10;
+                    if x != 0 { hc.mem_ef = x * 4 }
+                }
+                193..=202 => { // tone portamento
+                    let x = VOLUME_COLUMN_PORTA[(v - 193) as usize];
+                    if x != 0 {
+                        if link_gxx { hc.mem_ef = x } else { hc.mem_g = x }
+                    }
+                }
+                203..=212 => { // vibrato
+                    let x = v - 203;
+                    if x != 0 { hc.vib_depth = x * 4 }
+                }
+                _ => (),
+            }
+        }
+
+        if v >= 203 && v <= 212 && !old_effects {
+            self.vibrato(ch);
+        }
+    }
+
+    // Volume column, other ticks
+    fn update_volume(&mut self, ch: usize) {
+        let v = self.hc[ch].vlc;
+        match v {
+            85..=94 => {
+                let hc = &mut self.hc[ch];
+                hc.vol = cmp::min(hc.vol + hc.mem_vlc, 64);
+            }
+            95..=104 => {
+                let hc = &mut self.hc[ch];
+                hc.vol = hc.vol.saturating_sub(hc.mem_vlc);
+            }
+            105..=114 => {
+                let val = self.hc[ch].mem_ef as u32 * 4;
+                self.hc[ch].freq = self.pitch_slide_down(self.hc[ch].freq, val);
+            }
+            115..=124 => {
+                let val = self.hc[ch].mem_ef as u32 * 4;
+                self.hc[ch].freq = self.pitch_slide_up(self.hc[ch].freq, val);
+            }
+            193..=202 => {
+                self.tone_porta(ch);
+            }
+            203..=212 => {
+                self.vibrato(ch);
+            }
+            _ => (),
+        }
+    }
+
+    fn porta_speed(&self, ch: usize) -> u32 {
+        let hc = &self.hc[ch];
+        (if self.link_gxx { hc.mem_ef } else { hc.mem_g }) as u32 * 4
+    }
+
+    fn tone_porta(&mut self, ch: usize) {
+        if self.foreground_slave(ch).is_none() {
+            return
+        }
+        let speed = self.porta_speed(ch);
+        let freq = self.hc[ch].freq;
+        let target = self.hc[ch].porta_target;
+        if freq < target {
+            self.hc[ch].freq = cmp::min(self.pitch_slide_up(freq, speed), target);
+        } else if freq > target {
+            self.hc[ch].freq = cmp::max(self.pitch_slide_down(freq, speed), target);
+        }
+    }
+
+    fn vibrato(&mut self, ch: usize) {
+        let (wave, speed, depth) = {
+            let hc = &self.hc[ch];
+            (hc.vib_wave, hc.vib_speed, hc.vib_depth)
+        };
+        let pos = self.hc[ch].vib_pos.wrapping_add(speed);
+        self.hc[ch].vib_pos = pos;
+        let mut delta = (self.waveform(wave, pos) * depth as i32) >> 6;
+        if self.old_effects {
+            delta = -delta * 2;
+        }
+        self.hc[ch].vib_delta = delta;
+    }
+
+    fn tremolo(&mut self, ch: usize) {
+        let (wave, speed, depth) = {
+            let hc = &self.hc[ch];
+            (hc.trm_wave, hc.trm_speed, hc.trm_depth)
+        };
+        let pos = self.hc[ch].trm_pos.wrapping_add(speed);
+        self.hc[ch].trm_pos = pos;
+        self.hc[ch].trm_delta = (self.waveform(wave, pos) * depth as i32) >> 6;
+    }
+
+    fn panbrello(&mut self, ch: usize) {
+        let (wave, speed, depth) = {
+            let hc = &self.hc[ch];
+            (hc.pbr_wave, hc.pbr_speed, hc.pbr_depth)
+        };
+        let val = if wave & 3 == 3 {
+            // random waveform holds its value for speed ticks
+            if self.hc[ch].pbr_pos == 0 {
+                self.hc[ch].pbr_val = ((self.random() & 0x7f) as i32 - 64) as i8;
+                self.hc[ch].pbr_pos = speed;
+            } else {
+                self.hc[ch].pbr_pos -= 1;
+            }
+            self.hc[ch].pbr_val as i32
+        } else {
+            let pos = self.hc[ch].pbr_pos.wrapping_add(speed);
+            self.hc[ch].pbr_pos = pos;
+            self.waveform(wave, pos)
+        };
+        self.hc[ch].pbr_delta = (val * depth as i32) >> 5;
+    }
+
+    fn tremor(&mut self, ch: usize) {
+        let old_effects = self.old_effects;
+        let hc = &mut self.hc[ch];
+        let add = if old_effects { 0 } else { 1 };
+        let on = (hc.val >> 4) + add;
+        let off = (hc.val & 0x0f) + add;
+        if hc.tremor_cnt == 0 {
+            hc.tremor_on = !hc.tremor_on;
+            hc.tremor_cnt = cmp::max(if hc.tremor_on { on } else { off }, 1);
+        }
+        hc.tremor_cnt -= 1;
+        hc.tremor_mute = !hc.tremor_on;
+    }
+
+    fn retrig(&mut self, ch: usize) {
+        let val = self.hc[ch].val;
+        let interval = val & 0x0f;
+        if interval == 0 {
+            return
+        }
+
+        if self.hc[ch].retrig_cnt > 1 {
+            self.hc[ch].retrig_cnt -= 1;
+            return
+        }
+        self.hc[ch].retrig_cnt = interval;
+
+        {
+            let hc = &mut self.hc[ch];
+            let vol = hc.vol as i32;
+            let vol = match val >> 4 {
+                1   => vol - 1,
+                2   => vol - 2,
+                3   => vol - 4,
+                4   => vol - 8,
+                5   => vol - 16,
+                6   => vol * 2 / 3,
+                7   => vol / 2,
+                9   => vol + 1,
+                0xa => vol + 2,
+                0xb => vol + 4,
+                0xc => vol + 8,
+                0xd => vol + 16,
+                0xe => vol * 3 / 2,
+                0xf => vol * 2,
+                _   => vol,
+            };
+            hc.vol = clip(vol, 0, 64) as u8;
+        }
+
+        if let Some(s) = self.foreground_slave(ch) {
+            self.sc[s].flags |= SF_NEW_NOTE;
+            self.sc[s].start_pos = 0;
+        }
+    }
+
+    // Dxy-style slides: fine slides on the first tick
+    fn fine_slide(val: u8) -> i32 {
+        let (x, y) = ((val >> 4) as i32, (val & 0x0f) as i32);
+        if y == 0x0f && x != 0 {
+            x
+        } else if x == 0x0f && y != 0 {
+            -y
+        } else {
+            0
+        }
+    }
+
+    // Dxy-style slides: normal slides on the other ticks
+    fn normal_slide(val: u8) -> i32 {
+        let (x, y) = ((val >> 4) as i32, (val & 0x0f) as i32);
+        if x == 0 {
+            -y
+        } else if y == 0 {
+            x
+        } else {
+            0
+        }
+    }
+
+    // Command processing, first tick
+    fn init_command(&mut self, ch: usize, module: &ItData) {
+        let c = self.hc[ch].cmd;
+        if c == 0 {
+            return
+        }
+
+        let mut val = self.hc[ch].val;
+
+        match (c + 64) as char {
+            'A' => {  // set speed
+                if val != 0 {
+                    self.speed = val;
+                }
+            }
+            'B' => {  // jump to order
+                self.jump_flag = true;
+                self.jump_order = val as usize;
+                if !self.break_flag {
+                    self.break_row = 0;
+                }
+            }
+            'C' => {  // break to row
+                self.break_flag = true;
+                self.break_row = val as usize;
+            }
+            'D' | 'K' | 'L' => {  // volume slide
+                let hc = &mut self.hc[ch];
+                if val == 0 { val = hc.mem_dkl } else { hc.mem_dkl = val }
+                let d = Self::fine_slide(val);
+                hc.vol = clip(hc.vol as i32 + d, 0, 64) as u8;
+            }
+            'E' | 'F' => {  // pitch slide down/up
+                {
+                    let hc = &mut self.hc[ch];
+                    if val == 0 { val = hc.mem_ef } else { hc.mem_ef = val }
+                }
+                let amount = if val >= 0xf0 {
+                    (val as u32 & 0x0f) * 4
+                } else if val >= 0xe0 {
+                    val as u32 & 0x0f
+                } else {
+                    0
+                };
+                if amount > 0 {
+                    let freq = self.hc[ch].freq;
+                    self.hc[ch].freq = if c == cmd('E') {
+                        self.pitch_slide_down(freq, amount)
+                    } else {
+                        self.pitch_slide_up(freq, amount)
+                    };
+                }
+            }
+            'G' => {  // tone portamento
+                let link_gxx = self.link_gxx;
+                let hc = &mut self.hc[ch];
+                if link_gxx {
+                    if val == 0 { val = hc.mem_ef } else { hc.mem_ef = val }
+                } else {
+                    if val == 0 { val = hc.mem_g } else { hc.mem_g = val }
+                }
+            }
+            'H' | 'U' => {  // vibrato, fine vibrato
+                {
+                    let hc = &mut self.hc[ch];
+                    if val >> 4 != 0 {
+                        hc.vib_speed = (val >> 4) * 4;
+                    }
+                    if val & 0x0f != 0 {
+                        hc.vib_depth = (val & 0x0f) * if c == cmd('H') { 4 } else { 1 };
+                    }
+                }
+                if !self.old_effects {
+                    self.vibrato(ch);
+                }
+            }
+            'I' => {  // tremor
+                let hc = &mut self.hc[ch];
+                if val == 0 { val = hc.mem_i } else { hc.mem_i = val }
+            }
+            'J' => {  // arpeggio
+                let hc = &mut self.hc[ch];
+                if val == 0 { val = hc.mem_j } else { hc.mem_j = val }
+            }
+            'M' => {  // set channel volume
+                if val <= 64 {
+                    self.hc[ch].cv = val;
+                }
+            }
+            'N' => {  // channel volume slide
+                let hc = &mut self.hc[ch];
+                if val == 0 { val = hc.mem_n } else { hc.mem_n = val }
+                let d = Self::fine_slide(val);
+                hc.cv = clip(hc.cv as i32 + d, 0, 64) as u8;
+            }
+            'P' => {  // pan slide
+                let hc = &mut self.hc[ch];
+                if val == 0 { val = hc.mem_p } else { hc.mem_p = val }
+                if hc.cp == PAN_SURROUND {
+                    hc.cp = 32;
+                }
+                let d = -Self::fine_slide(val);
+                hc.cp = clip(hc.cp as i32 + d, 0, 64) as u8;
+            }
+            'Q' => {  // retrig
+                let note_on = self.hc[ch].note_on;
+                {
+                    let hc = &mut self.hc[ch];
+                    if val == 0 { val = hc.mem_q } else { hc.mem_q = val }
+                }
+                self.hc[ch].val = val;
+                if note_on {
+                    self.hc[ch].retrig_cnt = val & 0x0f;
+                } else {
+                    self.retrig(ch);
+                }
+            }
+            'R' => {  // tremolo
+                {
+                    let hc = &mut self.hc[ch];
+                    if val >> 4 != 0 {
+                        hc.trm_speed = (val >> 4) * 4;
+                    }
+                    if val & 0x0f != 0 {
+                        hc.trm_depth = (val & 0x0f) * 4;
+                    }
+                }
+                if !self.old_effects {
+                    self.tremolo(ch);
+                }
+            }
+            'S' => {  // special
+                val = self.hc[ch].val;  // memory already resolved
+                self.init_special(ch, val, module);
+            }
+            'T' => {  // set tempo
+                let hc = &mut self.hc[ch];
+                if val == 0 { val = hc.mem_t } else { hc.mem_t = val }
+                if val >= 0x20 {
+                    self.tempo = val;
+                }
+            }
+            'V' => {  // set global volume
+                if val <= 0x80 {
+                    self.gv = val;
+                }
+            }
+            'W' => {  // global volume slide
+                {
+                    let hc = &mut self.hc[ch];
+                    if val == 0 { val = hc.mem_w } else { hc.mem_w = val }
+                }
+                let d = Self::fine_slide(val);
+                self.gv = clip(self.gv as i32 + d, 0, 128) as u8;
+            }
+            'X' => {  // set pan
+                self.hc[ch].cp = ((val as u32 + 2) >> 2) as u8;
+            }
+            'Y' => {  // panbrello
+                {
+                    let hc = &mut self.hc[ch];
+                    if val >> 4 != 0 {
+                        hc.pbr_speed = val >> 4;
+                    }
+                    if val & 0x0f != 0 {
+                        hc.pbr_depth = val & 0x0f;
+                    }
+                }
+                self.panbrello(ch);
+            }
+            'Z' => {  // MIDI macro
+                let hc = &mut self.hc[ch];
+                if val < 0x80 {
+                    if hc.sfx == 0 {
+                        hc.cutoff = val;
+                    }
+                } else if val <= 0x8f {
+                    hc.resonance = (val & 0x0f) * 8;
+                }
+            }
+            _ => (),
+        }
+
+        self.hc[ch].val = val;
+
+        // Tick 0 of tremor and arpeggio
+        if c == cmd('I') {
+            self.tremor(ch);
+        }
+    }
+
+    fn init_special(&mut self, ch: usize, val: u8, module: &ItData) {
+        let x = val & 0x0f;
+        match val >> 4 {
+            0x3 => self.hc[ch].vib_wave = x & 3,
+            0x4 => self.hc[ch].trm_wave = x & 3,
+            0x5 => {
+                self.hc[ch].pbr_wave = x & 3;
+                self.hc[ch].pbr_pos = 0;
+            }
+            0x6 => self.tick_delay += x as usize,
+            0x7 => self.init_nna_command(ch, x, module),
+            0x8 => self.hc[ch].cp = ((x as u32 * 17 + 2) >> 2) as u8,
+            0x9 => if x == 1 {
+                       self.hc[ch].cp = PAN_SURROUND;
+                   },
+            0xa => self.hc[ch].high_offset = x,
+            0xb => {  // pattern loop
+                if x == 0 {
+                    self.hc[ch].loop_row = self.cur_row as u8;
+                } else {
+                    let hc = &mut self.hc[ch];
+                    if hc.loop_cnt == 0 {
+                        hc.loop_cnt = x;
+                        self.loop_flag = true;
+                        self.loop_row = hc.loop_row as usize;
+                    } else {
+                        hc.loop_cnt -= 1;
+                        if hc.loop_cnt != 0 {
+                            self.loop_flag = true;
+                            self.loop_row = hc.loop_row as usize;
+                        } else {
+                            hc.loop_row = self.cur_row as u8 + 1;
+                        }
+                    }
+                }
+            }
+            0xc => self.hc[ch].note_cut = cmp::max(x, 1),
+            0xe => {  // pattern delay
+                if !self.row_delay_on {
+                    self.row_delay = x;
+                    self.row_delay_on = true;
+                }
+            }
+            0xf => self.hc[ch].sfx = x,
+            _   => (),
+        }
+    }
+
+    fn init_nna_command(&mut self, ch: usize, x: u8, module: &ItData) {
+        match x {
+            0..=2 => {  // past note cut, off, fade
+                for i in 0..MAX_SLAVE_CHANNELS {
+                    let sc = self.sc[i];
+                    if sc.flags & SF_ON == 0 || sc.flags & SF_DISOWNED == 0 || sc.hcn as usize != ch {
+                        continue
+                    }
+                    match x {
+                        0 => self.note_cut(i),
+                        1 => self.key_off(i, module),
+                        _ => self.note_fade(i),
+                    }
+                }
+            }
+            3..=6 => {  // set NNA
+                if let Some(s) = self.foreground_slave(ch) {
+                    self.sc[s].nna = match x {
+                        3 => NNA_CUT,
+                        4 => 1,
+                        5 => NNA_OFF,
+                        _ => NNA_FADE,
+                    };
+                }
+            }
+            7..=12 => {  // envelope control
+                if let Some(s) = self.foreground_slave(ch) {
+                    let flag = match x {
+                        7 | 8   => SF_VOL_ENV,
+                        9 | 10  => SF_PAN_ENV,
+                        _       => SF_PIT_ENV,
+                    };
+                    if x & 1 != 0 {
+                        self.sc[s].flags &= !flag;
+                    } else {
+                        self.sc[s].flags |= flag;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Command processing, other ticks
+    fn update_effects(&mut self, ch: usize, module: &ItData) {
+        // delayed note
+        if self.hc[ch].note_delay != 0 && self.hc[ch].note_delay as usize == self.tick {
+            self.hc[ch].note_delay = 0;
+            self.init_note(ch, module);
+            self.init_volume(ch);
+        }
+
+        // note cut
+        if self.hc[ch].note_cut != 0 && self.hc[ch].note_cut as usize == self.tick {
+            self.hc[ch].note_cut = 0;
+            if let Some(s) = self.foreground_slave(ch) {
+                self.note_cut(s);
+            }
+        }
+
+        self.update_volume(ch);
+
+        let c = self.hc[ch].cmd;
+        if c == 0 {
+            return
+        }
+        let val = self.hc[ch].val;
+
+        match (c + 64) as char {
+            'D' | 'K' | 'L' => {
+                let d = Self::normal_slide(val);
+                let hc = &mut self.hc[ch];
+                hc.vol = clip(hc.vol as i32 + d, 0, 64) as u8;
+                if c == cmd('K') {
+                    self.vibrato(ch);
+                } else if c == cmd('L') {
+                    self.tone_porta(ch);
+                }
+            }
+            'E' => {
+                if val < 0xe0 {
+                    self.hc[ch].freq = self.pitch_slide_down(self.hc[ch].freq, val as u32 * 4);
+                }
+            }
+            'F' => {
+                if val < 0xe0 {
+                    self.hc[ch].freq = self.pitch_slide_up(self.hc[ch].freq, val as u32 * 4);
+                }
+            }
+            'G' => self.tone_porta(ch),
+            'H' | 'U' => self.vibrato(ch),
+            'I' => self.tremor(ch),
+            'J' => {
+                let semi = match self.tick % 3 {
+                    0 => 0,
+                    1 => val >> 4,
+                    _ => val & 0x0f,
+                };
+                self.hc[ch].arp_semi = semi;
+            }
+            'N' => {
+                let d = Self::normal_slide(val);
+                let hc = &mut self.hc[ch];
+                hc.cv = clip(hc.cv as i32 + d, 0, 64) as u8;
+            }
+            'P' => {
+                let d = -Self::normal_slide(val);
+                let hc = &mut self.hc[ch];
+                if hc.cp != PAN_SURROUND {
+                    hc.cp = clip(hc.cp as i32 + d, 0, 64) as u8;
+                }
+            }
+            'Q' => self.retrig(ch),
+            'R' => self.tremolo(ch),
+            'T' => {
+                let tempo = self.tempo as i32;
+                if val >> 4 == 0 {
+                    self.tempo = cmp::max(tempo - (val & 0x0f) as i32, 32) as u8;
+                } else if val >> 4 == 1 {
+                    self.tempo = cmp::min(tempo + (val & 0x0f) as i32, 255) as u8;
+                }
+            }
+            'W' => {
+                let d = Self::normal_slide(val);
+                self.gv = clip(self.gv as i32 + d, 0, 128) as u8;
+            }
+            'Y' => self.panbrello(ch),
+            _   => (),
+        }
+    }
+
+    fn process_row(&mut self, module: &ItData) {
+        self.row_delay_on = false;
+
+        for ch in 0..self.num_channels {
+            let e = if self.cur_pattern < module.patterns.len() && self.cur_row < self.num_rows {
+                module.patterns[self.cur_pattern].event(self.cur_row, ch).clone()
+            } else {
+                ItEvent::new()
+            };
+
+            {
+                let hc = &mut self.hc[ch];
+                hc.msk = e.mask;
+                hc.nte = e.note;
+                hc.ins = e.ins;
+                hc.vlc = if e.mask & IT_MASK_VOL != 0 { e.vol } else { NO_VOLUME };
+                if e.mask & IT_MASK_CMD != 0 {
+                    hc.cmd = e.cmd;
+                    hc.val = e.info;
+                } else {
+                    hc.cmd = 0;
+                    hc.val = 0;
+                }
+                hc.note_delay = 0;
+                hc.note_cut = 0;
+                hc.note_on = false;
+
+                if hc.cmd == cmd('S') {
+                    if hc.val == 0 { hc.val = hc.mem_s } else { hc.mem_s = hc.val }
+                }
+            }
+
+            let val = self.hc[ch].val;
+            if self.hc[ch].cmd == cmd('S') && val >> 4 == 0xd {
+                self.hc[ch].note_delay = cmp::max(val & 0x0f, 1);
+            } else {
+                self.init_note(ch, module);
+                self.init_volume(ch);
+            }
+
+            self.init_command(ch, module);
+        }
+    }
+
+    fn process_tick(&mut self, module: &ItData) {
+        for ch in 0..self.num_channels {
+            let hc = &mut self.hc[ch];
+            hc.vib_delta = 0;
+            hc.arp_semi = 0;
+            hc.trm_delta = 0;
+            hc.pbr_delta = 0;
+            hc.tremor_mute = false;
+        }
+
+        self.tick += 1;
+        if self.tick >= self.speed as usize + self.tick_delay {
+            self.tick = 0;
+            self.tick_delay = 0;
+
+            if self.row_delay > 0 {
+                // Repeat row without notes
+                self.row_delay -= 1;
+                for ch in 0..self.num_channels {
+                    if self.hc[ch].cmd != cmd('S') {
+                        self.init_command(ch, module);
+                    }
+                }
+            } else {
+                if !self.first_row {
+                    self.next_row(module);
+                }
+                self.first_row = false;
+                self.process_row(module);
+            }
+        } else {
+            for ch in 0..self.num_channels {
+                self.update_effects(ch, module);
+            }
+        }
+
+        // The row closing a pattern loop is still inside the loop
+        self.inside_loop = false;
+        for ch in 0..self.num_channels {
+            let hc = &self.hc[ch];
+            self.inside_loop |= hc.loop_cnt != 0 || (hc.cmd == cmd('S') && hc.val >> 4 == 0xb && hc.val & 0x0f != 0);
+        }
+    }
+
+    // Frequency, volume and pan after the tick modifiers
+    fn host_values(&self, ch: usize) -> (u32, u8, u8) {
+        let hc = &self.hc[ch];
+
+        let mut freq = hc.freq;
+        if hc.arp_semi != 0 {
+            freq = linear_slide_up(freq, hc.arp_semi as u32 * 64);
+        }
+        freq = self.pitch_slide(freq, hc.vib_delta);
+
+        let vol = if hc.tremor_mute {
+            0
+        } else {
+            clip(hc.vol as i32 + hc.trm_delta, 0, 64) as u8
+        };
+
+        let pan = if hc.cp == PAN_SURROUND {
+            PAN_SURROUND
+        } else {
+            clip(hc.cp as i32 + hc.pbr_delta, 0, 64) as u8
+        };
+
+        (freq, vol, pan)
+    }
+
+    fn set_mixer_loop(voice: usize, sh: &ItSample, released: bool, mixer: &mut Mixer) {
+        let len = sh.length;
+        if sh.flg & IT_SMP_SUSLOOP != 0 && !released && sh.sus_loop_end > sh.sus_loop_begin {
+            mixer.set_loop(voice, sh.sus_loop_begin, cmp::min(sh.sus_loop_end, len), true, sh.flg & IT_SMP_BIDI_SUS != 0);
+        } else if sh.flg & IT_SMP_LOOP != 0 && sh.loop_end > sh.loop_begin {
+            mixer.set_loop(voice, sh.loop_begin, cmp::min(sh.loop_end, len), true, sh.flg & IT_SMP_BIDI_LOOP != 0);
+        } else {
+            mixer.set_loop(voice, 0, 0, false, false);
+        }
+    }
+
+    fn update_slaves(&mut self, module: &ItData, mixer: &mut Mixer) {
+        for i in 0..MAX_SLAVE_CHANNELS {
+            if self.sc[i].flags & SF_CUT != 0 {
+                mixer.reset_voice(i);
+                self.sc[i].flags = 0;
+                continue
+            }
+
+            if self.sc[i].flags & SF_ON == 0 {
+                continue
+            }
+
+            // Sample ended
+            if self.sc[i].flags & SF_NEW_NOTE == 0 && !mixer.voice_active(i) {
+                self.sc[i].flags = 0;
+                continue
+            }
+
+            let ch = self.sc[i].hcn as usize;
+            if self.sc[i].flags & SF_DISOWNED == 0 {
+                let (freq, vol, pan) = self.host_values(ch);
+                let hc = &self.hc[ch];
+                let sc = &mut self.sc[i];
+                sc.freq = freq;
+                sc.vol = vol;
+                sc.pan = pan;
+                sc.cv = hc.cv;
+                sc.cutoff = hc.cutoff;
+                sc.resonance = hc.resonance;
+            }
+
+            let mut env_vol = 64 << 16;
+            let mut env_pan = 0;
+            let mut env_pit = 0;
+            let mut env_flt = None;
+
+            if self.ins_mode && self.sc[i].ins > 0 {
+                let ins = &module.instruments[self.sc[i].ins as usize - 1];
+                let sc = &mut self.sc[i];
+                let released = sc.flags & SF_KEY_OFF != 0;
+
+                if sc.flags & SF_VOL_ENV != 0 {
+                    env_vol = sc.vol_env.update(&ins.vol_env, released);
+                    if sc.vol_env.end {
+                        sc.flags |= SF_NOTE_FADE;
+                        if env_vol <= 0 {
+                            sc.flags = SF_CUT;
+                        }
+                    }
+                }
+
+                if sc.flags & SF_PAN_ENV != 0 {
+                    env_pan = sc.pan_env.update(&ins.pan_env, released);
+                }
+
+                if sc.flags & SF_PIT_ENV != 0 {
+                    let val = sc.pit_env.update(&ins.pit_env, released);
+                    if ins.pit_env.flg & IT_ENV_FILTER != 0 {
+                        env_flt = Some(val);
+                    } else {
+                        env_pit = val;
+                    }
+                }
+
+                if sc.flags & SF_NOTE_FADE != 0 {
+                    sc.fadeout -= ins.fadeout as i32;
+                    if sc.fadeout <= 0 {
+                        sc.fadeout = 0;
+                        sc.flags = SF_CUT;
+                    }
+                }
+            } else if self.sc[i].flags & SF_NOTE_FADE != 0 {
+                self.sc[i].flags = SF_CUT;
+            }
+
+            if self.sc[i].flags & SF_CUT != 0 {
+                mixer.reset_voice(i);
+                self.sc[i].flags = 0;
+                continue
+            }
+
+            let sh = &module.smp_headers[self.sc[i].smp as usize - 1];
+
+            // Sample auto-vibrato
+            let mut freq = self.sc[i].freq;
+            if sh.vid != 0 {
+                let depth = cmp::min(self.sc[i].av_depth as u32 + sh.vir as u32, (sh.vid as u32) << 8);
+                let pos = self.sc[i].av_pos.wrapping_add(sh.vis);
+                self.sc[i].av_depth = depth as u16;
+                self.sc[i].av_pos = pos;
+                let delta = (self.waveform(sh.vit, pos) * (depth >> 8) as i32) >> 6;
+                freq = if delta > 0 {
+                    linear_slide_up(freq, delta as u32)
+                } else {
+                    linear_slide_down(freq, -delta as u32)
+                };
+            }
+
+            // Pitch envelope, in half semitones
+            if env_pit != 0 {
+                let delta = env_pit >> 11;
+                freq = if delta > 0 {
+                    linear_slide_up(freq, delta as u32)
+                } else {
+                    linear_slide_down(freq, -delta as u32)
+                };
+            }
+
+            let sc = &mut self.sc[i];
+
+            // Final volume
+            let vol = if self.hc[ch].disabled { 0 } else { sc.vol };
+            let fv = vol as f64 * sc.cv as f64 * sc.svl as f64 * self.gv as f64 *
+                     (env_vol as f64 / 65536.0) * sc.fadeout as f64 /
+                     (64.0 * 64.0 * 64.0 * 128.0 * 64.0 * 1024.0);
+            sc.fv = (fv * 128.0) as u32;
+
+            // Final pan
+            let pan = if !self.stereo || sc.pan == PAN_SURROUND {
+                0
+            } else {
+                let mut pan = clip(sc.pan as i32 + sc.pan_ofs as i32, 0, 64);
+                if env_pan != 0 {
+                    pan += ((env_pan as i64 * (32 - (pan - 32).abs()) as i64) >> 21) as i32;
+                }
+                pan = (((pan - 32) * self.sep as i32) >> 7) + 32;
+                clip(pan * 4 - 128, -128, 127) as isize
+            };
+
+            if sc.flags & SF_NEW_NOTE != 0 {
+                mixer.set_voice_channel(i, sc.hcn as usize);
+                mixer.set_sample(i, sc.smp as usize);
+                Self::set_mixer_loop(i, sh, sc.flags & SF_KEY_OFF != 0, mixer);
+                mixer.set_voicepos(i, sc.start_pos as f64);
+                sc.flags &= !(SF_NEW_NOTE | SF_UPDATE_LOOP);
+            } else if sc.flags & SF_UPDATE_LOOP != 0 {
+                Self::set_mixer_loop(i, sh, true, mixer);
+                sc.flags &= !SF_UPDATE_LOOP;
+            }
+
+            mixer.set_volume(i, (fv * 1024.0 * self.mv as f64 / 128.0) as usize);
+            mixer.set_pan(i, pan);
+            mixer.set_period(i, if freq > 0 { MIXER_PERIOD_BASE / freq as f64 } else { 0.0 });
+
+            // Resonant filter
+            let cutoff = match env_flt {
+                Some(val) => {
+                    let m = (val * 8) >> 16;  // -256..256
+                    sc.cutoff as i32 * (m + 256) / 256
+                }
+                None => sc.cutoff as i32,
+            };
+            if env_flt.is_some() || sc.cutoff < 127 || sc.resonance > 0 {
+                mixer.set_filter(i, cutoff as usize, sc.resonance as usize);
+            } else {
+                mixer.reset_filter(i);
+            }
+        }
+    }
+}
+
+fn clip(val: i32, min: i32, max: i32) -> i32 {
+    if val < min {
+        min
+    } else if val > max {
+        max
+    } else {
+        val
+    }
+}
+
+fn cmd(c: char) -> u8 {
+    c as u8 - 64
+}
+
+
+impl FormatPlayer for ItPlay {
+    fn start(&mut self, data: &mut PlayerData, mdata: &ModuleData, mixer: &mut Mixer) {
+
+        let module = mdata.as_any().downcast_ref::<ItData>().unwrap();
+
+        mixer.set_num_voices(MAX_SLAVE_CHANNELS);
+        mixer.enable_ramp(true);
+
+        self.num_channels  = cmp::min(module.channels, MAX_HOST_CHANNELS);
+        self.speed         = if module.i_s != 0 { module.i_s } else { 6 };
+        self.tempo         = if module.i_t >= 32 { module.i_t } else { 125 };
+        self.gv            = cmp::min(module.g_v, 128);
+        self.mv            = cmp::min(module.m_v, 128);
+        self.sep           = cmp::min(module.sep, 128);
+        self.linear_slides = module.flags & IT_LINEAR_SLIDES != 0;
+        self.old_effects   = module.flags & IT_OLD_EFFECTS != 0;
+        self.link_gxx      = module.flags & IT_LINK_GXX != 0;
+        self.stereo        = module.flags & IT_STEREO != 0;
+        self.ins_mode      = module.use_instruments();
+        self.tick_delay    = 0;
+        self.row_delay     = 0;
+        self.break_flag    = false;
+        self.jump_flag     = false;
+        self.loop_flag     = false;
+        self.first_row     = true;
+
+        for i in 0..MAX_HOST_CHANNELS {
+            let mut hc = HostChannel::default();
+            hc.scn = NO_SLAVE;
+            hc.cp = module.chnl_pan[i] & 0x7f;
+            if hc.cp > 64 && hc.cp != PAN_SURROUND {
+                hc.cp = 32;
+            }
+            hc.disabled = module.chnl_pan[i] & 0x80 != 0;
+            hc.cv = cmp::min(module.chnl_vol[i], 64);
+            hc.cutoff = 127;
+            hc.vlc = NO_VOLUME;
+            self.hc[i] = hc;
+        }
+
+        for i in 0..MAX_SLAVE_CHANNELS {
+            self.sc[i] = SlaveChannel::default();
+        }
+
+        let pos = data.pos;
+        self.set_order(pos, &module);
+        self.cur_row = 0;
+        self.tick = self.speed as usize - 1;
+
+        data.speed = self.speed as usize;
+        data.tempo = self.tempo as f32;
+        data.time  = 0.0;
+
+        data.initial_speed = data.speed;
+        data.initial_tempo = data.tempo;
+    }
+
+    fn play(&mut self, data: &mut PlayerData, mdata: &ModuleData, mut mixer: &mut Mixer) {
+
+        let module = mdata.as_any().downcast_ref::<ItData>().unwrap();
+
+        self.process_tick(&module);
+        self.update_slaves(&module, &mut mixer);
+
+        data.frame = (self.tick + 1) % (self.speed as usize + self.tick_delay);
+        data.row = self.cur_row;
+        data.pos = self.cur_order;
+        data.speed = self.speed as usize;
+        data.tempo = self.tempo as f32;
+        data.inside_loop = self.inside_loop;
+    }
+
+    fn reset(&mut self) {
+    }
+
+    fn save_state(&self) -> State {
+        scan::save_state(self)
+    }
+
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{linear_slide_up, linear_slide_down, amiga_slide, FINE_SINE_DATA};
+
+    #[test]
+    fn test_linear_slide() {
+        // one octave is 768 units
+        assert_eq!(linear_slide_up(8363, 768), 16726);
+        assert_eq!(linear_slide_down(16726, 768), 8363);
+    }
+
+    #[test]
+    fn test_amiga_slide() {
+        // C-5 at 8363 Hz is period 1712 in ST3 units
+        assert_eq!(amiga_slide(8363, 0), 8363);
+        assert_eq!(amiga_slide(8363, 856), 16726);
+    }
+
+    #[test]
+    fn test_sine_table() {
+        assert_eq!(&FINE_SINE_DATA[0..8], &[0, 2, 3, 5, 6, 8, 9, 11]);
+        assert_eq!(FINE_SINE_DATA[64], 64);
+        assert_eq!(FINE_SINE_DATA[192], -64);
+    }
+}
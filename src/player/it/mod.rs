@@ -0,0 +1,27 @@
+mod itplay;
+
+use module::Module;
+use player::{Options, PlayerListEntry, PlayerInfo, FormatPlayer};
+use ::*;
+
+pub struct It;
+
+impl PlayerListEntry for It {
+    fn info(&self) -> PlayerInfo {
+        PlayerInfo {
+           id         : "it",
+           name       : "itplay(ox) 2.14",
+           description: "An Impulse Tracker 2.14 compatible replayer",
+           author     : "Claudio Matsuoka",
+           accepts    : &[ "it" ],
+        }
+    }
+
+    fn player(&self, module: &Module, options: Options) -> Box<FormatPlayer> {
+        Box::new(self::itplay::ItPlay::new(module, options))
+    }
+
+    fn import(&self, module: Module) -> Result<Module, Error> {
+        Ok(module)
+    }
+}
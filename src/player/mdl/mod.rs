@@ -0,0 +1,27 @@
+mod player;
+
+use module::Module;
+use player::{Options, PlayerListEntry, PlayerInfo, FormatPlayer};
+use ::*;
+
+pub struct Mdl;
+
+impl PlayerListEntry for Mdl {
+   fn info(&self) -> PlayerInfo {
+       PlayerInfo {
+          id         : "mdl",
+          name       : "Digitrakker replayer",
+          description: "A replayer for Digitrakker modules",
+          author     : "Claudio Matsuoka",
+          accepts    : &[ "mdl" ],
+       }
+   }
+
+   fn player(&self, module: &Module, options: Options) -> Box<FormatPlayer> {
+       Box::new(self::player::MdlPlayer::new(module, options))
+   }
+
+   fn import(&self, module: Module) -> Result<Module, Error> {
+       Ok(module)
+   }
+}
@@ -0,0 +1,677 @@
+use std::cmp;
+use std::f64::consts::PI;
+use module::{Module, ModuleData};
+use player::{Options, PlayerData, FormatPlayer, State};
+use player::scan::{self, SaveRestore};
+use format::mdl::{MdlData, MdlEnvelope, MdlSampleMap, MDL_NOTE_OFF, MDL_SMP_BIDI};
+use mixer::Mixer;
+use Error;
+
+/// Digitrakker replayer
+///
+/// An oxdz player for modules created with Digitrakker by Thomas Pytel
+/// and Lukas Engel. Each event has two effect columns: the first one
+/// holds pitch effects and the second one volume effects, while commands
+/// 7 to F have the same meaning in both columns. Instruments map note
+/// ranges to samples, each with its own volume, pan and frequency
+/// envelopes.
+///
+/// This is a clean-room implementation written from the MDL format
+/// description, not a port of the Digitrakker replay routine, so effect
+/// timing in corner cases may differ from the original player.
+
+const MIXER_PERIOD_BASE: f64 = 428.0 * 8287.0;
+
+// Pitches are kept in 1/64 semitone units, C-4 plays at the sample C4 speed
+const C4_PITCH       : i32 = 48 * 64;
+
+// Sample map envelope flags
+const ENV_ON         : u8 = 0x80;
+const ENV_SET        : u8 = 0x40;  // use the sample map volume or pan
+
+const FX_PORTA_UP    : u8 = 0x1;   // first column
+const FX_PORTA_DOWN  : u8 = 0x2;
+const FX_TONEPORTA   : u8 = 0x3;
+const FX_VIBRATO     : u8 = 0x4;
+const FX_ARPEGGIO    : u8 = 0x5;
+const FX_VOLSLIDE_UP : u8 = 0x1;   // second column
+const FX_VOLSLIDE_DN : u8 = 0x2;
+const FX_RETRIG      : u8 = 0x3;
+const FX_TREMOLO     : u8 = 0x4;
+const FX_TREMOR      : u8 = 0x5;
+const FX_TEMPO       : u8 = 0x7;   // both columns
+const FX_PAN         : u8 = 0x8;
+const FX_JUMP        : u8 = 0xb;
+const FX_GLOBALVOL   : u8 = 0xc;
+const FX_BREAK       : u8 = 0xd;
+const FX_EXTENDED    : u8 = 0xe;
+const FX_SPEED       : u8 = 0xf;
+
+const EX_PAN_LEFT    : u8 = 0x1;
+const EX_PAN_RIGHT   : u8 = 0x2;
+const EX_PATTERN_LOOP: u8 = 0x6;
+const EX_RETRIG      : u8 = 0x9;
+const EX_GVOL_UP     : u8 = 0xa;
+const EX_GVOL_DOWN   : u8 = 0xb;
+const EX_NOTE_CUT    : u8 = 0xc;
+const EX_NOTE_DELAY  : u8 = 0xd;
+
+
+#[derive(SaveRestore)]
+pub struct MdlPlayer {
+    options    : Options,
+
+    pos        : usize,
+    row        : usize,
+    tick       : usize,
+    speed      : usize,
+    tempo      : usize,
+    global_vol : usize,
+    jump       : Option<usize>,
+    brk        : Option<usize>,
+    loop_row   : Option<usize>,
+    inside_loop: bool,
+    channels   : Vec<MdlChannel>,
+}
+
+impl MdlPlayer {
+    pub fn new(module: &Module, options: Options) -> Self {
+
+        let module = module.data.as_any().downcast_ref::<MdlData>().unwrap();
+
+        MdlPlayer {
+            options,
+
+            pos        : 0,
+            row        : 0,
+            tick       : 0,
+            speed      : 6,
+            tempo      : 125,
+            global_vol : 255,
+            jump       : None,
+            brk        : None,
+            loop_row   : None,
+            inside_loop: false,
+            channels   : vec![MdlChannel::new(); module.channels],
+        }
+    }
+
+    fn play_row(&mut self, module: &MdlData, mixer: &mut Mixer) {
+        let pat = module.orders[self.pos] as usize;
+        if pat >= module.patterns.len() {
+            return
+        }
+
+        for chn in 0..self.channels.len() {
+            let e = module.event(pat, self.row, chn);
+
+            {
+                let ch = &mut self.channels[chn];
+                ch.fx = [e.e1, e.e2];
+                ch.fxp = [ch.memory(0, e.e1, e.p1), ch.memory(1, e.e2, e.p2)];
+                ch.arp = 0;
+                ch.delay = 0;
+                ch.cut = 0;
+
+                for col in 0..2 {
+                    if ch.fx[col] == FX_EXTENDED && ch.fxp[col] >> 4 == EX_NOTE_DELAY {
+                        ch.delay = (ch.fxp[col] & 0x0f) as usize;
+                    }
+                }
+
+                if ch.delay > 0 {
+                    ch.delayed = [e.note, e.ins, e.vol];
+                } else {
+                    ch.play_note(chn, e.note, e.ins, e.vol, module, mixer);
+                }
+            }
+
+            for col in 0..2 {
+                self.fx_row(chn, col);
+            }
+        }
+
+        self.inside_loop = self.channels.iter().any(|ch| ch.loop_count != 0);
+    }
+
+    // Effects processed in the first tick of the row
+    fn fx_row(&mut self, chn: usize, col: usize) {
+        let ch = &mut self.channels[chn];
+        let (cmd, p) = (ch.fx[col], ch.fxp[col]);
+
+        match (col, cmd) {
+            (0, FX_PORTA_UP) => {
+                ch.pitch += fine_value(p);
+            },
+            (0, FX_PORTA_DOWN) => {
+                ch.pitch -= fine_value(p);
+            },
+            (1, FX_VOLSLIDE_UP) => {
+                ch.volume = cmp::min(255, ch.volume + fine_value(p) as usize);
+            },
+            (1, FX_VOLSLIDE_DN) => {
+                ch.volume = ch.volume.saturating_sub(fine_value(p) as usize);
+            },
+            (_, FX_TEMPO) => if p != 0 {
+                self.tempo = p as usize;
+            },
+            (_, FX_PAN) => {
+                ch.pan = cmp::min(127, p as usize);
+            },
+            (_, FX_JUMP) => {
+                self.jump = Some(p as usize);
+            },
+            (_, FX_GLOBALVOL) => {
+                self.global_vol = p as usize;
+            },
+            (_, FX_BREAK) => {
+                self.brk = Some(p as usize);
+            },
+            (_, FX_SPEED) => if p != 0 {
+                self.speed = p as usize;
+            },
+            (_, FX_EXTENDED) => {
+                let x = (p & 0x0f) as usize;
+                match p >> 4 {
+                    EX_PAN_LEFT    => ch.pan = ch.pan.saturating_sub(x),
+                    EX_PAN_RIGHT   => ch.pan = cmp::min(127, ch.pan + x),
+                    EX_GVOL_UP     => self.global_vol = cmp::min(255, self.global_vol + x),
+                    EX_GVOL_DOWN   => self.global_vol = self.global_vol.saturating_sub(x),
+                    EX_NOTE_CUT    => ch.cut = x + 1,
+                    EX_PATTERN_LOOP => {
+                        if x == 0 {
+                            ch.loop_start = self.row;
+                        } else {
+                            if ch.loop_count == 0 {
+                                ch.loop_count = x;
+                            } else {
+                                ch.loop_count -= 1;
+                            }
+                            if ch.loop_count > 0 {
+                                self.loop_row = Some(ch.loop_start);
+                            }
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // Effects processed in all ticks after the first one
+    fn fx_tick(&mut self, chn: usize, col: usize, mixer: &mut Mixer) {
+        let tick = self.tick;
+        let ch = &mut self.channels[chn];
+        let (cmd, p) = (ch.fx[col], ch.fxp[col]);
+
+        match (col, cmd) {
+            (0, FX_PORTA_UP) => if p < 0xe0 {
+                ch.pitch += p as i32 * 4;
+            },
+            (0, FX_PORTA_DOWN) => if p < 0xe0 {
+                ch.pitch -= p as i32 * 4;
+            },
+            (0, FX_TONEPORTA) => {
+                let d = p as i32 * 4;
+                if ch.pitch < ch.target {
+                    ch.pitch = cmp::min(ch.target, ch.pitch + d);
+                } else {
+                    ch.pitch = cmp::max(ch.target, ch.pitch - d);
+                }
+            },
+            (0, FX_VIBRATO) => {
+                ch.vib_pos = (ch.vib_pos + (p >> 4) as usize) % 64;
+            },
+            (0, FX_ARPEGGIO) => {
+                ch.arp = match tick % 3 {
+                    1 => (p >> 4) as i32 * 64,
+                    2 => (p & 0x0f) as i32 * 64,
+                    _ => 0,
+                };
+            },
+            (1, FX_VOLSLIDE_UP) => if p < 0xe0 {
+                ch.volume = cmp::min(255, ch.volume + p as usize);
+            },
+            (1, FX_VOLSLIDE_DN) => if p < 0xe0 {
+                ch.volume = ch.volume.saturating_sub(p as usize);
+            },
+            (1, FX_RETRIG) => {
+                let interval = (p & 0x0f) as usize;
+                if interval > 0 && tick % interval == 0 {
+                    ch.volume = retrig_volume(ch.volume, p >> 4);
+                    mixer.set_voicepos(chn, 0.0);
+                }
+            },
+            (1, FX_TREMOLO) => {
+                ch.trem_pos = (ch.trem_pos + (p >> 4) as usize) % 64;
+            },
+            (1, FX_TREMOR) => {
+                let on = (p >> 4) as usize + 1;
+                let off = (p & 0x0f) as usize + 1;
+                ch.tremor_count = (ch.tremor_count + 1) % (on + off);
+            },
+            (_, FX_EXTENDED) => {
+                let x = (p & 0x0f) as usize;
+                if p >> 4 == EX_RETRIG && x > 0 && tick % x == 0 {
+                    mixer.set_voicepos(chn, 0.0);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn play_effects(&mut self, module: &MdlData, mixer: &mut Mixer) {
+        let tick = self.tick;
+        for chn in 0..self.channels.len() {
+            {
+                let ch = &mut self.channels[chn];
+                if ch.delay > 0 && ch.delay == tick {
+                    let d = ch.delayed;
+                    ch.play_note(chn, d[0], d[1], d[2], module, mixer);
+                }
+                if ch.cut > 0 && ch.cut - 1 == tick {
+                    ch.volume = 0;
+                }
+            }
+            if tick > 0 {
+                for col in 0..2 {
+                    self.fx_tick(chn, col, mixer);
+                }
+            }
+        }
+    }
+
+    fn update_channels(&mut self, module: &MdlData, mixer: &mut Mixer) {
+        let global_vol = self.global_vol as f64 / 255.0;
+
+        for chn in 0..self.channels.len() {
+            let ch = &mut self.channels[chn];
+            if ch.smp == 0 {
+                continue
+            }
+
+            let vol_env = ch.envelope(0, &module.vol_env);
+            let pan_env = ch.envelope(1, &module.pan_env);
+            let freq_env = ch.envelope(2, &module.freq_env);
+
+            // Volume
+            let mut vol = ch.volume as f64;
+            if ch.fx[1] == FX_TREMOLO {
+                vol += sine(ch.trem_pos) * (ch.fxp[1] & 0x0f) as f64 * 4.0;
+                vol = vol.max(0.0).min(255.0);
+            }
+            if ch.fx[1] == FX_TREMOR {
+                let on = (ch.fxp[1] >> 4) as usize + 1;
+                if ch.tremor_count >= on {
+                    vol = 0.0;
+                }
+            }
+            if let Some(y) = vol_env {
+                vol = vol * y as f64 / 63.0;
+            }
+            if !ch.key_on {
+                ch.fade = if vol_env.is_some() { ch.fade.saturating_sub(ch.fadeout) } else { 0 };
+            }
+            vol = vol * ch.fade as f64 / 65536.0 * global_vol;
+            mixer.set_volume(chn, (vol * 1024.0 / 255.0) as usize);
+
+            // Pan
+            let mut pan = ch.pan as isize;
+            if let Some(y) = pan_env {
+                pan = cmp::max(0, cmp::min(127, pan + (y as isize - 32) * 2));
+            }
+            mixer.set_pan(chn, pan * 2 - 128);
+
+            // Pitch
+            let mut pitch = ch.pitch + ch.arp;
+            if ch.fx[0] == FX_VIBRATO {
+                pitch += (sine(ch.vib_pos) * (ch.fxp[0] & 0x0f) as f64 * 8.0) as i32;
+            }
+            if let Some(y) = freq_env {
+                pitch += (y as i32 - 32) * 16;
+            }
+            let freq = ch.c4speed as f64 * 2.0_f64.powf((pitch - C4_PITCH) as f64 / 768.0);
+            if freq > 0.0 {
+                mixer.set_period(chn, MIXER_PERIOD_BASE / freq);
+            }
+        }
+    }
+
+    fn next_tick(&mut self, module: &MdlData) {
+        self.tick += 1;
+        if self.tick < self.speed {
+            return
+        }
+
+        self.tick = 0;
+
+        if let Some(row) = self.loop_row.take() {
+            self.row = row;
+            return
+        }
+
+        if self.jump.is_some() || self.brk.is_some() {
+            self.pos = match self.jump.take() {
+                Some(pos) => pos,
+                None      => self.pos + 1,
+            };
+            self.row = self.brk.take().unwrap_or(0);
+        } else {
+            self.row += 1;
+            if self.row < module.rows(module.orders[self.pos] as usize) {
+                return
+            }
+            self.row = 0;
+            self.pos += 1;
+        }
+
+        if self.pos >= module.len() {
+            self.pos = if (module.restart as usize) < module.len() { module.restart as usize } else { 0 };
+        }
+        if self.row >= module.rows(module.orders[self.pos] as usize) {
+            self.row = 0;
+        }
+    }
+}
+
+// Fine slides have parameters Fx, extra fine slides have parameters Ex
+fn fine_value(p: u8) -> i32 {
+    match p {
+        0xf0..=0xff => (p & 0x0f) as i32 * 4,
+        0xe0..=0xef => (p & 0x0f) as i32,
+        _           => 0,
+    }
+}
+
+fn retrig_volume(vol: usize, x: u8) -> usize {
+    let v = vol as isize;
+    let v = match x {
+        0x1..=0x5 => v - (4 << (x - 1)),
+        0x6       => v * 2 / 3,
+        0x7       => v / 2,
+        0x9..=0xd => v + (4 << (x - 9)),
+        0xe       => v * 3 / 2,
+        0xf       => v * 2,
+        _         => v,
+    };
+    cmp::max(0, cmp::min(255, v)) as usize
+}
+
+fn sine(pos: usize) -> f64 {
+    (pos as f64 * PI / 32.0).sin()
+}
+
+fn env_value(env: &MdlEnvelope, pos: u16) -> u8 {
+    let node = &env.node;
+    match node.iter().position(|x| x.0 > pos) {
+        None    => node[node.len() - 1].1,
+        Some(0) => node[0].1,
+        Some(i) => {
+            let (x1, y1) = (node[i - 1].0 as i32, node[i - 1].1 as i32);
+            let (x2, y2) = (node[i].0 as i32, node[i].1 as i32);
+            (y1 + (y2 - y1) * (pos as i32 - x1) / (x2 - x1)) as u8
+        },
+    }
+}
+
+fn env_advance(env: &MdlEnvelope, pos: u16, key_on: bool) -> u16 {
+    let node = &env.node;
+    if let Some(s) = env.sustain() {
+        if key_on && s < node.len() && pos == node[s].0 {
+            return pos
+        }
+    }
+    let mut pos = pos + 1;
+    if env.has_loop() && (env.lpe as usize) < node.len() && pos > node[env.lpe as usize].0 {
+        pos = node[env.lpb as usize].0;
+    }
+    cmp::min(pos, node[node.len() - 1].0)
+}
+
+
+#[derive(Default,Clone,SaveRestore)]
+struct MdlChannel {
+    ins         : usize,
+    smp         : usize,
+    note        : u8,
+    c4speed     : u32,
+    pitch       : i32,
+    target      : i32,
+    arp         : i32,
+    volume      : usize,
+    pan         : usize,
+    key_on      : bool,
+    fade        : usize,
+    fadeout     : usize,
+    env_flags   : [u8; 3],
+    env_pos     : [u16; 3],
+    fx          : [u8; 2],
+    fxp         : [u8; 2],
+    mem         : [u8; 32],
+    vib_pos     : usize,
+    trem_pos    : usize,
+    tremor_count: usize,
+    loop_start  : usize,
+    loop_count  : usize,
+    delay       : usize,
+    delayed     : [u8; 3],
+    cut         : usize,
+}
+
+impl MdlChannel {
+    pub fn new() -> Self {
+        MdlChannel {
+            volume: 255,
+            pan   : 64,
+            ..Default::default()
+        }
+    }
+
+    // Effects with a zero parameter use the last parameter of the same
+    // effect in that column
+    fn memory(&mut self, col: usize, cmd: u8, p: u8) -> u8 {
+        if cmd == 0 || cmd >= FX_TEMPO {
+            return p
+        }
+        let i = col * 16 + cmd as usize;
+        if p == 0 {
+            self.mem[i]
+        } else {
+            self.mem[i] = p;
+            p
+        }
+    }
+
+    fn map<'a>(&self, note: u8, module: &'a MdlData) -> Option<&'a MdlSampleMap> {
+        match self.ins.checked_sub(1).and_then(|i| module.instruments.get(i)) {
+            Some(ins) => ins.map(note),
+            None      => None,
+        }
+    }
+
+    fn set_defaults(&mut self, map: &MdlSampleMap) {
+        if map.vol_env & ENV_SET != 0 {
+            self.volume = map.volume as usize;
+        }
+        if map.pan_env & ENV_SET != 0 {
+            self.pan = cmp::min(127, map.pan as usize);
+        }
+    }
+
+    fn play_note(&mut self, chn: usize, note: u8, ins: u8, vol: u8, module: &MdlData, mixer: &mut Mixer) {
+        if ins != 0 {
+            self.ins = ins as usize;
+        }
+
+        if note == MDL_NOTE_OFF {
+            self.key_on = false;
+        } else if note != 0 {
+            if self.fx[0] == FX_TONEPORTA && self.smp != 0 {
+                self.target = (note as i32 - 1) * 64;
+            } else {
+                self.trigger(chn, note, module, mixer);
+            }
+        } else if ins != 0 {
+            let note = self.note;
+            if let Some(map) = self.map(note, module) {
+                self.set_defaults(map);
+            }
+        }
+
+        if vol != 0 {
+            self.volume = vol as usize;
+        }
+    }
+
+    fn trigger(&mut self, chn: usize, note: u8, module: &MdlData, mixer: &mut Mixer) {
+        let map = match self.map(note, module) {
+            Some(map) => map,
+            None      => return,
+        };
+        let smp = map.sample as usize;
+        let sh = match smp.checked_sub(1).and_then(|i| module.smp_headers.get(i)) {
+            Some(sh) if sh.length > 0 => sh,
+            _                         => return,
+        };
+
+        self.set_defaults(map);
+        self.smp = smp;
+        self.note = note;
+        self.c4speed = sh.c4speed;
+        self.pitch = (note as i32 - 1) * 64;
+        self.target = self.pitch;
+        self.key_on = true;
+        self.fade = 0x10000;
+        self.fadeout = map.fadeout as usize;
+        self.env_flags = [map.vol_env, map.pan_env, map.freq_env];
+        self.env_pos = [0; 3];
+        self.vib_pos = 0;
+        self.trem_pos = 0;
+        self.tremor_count = 0;
+
+        mixer.set_sample(chn, smp);
+        mixer.set_voicepos(chn, 0.0);
+        mixer.set_loop(chn, sh.loop_start, sh.loop_start + sh.loop_len, sh.loop_len > 0, sh.flags & MDL_SMP_BIDI != 0);
+    }
+
+    // Return the current envelope value and advance the envelope position
+    fn envelope(&mut self, i: usize, list: &[MdlEnvelope]) -> Option<u8> {
+        if self.env_flags[i] & ENV_ON == 0 {
+            return None
+        }
+        let env = &list[(self.env_flags[i] & 0x3f) as usize];
+        if env.node.is_empty() {
+            return None
+        }
+        let val = env_value(env, self.env_pos[i]);
+        self.env_pos[i] = env_advance(env, self.env_pos[i], self.key_on);
+        Some(val)
+    }
+}
+
+
+impl FormatPlayer for MdlPlayer {
+    fn start(&mut self, data: &mut PlayerData, mdata: &ModuleData, mixer: &mut Mixer) {
+
+        let module = mdata.as_any().downcast_ref::<MdlData>().unwrap();
+
+        self.pos = data.pos;
+        self.row = 0;
+        self.tick = 0;
+        self.speed = if module.speed == 0 { 6 } else { module.speed as usize };
+        self.tempo = if module.tempo == 0 { 125 } else { module.tempo as usize };
+        self.global_vol = module.global_vol as usize;
+        self.jump = None;
+        self.brk = None;
+        self.loop_row = None;
+
+        data.speed = self.speed;
+        data.tempo = self.tempo as f32;
+        data.time  = 0.0;
+
+        data.initial_speed = data.speed;
+        data.initial_tempo = data.tempo;
+
+        for chn in 0..self.channels.len() {
+            self.channels[chn] = MdlChannel::new();
+            self.channels[chn].pan = (module.chn_pan[chn] & 0x7f) as usize;
+            mixer.set_pan(chn, self.channels[chn].pan as isize * 2 - 128);
+        }
+    }
+
+    fn play(&mut self, data: &mut PlayerData, mdata: &ModuleData, mut mixer: &mut Mixer) {
+
+        let module = mdata.as_any().downcast_ref::<MdlData>().unwrap();
+
+        if self.tick == 0 {
+            self.play_row(&module, &mut mixer);
+        }
+        self.play_effects(&module, &mut mixer);
+        self.update_channels(&module, &mut mixer);
+
+        // report the position of the next frame, as the other players do
+        self.next_tick(&module);
+
+        data.frame = self.tick;
+        data.row = self.row;
+        data.pos = self.pos;
+        data.speed = self.speed;
+        data.tempo = self.tempo as f32;
+        data.inside_loop = self.inside_loop;
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.row = 0;
+        self.tick = 0;
+    }
+
+    fn save_state(&self) -> State {
+        scan::save_state(self)
+    }
+
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use format::mdl::mdl_module;
+    use player::TestPlayer;
+    use ::*;
+
+    #[test]
+    fn test_play_mdl() {
+        let b = mdl_module();
+        let ox = Oxdz::new(&b, 44100, "").unwrap();
+        assert_eq!(ox.player_info().unwrap().id, "mdl");
+
+        // 80 rows at speed 6 and 125 bpm
+        let songs = ox.subsongs();
+        assert_eq!(songs[0].time, 80 * 6 * 20);
+
+        // row 0 slides the volume down by 8 per tick in the second column,
+        // scaled by the volume envelope going from 63 to 32 in 4 ticks; the
+        // slide stops in row 1 and the envelope holds its last node
+        let mut p = TestPlayer::new(&b, "mdl");
+        let mut vol = Vec::new();
+        for _ in 0..7 {
+            p.play();
+            vol.push(p.mixer.volume(0));
+        }
+        assert_eq!(vol, vec![771, 656, 538, 428, 326, 310, 310]);
+        assert_eq!((p.data.row, p.data.frame), (1, 1));
+
+        // the first column slides the pitch up by 8/64 semitone per tick and
+        // the frequency envelope adds a semitone per tick up to 4 semitones
+        p.start();
+        for &pitch in &[0, 72, 144, 216, 288, 296, 296] {
+            p.play();
+            let period = 428.0 * 8287.0 / (8363.0 * 2.0_f64.powf(pitch as f64 / 768.0));
+            assert!((p.mixer.period(0) - period).abs() < 1e-6);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-mod scan;
+pub mod scan;
 mod protracker;
 mod noisetracker;
 mod soundtracker;
@@ -8,18 +8,31 @@ mod st3;
 mod ft2;
 mod hmn;
 mod fasttracker;
+mod it;
+mod c669;
+mod mdl;
+mod imf;
+mod sfx;
 
 pub use mixer::Mixer;
+pub use mixer::{OutputSample, I24};
 
 use std::cmp;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::default::Default;
 use module::{Module, ModuleData};
-use player::scan::{ScanData, OrdData};
-use util::MemOpExt;
+use player::scan::{ScanData, OrdData, Checkpoint, SaveRestore};
+use mixer::MixerState;
 use ::*;
 
+// Give up seeking to a row that isn't reached after this many frames
+const MAX_SEEK_FRAMES: usize = 65536;
+
+// End the scan if no new row is entered for longer than the slowest row
+// with the longest pattern delay, e.g. a row jumping to itself
+const MAX_ROW_TICKS: usize = 256 * 16;
+
 
 fn all() -> Vec<Box<PlayerListEntry>> {
     vec![
@@ -34,6 +47,11 @@ fn all() -> Vec<Box<PlayerListEntry>> {
         Box::new(st3::St3),
         Box::new(ft2::Ft2),
         Box::new(hmn::Hmn),
+        Box::new(it::It),
+        Box::new(c669::C669),
+        Box::new(mdl::Mdl),
+        Box::new(imf::Imf),
+        Box::new(sfx::Sfx),
     ]
 }
 
@@ -104,11 +122,11 @@ pub trait FormatPlayer: Send + Sync {
     fn start(&mut self, &mut PlayerData, &ModuleData, &mut Mixer);
     fn play(&mut self, &mut PlayerData, &ModuleData, &mut Mixer);
     fn reset(&mut self);
-    unsafe fn save_state(&self) -> State;
-    unsafe fn restore_state(&mut self, &State);
+    fn save_state(&self) -> State;
+    fn restore_state(&mut self, &State) -> Result<(), Error>;
 }
 
-#[derive(Default)]
+#[derive(Default, SaveRestore)]
 pub struct PlayerData {
     pub pos  : usize,
     pub row  : usize,
@@ -168,6 +186,8 @@ pub struct Player<'a> {
     mixer         : Mixer<'a>,
     loop_count    : usize,
     end           : bool,
+    fade_out      : u32,
+    fade_pos      : Option<usize>,
 
     // for buffer fill
     consumed      : usize,
@@ -176,6 +196,14 @@ pub struct Player<'a> {
 
     ord_data      : Vec<OrdData>,
     scan_cnt      : Vec<Vec<u32>>,
+    scan_time     : Vec<Vec<f32>>,    // time of the first visit to each row
+    checkpoints   : Vec<Checkpoint>,
+    checkpoint_rows: usize,
+    num_songs     : usize,
+
+    // player and mixer states before start, to scan each song from scratch
+    init_state    : State,
+    init_mixer    : State,
 }
 
 impl<'a> Player<'a> {
@@ -200,9 +228,15 @@ impl<'a> Player<'a> {
             scan_cnt.push(vec![0; module.rows(pat as usize)]);
         }
 
+        let scan_time = scan_cnt.iter().map(|x| vec![0.0; x.len()]).collect();
+
         let module_len = module.len();
         let mixer = Mixer::new(module.channels, rate, module.data.samples());
 
+        let init_state = format_player.save_state();
+        let mut init_mixer = State::new();
+        mixer.save(&mut init_mixer);
+
         Ok(Player {
             data      : Box::new(PlayerData::new()),
             module,
@@ -211,18 +245,77 @@ impl<'a> Player<'a> {
             total_time: 0,
             loop_count: 0,
             end       : false,
+            fade_out  : 0,
+            fade_pos  : None,
             consumed  : 0,
             in_pos    : 0,
             in_size   : 0,
             ord_data  : vec![OrdData::new(); module_len],
             scan_cnt,
+            scan_time,
+            checkpoints: Vec::new(),
+            checkpoint_rows: 0,
+            num_songs : 0,
+            init_state,
+            init_mixer,
         })
     }
 
+    /// Scan the module to find the duration and end point of the main song
+    /// and of the subsongs starting at orders not played by previous songs.
     pub fn scan(&mut self) -> &Self {
+
+        // the scan can be repeated, e.g. to change the checkpoint interval
+        for cnt in self.scan_cnt.iter_mut() {
+            for x in cnt.iter_mut() {
+                *x = 0;
+            }
+        }
+        for o in self.ord_data.iter_mut() {
+            *o = OrdData::new();
+        }
+        self.checkpoints.clear();
+
+        let mut song = 0;
+        let mut entry = 0;
+        loop {
+            self.scan_song(song, entry);
+            song += 1;
+            if song >= MAX_SEQUENCES {
+                break
+            }
+
+            entry = match (0..self.scan_cnt.len()).find(|&p| !self.ord_data[p].used && !self.scan_cnt[p].is_empty()) {
+                Some(pos) => pos,
+                None      => break,
+            };
+            debug!("scan: song {} starts at position {}", song, entry);
+        }
+        self.num_songs = song;
+        self.total_time = self.data.scan_data[0].time;
+
+        self.data.reset();
+        self.mixer.reset();
+        self.reset();
+
+        self
+    }
+
+    fn scan_song(&mut self, song: usize, entry: usize) {
         let mut prev_pos = 9999;
         let mut prev_row = 9999;
         let mut prev_loop_count = 9999;
+        let mut stalled = 0;
+
+        if let Err(e) = self.format_player.restore_state(&self.init_state) {
+            debug!("can't restore initial player state: {}", e);
+        }
+        if let Err(e) = self.mixer.restore(&mut &self.init_mixer[..]) {
+            debug!("can't restore initial mixer state: {}", e);
+        }
+        self.data.reset();
+        self.data.song = song;
+        self.data.pos = entry;
 
         self.format_player.start(&mut self.data, &*self.module.data, &mut self.mixer);
 
@@ -231,7 +324,18 @@ impl<'a> Player<'a> {
             let row = self.data.row;
             let loop_count = self.data.loop_count;
 
-            if prev_row != row || prev_pos != pos || prev_loop_count != loop_count {
+            // a jump past the song length is reported until the player wraps
+            // to a valid order in the next row
+            let valid = pos < self.scan_cnt.len() && row < self.scan_cnt[pos].len();
+
+            if !valid || (prev_row == row && prev_pos == pos && prev_loop_count == loop_count) {
+                stalled += 1;
+                if stalled > MAX_ROW_TICKS {
+                    debug!("scan: no new row after {}/{}", pos, row);
+                    break;
+                }
+            } else {
+                stalled = 0;
 
                 // FIXME
                 //debug!("scan: check {}/{}", pos, row);
@@ -246,47 +350,93 @@ impl<'a> Player<'a> {
 
 
                 self.scan_cnt[pos][row] += 1;
+                if self.scan_cnt[pos][row] == 1 {
+                    self.scan_time[pos][row] = self.data.time;
+                }
                 prev_loop_count = loop_count;
                 prev_row = row;
 
                 if prev_pos != pos && !self.ord_data[pos].used {
-                    unsafe{ self.ord_data[pos].state = self.format_player.save_state(); }
+                    self.ord_data[pos].state = self.format_player.save_state();
+                    self.ord_data[pos].mixer.clear();
+                    self.mixer.save(&mut self.ord_data[pos].mixer);
                     self.ord_data[pos].time = self.data.time;
-                    prev_pos = pos;
+                    self.ord_data[pos].song = song;
                     self.ord_data[pos].used = true;
                     //debug!("scan: pos {}: time {}", pos, self.ord_data[pos].time);
                 }
+                prev_pos = pos;
+
+                if self.checkpoint_rows > 0 && row % self.checkpoint_rows == 0 && self.scan_cnt[pos][row] == 1 {
+                    let mut mixer_state = State::new();
+                    self.mixer.save(&mut mixer_state);
+                    self.checkpoints.push(Checkpoint{
+                        pos,
+                        row,
+                        state      : self.format_player.save_state(),
+                        mixer      : mixer_state,
+                        time       : self.data.time,
+                        speed      : self.data.speed,
+                        tempo      : self.data.tempo,
+                        loop_count : self.data.loop_count,
+                        inside_loop: self.data.inside_loop,
+                    });
+                }
             }
 
-            self.format_player.play(&mut self.data, &*self.module.data, &mut self.mixer);
+            self.play_tick();
 
+            // keep track of sample positions for seeking
+            self.mixer.skip();
         }
-        self.total_time = self.data.time as u32;
 
         debug!("end position is {}/{}", self.data.pos, self.data.row);
-        let song = self.data.song;
-        self.data.scan_data[song].num = self.scan_cnt[self.data.pos][self.data.row] as usize;
-        self.data.scan_data[song].row = self.data.row;
-        self.data.scan_data[song].ord = self.data.pos;
+        let (pos, row) = (self.data.pos, self.data.row);
+        let valid = pos < self.scan_cnt.len() && row < self.scan_cnt[pos].len();
+        self.data.scan_data[song].num = if valid { self.scan_cnt[pos][row] as usize } else { 1 };
+        self.data.scan_data[song].row = row;
+        self.data.scan_data[song].ord = pos;
         self.data.scan_data[song].frame = self.data.frame;
-
-        self.reset();
-        self.data.reset();
-        self.mixer.reset();
-
-        self
+        self.data.scan_data[song].entry = entry;
+        self.data.scan_data[song].time = self.data.time as u32;
+
+        // the song loops back to a row in one of its own orders, or continues
+        // in a previous song
+        self.data.scan_data[song].loop_time = if valid && self.ord_data[pos].used && self.ord_data[pos].song == song {
+            Some(self.scan_time[pos][row] as u32)
+        } else {
+            None
+        };
     }
 
+    // Restore the initial player state, including the mixer settings made
+    // when the player started.
     pub fn reset(&mut self) {
-        unsafe {
-            self.format_player.restore_state(&self.ord_data[0].state);
+        if self.ord_data[0].used {
+            if let Err(e) = self.format_player.restore_state(&self.ord_data[0].state) {
+                debug!("can't restore player state: {}", e);
+            }
+            if let Err(e) = self.mixer.restore(&mut &self.ord_data[0].mixer[..]) {
+                debug!("can't restore mixer state: {}", e);
+            }
         }
     }
 
+    /// Record a checkpoint every `rows` rows in the next scan, 0 disables
+    /// checkpoints. Checkpoints let `seek_to` start replay from any row
+    /// without playing the order from its beginning.
+    pub fn set_checkpoint_rows(&mut self, rows: usize) {
+        self.checkpoint_rows = rows;
+    }
+
     pub fn set_interpolator(&mut self, name: &str) -> Result<(), Error> {
         self.mixer.set_interpolator(name)
     }
 
+    pub fn set_ramp_length(&mut self, samples: usize) {
+        self.mixer.set_ramp_length(samples)
+    }
+
     pub fn mixer_voices(&self) -> usize {
         self.mixer.num_voices()
     }
@@ -301,57 +451,245 @@ impl<'a> Player<'a> {
         self
     }
 
-    pub fn play_frame(&mut self) -> &mut Self {
-        self.data.check_end_of_module();
+    // Play one tick, advance the replay time and set the mixer frame size.
+    // The frame duration is the same for all players, 20ms at 125 BPM.
+    fn play_tick(&mut self) {
         self.format_player.play(&mut self.data, &*self.module.data, &mut self.mixer);
+        self.data.time += 20.0 * 125.0 / self.data.tempo;
         self.mixer.set_tempo(self.data.tempo as f64);
+    }
+
+    pub fn play_frame(&mut self) -> &mut Self {
+        self.data.check_end_of_module();
+        self.play_tick();
         self.mixer.mix();
 
         self
     }
 
-    pub fn fill_buffer(&mut self, out_buffer: &mut [i16], loops: usize) {
-        let mut filled = 0;
+    /// Play a frame and render each module channel to its own buffer, in
+    /// addition to the main mix.
+    pub fn play_frame_stems(&mut self) -> Vec<&[i16]> {
+        self.set_stems(true);
+        self.play_frame();
+        self.mixer.stem_buffers()
+    }
+
+    /// Enable or disable per-channel stem buffers.
+    pub fn set_stems(&mut self, val: bool) {
+        let channels = if val { self.module.channels } else { 0 };
+        if self.mixer.num_stems() != channels {
+            self.mixer.set_stems(channels);
+        }
+    }
+
+    /// The 32-bit accumulator of each channel in the last frame, when stems
+    /// are enabled.
+    pub fn stem_mix_buffers(&self) -> Vec<&[i32]> {
+        self.mixer.stem_frames()
+    }
+
+    /// Fill the buffer with rendered samples. Replay ends after `loops`
+    /// loops, or never if `loops` is 0, followed by the fade-out set with
+    /// `set_fade_out()`. Returns the number of samples written before the
+    /// end of replay, the rest of the buffer is cleared.
+    pub fn fill_buffer<T: OutputSample>(&mut self, out_buffer: &mut [T], loops: usize) -> usize {
         let size = out_buffer.len();
+        let filled = self.fill(size, loops, |mixer, ofs, pos, len| {
+            mixer.render(&mut out_buffer[pos..pos+len], ofs);
+        });
+
+        // Clear rest of the buffer
+        for x in &mut out_buffer[filled..] {
+            *x = T::default();
+        }
+
+        filled
+    }
+
+    /// Like `fill_buffer()`, but fill one buffer per module channel with
+    /// the output of that channel. All buffers must have the same size.
+    pub fn fill_stem_buffers<T: OutputSample>(&mut self, out_buffers: &mut [Vec<T>], loops: usize) -> usize {
+        self.set_stems(true);
+        let size = out_buffers.iter().map(|b| b.len()).min().unwrap_or(0);
+        let filled = self.fill(size, loops, |mixer, ofs, pos, len| {
+            for (chn, out) in out_buffers.iter_mut().enumerate() {
+                mixer.render_stem(chn, &mut out[pos..pos+len], ofs);
+            }
+        });
+
+        for out in out_buffers.iter_mut() {
+            for x in &mut out[filled..] {
+                *x = T::default();
+            }
+        }
+
+        filled
+    }
+
+    // Play frames until size samples are rendered or replay ends, calling
+    // render with the mixer, frame offset, output position and length of
+    // each chunk. Returns the number of samples rendered.
+    fn fill<F>(&mut self, size: usize, loops: usize, mut render: F) -> usize
+    where F: FnMut(&Mixer, usize, usize, usize)
+    {
+        let mut filled = 0;
+        let fade_len = self.fade_out as usize * self.mixer.rate as usize / 1000;
 
         // Fill buffer
         while filled < size {
             // Check if buffer full
             if self.consumed == self.in_size {
+                if self.fade_pos.map_or(false, |pos| pos >= fade_len) {
+                    self.end = true;
+                }
+                if self.end {
+                    break
+                }
+
                 self.play_frame();
+                self.loop_count = self.data.loop_count;
 
                 // Check end of module
-                if self.end() || (loops > 0 && self.loop_count >= loops) {
-                    // Start of frame, return end of replay
-                    if filled == 0 {
-                        self.consumed = 0;
-                        self.in_size = 0;
-                        return;
-                    } else {
-                        self.end = false;
+                if self.fade_pos.is_none() && loops > 0 && self.loop_count >= loops {
+                    if fade_len == 0 {
+                        self.end = true;
+                        break
                     }
-
-                    // Clear rest of the buffer
-                    out_buffer[filled..].fill(0, size - filled);
+                    self.fade_pos = Some(0);
                 }
 
                 self.consumed = 0;
                 self.in_pos = 0;
                 self.in_size = self.buffer().len();
+
+                // The fade-out can end in the middle of the frame
+                if let Some(pos) = self.fade_pos {
+                    self.mixer.fade(pos, fade_len);
+                    let len = cmp::min(self.in_size / 2, fade_len - pos);
+                    self.in_size = len * 2;
+                    self.fade_pos = Some(pos + len);
+                }
             }
 
             // Copy frame data to user buffer
             let copy_size = cmp::min(size - filled, self.in_size - self.consumed);
-            out_buffer[filled..filled+copy_size].copy_from_slice(&self.buffer()[self.consumed..self.consumed+copy_size]);
+            render(&self.mixer, self.consumed, filled, copy_size);
             self.consumed += copy_size;
             filled += copy_size;
         }
+
+        filled
+    }
+
+    /// Set the length in ms of the fade-out played by `fill_buffer()`
+    /// after the last loop.
+    pub fn set_fade_out(&mut self, ms: u32) {
+        self.fade_out = ms;
+    }
+
+    pub fn fade_out(&self) -> u32 {
+        self.fade_out
     }
 
+    /// True if `fill_buffer()` reached the end of replay.
     pub fn end(&self) -> bool {
         self.end
     }
 
+    // Restart the loop count and cancel the fade-out after a position change
+    fn clear_end(&mut self) {
+        self.data.loop_count = 0;
+        self.data.end_point = self.data.scan_data[self.data.song].num;
+        self.loop_count = 0;
+        self.fade_pos = None;
+        self.end = false;
+    }
+
+    /// The number of times the end of the song was reached.
+    pub fn loop_count(&self) -> usize {
+        self.data.loop_count
+    }
+
+    fn snapshot_version() -> u32 {
+        PlayerData::version().rotate_left(5) ^ Mixer::version()
+    }
+
+    /// Save the complete playback state, including the mixer voices and
+    /// the part of the current frame that wasn't consumed yet.
+    pub fn snapshot(&self) -> State {
+        let mut state = Vec::new();
+        Self::snapshot_version().save(&mut state);
+        self.mixer.rate.save(&mut state);
+        self.format_player.save_state().save(&mut state);
+        self.data.save(&mut state);
+        self.mixer.save(&mut state);
+        self.mixer.frame().to_vec().save(&mut state);
+        self.loop_count.save(&mut state);
+        self.end.save(&mut state);
+        self.fade_pos.save(&mut state);
+        self.consumed.save(&mut state);
+        self.in_pos.save(&mut state);
+        self.in_size.save(&mut state);
+        state
+    }
+
+    /// Restore a playback state saved by `snapshot`.
+    pub fn restore_snapshot(&mut self, mut state: &[u8]) -> Result<(), Error> {
+        let mut version = 0_u32;
+        let mut rate = 0_u32;
+        version.restore(&mut state)?;
+        rate.restore(&mut state)?;
+        if version != Self::snapshot_version() {
+            return Err(Error::Player("snapshot version mismatch".to_owned()))
+        }
+        if rate != self.mixer.rate {
+            return Err(Error::Player(format!("snapshot sampling rate is {}, expected {}", rate, self.mixer.rate)))
+        }
+
+        // Decode and validate everything before changing the player
+        let mut player_state = State::new();
+        let mut data = Box::new(PlayerData::new());
+        let mut frame = Vec::<i32>::new();
+        let mut loop_count = 0_usize;
+        let mut end = false;
+        let mut fade_pos: Option<usize> = None;
+        let mut consumed = 0_usize;
+        let mut in_pos = 0_usize;
+        let mut in_size = 0_usize;
+        player_state.restore(&mut state)?;
+        data.restore(&mut state)?;
+        let mixer_state = MixerState::decode(&mut state)?;
+        frame.restore(&mut state)?;
+        loop_count.restore(&mut state)?;
+        end.restore(&mut state)?;
+        fade_pos.restore(&mut state)?;
+        consumed.restore(&mut state)?;
+        in_pos.restore(&mut state)?;
+        in_size.restore(&mut state)?;
+        if !state.is_empty() {
+            return Err(Error::Player("invalid snapshot size".to_owned()))
+        }
+        if frame.len() != mixer_state.framesize() * 2 {
+            return Err(Error::Player("invalid frame size".to_owned()))
+        }
+
+        // The format player is left unchanged if its state is rejected
+        self.format_player.restore_state(&player_state)?;
+
+        self.data = data;
+        self.mixer.apply_state(mixer_state);
+        self.mixer.set_frame(&frame)?;
+        self.loop_count = loop_count;
+        self.end = end;
+        self.fade_pos = fade_pos;
+        self.consumed = consumed;
+        self.in_pos = in_pos;
+        self.in_size = in_size;
+
+        Ok(())
+    }
+
     pub fn info(&mut self, info: &mut FrameInfo) -> &mut Self {
         info.pos = self.data.pos;
         info.row = self.data.row;
@@ -360,6 +698,7 @@ impl<'a> Player<'a> {
         info.speed = self.data.speed;
         info.tempo = self.data.tempo as usize;
         info.loop_count = self.data.loop_count;
+        info.end = self.end;
         info.time = self.data.time;
         info.pattern = self.module.pattern_in_position(info.pos);
         info.num_rows = match info.pattern {
@@ -387,23 +726,182 @@ impl<'a> Player<'a> {
         self.mixer.set_mute_all(val)
     }
 
+    // Restore the player and mixer states saved when the scan reached the
+    // start of an order.
+    fn restore_position(&mut self, pos: usize) -> Result<(), Error> {
+        if pos >= self.ord_data.len() || !self.ord_data[pos].used {
+            return Err(Error::Player(format!("no state for position {}", pos)))
+        }
+        self.format_player.restore_state(&self.ord_data[pos].state)?;
+        self.mixer.restore(&mut &self.ord_data[pos].mixer[..])?;
+        self.data.pos = pos;
+        self.data.row = 0;
+        self.data.frame = 0;
+        self.data.time = self.ord_data[pos].time;
+        self.consumed = 0;
+        self.in_pos = 0;
+        self.in_size = 0;
+        Ok(())
+    }
+
     pub fn set_position(&mut self, pos: usize) -> &Self {
-        if pos < self.ord_data.len() {
-            unsafe{ self.format_player.restore_state(&self.ord_data[pos].state) }
-            self.data.time = self.ord_data[pos].time;
+        if let Err(e) = self.restore_position(pos) {
+            debug!("can't set position: {}", e);
         }
         self
     }
 
-    pub fn set_song(&mut self, song: usize) -> &Self {
-        self.data.song = song;
-        //self.data.pos = 0; FIXME: songs may start at pos != 0
+    /// Seek to a time in milliseconds. The state of the nearest order
+    /// before the seek point is restored, and frames are played without
+    /// mixing until the frame containing the requested time is reached.
+    pub fn seek_ms(&mut self, ms: u32) -> &Self {
+        let t = cmp::min(ms, self.total_time) as f32;
+
+        let mut pos = None;
+        for (i, o) in self.ord_data.iter().enumerate() {
+            if o.used && o.song == self.data.song && o.time <= t && pos.map_or(true, |p: usize| o.time > self.ord_data[p].time) {
+                pos = Some(i);
+            }
+        }
+
+        match pos {
+            Some(p) => if let Err(e) = self.restore_position(p) {
+                debug!("can't seek: {}", e);
+                return self
+            },
+            None    => return self,
+        }
+
+        self.clear_end();
+
+        loop {
+            let start = self.data.time;
+            self.data.check_end_of_module();
+            self.play_tick();
+
+            if self.data.time > t {
+                // skip the part of the frame before the seek point
+                self.mixer.mix();
+                let skip = ((t - start) * self.mixer.rate as f32 / 1000.0) as usize * 2;
+                self.in_size = self.buffer().len();
+                self.consumed = cmp::min(skip, self.in_size);
+                break
+            }
+
+            self.mixer.skip();
+        }
+
         self
     }
 
+    /// Seek to a row in an order. The closest checkpoint before the row
+    /// is restored, or the start of the order if there is none, and rows
+    /// are played without mixing until the requested row is reached.
+    pub fn seek_to(&mut self, pos: usize, row: usize) -> Result<(), Error> {
+        let cp = self.checkpoints.iter().enumerate()
+                     .filter(|&(_, c)| c.pos == pos && c.row <= row)
+                     .max_by_key(|&(_, c)| c.row).map(|(i, _)| i);
+
+        match cp {
+            Some(i) => {
+                let c = &self.checkpoints[i];
+                self.format_player.restore_state(&c.state)?;
+                self.mixer.restore(&mut &c.mixer[..])?;
+                self.data.pos = c.pos;
+                self.data.row = c.row;
+                self.data.frame = 0;
+                self.data.time = c.time;
+                self.data.speed = c.speed;
+                self.data.tempo = c.tempo;
+                self.data.loop_count = c.loop_count;
+                self.data.inside_loop = c.inside_loop;
+                self.consumed = 0;
+                self.in_pos = 0;
+                self.in_size = 0;
+            },
+            None => {
+                self.restore_position(pos)?;
+                self.data.loop_count = 0;
+            },
+        }
+
+        let loop_count = self.data.loop_count;
+        self.clear_end();
+        self.data.loop_count = loop_count;
+        self.loop_count = loop_count;
+
+        let mut frames = 0;
+        while self.data.pos != pos || self.data.row != row {
+            if self.data.pos != pos || frames > MAX_SEEK_FRAMES {
+                return Err(Error::Player(format!("row {} not reached in position {}", row, pos)))
+            }
+            self.data.check_end_of_module();
+            self.play_tick();
+            self.mixer.skip();
+            frames += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn num_songs(&self) -> usize {
+        self.num_songs
+    }
+
+    pub fn songs(&self) -> Vec<SongInfo> {
+        self.data.scan_data[..self.num_songs].iter().map(|x| SongInfo{
+            entry    : x.entry,
+            time     : x.time,
+            loop_pos : x.ord,
+            loop_row : x.row,
+            loop_time: x.loop_time,
+        }).collect()
+    }
+
+    /// Select a song and restart replay at its entry point.
+    pub fn set_song(&mut self, song: usize) -> Result<(), Error> {
+        if song >= self.num_songs {
+            return Err(Error::Player(format!("invalid song {}", song)))
+        }
+        let entry = self.data.scan_data[song].entry;
+        self.restore_position(entry)?;
+        self.data.song = song;
+        self.clear_end();
+        self.total_time = self.data.scan_data[song].time;
+        Ok(())
+    }
+
     pub fn buffer(&self) -> &[i16] {
         self.mixer.buffer()
     }
+
+    /// The 32-bit mixer accumulator of the last frame, before the output
+    /// conversion.
+    pub fn mix_buffer(&self) -> &[i32] {
+        self.mixer.frame()
+    }
+
+    pub fn buffer_as<T: OutputSample>(&self, out: &mut Vec<T>) {
+        out.clear();
+        out.resize(self.mixer.buffer().len(), T::default());
+        self.mixer.render(out, 0);
+    }
+}
+
+/// The entry point and duration of a song in the module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SongInfo {
+    /// The first order of the song.
+    pub entry: usize,
+    /// Replay time in ms.
+    pub time : u32,
+    /// The order the song loops back to when it ends.
+    pub loop_pos : usize,
+    /// The row the song loops back to when it ends.
+    pub loop_row : usize,
+    /// The time in ms of the loop target, or None if the song continues
+    /// in orders belonging to a previous song.
+    pub loop_time: Option<u32>,
 }
 
 #[derive(Default, Clone)]
@@ -433,6 +931,7 @@ pub struct FrameInfo {
     pub tempo     : usize,
     pub speed     : usize,
     pub loop_count: usize,
+    pub end       : bool,
     pub time      : f32,
     pub channel_info: Vec<ChannelInfo>,
 }
@@ -484,3 +983,36 @@ impl Options {
     }
 }
 
+
+
+/// Drive a format player directly, without the song scan and end of module
+/// handling in `Player`, so tests can check mixer values tick by tick.
+#[cfg(test)]
+pub struct TestPlayer {
+    pub module: Module,
+    pub data  : PlayerData,
+    pub mixer : Mixer<'static>,
+    player    : Box<FormatPlayer>,
+}
+
+#[cfg(test)]
+impl TestPlayer {
+    pub fn new(b: &[u8], player_id: &str) -> Self {
+        let entry = list_by_id(player_id).unwrap();
+        let module = entry.import(format::load(b, player_id).unwrap()).unwrap();
+        let mixer = Mixer::new(module.channels, 44100, module.data.samples());
+        let player = entry.player(&module, Options::from_str(""));
+
+        let mut p = TestPlayer{ module, data: PlayerData::new(), mixer, player };
+        p.start();
+        p
+    }
+
+    pub fn start(&mut self) {
+        self.player.start(&mut self.data, &*self.module.data, &mut self.mixer);
+    }
+
+    pub fn play(&mut self) {
+        self.player.play(&mut self.data, &*self.module.data, &mut self.mixer);
+    }
+}
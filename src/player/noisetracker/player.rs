@@ -1,8 +1,9 @@
 use module::{Module, ModuleData};
 use player::{Options, PlayerData, FormatPlayer, State};
-use player::scan::SaveRestore;
+use player::scan::{self, SaveRestore};
 use format::mk::ModData;
 use mixer::Mixer;
+use Error;
 
 /// NT1.1 Replayer
 ///
@@ -339,7 +340,7 @@ impl ModPlayer {
 }
 
 
-#[derive(Clone,Copy,Default)]
+#[derive(Clone,Copy,Default,SaveRestore)]
 struct ChannelData {
     n_0_note        : u16,
     n_2_cmd         : u8,
@@ -395,6 +396,8 @@ impl FormatPlayer for ModPlayer {
         data.initial_speed = data.speed;
         data.initial_tempo = data.tempo;
 
+        self.mt_songpos = data.pos as u8;
+
         let pan = match self.options.option_int("pan") {
             Some(val) => val,
             None      => 70,
@@ -420,7 +423,6 @@ impl FormatPlayer for ModPlayer {
         data.row = self.mt_pattpos as usize;
         data.pos = self.mt_songpos as usize;
         data.speed = self.mt_speed as usize;
-        data.time += 20.0;
 
         // Is this correct? workaround for captive.mod
         if data.pos == 255 {
@@ -436,11 +438,11 @@ impl FormatPlayer for ModPlayer {
         self.mt_pattpos = 0;
     }
 
-    unsafe fn save_state(&self) -> State {
-        self.save()
+    fn save_state(&self) -> State {
+        scan::save_state(self)
     }
 
-    unsafe fn restore_state(&mut self, state: &State) {
-        self.restore(&state)
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
     }
 }
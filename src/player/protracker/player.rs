@@ -1,8 +1,9 @@
 use module::{Module, ModuleData};
 use player::{Options, PlayerData, FormatPlayer, State};
-use player::scan::SaveRestore;
+use player::scan::{self, SaveRestore};
 use format::mk::ModData;
 use mixer::Mixer;
+use Error;
 
 /// PT2.1A Replayer
 ///
@@ -869,7 +870,7 @@ lazy_static! {
 }
 
 
-#[derive(Clone,Copy,Default)]
+#[derive(Clone,Copy,Default,SaveRestore)]
 struct ChannelData {
     n_note         : u16,
     n_cmd          : u8,
@@ -918,6 +919,8 @@ impl FormatPlayer for ModPlayer {
         data.initial_speed = data.speed;
         data.initial_tempo = data.tempo;
 
+        self.mt_song_pos = data.pos as u8;
+
         for i in 0..31 {
             self.mt_samplestarts[i] = module.samples[i].address;
         }
@@ -948,7 +951,6 @@ impl FormatPlayer for ModPlayer {
         data.pos = self.mt_song_pos as usize;
         data.speed = self.mt_speed as usize;
         data.tempo = self.cia_tempo as f32;
-        data.time += 20.0 * 125.0 / data.tempo;
 
         data.inside_loop = false;
         for chn in 0..4 {
@@ -969,11 +971,11 @@ impl FormatPlayer for ModPlayer {
         self.mt_pattern_pos     = 0;
     }
 
-    unsafe fn save_state(&self) -> State {
-        self.save()
+    fn save_state(&self) -> State {
+        scan::save_state(self)
     }
 
-    unsafe fn restore_state(&mut self, state: &State) {
-        self.restore(&state)
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
     }
 }
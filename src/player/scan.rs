@@ -1,31 +1,370 @@
-use player::State;
+use player::{State, Options};
+use ::*;
 
+const STATE_MAGIC  : &'static [u8] = b"OXST";
+const STATE_FORMAT : u8 = 1;
+const HEADER_SIZE  : usize = 9;
+
+/// Player state serialization. Fields are written one by one in declaration
+/// order, and the version tag identifies the layout of the saved structure.
 pub trait SaveRestore {
-    unsafe fn save(&self) -> Vec<u8>;
-    unsafe fn restore(&mut self, &Vec<u8>);
+    fn save(&self, &mut Vec<u8>);
+    fn restore(&mut self, &mut &[u8]) -> Result<(), Error>;
+    fn version() -> u32 where Self: Sized;
+}
+
+/// Serialize the player state with a header containing its version tag.
+pub fn save_state<T: SaveRestore>(player: &T) -> State {
+    let mut state = Vec::new();
+    state.extend_from_slice(STATE_MAGIC);
+    state.push(STATE_FORMAT);
+    T::version().save(&mut state);
+    player.save(&mut state);
+    state
+}
+
+/// Restore a player state saved by `save_state`. States saved by a player
+/// with a different layout are rejected before the player is changed, and
+/// the player is left unchanged if the state body is truncated or corrupt.
+pub fn restore_state<T: SaveRestore>(player: &mut T, state: &State) -> Result<(), Error> {
+    if state.len() < HEADER_SIZE || &state[..4] != STATE_MAGIC {
+        return Err(Error::Player("invalid player state".to_owned()))
+    }
+
+    let mut b = &state[5..];
+    let mut version = 0_u32;
+    version.restore(&mut b)?;
+    if state[4] != STATE_FORMAT || version != T::version() {
+        return Err(Error::Player("player state version mismatch".to_owned()))
+    }
+
+    // Keep a copy of the current state to roll back to if the body turns
+    // out to be truncated or corrupt halfway through.
+    let mut backup = Vec::new();
+    player.save(&mut backup);
+
+    let res = match player.restore(&mut b) {
+        Ok(_) if !b.is_empty() => Err(Error::Player("invalid player state size".to_owned())),
+        res                    => res,
+    };
+    if res.is_err() {
+        player.restore(&mut &backup[..])?;
+    }
+
+    res
+}
+
+fn take<'a>(b: &mut &'a [u8], size: usize) -> Result<&'a [u8], Error> {
+    if b.len() < size {
+        return Err(Error::Player("player state too short".to_owned()))
+    }
+    let (head, tail) = b.split_at(size);
+    *b = tail;
+    Ok(head)
 }
 
-#[derive(Default, Clone)]
+macro_rules! impl_save_restore {
+    ( $t:ty, $size:expr, $tag:expr ) => {
+        impl SaveRestore for $t {
+            fn save(&self, state: &mut Vec<u8>) {
+                state.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn restore(&mut self, state: &mut &[u8]) -> Result<(), Error> {
+                let mut buf = [0; $size];
+                buf.copy_from_slice(take(state, $size)?);
+                *self = <$t>::from_le_bytes(buf);
+                Ok(())
+            }
+
+            fn version() -> u32 {
+                $tag
+            }
+        }
+    }
+}
+
+impl_save_restore!(u8,  1, 1);
+impl_save_restore!(i8,  1, 2);
+impl_save_restore!(u16, 2, 3);
+impl_save_restore!(i16, 2, 4);
+impl_save_restore!(u32, 4, 5);
+impl_save_restore!(i32, 4, 6);
+impl_save_restore!(u64, 8, 7);
+impl_save_restore!(i64, 8, 8);
+impl_save_restore!(f32, 4, 9);
+impl_save_restore!(f64, 8, 10);
+
+// Sizes are stored as 64-bit values so states don't depend on the platform
+impl SaveRestore for usize {
+    fn save(&self, state: &mut Vec<u8>) {
+        (*self as u64).save(state)
+    }
+
+    fn restore(&mut self, state: &mut &[u8]) -> Result<(), Error> {
+        let mut val = 0_u64;
+        val.restore(state)?;
+        *self = val as usize;
+        Ok(())
+    }
+
+    fn version() -> u32 {
+        11
+    }
+}
+
+impl SaveRestore for isize {
+    fn save(&self, state: &mut Vec<u8>) {
+        (*self as i64).save(state)
+    }
+
+    fn restore(&mut self, state: &mut &[u8]) -> Result<(), Error> {
+        let mut val = 0_i64;
+        val.restore(state)?;
+        *self = val as isize;
+        Ok(())
+    }
+
+    fn version() -> u32 {
+        16
+    }
+}
+
+impl SaveRestore for bool {
+    fn save(&self, state: &mut Vec<u8>) {
+        state.push(*self as u8)
+    }
+
+    fn restore(&mut self, state: &mut &[u8]) -> Result<(), Error> {
+        *self = take(state, 1)?[0] != 0;
+        Ok(())
+    }
+
+    fn version() -> u32 {
+        12
+    }
+}
+
+impl<T: SaveRestore + Default> SaveRestore for Vec<T> {
+    fn save(&self, state: &mut Vec<u8>) {
+        self.len().save(state);
+        self.iter().for_each(|x| x.save(state));
+    }
+
+    fn restore(&mut self, state: &mut &[u8]) -> Result<(), Error> {
+        let mut len = 0_usize;
+        len.restore(state)?;
+        if len > state.len() {
+            return Err(Error::Player("player state too short".to_owned()))
+        }
+        self.clear();
+        self.resize_with(len, Default::default);
+        for x in self.iter_mut() {
+            x.restore(state)?;
+        }
+        Ok(())
+    }
+
+    fn version() -> u32 {
+        T::version().rotate_left(7) ^ 13
+    }
+}
+
+impl<T: SaveRestore + Default> SaveRestore for Option<T> {
+    fn save(&self, state: &mut Vec<u8>) {
+        self.is_some().save(state);
+        if let Some(ref x) = *self {
+            x.save(state);
+        }
+    }
+
+    fn restore(&mut self, state: &mut &[u8]) -> Result<(), Error> {
+        let mut is_some = false;
+        is_some.restore(state)?;
+        if !is_some {
+            *self = None;
+            return Ok(())
+        }
+        if self.is_none() {
+            *self = Some(T::default());
+        }
+        match *self {
+            Some(ref mut x) => x.restore(state),
+            None            => Ok(()),
+        }
+    }
+
+    fn version() -> u32 {
+        T::version().rotate_left(7) ^ 17
+    }
+}
+
+impl<T: SaveRestore> SaveRestore for Box<T> {
+    fn save(&self, state: &mut Vec<u8>) {
+        (**self).save(state)
+    }
+
+    fn restore(&mut self, state: &mut &[u8]) -> Result<(), Error> {
+        (**self).restore(state)
+    }
+
+    fn version() -> u32 {
+        T::version()
+    }
+}
+
+macro_rules! impl_save_restore_array {
+    ( $( $n:expr ),* ) => {
+        $(
+            impl<T: SaveRestore> SaveRestore for [T; $n] {
+                fn save(&self, state: &mut Vec<u8>) {
+                    self.iter().for_each(|x| x.save(state));
+                }
+
+                fn restore(&mut self, state: &mut &[u8]) -> Result<(), Error> {
+                    for x in self.iter_mut() {
+                        x.restore(state)?;
+                    }
+                    Ok(())
+                }
+
+                fn version() -> u32 {
+                    T::version().rotate_left(7) ^ ($n as u32).wrapping_mul(31) ^ 14
+                }
+            }
+        )*
+    }
+}
+
+// Arrays are implemented for the sizes used by the players, like std did
+// before const generics
+impl_save_restore_array!( 1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15, 16,
+                         17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32,
+                         64, 128, 256);
+
+// Options are set when the player is created and are not part of its state
+impl SaveRestore for Options {
+    fn save(&self, _state: &mut Vec<u8>) {
+    }
+
+    fn restore(&mut self, _state: &mut &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn version() -> u32 {
+        15
+    }
+}
+
+#[derive(Default, Clone, SaveRestore)]
 pub struct ScanData {
-    pub ord  : usize,
-    pub row  : usize,
-    pub frame: usize,
-    pub num  : usize,
+    pub ord      : usize,
+    pub row      : usize,
+    pub frame    : usize,
+    pub num      : usize,
+    pub entry    : usize,
+    pub time     : u32,
+    pub loop_time: Option<u32>,
 }
 
 #[derive(Clone)]
 pub struct OrdData {
     pub state: State,
+    pub mixer: State,
     pub time : f32,
     pub used : bool,
+    pub song : usize,
 }
 
 impl OrdData {
     pub fn new() -> Self {
         OrdData{
             state: vec![0; 0],
+            mixer: vec![0; 0],
             time : 0.0,
             used : false,
+            song : 0,
         }
     }
 }
+
+/// Player state recorded by the scan at the first visit of a row.
+pub struct Checkpoint {
+    pub pos        : usize,
+    pub row        : usize,
+    pub state      : State,
+    pub mixer      : State,
+    pub time       : f32,
+    pub speed      : usize,
+    pub tempo      : f32,
+    pub loop_count : usize,
+    pub inside_loop: bool,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default, SaveRestore)]
+    struct Channel {
+        vol : u8,
+        freq: [i16; 2],
+    }
+
+    #[derive(Default, SaveRestore)]
+    struct TestPlayer {
+        pos  : usize,
+        time : f32,
+        flag : bool,
+        chn  : [Channel; 2],
+        table: Vec<Vec<u16>>,
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let mut p = TestPlayer::default();
+        p.pos = 3;
+        p.time = 1.5;
+        p.flag = true;
+        p.chn[1].vol = 64;
+        p.chn[1].freq[0] = -428;
+        p.table = vec![vec![1, 2], vec![], vec![3]];
+        let state = save_state(&p);
+
+        let mut q = TestPlayer::default();
+        q.table = vec![vec![9]; 5];
+        restore_state(&mut q, &state).unwrap();
+        assert_eq!((q.pos, q.time, q.flag), (3, 1.5, true));
+        assert_eq!((q.chn[1].vol, q.chn[1].freq), (64, [-428, 0]));
+        assert_eq!(q.table, p.table);
+    }
+
+    #[test]
+    fn test_restore_invalid() {
+        let mut p = TestPlayer::default();
+        let state = save_state(&p);
+        assert!(restore_state(&mut p, &vec![]).is_err());
+        assert!(restore_state(&mut p, &state[..state.len() - 1].to_vec()).is_err());
+
+        let mut c = Channel::default();
+        assert!(restore_state(&mut c, &state).is_err());
+        assert!(TestPlayer::version() != Channel::version());
+    }
+
+    #[test]
+    fn test_restore_truncated() {
+        let mut p = TestPlayer::default();
+        p.pos = 3;
+        p.table = vec![vec![1, 2]];
+        let state = save_state(&p);
+
+        // the position is decoded before the body runs out
+        let mut q = TestPlayer::default();
+        q.pos = 7;
+        q.table = vec![vec![9]];
+        assert!(restore_state(&mut q, &state[..state.len() - 2].to_vec()).is_err());
+        assert_eq!(q.pos, 7);
+        assert_eq!(q.table, vec![vec![9]]);
+    }
+}
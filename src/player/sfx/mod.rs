@@ -0,0 +1,27 @@
+mod player;
+
+use module::Module;
+use player::{Options, PlayerListEntry, PlayerInfo, FormatPlayer};
+use ::*;
+
+pub struct Sfx;
+
+impl PlayerListEntry for Sfx {
+   fn info(&self) -> PlayerInfo {
+       PlayerInfo {
+          id         : "sfx",
+          name       : "SoundFX 1.3/2.0 replayer",
+          description: "A port of the SoundFX 1.3 and 2.0 replay routine by Linel Software",
+          author     : "Claudio Matsuoka",
+          accepts    : &[ "sfx" ],
+       }
+   }
+
+   fn player(&self, module: &Module, options: Options) -> Box<FormatPlayer> {
+       Box::new(self::player::SfxPlayer::new(module, options))
+   }
+
+   fn import(&self, module: Module) -> Result<Module, Error> {
+       Ok(module)
+   }
+}
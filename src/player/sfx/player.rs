@@ -0,0 +1,429 @@
+use module::{Module, ModuleData};
+use player::{Options, PlayerData, FormatPlayer, State};
+use player::scan::{self, SaveRestore};
+use format::sfx::*;
+use mixer::Mixer;
+use Error;
+
+/// SoundFX 1.3/2.0 replayer
+///
+/// An oxdz player based on the SoundFX replay routine by Linel Software.
+/// Both versions share the same replay code, the 31-instrument modules
+/// saved by SoundFX 2.0 use the high nibble of the note word for the
+/// instrument number like Protracker modules do.
+
+#[derive(SaveRestore)]
+pub struct SfxPlayer {
+    options     : Options,
+
+    channel_data: [ChannelData; 4],
+    step_control: [StepControl; 4],
+    sample_ptr  : [u32; 31],
+    timer       : u16,
+    track_pos   : u16,
+    pattern_pos : u8,    // u32, in steps instead of bytes
+    break_flag  : bool,
+    song_length : u16,
+}
+
+impl SfxPlayer {
+    pub fn new(module: &Module, options: Options) -> Self {
+
+        let module = module.data.as_any().downcast_ref::<SfxData>().unwrap();
+
+        SfxPlayer {
+            options,
+
+            channel_data: [ChannelData::new(); 4],
+            step_control: [StepControl::new(); 4],
+            sample_ptr  : [0; 31],
+            timer       : 0,
+            track_pos   : 0,
+            pattern_pos : 0,
+            break_flag  : false,
+            song_length : module.song_length as u16,
+        }
+    }
+
+    fn music(&mut self, module: &SfxData, mut mixer: &mut Mixer) {
+        self.timer += 1;                     // addq.w  #1,Timer
+        if self.timer == 6 {                 // cmp.w   #6,Timer
+            self.timer = 0;                  // clr.w   Timer
+            self.play_sound(&module, &mut mixer);
+        } else {
+            // CheckEffects
+            for chn in 0..4 {
+                self.make_effects(chn, &mut mixer);
+            }
+        }
+    }
+
+    fn make_effects(&mut self, chn: usize, mut mixer: &mut Mixer) {
+        {
+            let sc = &mut self.step_control[chn];
+            if sc.n_0_step != 0 {                             // move.w  (a4),d0 / beq.s   NoStep
+                sc.n_2_period += sc.n_0_step;                 // add.w   d0,2(a4)
+                let reached = if sc.n_0_step > 0 {
+                    sc.n_2_period >= sc.n_4_target            // cmp.w   d0,d1 / bhi.s   StepOk
+                } else {
+                    sc.n_2_period <= sc.n_4_target            // StepItUp: cmp.w   d0,d1 / blt.s   StepOk
+                };
+                if reached {
+                    sc.n_0_step = 0;                          // clr.w   (a4)
+                    sc.n_2_period = sc.n_4_target;            // move.w  d1,d0
+                }
+                // StepOk
+                mixer.set_period(chn, sc.n_2_period as f64);  // move.w  d0,6(a5)
+                return
+            }
+        }
+
+        // NoStep
+        match self.channel_data[chn].n_2_cmd & 0x0f {
+            1 => self.appreggiato(chn, &mut mixer),
+            2 => self.pitchbend(chn, &mut mixer),
+            3 => mixer.enable_filter(true),     // LedOn
+            4 => mixer.enable_filter(false),    // LedOff
+            _ => (),
+        }
+    }
+
+    fn appreggiato(&mut self, chn: usize, mixer: &mut Mixer) {
+        let ch = &mut self.channel_data[chn];
+        let val = match self.timer {
+            1 | 5 => ch.n_3_cmdlo >> 4,    // Arpe1
+            2 | 4 => ch.n_3_cmdlo & 0x0f,  // Arpe2
+            _     => {                     // Arpe3
+                mixer.set_period(chn, ch.n_16_period as f64);
+                return
+            }
+        } as usize;
+
+        // Arpe4
+        for i in 0..NOTE_TABLE.len() {
+            if NOTE_TABLE[i] < 0 {
+                break                      // oxdz: add sanity check
+            }
+            if NOTE_TABLE[i] == ch.n_16_period {
+                // Arpe5
+                if NOTE_TABLE[i+val] > 0 {
+                    mixer.set_period(chn, NOTE_TABLE[i+val] as f64);  // move.w  d2,6(a5)
+                }
+                return
+            }
+        }
+    }
+
+    fn pitchbend(&mut self, chn: usize, mixer: &mut Mixer) {
+        let ch = &mut self.channel_data[chn];
+        let val = (ch.n_3_cmdlo >> 4) as i16;
+        if val != 0 {
+            ch.n_0_note += val;                         // add.w   d0,(a6)
+            mixer.set_period(chn, ch.n_0_note as f64);  // move.w  (a6),6(a5)
+            return
+        }
+        // pitch2
+        let val = (ch.n_3_cmdlo & 0x0f) as i16;
+        if val != 0 {
+            ch.n_0_note -= val;                         // sub.w   d0,(a6)
+            mixer.set_period(chn, ch.n_0_note as f64);  // move.w  (a6),6(a5)
+        }
+        // pitch3
+    }
+
+    // StepFinder: the low nibble of the parameter is the step speed, the
+    // high nibble is the number of halftones to slide from the last note.
+    fn set_step(&mut self, chn: usize, down: bool) {
+        let ch = &self.channel_data[chn];
+        let sc = &mut self.step_control[chn];
+
+        sc.n_2_period = ch.n_16_period;                   // move.w  (a6),2(a4)
+        let speed = (ch.n_3_cmdlo & 0x0f) as i16;
+        sc.n_0_step = if down { -speed } else { speed };  // neg.w   d2 / move.w  d2,(a4)
+
+        let num = (ch.n_3_cmdlo >> 4) as isize;
+        sc.n_4_target = ch.n_16_period;                   // EndStepUpFind
+        for i in 0..NOTE_TABLE.len() {
+            if NOTE_TABLE[i] < 0 {
+                break
+            }
+            if NOTE_TABLE[i] == ch.n_16_period {
+                // StepUpFound
+                let j = if down { i as isize + num } else { i as isize - num };
+                if j >= 0 && NOTE_TABLE[j as usize] > 0 {
+                    sc.n_4_target = NOTE_TABLE[j as usize];  // move.w  (a0,d2.w),d0 / move.w  d0,4(a4)
+                }
+                break
+            }
+        }
+    }
+
+    fn play_sound(&mut self, module: &SfxData, mut mixer: &mut Mixer) {
+        let pat = match module.pattern_in_position(self.track_pos as usize) {
+            Some(val) => val,
+            None      => return,
+        };
+
+        for chn in 0..4 {
+            self.play_note(pat, chn, &module, &mut mixer);
+        }
+
+        self.pattern_pos += 1;                       // add.l   #16,PatternPos
+        if self.pattern_pos == 64 || self.break_flag {
+            // NewPattern
+            self.pattern_pos = 0;                    // clr.l   PatternPos
+            self.break_flag = false;                 // clr.w   BreakFlag
+            self.track_pos += 1;                     // addq.l  #1,TrackPos
+            if self.track_pos >= self.song_length {  // cmp.w   SongLength,d0
+                self.track_pos = 0;                  // clr.l   TrackPos
+            }
+        }
+    }
+
+    fn play_note(&mut self, pat: usize, chn: usize, module: &SfxData, mixer: &mut Mixer) {
+        let event = module.patterns.event(pat, self.pattern_pos, chn);
+
+        let ch = &mut self.channel_data[chn];
+        ch.n_0_note = event.note as i16;             // move.l  (a0,d1.l),(a6)
+        ch.n_2_cmd = event.cmd;
+        ch.n_3_cmdlo = event.cmdlo;
+
+        match event.note {
+            SFX_NOTE_PIC | SFX_NOTE_UNK => return,  // NoChannel
+            SFX_NOTE_STP => {
+                mixer.set_volume(chn, 0);            // clr.w   8(a5)
+                return
+            }
+            SFX_NOTE_BRK => {
+                self.break_flag = true;              // move.w  #1,BreakFlag
+                return
+            }
+            _ => (),
+        }
+
+        let ins = (((event.note & 0x1000) >> 8) | (event.cmd as u16 >> 4)) as usize;
+        let note = (event.note & 0x0fff) as i16;
+        ch.n_0_note = note;
+
+        if ins != 0 && ins <= module.instruments.len() {
+            let instrument = &module.instruments[ins - 1];
+            ch.n_4_samplestart = self.sample_ptr[ins - 1];                  // move.l  (a1,d2),4(a6)
+            ch.n_8_length = (instrument.size / 2) as u16;                   // move.w  (a3,d4),8(a6)
+            ch.n_18_volume = instrument.volume as i16;                      // move.w  2(a3,d4),18(a6)
+            ch.n_10_loopstart = ch.n_4_samplestart + instrument.repeat as u32;  // move.l  d2,10(a6)
+            ch.n_14_replen = instrument.replen;                             // move.w  6(a3,d4),14(a6)
+        }
+
+        // SetVolume
+        match ch.n_2_cmd & 0x0f {
+            5 => {                                                          // ChangeUpVolume
+                ch.n_18_volume += ch.n_3_cmdlo as i16;
+                if ch.n_18_volume > 64 {
+                    ch.n_18_volume = 64;
+                }
+            }
+            6 => {                                                          // ChangeDownVolume
+                ch.n_18_volume -= ch.n_3_cmdlo as i16;
+                if ch.n_18_volume < 0 {
+                    ch.n_18_volume = 0;
+                }
+            }
+            _ => (),
+        }
+        if ins != 0 || ch.n_2_cmd & 0x0f == 5 || ch.n_2_cmd & 0x0f == 6 {
+            mixer.set_volume(chn, (ch.n_18_volume as usize) << 4);         // move.w  18(a6),8(a5)
+        }
+
+        // NoNewIns
+        if note != 0 {
+            self.step_control[chn].n_0_step = 0;                            // clr.w   (a4)
+            ch.n_16_period = note;                                          // move.w  (a6),16(a6)
+            mixer.set_sample_ptr(chn, ch.n_4_samplestart);                  // move.l  4(a6),(a5)
+            mixer.set_period(chn, note as f64);                             // move.w  (a6),6(a5)
+
+            // the loop is set after starting the DMA
+            let loop_start = ch.n_10_loopstart - ch.n_4_samplestart;
+            mixer.enable_loop(chn, ch.n_14_replen > 1);
+            mixer.set_loop_start(chn, loop_start);                          // move.l  10(a6),(a5)
+            mixer.set_loop_end(chn, loop_start + ch.n_14_replen as u32 * 2);  // move.w  14(a6),4(a5)
+        }
+
+        match ch.n_2_cmd & 0x0f {
+            7 => self.set_step(chn, false),  // SetStepUp
+            8 => self.set_step(chn, true),   // SetStepDown
+            _ => (),
+        }
+    }
+}
+
+
+//------------------------------------------------
+//       ChannelData - structure
+//
+//       00.w    note
+//       02.b    instrument and effect
+//       03.b    effect parameter
+//       04.l    sample start
+//       08.w    sample length in words
+//       10.l    loop start
+//       14.w    loop length in words
+//       16.w    note period for arpeggio
+//       18.w    volume
+//
+//       StepControl - structure
+//
+//       00.w    step speed
+//       02.w    current period
+//       04.w    target period
+//------------------------------------------------
+
+#[derive(Clone,Copy,Default,SaveRestore)]
+struct ChannelData {
+    n_0_note       : i16,
+    n_2_cmd        : u8,
+    n_3_cmdlo      : u8,
+    n_4_samplestart: u32,
+    n_8_length     : u16,
+    n_10_loopstart : u32,
+    n_14_replen    : u16,
+    n_16_period    : i16,
+    n_18_volume    : i16,
+}
+
+impl ChannelData {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[derive(Clone,Copy,Default,SaveRestore)]
+struct StepControl {
+    n_0_step  : i16,
+    n_2_period: i16,
+    n_4_target: i16,
+}
+
+impl StepControl {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+lazy_static! {
+    static ref NOTE_TABLE: Box<[i16; 56]> = Box::new([
+        1076, 1016, 960, 906,
+        856, 808, 762, 720, 678, 640, 604, 570,
+        538, 508, 480, 453, 428, 404, 381, 360,
+        339, 320, 302, 285, 269, 254, 240, 226,
+        214, 202, 190, 180, 170, 160, 151, 143,
+        135, 127, 120, 113, 113, 113, 113, 113,
+        113, 113, 113, 113, 113, 113, 113, 113,
+        113, 113, 113, -1
+    ]);
+}
+
+
+impl FormatPlayer for SfxPlayer {
+    fn start(&mut self, data: &mut PlayerData, mdata: &ModuleData, mixer: &mut Mixer) {
+
+        let module = mdata.as_any().downcast_ref::<SfxData>().unwrap();
+
+        for i in 0..module.samples.len() {
+            self.sample_ptr[i] = module.samples[i].address;
+        }
+
+        data.speed = 6;
+        data.tempo = module.tempo();
+        data.time  = 0.0;
+
+        data.initial_speed = data.speed;
+        data.initial_tempo = data.tempo;
+
+        self.track_pos = data.pos as u16;
+
+        let pan = match self.options.option_int("pan") {
+            Some(val) => val,
+            None      => 70,
+        };
+        let panl = -128 * pan / 100;
+        let panr = 127 * pan / 100;
+
+        mixer.set_pan(0, panl);
+        mixer.set_pan(1, panr);
+        mixer.set_pan(2, panr);
+        mixer.set_pan(3, panl);
+
+        mixer.enable_paula(true);
+    }
+
+    fn play(&mut self, data: &mut PlayerData, mdata: &ModuleData, mut mixer: &mut Mixer) {
+
+        let module = mdata.as_any().downcast_ref::<SfxData>().unwrap();
+
+        self.music(&module, &mut mixer);
+
+        data.frame = self.timer as usize;
+        data.row = self.pattern_pos as usize;
+        data.pos = self.track_pos as usize;
+    }
+
+    fn reset(&mut self) {
+        self.timer       = 0;
+        self.track_pos   = 0;
+        self.pattern_pos = 0;
+        self.break_flag  = false;
+    }
+
+    fn save_state(&self) -> State {
+        scan::save_state(self)
+    }
+
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use format::sfx::sfx_module;
+    use player::TestPlayer;
+    use ::*;
+
+    #[test]
+    fn test_play_sfx() {
+        let b = sfx_module(15);
+        let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+        assert_eq!(ox.player_info().unwrap().id, "sfx");
+
+        // 96 rows at speed 6 and 122 bpm
+        let songs = ox.subsongs();
+        assert_eq!(songs[0].time, 96 * 6 * 2500 / 122);
+        for _ in 0..6 {
+            ox.play_frame();
+        }
+        assert!(ox.buffer().iter().any(|&x| x != 0));
+
+        // the first step is played in the sixth frame
+        let mut p = TestPlayer::new(&b, "sfx");
+        for _ in 0..5 {
+            p.play();
+            assert_eq!(p.data.row, 0);
+            assert_eq!(p.mixer.period(1), 0.0);
+        }
+        p.play();
+        assert_eq!((p.data.row, p.data.frame), (1, 0));
+        assert_eq!(p.mixer.period(1), 428.0);
+
+        // the pitchbend lowers the period by 2 in each of the five frames
+        // between steps, and the next step keeps the bent period
+        for i in 1..6 {
+            p.play();
+            assert_eq!((p.data.row, p.data.frame), (1, i));
+            assert_eq!(p.mixer.period(1), 428.0 - 2.0 * i as f64);
+        }
+        p.play();
+        assert_eq!((p.data.row, p.data.frame), (2, 0));
+        assert_eq!(p.mixer.period(1), 418.0);
+    }
+}
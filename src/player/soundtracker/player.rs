@@ -1,8 +1,9 @@
 use module::{Module, ModuleData};
 use player::{Options, PlayerData, FormatPlayer, State};
-use player::scan::SaveRestore;
+use player::scan::{self, SaveRestore};
 use format::st::StData;
 use mixer::Mixer;
+use Error;
 
 /// D.O.C SoundTracker V2.0 replayer
 ///
@@ -229,7 +230,7 @@ impl StPlayer {
 }
 
 
-#[derive(Clone,Copy,Default)]
+#[derive(Clone,Copy,Default,SaveRestore)]
 struct AudTemp {
     n_0_note        : u16,
     n_2_cmd         : u8,
@@ -276,6 +277,8 @@ impl FormatPlayer for StPlayer {
         data.initial_speed = data.speed;
         data.initial_tempo = data.tempo;
 
+        self.mt_partnrplay = data.pos as u8;
+
         let pan = match self.options.option_int("pan") {
             Some(val) => val,
             None      => 70,
@@ -301,7 +304,6 @@ impl FormatPlayer for StPlayer {
         data.row = self.mt_partnote as usize;
         data.pos = self.mt_partnrplay as usize;
         data.speed = self.mt_speed as usize;
-        data.time += 20.0 * 125.0 / data.tempo as f32;
     }
 
     fn reset(&mut self) {
@@ -312,11 +314,11 @@ impl FormatPlayer for StPlayer {
         self.mt_partnote = 0;
     }
 
-    unsafe fn save_state(&self) -> State {
-        self.save()
+    fn save_state(&self) -> State {
+        scan::save_state(self)
     }
 
-    unsafe fn restore_state(&mut self, state: &State) {
-        self.restore(&state)
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
     }
 }
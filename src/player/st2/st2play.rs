@@ -1,8 +1,9 @@
 use module::{Module, ModuleData};
 use player::{Options, PlayerData, FormatPlayer, State};
-use player::scan::SaveRestore;
+use player::scan::{self, SaveRestore};
 use format::stm::StmData;
 use mixer::Mixer;
+use Error;
 
 /// Scream Tracker 2 replayer
 ///
@@ -369,7 +370,7 @@ impl St2Play {
 }
 
 
-#[derive(Default,Copy,Clone)]
+#[derive(Default,Copy,Clone,SaveRestore)]
 struct St2Channel {
     //on               : bool,
     //empty            : bool,
@@ -413,7 +414,7 @@ impl FormatPlayer for St2Play {
         let module = mdata.as_any().downcast_ref::<StmData>().unwrap();
 
         self.tempo = 0x60;
-        self.order_next = 0;
+        self.order_next = data.pos as u16;
 
         // sr/x = (sr*250)/(T*100) => T = 25*x/10
         data.tempo = self.tempo_factor as f32;
@@ -467,17 +468,16 @@ impl FormatPlayer for St2Play {
 
         data.speed = self.ticks_per_row as usize;
         data.tempo = 2.5 * self.tempo_factor as f32;
-        data.time += 20.0 * 125.0 / data.tempo;
     }
 
     fn reset(&mut self) {
     }
 
-    unsafe fn save_state(&self) -> State {
-        self.save()
+    fn save_state(&self) -> State {
+        scan::save_state(self)
     }
 
-    unsafe fn restore_state(&mut self, state: &State) {
-        self.restore(&state)
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
     }
 }
@@ -1,8 +1,9 @@
 use module::{Module, ModuleData};
 use player::{Options, PlayerData, FormatPlayer, State};
-use player::scan::SaveRestore;
+use player::scan::{self, SaveRestore};
 use format::s3m::S3mData;
 use mixer::Mixer;
+use Error;
 
 /// S3M replayer
 ///
@@ -62,7 +63,7 @@ const BEROTRACKER    : u8 = 6;
 // there is also CREAMTRACKER (7), but let's ignore that for now
 
 // STRUCTS
-#[derive(Default)]
+#[derive(Default,SaveRestore)]
 struct Chn {
     aorgvol       : i8,
     avol          : i8,
@@ -2102,6 +2103,8 @@ impl FormatPlayer for St3Play {
 
         let module = mdata.as_any().downcast_ref::<S3mData>().unwrap();
 
+        mixer.enable_ramp(true);
+
         self.soundcardtype = SOUNDCARD_GUS;
 
         self.loadheaderparms(&module);
@@ -2143,22 +2146,21 @@ impl FormatPlayer for St3Play {
 
         data.frame = self.musiccount as usize;
         data.row = self.np_row as usize;
-        data.pos = self.np_ord as usize - 1;
+        data.pos = if self.np_ord > 0 { self.np_ord as usize - 1 } else { 0 };  // np_ord is 0 after B00
         data.speed = self.musicmax as usize;
         data.tempo = self.tempo as f32;
-        data.time += 20.0 * 125.0 / data.tempo as f32;
         data.inside_loop = self.inside_loop;
     }
 
     fn reset(&mut self) {
     }
 
-    unsafe fn save_state(&self) -> State {
-        self.save()
+    fn save_state(&self) -> State {
+        scan::save_state(self)
     }
 
-    unsafe fn restore_state(&mut self, state: &State) {
-        self.restore(&state)
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
     }
 }
 
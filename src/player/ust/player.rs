@@ -1,8 +1,9 @@
 use module::{Module, ModuleData};
 use player::{Options, PlayerData, FormatPlayer, State};
-use player::scan::SaveRestore;
+use player::scan::{self, SaveRestore};
 use format::st::StData;
 use mixer::Mixer;
+use Error;
 
 /// Ultimate Soundtracker V27 replayer
 ///
@@ -209,7 +210,7 @@ impl USTPlayer {
 //       22.w    dma-bit
 //------------------------------------------------
 
-#[derive(Clone,Copy,Default)]
+#[derive(Clone,Copy,Default,SaveRestore)]
 struct DataChnx {
     n_0_note            : i16,
     n_2_sound_number    : u8,
@@ -256,6 +257,8 @@ impl FormatPlayer for USTPlayer {
         data.initial_speed = data.speed;
         data.initial_tempo = data.tempo;
 
+        self.trkpos = data.pos as u16;
+
         let pan = match self.options.option_int("pan") {
             Some(val) => val,
             None      => 70,
@@ -280,7 +283,6 @@ impl FormatPlayer for USTPlayer {
         data.frame = self.timpos as usize;
         data.row = self.patpos as usize;
         data.pos = self.trkpos as usize;
-        data.time += 20.0 * 125.0 / data.tempo as f32;
     }
 
     fn reset(&mut self) {
@@ -289,11 +291,11 @@ impl FormatPlayer for USTPlayer {
         self.patpos = 0;
     }
 
-    unsafe fn save_state(&self) -> State {
-        self.save()
+    fn save_state(&self) -> State {
+        scan::save_state(self)
     }
 
-    unsafe fn restore_state(&mut self, state: &State) {
-        self.restore(&state)
+    fn restore_state(&mut self, state: &State) -> Result<(), Error> {
+        scan::restore_state(self, state)
     }
 }
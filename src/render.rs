@@ -0,0 +1,372 @@
+//! Render modules to audio files.
+//!
+//! The current song is rendered from its start to the end of the last
+//! loop, optionally followed by a fade-out. Files can be written as WAV,
+//! AIFF or headerless PCM, with a mix of all channels or with one stem
+//! per module channel.
+
+use std::fs::File;
+use std::slice;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use player::{OutputSample, I24};
+use stream::SampleFormat;
+use util::BinaryWrite;
+use ::*;
+
+// Sample frames rendered in each fill_buffer() call
+const RENDER_CHUNK: usize = 4096;
+
+/// Audio file formats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileFormat {
+    /// RIFF WAVE, little-endian samples.
+    Wav,
+    /// AIFF, or AIFF-C for floating point samples. Big-endian samples.
+    Aiff,
+    /// Interleaved little-endian samples with no header.
+    Raw,
+}
+
+impl FileFormat {
+    /// The usual file name extension for the format.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            FileFormat::Wav  => "wav",
+            FileFormat::Aiff => "aiff",
+            FileFormat::Raw  => "raw",
+        }
+    }
+}
+
+pub struct RenderOptions {
+    /// The output file format.
+    pub file_format: FileFormat,
+    /// The output sample format.
+    pub sample_format: SampleFormat,
+    /// The number of times to play the song, at least once.
+    pub loops: usize,
+    /// The length in ms of the fade-out played after the last loop.
+    pub fade_out: u32,
+    /// Write one file per module channel in `render_file()`.
+    pub stems: bool,
+}
+
+impl RenderOptions {
+    /// Create options to render a single loop to a 16-bit WAV file.
+    pub fn new() -> Self {
+        RenderOptions {
+            file_format  : FileFormat::Wav,
+            sample_format: SampleFormat::S16,
+            loops        : 1,
+            fade_out     : 0,
+            stems        : false,
+        }
+    }
+}
+
+/// Render the current song mixing all channels. Returns the number of
+/// sample frames written.
+pub fn render<W: Write + Seek>(oxdz: &mut Oxdz, out: &mut W, opts: &RenderOptions) -> Result<usize, Error> {
+    render_outputs(oxdz, slice::from_mut(out), opts, false)
+}
+
+/// Render the current song with one output per module channel.
+pub fn render_stems<W: Write + Seek>(oxdz: &mut Oxdz, outs: &mut [W], opts: &RenderOptions) -> Result<usize, Error> {
+    let channels = oxdz.player.module.channels;
+    if outs.len() != channels {
+        return Err(Error::Player(format!("{} outputs for {} channels", outs.len(), channels)))
+    }
+    render_outputs(oxdz, outs, opts, true)
+}
+
+/// Render the current song to a file. When rendering stems, the channel
+/// number is appended to the file name, e.g. `song-01.wav`.
+pub fn render_file<P: AsRef<Path>>(oxdz: &mut Oxdz, path: P, opts: &RenderOptions) -> Result<(), Error> {
+    let path = path.as_ref();
+    if !opts.stems {
+        let mut out = BufWriter::new(File::create(path)?);
+        render(oxdz, &mut out, opts)?;
+        out.flush()?;
+        return Ok(())
+    }
+
+    let mut outs = Vec::new();
+    for chn in 0..oxdz.player.module.channels {
+        outs.push(BufWriter::new(File::create(stem_path(path, chn, opts.file_format))?));
+    }
+    render_stems(oxdz, &mut outs, opts)?;
+    for out in &mut outs {
+        out.flush()?;
+    }
+    Ok(())
+}
+
+fn stem_path(path: &Path, chn: usize, format: FileFormat) -> PathBuf {
+    let stem = path.file_stem().and_then(|x| x.to_str()).unwrap_or("out");
+    let ext = path.extension().and_then(|x| x.to_str()).unwrap_or(format.extension());
+    path.with_file_name(format!("{}-{:02}.{}", stem, chn + 1, ext))
+}
+
+fn render_outputs<W: Write + Seek>(oxdz: &mut Oxdz, outs: &mut [W], opts: &RenderOptions, stems: bool) -> Result<usize, Error> {
+    let rate = oxdz.rate;
+
+    // the headers are written again when the data size is known
+    let mut start = Vec::new();
+    for out in outs.iter_mut() {
+        start.push(out.seek(SeekFrom::Current(0))?);
+        out.write_all(&header(opts, rate, 0))?;
+    }
+
+    let song = oxdz.player.data.song;
+    oxdz.player.set_song(song)?;
+    oxdz.player.set_stems(stems);
+    let frames = render_data(oxdz, outs, opts, stems);
+    oxdz.player.set_stems(false);
+    let frames = frames?;
+
+    for (out, &pos) in outs.iter_mut().zip(start.iter()) {
+        let end = out.seek(SeekFrom::Current(0))?;
+        out.seek(SeekFrom::Start(pos))?;
+        out.write_all(&header(opts, rate, frames))?;
+        out.seek(SeekFrom::Start(end))?;
+    }
+
+    Ok(frames)
+}
+
+// Rendering goes through fill_buffer(), so files end and fade out exactly
+// like streamed playback.
+fn render_data<W: Write>(oxdz: &mut Oxdz, outs: &mut [W], opts: &RenderOptions, stems: bool) -> Result<usize, Error> {
+    let fade_out = oxdz.player.fade_out();
+    oxdz.player.set_fade_out(opts.fade_out);
+    let res = write_data(oxdz, outs, opts, stems);
+    oxdz.player.set_fade_out(fade_out);
+    res
+}
+
+fn write_data<W: Write>(oxdz: &mut Oxdz, outs: &mut [W], opts: &RenderOptions, stems: bool) -> Result<usize, Error> {
+    match opts.sample_format {
+        SampleFormat::S16 => write_samples::<i16, W>(oxdz, outs, opts, stems),
+        SampleFormat::S24 => write_samples::<I24, W>(oxdz, outs, opts, stems),
+        SampleFormat::S32 => write_samples::<i32, W>(oxdz, outs, opts, stems),
+        SampleFormat::F32 => write_samples::<f32, W>(oxdz, outs, opts, stems),
+    }
+}
+
+fn write_samples<T: OutputSample, W: Write>(oxdz: &mut Oxdz, outs: &mut [W], opts: &RenderOptions, stems: bool) -> Result<usize, Error> {
+    let loops = if opts.loops > 0 { opts.loops } else { 1 };
+    let bytes = opts.sample_format.bytes();
+    let big_endian = opts.file_format == FileFormat::Aiff;
+
+    let mut frames = 0;
+    let mut bufs = vec![vec![T::default(); RENDER_CHUNK * 2]; outs.len()];
+    let mut b = Vec::new();
+
+    loop {
+        let filled = if stems {
+            oxdz.player.fill_stem_buffers(&mut bufs, loops)
+        } else {
+            oxdz.player.fill_buffer(&mut bufs[0], loops)
+        };
+
+        for (out, buf) in outs.iter_mut().zip(bufs.iter()) {
+            b.clear();
+            for x in &buf[..filled] {
+                x.write_le(&mut b);
+            }
+            if big_endian {
+                for smp in b.chunks_mut(bytes) {
+                    smp.reverse();
+                }
+            }
+            out.write_all(&b)?;
+        }
+        frames += filled / 2;
+
+        if filled < RENDER_CHUNK * 2 {
+            return Ok(frames)
+        }
+    }
+}
+
+fn header(opts: &RenderOptions, rate: u32, frames: usize) -> Vec<u8> {
+    match opts.file_format {
+        FileFormat::Wav  => wav_header(opts.sample_format, rate, frames),
+        FileFormat::Aiff => aiff_header(opts.sample_format, rate, frames),
+        FileFormat::Raw  => Vec::new(),
+    }
+}
+
+fn wav_header(format: SampleFormat, rate: u32, frames: usize) -> Vec<u8> {
+    let bytes = format.bytes() as u32;
+    let data_size = frames as u32 * 2 * bytes;
+    let float = format == SampleFormat::F32;
+
+    let mut b = Vec::new();
+    b.write_string("RIFF", 4);
+    b.write32l(if float { 50 } else { 36 } + data_size);
+    b.write_string("WAVE", 4);
+
+    b.write_string("fmt ", 4);
+    b.write32l(if float { 18 } else { 16 });
+    b.write16l(if float { 3 } else { 1 });  // IEEE float or PCM
+    b.write16l(2);
+    b.write32l(rate);
+    b.write32l(rate * 2 * bytes);
+    b.write16l(2 * bytes as u16);
+    b.write16l(8 * bytes as u16);
+    if float {
+        b.write16l(0);
+        b.write_string("fact", 4);
+        b.write32l(4);
+        b.write32l(frames as u32);
+    }
+
+    b.write_string("data", 4);
+    b.write32l(data_size);
+    b
+}
+
+// Floating point samples need the AIFF-C format
+fn aiff_header(format: SampleFormat, rate: u32, frames: usize) -> Vec<u8> {
+    let bytes = format.bytes() as u32;
+    let data_size = frames as u32 * 2 * bytes;
+    let float = format == SampleFormat::F32;
+    let comm_size = if float { 18 + 4 + 22 } else { 18 };
+    let form_size = 4 + if float { 12 } else { 0 } + 8 + comm_size + 16 + data_size;
+
+    let mut b = Vec::new();
+    b.write_string("FORM", 4);
+    b.write32b(form_size);
+    b.write_string(if float { "AIFC" } else { "AIFF" }, 4);
+
+    if float {
+        b.write_string("FVER", 4);
+        b.write32b(4);
+        b.write32b(0xa2805140);  // AIFF-C version 1
+    }
+
+    b.write_string("COMM", 4);
+    b.write32b(comm_size);
+    b.write16b(2);
+    b.write32b(frames as u32);
+    b.write16b(8 * bytes as u16);
+    write_extended(&mut b, rate);
+    if float {
+        b.write_string("fl32", 4);
+        b.write8(21);
+        b.write_string("32-bit floating point", 21);
+    }
+
+    b.write_string("SSND", 4);
+    b.write32b(8 + data_size);
+    b.write32b(0);  // offset
+    b.write32b(0);  // block size
+    b
+}
+
+// 80-bit IEEE 754 extended precision, used for the AIFF sampling rate
+fn write_extended(b: &mut Vec<u8>, val: u32) {
+    if val == 0 {
+        b.extend_from_slice(&[0; 10]);
+        return
+    }
+    let shift = val.leading_zeros();
+    b.write16b((16383 + 31 - shift) as u16);
+    b.write32b(val << shift);
+    b.write32b(0);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use byteorder::{ByteOrder, LittleEndian};
+    use tests::test_mod;
+
+    fn render_mod(opts: &RenderOptions) -> Vec<u8> {
+        let b = test_mod("test");
+        let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+        let mut out = Cursor::new(Vec::new());
+        render(&mut ox, &mut out, opts).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn test_extended() {
+        let mut b = Vec::new();
+        write_extended(&mut b, 44100);
+        assert_eq!(b, [0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_render_wav() {
+        let frames = 64 * 6 * 882;
+        let b = render_mod(&RenderOptions::new());
+        assert_eq!(&b[..4], b"RIFF");
+        assert_eq!(b.len(), 44 + frames * 4);
+        assert_eq!(b[40..44], [(frames * 4) as u8, (frames * 4 >> 8) as u8, (frames * 4 >> 16) as u8, 0]);
+
+        // two loops with a fade-out of 500ms in 32-bit floating point
+        let mut opts = RenderOptions::new();
+        opts.loops = 2;
+        opts.fade_out = 500;
+        opts.sample_format = SampleFormat::F32;
+        let b = render_mod(&opts);
+        assert_eq!(b.len(), 58 + (2 * frames + 22050) * 8);
+        assert!(LittleEndian::read_f32(&b[b.len() - 4..]).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_render_matches_fill_buffer() {
+        let mut opts = RenderOptions::new();
+        opts.file_format = FileFormat::Raw;
+        opts.loops = 2;
+        opts.fade_out = 300;
+        let raw = render_mod(&opts);
+
+        let b = test_mod("test");
+        let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+        ox.set_fade_out(300);
+        let mut expected = Vec::new();
+        let mut buf = vec![0_i16; 1000];
+        while !ox.end() {
+            ox.fill_buffer(&mut buf, 2);
+            for x in &buf {
+                x.write_le(&mut expected);
+            }
+        }
+        assert_eq!(&raw[..], &expected[..raw.len()]);
+        assert!(expected[raw.len()..].iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn test_render_aiff() {
+        let mut opts = RenderOptions::new();
+        opts.file_format = FileFormat::Aiff;
+        let wav = render_mod(&RenderOptions::new());
+        let aiff = render_mod(&opts);
+        assert_eq!(&aiff[..4], b"FORM");
+        assert_eq!(&aiff[8..12], b"AIFF");
+        assert_eq!(aiff[4..8], [0, 0x14, 0xac, 0x2e]);
+        assert_eq!(aiff.len(), 54 + wav.len() - 44);
+        assert_eq!(aiff[54..58], [wav[45], wav[44], wav[47], wav[46]]);
+    }
+
+    #[test]
+    fn test_render_stems() {
+        let b = test_mod("test");
+        let mut ox = Oxdz::new(&b, 44100, "").unwrap();
+        let opts = RenderOptions::new();
+        let mut outs = vec![Cursor::new(Vec::new()); 4];
+        render_stems(&mut ox, &mut outs, &opts).unwrap();
+
+        // only the first channel has notes
+        let mix = render_mod(&opts);
+        assert_eq!(outs[0].get_ref(), &mix);
+        assert!(outs[1].get_ref()[44..].iter().all(|&x| x == 0));
+        assert_eq!(stem_path(Path::new("/tmp/song.wav"), 1, FileFormat::Wav), Path::new("/tmp/song-02.wav"));
+    }
+}
@@ -0,0 +1,145 @@
+use std::cmp;
+use std::io::{self, Read};
+use player::{OutputSample, I24};
+use ::*;
+
+/// Sample formats for rendered streams. Samples are interleaved stereo
+/// in little-endian byte order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    /// 16-bit signed.
+    S16,
+    /// 24-bit signed, packed in 3 bytes.
+    S24,
+    /// 32-bit signed.
+    S32,
+    /// 32-bit floating point, 1.0 is full scale.
+    F32,
+}
+
+impl SampleFormat {
+    /// The size of a sample in bytes.
+    pub fn bytes(&self) -> usize {
+        match *self {
+            SampleFormat::S16 => 2,
+            SampleFormat::S24 => 3,
+            SampleFormat::S32 => 4,
+            SampleFormat::F32 => 4,
+        }
+    }
+}
+
+
+/// Rendered audio stream created by `Oxdz::into_stream()`. Iterating the
+/// stream yields the PCM data of each frame, and reading it yields a
+/// continuous byte stream. The stream ends when the requested number of
+/// loops is completed or when replay ends.
+pub struct Stream<'a> {
+    oxdz  : Oxdz<'a>,
+    format: SampleFormat,
+    loops : usize,
+    done  : usize,     // loops completed
+    end   : bool,
+
+    // for read
+    frame : Vec<u8>,
+    pos   : usize,
+}
+
+impl<'a> Stream<'a> {
+    pub fn new(oxdz: Oxdz<'a>, format: SampleFormat, loops: usize) -> Self {
+        Stream {
+            oxdz,
+            format,
+            loops,
+            done  : 0,
+            end   : false,
+            frame : Vec::new(),
+            pos   : 0,
+        }
+    }
+
+    /// The number of loops completed so far.
+    pub fn loops(&self) -> usize {
+        self.done
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    /// Access the player, e.g. to retrieve frame information.
+    pub fn oxdz(&mut self) -> &mut Oxdz<'a> {
+        &mut self.oxdz
+    }
+
+    pub fn into_inner(self) -> Oxdz<'a> {
+        self.oxdz
+    }
+
+    fn encode<T: OutputSample>(&self, out: &mut Vec<u8>) {
+        let mut buffer = Vec::<T>::new();
+        self.oxdz.buffer_as(&mut buffer);
+        for s in &buffer {
+            s.write_le(out);
+        }
+    }
+
+    fn next_frame(&mut self, out: &mut Vec<u8>) -> bool {
+        if self.end {
+            return false
+        }
+
+        self.oxdz.play_frame();
+
+        // the frame that completes a loop belongs to the next loop
+        let loop_count = self.oxdz.player.loop_count();
+        if loop_count > self.done {
+            self.done = loop_count;
+        }
+        if self.oxdz.player.end() || (self.loops > 0 && self.done >= self.loops) {
+            self.end = true;
+            return false
+        }
+
+        out.clear();
+        match self.format {
+            SampleFormat::S16 => self.encode::<i16>(out),
+            SampleFormat::S24 => self.encode::<I24>(out),
+            SampleFormat::S32 => self.encode::<i32>(out),
+            SampleFormat::F32 => self.encode::<f32>(out),
+        }
+        true
+    }
+}
+
+impl<'a> Iterator for Stream<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = Vec::new();
+        if self.next_frame(&mut frame) {
+            Some(frame)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Read for Stream<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.frame.len() {
+            let mut frame = Vec::new();
+            if !self.next_frame(&mut frame) {
+                return Ok(0)
+            }
+            self.frame = frame;
+            self.pos = 0;
+        }
+
+        let size = cmp::min(buf.len(), self.frame.len() - self.pos);
+        buf[..size].copy_from_slice(&self.frame[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
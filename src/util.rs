@@ -1,4 +1,3 @@
-use std::ptr;
 use std::slice;
 use byteorder::{ByteOrder, BigEndian, LittleEndian};
 use Error;
@@ -42,17 +41,6 @@ macro_rules! magic4 {
 }
 
 
-pub trait MemOpExt<T> {
-    fn fill(&mut self, u8, usize);
-}
-
-impl<'a, T> MemOpExt<T> for [T] {
-    fn fill(&mut self, val: u8, amt: usize) {
-        unsafe { ptr::write_bytes(self.as_mut_ptr(), val, amt * std::mem::size_of::<T>() - 1); }
-    }
-}
-
-
 pub trait BinaryRead {
     fn read_string(&self, ofs: usize, size: usize) -> Result<String, Error>;
     fn read32b(&self, ofs: usize) -> Result<u32, Error>;
@@ -107,6 +95,62 @@ impl<'a> BinaryRead for &'a [u8] {
 }
 
 
+pub trait BinaryWrite {
+    fn write_string(&mut self, s: &str, size: usize);
+    fn write16b(&mut self, val: u16);
+    fn write32b(&mut self, val: u32);
+    fn write32l(&mut self, val: u32);
+    fn write16l(&mut self, val: u16);
+    fn write8(&mut self, val: u8);
+    fn pad_to(&mut self, align: usize);
+}
+
+impl BinaryWrite for Vec<u8> {
+    // Strings are truncated or padded with NULs to the field size
+    fn write_string(&mut self, s: &str, size: usize) {
+        let b = s.as_bytes();
+        let n = if b.len() > size { size } else { b.len() };
+        self.extend_from_slice(&b[..n]);
+        self.extend(std::iter::repeat(0).take(size - n));
+    }
+
+    fn write16b(&mut self, val: u16) {
+        let mut b = [0; 2];
+        BigEndian::write_u16(&mut b, val);
+        self.extend_from_slice(&b);
+    }
+
+    fn write32b(&mut self, val: u32) {
+        let mut b = [0; 4];
+        BigEndian::write_u32(&mut b, val);
+        self.extend_from_slice(&b);
+    }
+
+    fn write32l(&mut self, val: u32) {
+        let mut b = [0; 4];
+        LittleEndian::write_u32(&mut b, val);
+        self.extend_from_slice(&b);
+    }
+
+    fn write16l(&mut self, val: u16) {
+        let mut b = [0; 2];
+        LittleEndian::write_u16(&mut b, val);
+        self.extend_from_slice(&b);
+    }
+
+    fn write8(&mut self, val: u8) {
+        self.push(val);
+    }
+
+    // Pad with zeros up to the next multiple of align bytes
+    fn pad_to(&mut self, align: usize) {
+        while self.len() % align != 0 {
+            self.push(0);
+        }
+    }
+}
+
+
 pub trait SliceConvert<'a> {
     fn as_slice_u8(&'a self) -> &'a [u8];
 }